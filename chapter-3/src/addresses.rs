@@ -0,0 +1,278 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Reading the multiaddresses that people type.
+//!
+//! A multiaddress such as `/ip4/10.0.0.1/tcp/63204/ws` is precise, but easy to get wrong, and the
+//! parser of libp2p only tells whether it is valid. `parse` explains what is wrong and, when it
+//! can guess, suggests a correction.
+//!
+//! It also expands shorthands. `10.0.0.1:63204`, `[::1]:63204` and `ws://10.0.0.1:63204` become
+//! websockets addresses, like the one we listen on by default, and `wss://` the secure variant.
+//! Natively, a host name such as `localhost:63204` is resolved, since there's no DNS transport.
+//!
+//! An address can parse and still be useless: without `/tcp` there's nothing to connect to, and
+//! browsers can only dial websockets.
+
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Port that the suggestions use, our default one.
+const DEFAULT_PORT: u16 = 63204;
+
+/// The protocols that one may write in our addresses, and whether a value follows them.
+const PROTOCOLS: &[(&str, bool)] = &[
+    ("ip4", true),
+    ("ip6", true),
+    ("dns4", true),
+    ("dns6", true),
+    ("tcp", true),
+    ("udp", true),
+    ("ws", false),
+    ("wss", false),
+    ("p2p", true),
+    ("ipfs", true),
+    ("p2p-circuit", false),
+];
+
+/// Why some text isn't a usable multiaddress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invalid {
+    /// Format string with one argument, `detail`.
+    reason: &'static str,
+    detail: String,
+    suggestion: Option<String>,
+}
+
+impl Invalid {
+    fn new(reason: &'static str, detail: &str) -> Invalid {
+        Invalid {
+            reason,
+            detail: detail.to_owned(),
+            suggestion: None,
+        }
+    }
+
+    fn suggest(mut self, suggestion: String) -> Invalid {
+        self.suggestion = Some(suggestion);
+        self
+    }
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = tr!(self.reason, self.detail);
+        match self.suggestion {
+            Some(ref suggestion) => {
+                write!(f, "{}", tr!("{}; did you mean {}?", reason, suggestion))
+            }
+            None => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Parses `text` as a multiaddress or one of the shorthands, and checks that we can dial or
+/// listen on it.
+pub fn parse(text: &str) -> Result<Multiaddr, Invalid> {
+    let text = text.trim();
+    let address = match text.parse::<Multiaddr>() {
+        Ok(address) => address,
+        Err(_) => match expand(text)? {
+            Some(address) => address,
+            None => return Err(diagnose(text)),
+        },
+    };
+    check(&address)?;
+    Ok(address)
+}
+
+/// Expands the shorthands, or returns `None` if `text` isn't one.
+fn expand(text: &str) -> Result<Option<Multiaddr>, Invalid> {
+    let (rest, secure) = if text.starts_with("ws://") {
+        (&text[5..], false)
+    } else if text.starts_with("wss://") {
+        (&text[6..], true)
+    } else {
+        (text, false)
+    };
+    let rest = rest.trim_right_matches('/');
+    let socket_address = match rest.parse::<SocketAddr>() {
+        Ok(socket_address) => socket_address,
+        Err(_) => match rest.rfind(':') {
+            Some(colon) if !rest.contains('/') => {
+                let port = &rest[colon + 1..];
+                let port = port
+                    .parse()
+                    .map_err(|_| Invalid::new("{} isn't a port number", port))?;
+                resolve(&rest[..colon], port)?
+            }
+            _ => return Ok(None),
+        },
+    };
+    let ip = match socket_address.ip() {
+        IpAddr::V4(ip) => AddrComponent::IP4(ip),
+        IpAddr::V6(ip) => AddrComponent::IP6(ip),
+    };
+    let websockets = if secure {
+        AddrComponent::WSS
+    } else {
+        AddrComponent::WS
+    };
+    let components = vec![ip, AddrComponent::TCP(socket_address.port()), websockets];
+    Ok(Some(components.into_iter().collect()))
+}
+
+/// Looks up the IP address of `host`, preferring IPv4.
+#[cfg(not(target_os = "emscripten"))]
+fn resolve(host: &str, port: u16) -> Result<SocketAddr, Invalid> {
+    use std::net::ToSocketAddrs;
+
+    let resolved: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|_| Invalid::new("couldn't resolve the host name {}", host))?
+        .collect();
+    resolved
+        .iter()
+        .find(|address| address.is_ipv4())
+        .or_else(|| resolved.first())
+        .cloned()
+        .ok_or_else(|| Invalid::new("couldn't resolve the host name {}", host))
+}
+
+#[cfg(target_os = "emscripten")]
+fn resolve(host: &str, _: u16) -> Result<SocketAddr, Invalid> {
+    Err(Invalid::new("{} must be an IP address; browsers can't resolve host names here", host))
+}
+
+/// Explains why `text`, which libp2p refused, isn't a multiaddress.
+fn diagnose(text: &str) -> Invalid {
+    if text.is_empty() {
+        let example = format!("/ip4/10.0.0.1/tcp/{}/ws", DEFAULT_PORT);
+        return Invalid::new("an address is expected, such as {}", &example);
+    }
+    if !text.starts_with('/') {
+        let invalid = Invalid::new("{} should start with a /", text);
+        let fixed = format!("/{}", text);
+        return match fixed.parse::<Multiaddr>() {
+            Ok(_) => invalid.suggest(fixed),
+            Err(_) => invalid.suggest(format!("/ip4/10.0.0.1/tcp/{}/ws", DEFAULT_PORT)),
+        };
+    }
+    let mut parts = text[1..].split('/');
+    while let Some(name) = parts.next() {
+        let takes_value = match PROTOCOLS.iter().find(|&&(known, _)| known == name) {
+            Some(&(_, takes_value)) => takes_value,
+            None if name.is_empty() => return Invalid::new("{} has an empty component", text),
+            None => {
+                let invalid = Invalid::new("/{} isn't a protocol", name);
+                return match closest(name) {
+                    Some(known) => {
+                        let (typo, known) = (format!("/{}", name), format!("/{}", known));
+                        invalid.suggest(text.replacen(&typo, &known, 1))
+                    }
+                    None => invalid,
+                };
+            }
+        };
+        if !takes_value {
+            continue;
+        }
+        let value = match parts.next() {
+            Some(value) if !value.is_empty() => value,
+            _ => return Invalid::new("/{} must be followed by a value", name),
+        };
+        let valid = match name {
+            "ip4" => value.parse::<Ipv4Addr>().is_ok(),
+            "ip6" => value.parse::<Ipv6Addr>().is_ok(),
+            "tcp" | "udp" => value.parse::<u16>().is_ok(),
+            _ => true,
+        };
+        if !valid {
+            return match name {
+                "ip4" => Invalid::new("{} isn't an IPv4 address", value),
+                "ip6" => Invalid::new("{} isn't an IPv6 address", value),
+                _ => Invalid::new("{} isn't a port number", value),
+            };
+        }
+    }
+    Invalid::new("{} isn't a valid multiaddress", text)
+}
+
+/// Checks that `address`, which parsed, can be dialed by us or listened on.
+fn check(address: &Multiaddr) -> Result<(), Invalid> {
+    let (mut host, mut tcp, mut websockets) = (false, false, false);
+    for component in address.iter() {
+        match component {
+            AddrComponent::IP4(_)
+            | AddrComponent::IP6(_)
+            | AddrComponent::DNS4(_)
+            | AddrComponent::DNS6(_) => host = true,
+            AddrComponent::TCP(_) => tcp = true,
+            AddrComponent::WS | AddrComponent::WSS => websockets = true,
+            _ => (),
+        }
+    }
+    let text = address.to_string();
+    if host && !tcp {
+        let reason = "{} lacks the /tcp component, with the port";
+        let suggestion = format!("{}/tcp/{}/ws", text, DEFAULT_PORT);
+        return Err(Invalid::new(reason, &text).suggest(suggestion));
+    }
+    if cfg!(target_os = "emscripten") && host && !websockets {
+        let reason = "{} lacks the /ws suffix, which browser peers require";
+        return Err(Invalid::new(reason, &text).suggest(format!("{}/ws", text)));
+    }
+    Ok(())
+}
+
+/// Returns true if browsers can reach `address`, which we listen on.
+pub fn reachable_by_browsers(address: &Multiaddr) -> bool {
+    address.iter().any(|component| match component {
+        AddrComponent::WS | AddrComponent::WSS => true,
+        _ => false,
+    })
+}
+
+/// Returns the known protocol that `name` is most likely a typo of.
+fn closest(name: &str) -> Option<&'static str> {
+    PROTOCOLS
+        .iter()
+        .map(|&(known, _)| (distance(name, known), known))
+        .filter(|&(distance, _)| distance <= 2)
+        .min()
+        .map(|(_, known)| known)
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = previous[j] + if a == b { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,240 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Limits on the nodes that dial us, for the relays that a whole workshop joins at once.
+//!
+//! With `--max-clients <n>`, we accept at most `n` inbound connections at the same time, and with
+//! `--max-joins <n>`, at most `n` new ones per minute. A connection beyond the limits gets a line
+//! that explains why, written before any protocol is negotiated, and is closed. The node on the
+//! other side sees its dial fail and tries another relay of `--relay`, or again later; the line
+//! itself only shows when decoding a capture of the connection.
+//!
+//! While the node drains, every node that dials us is turned away, and told where to go instead.
+//!
+//! Our own dials are never limited.
+
+use display::{self, Verbosity};
+use drain::Drain;
+use futures::future::{self, Either};
+use futures::{Future, IntoFuture, Poll, Stream};
+use libp2p::core::Transport;
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_io::{io, AsyncRead, AsyncWrite};
+
+/// Period over which `--max-joins` counts the new connections.
+const JOIN_PERIOD: Duration = Duration::from_secs(60);
+/// Start of the line sent to the nodes that are turned away.
+pub const REJECTION: &str = "rustfest-chat: sorry, ";
+
+#[derive(Clone)]
+pub struct Admission<T> {
+    inner: T,
+    limits: Rc<RefCell<Limits>>,
+}
+
+struct Limits {
+    max_clients: Option<usize>,
+    max_joins: Option<usize>,
+    drain: Drain,
+    /// Inbound connections currently open.
+    clients: usize,
+    /// When the inbound connections of the last `JOIN_PERIOD` were accepted.
+    joins: VecDeque<Instant>,
+    /// True once we said that we are turning nodes away, until one is accepted again.
+    warned: bool,
+}
+
+impl Limits {
+    /// Counts a new client, or explains why it is turned away.
+    fn admit(&mut self, now: Instant) -> Result<(), String> {
+        if let Some(reason) = self.drain.refusal() {
+            return Err(reason);
+        }
+        while self
+            .joins
+            .front()
+            .map(|&at| now.duration_since(at) >= JOIN_PERIOD)
+            .unwrap_or(false)
+        {
+            self.joins.pop_front();
+        }
+        let refused = match (self.max_clients, self.max_joins) {
+            (Some(max), _) if self.clients >= max => Some(format!(
+                "this node already serves {} nodes; try another relay",
+                max
+            )),
+            (_, Some(max)) if self.joins.len() >= max => Some(format!(
+                "{} nodes joined through this node in the last minute; try again in a moment",
+                max
+            )),
+            _ => None,
+        };
+        if let Some(reason) = refused {
+            if !self.warned {
+                self.warned = true;
+                display::chatter(&tr!(
+                    "* Turning away the nodes that dial us, because of --max-clients or \
+                     --max-joins"
+                ));
+            }
+            return Err(reason);
+        }
+        self.warned = false;
+        self.clients += 1;
+        self.joins.push_back(now);
+        Ok(())
+    }
+}
+
+impl<T> Admission<T> {
+    pub fn new(
+        inner: T,
+        max_clients: Option<usize>,
+        max_joins: Option<usize>,
+        drain: Drain,
+    ) -> Admission<T> {
+        Admission {
+            inner,
+            limits: Rc::new(RefCell::new(Limits {
+                max_clients,
+                max_joins,
+                drain,
+                clients: 0,
+                joins: VecDeque::new(),
+                warned: false,
+            })),
+        }
+    }
+}
+
+impl<T> Transport for Admission<T>
+where
+    T: Transport + 'static,
+    T::Output: AsyncWrite + 'static,
+    T::Listener: 'static,
+    T::ListenerUpgrade: 'static,
+    T::Dial: 'static,
+{
+    type Output = Admitted<T::Output>;
+    type Listener = Box<Stream<Item = Self::ListenerUpgrade, Error = IoError>>;
+    type ListenerUpgrade = Box<Future<Item = (Self::Output, Multiaddr), Error = IoError>>;
+    type Dial = Box<Future<Item = (Self::Output, Multiaddr), Error = IoError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let Admission { inner, limits } = self;
+        match inner.listen_on(addr) {
+            Ok((listener, addr)) => {
+                let listener = listener.map(move |upgrade| {
+                    let limits = limits.clone();
+                    let upgrade = upgrade.into_future().and_then(move |(socket, addr)| {
+                        let admitted = limits.borrow_mut().admit(Instant::now());
+                        match admitted {
+                            Ok(()) => {
+                                let socket = Admitted {
+                                    inner: socket,
+                                    limits: Some(limits),
+                                };
+                                Either::A(future::ok((socket, addr)))
+                            }
+                            Err(reason) => {
+                                display::event(
+                                    Verbosity::Verbose,
+                                    &format!("Turned away {}: {}", addr, reason),
+                                );
+                                let line = format!("{}{}\n", REJECTION, reason);
+                                let rejected = io::write_all(socket, line.into_bytes());
+                                Either::B(rejected.then(move |_| {
+                                    Err(IoError::new(ErrorKind::ConnectionRefused, reason))
+                                }))
+                            }
+                        }
+                    });
+                    Box::new(upgrade) as Box<Future<Item = _, Error = _>>
+                });
+                Ok((Box::new(listener), addr))
+            }
+            Err((inner, addr)) => Err((Admission { inner, limits }, addr)),
+        }
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let Admission { inner, limits } = self;
+        match inner.dial(addr) {
+            Ok(dial) => {
+                let dial = dial.into_future().map(|(socket, addr)| {
+                    let socket = Admitted {
+                        inner: socket,
+                        limits: None,
+                    };
+                    (socket, addr)
+                });
+                Ok(Box::new(dial))
+            }
+            Err((inner, addr)) => Err((Admission { inner, limits }, addr)),
+        }
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+/// A connection, which counts as a client until it is dropped if it is inbound.
+pub struct Admitted<S> {
+    inner: S,
+    limits: Option<Rc<RefCell<Limits>>>,
+}
+
+impl<S> Drop for Admitted<S> {
+    fn drop(&mut self) {
+        if let Some(ref limits) = self.limits {
+            limits.borrow_mut().clients -= 1;
+        }
+    }
+}
+
+impl<S: Read> Read for Admitted<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Write> Write for Admitted<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Admitted<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for Admitted<S> {
+    fn shutdown(&mut self) -> Poll<(), IoError> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,450 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Voice calls with a single peer: a proof of concept of low-latency media over libp2p.
+//!
+//! `/call <multiaddr>` opens a dedicated connection, negotiated with its own protocol name like
+//! tic-tac-toe, since our connections don't multiplex substreams. Each side captures its
+//! microphone with cpal, encodes frames of 20 ms with Opus and sends them with their sequence
+//! number. Incoming calls are only accepted with `--accept-calls`.
+//!
+//! The network delivers frames irregularly, so the receiver holds them in a jitter buffer. Playback
+//! starts once `JITTER_FRAMES` frames are queued, frames are played in the order of their sequence
+//! numbers, late frames are dropped, and the Opus decoder conceals the missing ones. If the queue
+//! grows beyond `MAX_BUFFERED_FRAMES`, the oldest frames are dropped so that the delay stays low.
+//!
+//! Capturing and playing sound needs the `audio-call` feature; without it, calls are declined.
+
+use bytes::Bytes;
+use futures::future::{self, FutureResult};
+use futures::sync::mpsc;
+use futures::{Future, Sink, Stream};
+use libp2p::core::{ConnectionUpgrade, Endpoint};
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{Error as IoError, ErrorKind};
+use std::iter;
+use std::rc::Rc;
+use tokio_io::codec::length_delimited;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Name of the protocol, negotiated with multistream-select.
+pub const PROTOCOL_NAME: &[u8] = b"/rustfest-chat/audio/1.0.0";
+
+/// Frames queued before playback starts, 60 ms.
+const JITTER_FRAMES: usize = 3;
+/// Frames beyond which the oldest are dropped, 500 ms.
+const MAX_BUFFERED_FRAMES: usize = 25;
+
+/// An encoded frame of sound.
+pub struct Frame {
+    pub sequence: u32,
+    /// Opus packet.
+    pub packet: Vec<u8>,
+}
+
+/// An open call connection, as produced by `AudioUpgrade`.
+pub struct AudioConnection {
+    pub endpoint: Endpoint,
+    pub incoming: Box<Stream<Item = Frame, Error = IoError>>,
+    pub outgoing: Box<Sink<SinkItem = Frame, SinkError = IoError>>,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct AudioUpgrade;
+
+impl<C> ConnectionUpgrade<C> for AudioUpgrade
+where
+    C: AsyncRead + AsyncWrite + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    type Output = AudioConnection;
+    type Future = FutureResult<AudioConnection, IoError>;
+
+    fn upgrade(self, socket: C, _: (), endpoint: Endpoint, _: &Multiaddr) -> Self::Future {
+        // Each frame is prefixed by its length, then starts with its sequence number.
+        let (sink, stream) = length_delimited::Framed::<_, Vec<u8>>::new(socket).split();
+        let incoming = stream.and_then(|bytes| {
+            if bytes.len() < 4 {
+                return Err(IoError::new(ErrorKind::InvalidData, "truncated audio frame"));
+            }
+            let sequence = bytes[..4].iter().fold(0, |n, &byte| (n << 8) | u32::from(byte));
+            Ok(Frame {
+                sequence,
+                packet: bytes[4..].to_vec(),
+            })
+        });
+        let outgoing = sink.with(|frame: Frame| -> Result<Vec<u8>, IoError> {
+            let mut bytes = Vec::with_capacity(4 + frame.packet.len());
+            bytes.extend((0..4).rev().map(|i| (frame.sequence >> (8 * i)) as u8));
+            bytes.extend_from_slice(&frame.packet);
+            Ok(bytes)
+        });
+        future::ok(AudioConnection {
+            endpoint,
+            incoming: Box::new(incoming),
+            outgoing: Box::new(outgoing),
+        })
+    }
+}
+
+/// Reorders the frames received, and holds a few of them to absorb the jitter of the network.
+#[derive(Default)]
+pub struct JitterBuffer {
+    frames: BTreeMap<u32, Vec<u8>>,
+    /// Sequence number of the next frame to play, once playing.
+    next: Option<u32>,
+}
+
+impl JitterBuffer {
+    pub fn push(&mut self, frame: Frame) {
+        if self.next.map(|next| frame.sequence < next).unwrap_or(false) {
+            return;
+        }
+        self.frames.insert(frame.sequence, frame.packet);
+        while self.frames.len() > MAX_BUFFERED_FRAMES {
+            let oldest = *self.frames.keys().next().expect("there are frames");
+            self.frames.remove(&oldest);
+            if self.next.map(|next| next <= oldest).unwrap_or(false) {
+                self.next = Some(oldest + 1);
+            }
+        }
+    }
+
+    /// Returns the next packet to decode, `Some(None)` if that frame was lost, or `None` if there
+    /// is nothing to play, in which case we wait until the buffer fills up again.
+    pub fn pop(&mut self) -> Option<Option<Vec<u8>>> {
+        if self.next.is_none() {
+            if self.frames.len() < JITTER_FRAMES {
+                return None;
+            }
+            self.next = self.frames.keys().next().cloned();
+        }
+        if self.frames.is_empty() {
+            self.next = None;
+            return None;
+        }
+        let next = self.next?;
+        self.next = Some(next.wrapping_add(1));
+        Some(self.frames.remove(&next))
+    }
+}
+
+/// The call in progress, if any.
+pub struct Calls {
+    /// The peer we are talking with.
+    current: Option<Multiaddr>,
+    accept: bool,
+    #[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+    engine: Option<engine::Engine>,
+}
+
+impl Calls {
+    /// If `accept` is false, the calls that others open are declined.
+    pub fn new(accept: bool) -> Rc<RefCell<Calls>> {
+        Rc::new(RefCell::new(Calls {
+            current: None,
+            accept,
+            #[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+            engine: None,
+        }))
+    }
+
+    /// Ends the call in progress.
+    pub fn hang_up(&mut self) {
+        match self.current.take() {
+            Some(peer) => {
+                self.stop();
+                say!("* You hung up on {}", peer);
+            }
+            None => say!("* You aren't in a call"),
+        }
+    }
+
+    /// Called when the connection to `peer` is closed.
+    fn connection_closed(&mut self, peer: &Multiaddr) {
+        if self.current.as_ref() == Some(peer) {
+            self.current = None;
+            self.stop();
+            say!("* The call with {} ended", peer);
+        }
+    }
+
+    /// Starts capturing and playing sound. The captured frames are sent to `frames`.
+    #[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+    fn start(&mut self, frames: mpsc::UnboundedSender<Frame>) -> Result<(), String> {
+        if self.engine.is_none() {
+            self.engine = Some(engine::Engine::new());
+        }
+        self.engine.as_ref().expect("just created").start(frames)
+    }
+
+    #[cfg(not(all(feature = "audio-call", not(target_os = "emscripten"))))]
+    fn start(&mut self, _: mpsc::UnboundedSender<Frame>) -> Result<(), String> {
+        Err("this build doesn't have the audio-call feature".to_owned())
+    }
+
+    fn stop(&mut self) {
+        #[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+        {
+            if let Some(ref engine) = self.engine {
+                engine.stop();
+            }
+        }
+    }
+
+    fn receive(&self, frame: Frame) {
+        #[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+        {
+            if let Some(ref engine) = self.engine {
+                engine.receive(frame);
+            }
+        }
+        #[cfg(not(all(feature = "audio-call", not(target_os = "emscripten"))))]
+        let _ = frame;
+    }
+}
+
+/// Handles a call connection opened by us or by a remote, and returns the future that drives it.
+pub fn handle_connection(
+    calls: Rc<RefCell<Calls>>,
+    connection: AudioConnection,
+    peer: Multiaddr,
+) -> Box<Future<Item = (), Error = IoError>> {
+    // Dropping the connection tells the remote that we declined.
+    {
+        let calls = calls.borrow();
+        if calls.current.is_some() {
+            say!("* Declined a call from {}: you are already in a call", peer);
+            return Box::new(future::ok(()));
+        }
+        if connection.endpoint == Endpoint::Listener && !calls.accept {
+            say!("* Declined a call from {}; pass --accept-calls to accept them", peer);
+            return Box::new(future::ok(()));
+        }
+    }
+
+    let (sender, receiver) = mpsc::unbounded();
+    if let Err(err) = calls.borrow_mut().start(sender) {
+        say!("* Can't call {}: {}", peer, err);
+        return Box::new(future::ok(()));
+    }
+    calls.borrow_mut().current = Some(peer.clone());
+    say!("* In a call with {}; `/hangup` to end it", peer);
+
+    let incoming = {
+        let calls = calls.clone();
+        connection.incoming.for_each(move |frame| {
+            calls.borrow().receive(frame);
+            Ok(())
+        })
+    };
+    // Stopping the engine drops `sender`, which ends `outgoing` and thus the connection.
+    let outgoing = connection
+        .outgoing
+        .send_all(receiver.map_err(|()| -> IoError { unreachable!() }))
+        .map(|_| ());
+    Box::new(
+        incoming
+            .select(outgoing)
+            .map(|_| ())
+            .map_err(|(err, _)| err)
+            .then(move |result| {
+                calls.borrow_mut().connection_closed(&peer);
+                result
+            }),
+    )
+}
+
+/// Capturing, encoding, decoding and playing sound, on a thread of its own.
+#[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+mod engine {
+    use super::{Frame, JitterBuffer};
+    use cpal::{self, EventLoop, Format, SampleFormat, SampleRate, StreamData, StreamId};
+    use cpal::{UnknownTypeInputBuffer, UnknownTypeOutputBuffer};
+    use futures::sync::mpsc;
+    use opus::{Application, Channels, Decoder, Encoder};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Opus only supports a few sample rates; this is its native one.
+    const SAMPLE_RATE: u32 = 48_000;
+    /// Samples per frame: 20 ms at 48 kHz.
+    const FRAME_SAMPLES: usize = 960;
+    /// Maximum size of an encoded frame.
+    const MAX_PACKET: usize = 4000;
+
+    /// The cpal event loop, which runs forever, and the state of the call it serves.
+    pub struct Engine {
+        event_loop: Arc<EventLoop>,
+        call: Arc<Mutex<Option<Call>>>,
+    }
+
+    struct Call {
+        input: StreamId,
+        output: StreamId,
+        encoder: Encoder,
+        decoder: Decoder,
+        /// Samples captured but not encoded yet.
+        captured: Vec<f32>,
+        /// Samples decoded but not played yet.
+        decoded: VecDeque<f32>,
+        jitter: JitterBuffer,
+        sequence: u32,
+        frames: mpsc::UnboundedSender<Frame>,
+    }
+
+    impl Engine {
+        pub fn new() -> Engine {
+            let event_loop = Arc::new(EventLoop::new());
+            let call: Arc<Mutex<Option<Call>>> = Arc::new(Mutex::new(None));
+            {
+                let (event_loop, call) = (event_loop.clone(), call.clone());
+                thread::spawn(move || {
+                    event_loop.run(move |id, data| {
+                        let mut call = call.lock().expect("the audio thread never panics");
+                        match data {
+                            StreamData::Input {
+                                buffer: UnknownTypeInputBuffer::F32(buffer),
+                            } => {
+                                if let Some(ref mut call) = *call {
+                                    if call.input == id {
+                                        call.capture(&buffer);
+                                    }
+                                }
+                            }
+                            StreamData::Output {
+                                buffer: UnknownTypeOutputBuffer::F32(mut buffer),
+                            } => {
+                                if let Some(ref mut call) = *call {
+                                    if call.output == id {
+                                        return call.play(&mut buffer);
+                                    }
+                                }
+                                for sample in buffer.iter_mut() {
+                                    *sample = 0.0;
+                                }
+                            }
+                            _ => {}
+                        }
+                    })
+                });
+            }
+            Engine { event_loop, call }
+        }
+
+        pub fn start(&self, frames: mpsc::UnboundedSender<Frame>) -> Result<(), String> {
+            let microphone = cpal::default_input_device().ok_or("there is no microphone")?;
+            let speakers = cpal::default_output_device().ok_or("there are no speakers")?;
+            let format = Format {
+                channels: 1,
+                sample_rate: SampleRate(SAMPLE_RATE),
+                data_type: SampleFormat::F32,
+            };
+            let error = |err| format!("{:?}", err);
+            let input = self
+                .event_loop
+                .build_input_stream(&microphone, &format)
+                .map_err(error)?;
+            let output = self
+                .event_loop
+                .build_output_stream(&speakers, &format)
+                .map_err(error)?;
+            let encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Voip)
+                .map_err(|err| err.to_string())?;
+            let decoder = Decoder::new(SAMPLE_RATE, Channels::Mono).map_err(|err| err.to_string())?;
+
+            *self.call.lock().expect("the audio thread never panics") = Some(Call {
+                input: input.clone(),
+                output: output.clone(),
+                encoder,
+                decoder,
+                captured: Vec::new(),
+                decoded: VecDeque::new(),
+                jitter: JitterBuffer::default(),
+                sequence: 0,
+                frames,
+            });
+            self.event_loop.play_stream(input);
+            self.event_loop.play_stream(output);
+            Ok(())
+        }
+
+        pub fn stop(&self) {
+            let call = self.call.lock().expect("the audio thread never panics").take();
+            if let Some(call) = call {
+                self.event_loop.destroy_stream(call.input);
+                self.event_loop.destroy_stream(call.output);
+            }
+        }
+
+        pub fn receive(&self, frame: Frame) {
+            if let Some(ref mut call) = *self.call.lock().expect("the audio thread never panics") {
+                call.jitter.push(frame);
+            }
+        }
+    }
+
+    impl Call {
+        fn capture(&mut self, samples: &[f32]) {
+            self.captured.extend_from_slice(samples);
+            while self.captured.len() >= FRAME_SAMPLES {
+                let frame: Vec<f32> = self.captured.drain(..FRAME_SAMPLES).collect();
+                let mut packet = vec![0; MAX_PACKET];
+                if let Ok(len) = self.encoder.encode_float(&frame, &mut packet) {
+                    packet.truncate(len);
+                    let sequence = self.sequence;
+                    self.sequence = sequence.wrapping_add(1);
+                    let _ = self.frames.unbounded_send(Frame { sequence, packet });
+                }
+            }
+        }
+
+        fn play(&mut self, samples: &mut [f32]) {
+            for sample in samples.iter_mut() {
+                if self.decoded.is_empty() {
+                    self.decode_next();
+                }
+                *sample = self.decoded.pop_front().unwrap_or(0.0);
+            }
+        }
+
+        fn decode_next(&mut self) {
+            let packet = match self.jitter.pop() {
+                Some(packet) => packet,
+                None => return,
+            };
+            // An empty packet asks the decoder to conceal a lost frame.
+            let packet = packet.unwrap_or_default();
+            let mut samples = vec![0.0; FRAME_SAMPLES];
+            if let Ok(len) = self.decoder.decode_float(&packet, &mut samples, false) {
+                self.decoded.extend(&samples[..len]);
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Fetching the older messages of a room from the peers, one page at a time.
+//!
+//! A node that joins a room only sees the messages published from then on. `/history more` asks
+//! the room for the `PAGE_SIZE` messages that come before the oldest one we know of in the
+//! current room. The cursor is the timestamp and ID of that message, so that every peer cuts its
+//! history at the same place even though their histories differ. Each peer that remembers older
+//! messages answers with a page; we show the first one and ignore the others. Typing
+//! `/history more` again fetches the page before that one, so a long history is never transferred
+//! at once.
+//!
+//! The messages of a page are relayed by the peer that answers, which could have made them up.
+//! They are displayed as coming from its history, and aren't added to ours.
+
+use history::History;
+use std::collections::HashMap;
+
+/// Number of messages in a page, at most.
+pub const PAGE_SIZE: usize = 20;
+
+/// Number of seconds after which a request or a page is no longer relevant.
+pub const REQUEST_TTL_SECS: u64 = 30;
+
+/// Where a page ends: it holds the messages strictly older than this one, ordered by timestamp,
+/// then by ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Cursor {
+    pub timestamp: u64,
+    pub id: u64,
+}
+
+/// A message of a page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Archived {
+    pub id: u64,
+    /// Name of the author, as displayed by the peer that answers.
+    pub name: String,
+    /// In seconds since the UNIX epoch.
+    pub timestamp: u64,
+    pub text: String,
+}
+
+impl Archived {
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            timestamp: self.timestamp,
+            id: self.id,
+        }
+    }
+}
+
+/// Returns the newest page of the messages of `room` older than `before`, or the newest ones if
+/// `None`, oldest first. The page holds at most `limit` messages and `max_bytes` of text. The
+/// boolean tells whether there are even older messages.
+pub fn page(
+    history: &History,
+    room: &str,
+    before: Option<Cursor>,
+    limit: usize,
+    max_bytes: usize,
+) -> (Vec<Archived>, bool) {
+    let mut older: Vec<Archived> = history
+        .iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| entry.room == room && !entry.deleted)
+        .map(|entry| Archived {
+            id: entry.id,
+            name: entry.name.clone(),
+            timestamp: entry.timestamp,
+            text: entry.text.clone(),
+        })
+        .filter(|archived| before.map(|before| archived.cursor() < before).unwrap_or(true))
+        .collect();
+    older.sort_by_key(|archived| archived.cursor());
+
+    let mut page = Vec::new();
+    let mut bytes = 0;
+    while let Some(archived) = older.pop() {
+        if page.len() >= limit || bytes + archived.text.len() > max_bytes {
+            older.push(archived);
+            break;
+        }
+        bytes += archived.text.len();
+        page.push(archived);
+    }
+    page.reverse();
+    (page, !older.is_empty())
+}
+
+pub struct Backfill {
+    /// Rooms of the pages that we asked for and didn't get yet, by request ID.
+    pending: HashMap<u64, String>,
+    /// The oldest message fetched so far, by room.
+    oldest: HashMap<String, Cursor>,
+}
+
+impl Backfill {
+    pub fn new() -> Backfill {
+        Backfill {
+            pending: HashMap::new(),
+            oldest: HashMap::new(),
+        }
+    }
+
+    /// Returns the cursor of the next page of `room`: the oldest of the messages we fetched and
+    /// of `history`. `None` if we know of no message in the room.
+    pub fn cursor(&self, room: &str, history: &History) -> Option<Cursor> {
+        let remembered = history
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.room == room)
+            .map(|entry| Cursor {
+                timestamp: entry.timestamp,
+                id: entry.id,
+            })
+            .min();
+        match (remembered, self.oldest.get(room).cloned()) {
+            (Some(remembered), Some(fetched)) => Some(remembered.min(fetched)),
+            (remembered, fetched) => remembered.or(fetched),
+        }
+    }
+
+    pub fn add_pending(&mut self, request: u64, room: String) {
+        self.pending.insert(request, room);
+    }
+
+    /// Called when a page answers one of our requests. Returns the room it was for, unless an
+    /// earlier page already answered.
+    pub fn complete(&mut self, request: u64, page: &[Archived]) -> Option<String> {
+        let room = self.pending.remove(&request)?;
+        if let Some(first) = page.first() {
+            let cursor = first.cursor();
+            let oldest = self.oldest.entry(room.clone()).or_insert(cursor);
+            *oldest = (*oldest).min(cursor);
+        }
+        Some(room)
+    }
+
+    /// Forgets how far we went back in `room`, or in every room if `None`.
+    pub fn purge(&mut self, room: Option<&str>) {
+        match room {
+            Some(room) => {
+                self.oldest.remove(room);
+            }
+            None => self.oldest.clear(),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Micro-batching of the messages we send.
+//!
+//! When text is piped to stdin, every line would otherwise become a separate envelope, each with
+//! its own signature and floodsub overhead. With batching enabled, the lines typed within a short
+//! delay are packed together and published as a single `Kind::Batch`.
+
+pub struct Batcher {
+    lines: Vec<String>,
+    max_lines: usize,
+}
+
+impl Batcher {
+    pub fn new(max_lines: usize) -> Batcher {
+        Batcher {
+            lines: Vec::new(),
+            max_lines: max_lines.max(1),
+        }
+    }
+
+    /// Adds a line to the batch. Returns the whole batch if it is full.
+    pub fn push(&mut self, line: String) -> Option<Vec<String>> {
+        self.lines.push(line);
+        if self.lines.len() >= self.max_lines {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    /// Number of lines waiting in the batch.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Empties the batch and returns its content.
+    pub fn take(&mut self) -> Vec<String> {
+        ::std::mem::replace(&mut self.lines, Vec::new())
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Discovery of the nodes of the local network with UDP multicast beacons.
+//!
+//! With `--beacon`, every node periodically sends a small datagram to a multicast group: a magic
+//! value, the port of its websockets listener and its `PeerId`. Every node also listens on the
+//! group, and dials the nodes it hears about for the first time. This needs neither mDNS nor any
+//! crate, which helps on the networks that block mDNS but not multicast in general.
+//!
+//! Like the TXT record of an mDNS service, the beacon also carries hints, as `key=value` lines
+//! after the `PeerId`: our nickname and the rooms we started in, so that we can tell who we found
+//! before the connection is even open. The rooms are left out with `--hash-topics`, which is
+//! meant to hide them, and the hints that don't fit in `MAX_HINTS_LEN` bytes are dropped. Clients
+//! that predate the hints take them for a part of the `PeerId`, which they only display.
+//!
+//! We listen on a fixed port, so only one node per machine can use beacons.
+//!
+//! The beacons aren't authenticated. The worst a forged beacon can do is make us dial an address,
+//! and the messages received over the resulting connection are signed anyway.
+
+use display;
+use futures::sync::mpsc;
+use futures::{Future, Sink, Stream};
+use libp2p::multiaddr::AddrComponent;
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashSet;
+use std::io::Error as IoError;
+use std::net::{self, IpAddr, Ipv4Addr, SocketAddr};
+use std::str;
+use std::time::Duration;
+use tokio_core::net::{UdpCodec, UdpSocket};
+use tokio_core::reactor::{Handle, Interval};
+use upgrade::{DialRequest, Protocol};
+
+/// Multicast group on which the beacons are sent, in the organization-local scope.
+pub const GROUP: [u8; 4] = [239, 255, 70, 77];
+const PORT: u16 = 63205;
+/// Identifies our beacons among the other traffic of the group.
+const MAGIC: &[u8] = b"rustfest-chat-beacon";
+/// Number of seconds between two beacons.
+const INTERVAL_SECS: u64 = 5;
+/// Maximum size of the hints, so that a beacon fits in any datagram.
+const MAX_HINTS_LEN: usize = 400;
+
+#[derive(Clone)]
+struct Beacon {
+    /// Port of the websockets listener of the sender.
+    port: u16,
+    /// Base58 `PeerId` of the sender.
+    peer: String,
+    /// The nickname of the sender, if it has one and told us.
+    nick: Option<String>,
+    /// Some of the rooms of the sender, if it told us.
+    rooms: Vec<String>,
+}
+
+impl Beacon {
+    /// Returns the hints of the beacon, one `key=value` per line, within `MAX_HINTS_LEN`.
+    fn hints(&self) -> String {
+        let mut hints = String::new();
+        if let Some(ref nick) = self.nick {
+            hints.push_str(&format!("\nnick={}", nick));
+        }
+        let mut rooms = String::new();
+        for room in &self.rooms {
+            let separator = if rooms.is_empty() { "\nrooms=" } else { "," };
+            if hints.len() + rooms.len() + separator.len() + room.len() > MAX_HINTS_LEN {
+                break;
+            }
+            rooms.push_str(separator);
+            rooms.push_str(room);
+        }
+        hints.push_str(&rooms);
+        if hints.len() > MAX_HINTS_LEN {
+            hints.clear();
+        }
+        hints
+    }
+
+    /// How to present the sender, such as `alice (rooms: general, rustfest)`.
+    fn describe(&self) -> String {
+        let name = self.nick.as_ref().unwrap_or(&self.peer);
+        if self.rooms.is_empty() {
+            name.clone()
+        } else {
+            tr!("{} (rooms: {})", name, self.rooms.join(", "))
+        }
+    }
+}
+
+/// Sends our beacons and dials the nodes whose beacons we receive, through `dial`. `listen` is
+/// the address of our websockets listener. `nick` and `rooms` are hints for the others, which
+/// may be empty.
+pub fn run(
+    peer_id: &PeerId,
+    nick: Option<String>,
+    rooms: Vec<String>,
+    listen: &Multiaddr,
+    handle: &Handle,
+    dial: mpsc::UnboundedSender<DialRequest>,
+) -> Result<Box<Future<Item = (), Error = IoError>>, IoError> {
+    let port = listen
+        .iter()
+        .filter_map(|component| match component {
+            AddrComponent::TCP(port) => Some(port),
+            _ => None,
+        })
+        .next()
+        .unwrap_or(0);
+    let ours = peer_id.to_base58();
+
+    let socket = net::UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0), PORT))?;
+    socket.join_multicast_v4(&Ipv4Addr::from(GROUP), &Ipv4Addr::new(0, 0, 0, 0))?;
+    let socket = UdpSocket::from_socket(socket, handle)?;
+    let (sink, stream) = socket.framed(Codec).split();
+
+    let beacon = Beacon {
+        port,
+        peer: ours.clone(),
+        nick,
+        rooms,
+    };
+    let send = Interval::new(Duration::from_secs(INTERVAL_SECS), handle)?
+        .map(move |()| beacon.clone())
+        .forward(sink)
+        .map(|_| ());
+
+    let mut dialed = HashSet::new();
+    let receive = stream.for_each(move |received| {
+        let (from, beacon) = match received {
+            Some(received) => received,
+            None => return Ok(()),
+        };
+        if beacon.peer == ours || !dialed.insert(beacon.peer.clone()) {
+            return Ok(());
+        }
+        let address = websockets_address(from, beacon.port);
+        display::chatter(&tr!("* Found {} at {} by its beacon", beacon.describe(), address));
+        let _ = dial.unbounded_send(DialRequest {
+            address,
+            protocol: Protocol::FloodSub,
+        });
+        Ok(())
+    });
+
+    Ok(Box::new(send.join(receive).map(|_| ())))
+}
+
+fn websockets_address(from: IpAddr, port: u16) -> Multiaddr {
+    let ip = match from {
+        IpAddr::V4(ip) => AddrComponent::IP4(ip),
+        IpAddr::V6(ip) => AddrComponent::IP6(ip),
+    };
+    vec![ip, AddrComponent::TCP(port), AddrComponent::WS]
+        .into_iter()
+        .collect()
+}
+
+struct Codec;
+
+impl UdpCodec for Codec {
+    /// The IP address of the sender and its beacon, or `None` if the datagram isn't a beacon.
+    type In = Option<(IpAddr, Beacon)>;
+    type Out = Beacon;
+
+    fn decode(&mut self, src: &SocketAddr, buf: &[u8]) -> Result<Self::In, IoError> {
+        if buf.len() < MAGIC.len() + 2 || !buf.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        let rest = &buf[MAGIC.len()..];
+        let port = (u16::from(rest[0]) << 8) | u16::from(rest[1]);
+        Ok(str::from_utf8(&rest[2..]).ok().map(|text| {
+            let mut lines = text.split('\n');
+            let mut beacon = Beacon {
+                port,
+                peer: lines.next().unwrap_or("").to_owned(),
+                nick: None,
+                rooms: Vec::new(),
+            };
+            // Unknown hints are ignored, for the versions to come.
+            for line in lines {
+                if line.starts_with("nick=") {
+                    beacon.nick = Some(line["nick=".len()..].to_owned());
+                } else if line.starts_with("rooms=") {
+                    let rooms = line["rooms=".len()..].split(',');
+                    beacon.rooms = rooms.map(|room| room.to_owned()).collect();
+                }
+            }
+            (src.ip(), beacon)
+        }))
+    }
+
+    fn encode(&mut self, beacon: Beacon, buf: &mut Vec<u8>) -> SocketAddr {
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&[(beacon.port >> 8) as u8, beacon.port as u8]);
+        buf.extend_from_slice(beacon.peer.as_bytes());
+        buf.extend_from_slice(beacon.hints().as_bytes());
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::from(GROUP)), PORT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon(nick: Option<&str>, rooms: Vec<String>) -> Beacon {
+        Beacon {
+            port: 30333,
+            peer: "QmPeer".to_owned(),
+            nick: nick.map(|nick| nick.to_owned()),
+            rooms,
+        }
+    }
+
+    fn round_trip(beacon: Beacon) -> Beacon {
+        let mut buf = Vec::new();
+        let to = Codec.encode(beacon, &mut buf);
+        let from = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)), to.port());
+        let (ip, beacon) = Codec.decode(&from, &buf).unwrap().unwrap();
+        assert_eq!(ip, from.ip());
+        beacon
+    }
+
+    #[test]
+    fn the_hints_survive_the_trip() {
+        let rooms = vec!["general".to_owned(), "rustfest".to_owned()];
+        let received = round_trip(beacon(Some("alice"), rooms.clone()));
+        assert_eq!(received.port, 30333);
+        assert_eq!(received.peer, "QmPeer");
+        assert_eq!(received.nick, Some("alice".to_owned()));
+        assert_eq!(received.rooms, rooms);
+        assert_eq!(received.describe(), "alice (rooms: general, rustfest)");
+    }
+
+    #[test]
+    fn a_beacon_without_hints_names_the_peer() {
+        let received = round_trip(beacon(None, Vec::new()));
+        assert_eq!(received.nick, None);
+        assert!(received.rooms.is_empty());
+        assert_eq!(received.describe(), "QmPeer");
+    }
+
+    #[test]
+    fn the_rooms_that_do_not_fit_are_dropped() {
+        let rooms: Vec<String> = (0..100).map(|n| format!("room-{:02}", n)).collect();
+        let sent = beacon(Some("alice"), rooms.clone());
+        assert!(sent.hints().len() <= MAX_HINTS_LEN);
+        let received = round_trip(sent);
+        assert!(!received.rooms.is_empty());
+        assert!(received.rooms.len() < rooms.len());
+        assert_eq!(&received.rooms[..], &rooms[..received.rooms.len()]);
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A bot that announces the pushes and the CI results of a project in a room of the chat.
+//!
+//! It accepts the webhooks of the forge or the CI server on `--listen`, and publishes an
+//! announcement for each one in `--room`, through the `/publish` of a node started with `--http`
+//! and `--http-publish`:
+//!
+//! ```text
+//! chapter-3 --topic general --http 127.0.0.1:8080 --http-publish
+//! announcer --node 127.0.0.1:8080 --room general
+//! ```
+//!
+//! We understand:
+//!
+//! - pushes from GitHub or GitLab, announced with the first line of each commit;
+//! - finished GitHub Actions runs and GitLab pipelines;
+//! - any JSON object with a `text` field, published as is, for scripts and other CI servers.
+//!
+//! Other events are acknowledged and ignored. There is no authentication, so the address should
+//! only be reachable by those allowed to announce, such as `127.0.0.1` behind a reverse proxy.
+//!
+//! Each request is handled on a thread of its own, and at most `CONCURRENT_REQUESTS` at the same
+//! time. A client that takes longer than `REQUEST_TIMEOUT` to send its request is disconnected,
+//! so that slow clients can't hold all of them.
+
+extern crate clap;
+#[macro_use]
+extern crate serde_json;
+
+use clap::{App, Arg};
+use serde_json::Value;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest request we accept. The pushes of the forges carry their commits.
+const MAX_REQUEST: usize = 1024 * 1024;
+/// Commits listed in the announcement of a push; the others are only counted.
+const MAX_COMMITS: usize = 5;
+/// Requests handled at the same time. The others are turned away.
+const CONCURRENT_REQUESTS: usize = 16;
+/// Time a client has to send its request, and the node to answer ours.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default value of `--listen`.
+const DEFAULT_LISTEN: &str = "127.0.0.1:8090";
+
+fn main() {
+    let matches = App::new("announcer")
+        .about("Announces in a room of the chat the pushes and the CI results posted to it")
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDRESS:PORT")
+                .takes_value(true)
+                .default_value(DEFAULT_LISTEN)
+                .help("Address on which we accept the webhooks"),
+        )
+        .arg(
+            Arg::with_name("node")
+                .long("node")
+                .value_name("ADDRESS:PORT")
+                .takes_value(true)
+                .required(true)
+                .help("The --http address of the node, which must run with --http-publish"),
+        )
+        .arg(
+            Arg::with_name("room")
+                .long("room")
+                .value_name("ROOM")
+                .takes_value(true)
+                .required(true)
+                .help("Room of the announcements, which the node must be in"),
+        )
+        .get_matches();
+    let listen: SocketAddr = matches
+        .value_of("listen")
+        .unwrap_or(DEFAULT_LISTEN)
+        .parse()
+        .expect("--listen expects an IP address and a port, such as 127.0.0.1:8090");
+    let node: SocketAddr = matches
+        .value_of("node")
+        .expect("--node is required")
+        .parse()
+        .expect("--node expects an IP address and a port, such as 127.0.0.1:8080");
+    let room = matches.value_of("room").expect("--room is required").to_owned();
+
+    let listener = TcpListener::bind(listen).expect("failed to listen for webhooks");
+    println!("Announcing in {} the webhooks posted to {}", room, listen);
+    let requests = Arc::new(AtomicUsize::new(0));
+    for socket in listener.incoming() {
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(_) => continue,
+        };
+        if requests.load(Ordering::SeqCst) >= CONCURRENT_REQUESTS {
+            let _ = respond(socket, "503 Service Unavailable");
+            continue;
+        }
+        requests.fetch_add(1, Ordering::SeqCst);
+        let (requests, room) = (requests.clone(), room.clone());
+        thread::spawn(move || {
+            // A failed request only concerns its sender.
+            if let Err(err) = serve(socket, &node, &room) {
+                eprintln!("Couldn't handle a webhook: {}", err);
+            }
+            requests.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Reads a request from `socket`, publishes its announcement if there is one, and answers.
+fn serve(mut socket: TcpStream, node: &SocketAddr, room: &str) -> Result<(), IoError> {
+    let request = read_request(&mut socket, Instant::now() + REQUEST_TIMEOUT)?;
+    let status = match handle_request(&request) {
+        (status, None) => status,
+        // The sender learns whether the announcement was published.
+        (status, Some(text)) => match publish(node, room, &text) {
+            Ok(()) => status,
+            Err(err) => {
+                eprintln!("Couldn't publish in {}: {}", room, err);
+                "502 Bad Gateway"
+            }
+        },
+    };
+    socket.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    respond(socket, status)
+}
+
+fn respond(mut socket: TcpStream, status: &str) -> Result<(), IoError> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    socket.write_all(response.as_bytes())
+}
+
+/// Publishes `text` in `room` through the `/publish` of the node.
+fn publish(node: &SocketAddr, room: &str, text: &str) -> Result<(), IoError> {
+    let body = json!({ "room": room, "text": text }).to_string();
+    let mut socket = TcpStream::connect_timeout(node, REQUEST_TIMEOUT)?;
+    socket.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    socket.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = format!(
+        "POST /publish HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        node,
+        body.len(),
+        body
+    );
+    socket.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    socket.read_to_string(&mut response)?;
+    let status = response.lines().next().unwrap_or("");
+    if status.split(' ').nth(1) == Some("204") {
+        Ok(())
+    } else {
+        let error = format!("the node answered {:?}", status);
+        Err(IoError::new(ErrorKind::Other, error))
+    }
+}
+
+struct Request {
+    method: String,
+    /// The `X-GitHub-Event` or `X-Gitlab-Event` header.
+    event: Option<String>,
+    body: Vec<u8>,
+}
+
+/// Returns the status of the response, and the announcement to publish if any.
+fn handle_request(request: &Request) -> (&'static str, Option<String>) {
+    if request.method != "POST" {
+        return ("405 Method Not Allowed", None);
+    }
+    match serde_json::from_slice::<Value>(&request.body) {
+        Ok(payload) => (
+            "204 No Content",
+            announcement(request.event.as_ref().map(|e| &e[..]), &payload),
+        ),
+        Err(_) => ("400 Bad Request", None),
+    }
+}
+
+/// Reads an HTTP request whose body has a `Content-Length`, unless it isn't whole by `deadline`.
+fn read_request(socket: &mut TcpStream, deadline: Instant) -> Result<Request, IoError> {
+    let mut buffer = Vec::new();
+    loop {
+        if let Some(request) = parse_request(&buffer)? {
+            return Ok(request);
+        }
+        if buffer.len() > MAX_REQUEST {
+            return Err(IoError::new(ErrorKind::InvalidData, "the request is too large"));
+        }
+        // The timeout of the socket applies to each read, and a client that sends a byte now and
+        // then would never reach it. The deadline applies to the whole request.
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(IoError::new(ErrorKind::TimedOut, "the request is too slow"));
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let mut chunk = [0; 4096];
+        let len = socket.read(&mut chunk)?;
+        if len == 0 {
+            return Err(IoError::new(ErrorKind::UnexpectedEof, "the request is incomplete"));
+        }
+        buffer.extend_from_slice(&chunk[..len]);
+    }
+}
+
+/// Parses `buffer` if it holds a whole request.
+fn parse_request(buffer: &[u8]) -> Result<Option<Request>, IoError> {
+    let end = match buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let head = String::from_utf8_lossy(&buffer[..end]);
+    let mut lines = head.split("\r\n");
+    let method = lines
+        .next()
+        .and_then(|line| line.split(' ').next())
+        .unwrap_or("")
+        .to_owned();
+    let mut event = None;
+    let mut length = 0;
+    for line in lines {
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => continue,
+        };
+        let (name, value) = (line[..colon].to_lowercase(), line[colon + 1..].trim());
+        match &name[..] {
+            "content-length" => {
+                length = value
+                    .parse()
+                    .map_err(|_| IoError::new(ErrorKind::InvalidData, "invalid Content-Length"))?
+            }
+            "x-github-event" | "x-gitlab-event" => event = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    let body = &buffer[end + 4..];
+    if body.len() < length {
+        return Ok(None);
+    }
+    Ok(Some(Request {
+        method,
+        event,
+        body: body[..length].to_vec(),
+    }))
+}
+/// Returns the announcement of the event, or `None` if it isn't worth one.
+fn announcement(event: Option<&str>, payload: &Value) -> Option<String> {
+    if let Some(text) = payload["text"].as_str() {
+        return Some(text.to_owned());
+    }
+    match event {
+        Some("push") | Some("Push Hook") => push(payload),
+        Some("workflow_run") => workflow_run(payload),
+        Some("Pipeline Hook") => pipeline(payload),
+        _ => None,
+    }
+}
+
+fn push(payload: &Value) -> Option<String> {
+    let repository = payload["repository"]["full_name"]
+        .as_str()
+        .or_else(|| payload["project"]["path_with_namespace"].as_str())?;
+    let branch = payload["ref"].as_str()?.trim_left_matches("refs/heads/");
+    let pusher = payload["pusher"]["name"]
+        .as_str()
+        .or_else(|| payload["user_name"].as_str())
+        .unwrap_or("someone");
+    // A push without commits deletes the branch, or only moves it.
+    let commits = payload["commits"].as_array()?;
+    if commits.is_empty() {
+        return None;
+    }
+    let count = payload["total_commits_count"]
+        .as_u64()
+        .unwrap_or(commits.len() as u64);
+    let mut text = format!(
+        "{} pushed {} commit{} to {} of {}",
+        pusher,
+        count,
+        if count == 1 { "" } else { "s" },
+        branch,
+        repository
+    );
+    for commit in commits.iter().take(MAX_COMMITS) {
+        let id = commit["id"].as_str().unwrap_or("");
+        let message = commit["message"].as_str().unwrap_or("");
+        text.push_str(&format!(
+            "\n{} {} ({})",
+            &id[..id.len().min(7)],
+            message.lines().next().unwrap_or(""),
+            commit["author"]["name"].as_str().unwrap_or("unknown")
+        ));
+    }
+    if count as usize > MAX_COMMITS {
+        text.push_str(&format!("\nand {} more", count as usize - MAX_COMMITS));
+    }
+    Some(text)
+}
+
+/// A GitHub Actions run, once it finished.
+fn workflow_run(payload: &Value) -> Option<String> {
+    if payload["action"] != "completed" {
+        return None;
+    }
+    let run = &payload["workflow_run"];
+    let mut text = format!(
+        "{} on {} of {}: {}",
+        run["name"].as_str()?,
+        run["head_branch"].as_str()?,
+        payload["repository"]["full_name"].as_str()?,
+        run["conclusion"].as_str().unwrap_or("finished")
+    );
+    if let Some(url) = run["html_url"].as_str() {
+        text.push_str(&format!(" {}", url));
+    }
+    Some(text)
+}
+
+/// A GitLab pipeline, once it finished.
+fn pipeline(payload: &Value) -> Option<String> {
+    let attributes = &payload["object_attributes"];
+    let status = attributes["status"].as_str()?;
+    if !["success", "failed", "canceled"].contains(&status) {
+        return None;
+    }
+    Some(format!(
+        "Pipeline {} on {} of {}: {}",
+        attributes["id"],
+        attributes["ref"].as_str()?,
+        payload["project"]["path_with_namespace"].as_str()?,
+        status
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushes_list_their_first_commits() {
+        let commits: Vec<Value> = (0..7)
+            .map(|n| {
+                json!({
+                    "id": format!("{:040}", n),
+                    "message": format!("Step {}\n\nWhy", n),
+                    "author": { "name": "Ada" },
+                })
+            })
+            .collect();
+        let payload = json!({
+            "ref": "refs/heads/main",
+            "repository": { "full_name": "pacman82/rustfest-2018-workshop" },
+            "pusher": { "name": "ada" },
+            "commits": commits,
+        });
+        let text = announcement(Some("push"), &payload).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(
+            lines.next(),
+            Some("ada pushed 7 commits to main of pacman82/rustfest-2018-workshop")
+        );
+        assert_eq!(lines.next(), Some("0000000 Step 0 (Ada)"));
+        assert_eq!(lines.last(), Some("and 2 more"));
+    }
+
+    #[test]
+    fn branch_deletions_are_ignored() {
+        let payload = json!({
+            "ref": "refs/heads/old",
+            "repository": { "full_name": "pacman82/rustfest-2018-workshop" },
+            "commits": [],
+        });
+        assert_eq!(announcement(Some("push"), &payload), None);
+    }
+
+    #[test]
+    fn a_request_is_parsed_once_its_body_is_complete() {
+        let head = "POST / HTTP/1.1\r\nX-GitHub-Event: push\r\nContent-Length: 2\r\n\r\n";
+        assert!(parse_request(head.as_bytes()).unwrap().is_none());
+        let request = parse_request(format!("{}{{}}", head).as_bytes()).unwrap().unwrap();
+        assert_eq!(request.event, Some("push".to_owned()));
+        assert_eq!(handle_request(&request), ("204 No Content", None));
+    }
+}
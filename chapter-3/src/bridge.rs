@@ -0,0 +1,359 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Mirroring the envelopes of a room to a NATS subject or a Redis stream, so that server-side
+//! systems can consume and inject chat traffic with the infrastructure they already run.
+//!
+//! With `--bridge <url>`, every envelope received in the first room of `--topic` is published as
+//! is on the subject of `nats://<host>[:<port>]/<subject>`, or appended to the stream of
+//! `redis://<host>[:<port>]/<stream>` in the field `envelope`. In the other direction, the
+//! envelopes published there are published in the room and shown to us. They must be signed like
+//! any other envelope, so the bridge can't be used to forge messages.
+//!
+//! NATS is told not to send us back what we publish. Redis streams have no such option, so our
+//! entries also have the field `origin` with our peer ID, and we skip them when reading.
+//!
+//! Both protocols are simple and the traffic is low, so we talk to the broker with blocking
+//! sockets on threads of their own, like the signature workers.
+//!
+//! Only compiled with the `broker-bridge` feature, which is on by default, and not in the
+//! browser.
+
+use bytes::Bytes;
+use futures::sync::mpsc as futures_mpsc;
+use std::io::{BufRead, BufReader, Error as IoError, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const NATS_PORT: u16 = 4222;
+const REDIS_PORT: u16 = 6379;
+/// Entries beyond which Redis may trim the stream.
+const REDIS_MAX_LEN: &[u8] = b"10000";
+
+/// What the broker sends us: an envelope, or the error that stopped the bridge.
+pub type Incoming = futures_mpsc::UnboundedReceiver<Result<Vec<u8>, IoError>>;
+type IncomingSender = futures_mpsc::UnboundedSender<Result<Vec<u8>, IoError>>;
+
+enum Broker {
+    Nats,
+    Redis,
+}
+
+/// Where `--bridge` points.
+struct Target {
+    broker: Broker,
+    address: String,
+    /// The subject or the stream.
+    name: String,
+}
+
+fn parse(url: &str) -> Result<Target, IoError> {
+    let invalid = |reason: &str| IoError::new(ErrorKind::InvalidInput, reason.to_owned());
+    let (broker, rest, port) = if url.starts_with("nats://") {
+        (Broker::Nats, &url["nats://".len()..], NATS_PORT)
+    } else if url.starts_with("redis://") {
+        (Broker::Redis, &url["redis://".len()..], REDIS_PORT)
+    } else {
+        return Err(invalid("expected a nats:// or a redis:// URL"));
+    };
+    let slash = rest
+        .find('/')
+        .ok_or_else(|| invalid("the URL has no subject or stream"))?;
+    let (host, name) = (&rest[..slash], &rest[slash + 1..]);
+    if host.is_empty() || name.is_empty() {
+        return Err(invalid("the URL has no host, or no subject or stream"));
+    }
+    let address = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:{}", host, port)
+    };
+    Ok(Target {
+        broker,
+        address,
+        name: name.to_owned(),
+    })
+}
+
+/// The envelopes waiting to be sent to the broker.
+pub struct Bridge {
+    outgoing: mpsc::Sender<Bytes>,
+}
+
+impl Bridge {
+    /// Connects to the broker at `url`. `origin`, our peer ID, marks what we append to a Redis
+    /// stream.
+    pub fn connect(url: &str, origin: &str) -> Result<(Bridge, Incoming), IoError> {
+        let target = parse(url)?;
+        let (outgoing, to_broker) = mpsc::channel();
+        let (from_broker, incoming) = futures_mpsc::unbounded();
+        match target.broker {
+            Broker::Nats => nats(&target, to_broker, from_broker)?,
+            Broker::Redis => redis(&target, origin, to_broker, from_broker)?,
+        }
+        Ok((Bridge { outgoing }, incoming))
+    }
+
+    /// Sends an envelope received from the room to the broker.
+    pub fn forward(&self, data: &Bytes) {
+        let _ = self.outgoing.send(data.clone());
+    }
+}
+
+/// Runs `work` on a thread, and reports the error that stops it to `incoming`.
+fn spawn<F>(incoming: IncomingSender, work: F)
+where
+    F: FnOnce(&IncomingSender) -> Result<(), IoError> + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(err) = work(&incoming) {
+            let _ = incoming.unbounded_send(Err(err));
+        }
+    });
+}
+
+fn nats(
+    target: &Target,
+    outgoing: mpsc::Receiver<Bytes>,
+    incoming: IncomingSender,
+) -> Result<(), IoError> {
+    let socket = TcpStream::connect(&target.address[..])?;
+    let mut reader = BufReader::new(socket.try_clone()?);
+    // The server starts with `INFO`, which we don't need.
+    reader.read_line(&mut String::new())?;
+    let writer = Arc::new(Mutex::new(socket));
+    write!(
+        writer.lock().expect("the bridge threads never panic"),
+        "CONNECT {{\"verbose\":false,\"echo\":false,\"name\":\"rustfest-chat\"}}\r\nSUB {} 1\r\n",
+        target.name
+    )?;
+
+    {
+        let writer = writer.clone();
+        let subject = target.name.clone();
+        spawn(incoming.clone(), move |_| {
+            for data in outgoing {
+                let mut writer = writer.lock().expect("the bridge threads never panic");
+                write!(writer, "PUB {} {}\r\n", subject, data.len())?;
+                writer.write_all(&data)?;
+                writer.write_all(b"\r\n")?;
+            }
+            Ok(())
+        });
+    }
+
+    spawn(incoming, move |incoming| loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(IoError::new(ErrorKind::UnexpectedEof, "the server closed the connection"));
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.first().cloned() {
+            Some("PING") => writer
+                .lock()
+                .expect("the bridge threads never panic")
+                .write_all(b"PONG\r\n")?,
+            // `MSG <subject> <sid> [reply-to] <length>`, then the payload.
+            Some("MSG") => {
+                let len: usize = words
+                    .last()
+                    .and_then(|len| len.parse().ok())
+                    .ok_or_else(|| IoError::new(ErrorKind::InvalidData, line.trim().to_owned()))?;
+                let mut payload = vec![0; len + 2];
+                reader.read_exact(&mut payload)?;
+                payload.truncate(len);
+                if incoming.unbounded_send(Ok(payload)).is_err() {
+                    return Ok(());
+                }
+            }
+            Some("-ERR") => return Err(IoError::new(ErrorKind::Other, line.trim().to_owned())),
+            _ => {}
+        }
+    });
+    Ok(())
+}
+
+fn redis(
+    target: &Target,
+    origin: &str,
+    outgoing: mpsc::Receiver<Bytes>,
+    incoming: IncomingSender,
+) -> Result<(), IoError> {
+    // `XREAD BLOCK` holds its connection, so appending needs another one.
+    let mut appender = TcpStream::connect(&target.address[..])?;
+    let mut reader = TcpStream::connect(&target.address[..])?;
+
+    {
+        let mut replies = BufReader::new(appender.try_clone()?);
+        let stream = target.name.clone();
+        let origin = origin.to_owned();
+        spawn(incoming.clone(), move |_| {
+            for data in outgoing {
+                appender.write_all(&command(&[
+                    b"XADD",
+                    stream.as_bytes(),
+                    b"MAXLEN",
+                    b"~",
+                    REDIS_MAX_LEN,
+                    b"*",
+                    b"origin",
+                    origin.as_bytes(),
+                    b"envelope",
+                    &data,
+                ]))?;
+                if let Value::Error(err) = read_value(&mut replies)? {
+                    return Err(IoError::new(ErrorKind::Other, err));
+                }
+            }
+            Ok(())
+        });
+    }
+
+    let mut replies = BufReader::new(reader.try_clone()?);
+    let stream = target.name.clone();
+    let origin = origin.to_owned();
+    spawn(incoming, move |incoming| {
+        // `$` asks for the entries appended from now on.
+        let mut last = "$".to_owned();
+        loop {
+            reader.write_all(&command(&[
+                b"XREAD",
+                b"BLOCK",
+                b"0",
+                b"STREAMS",
+                stream.as_bytes(),
+                last.as_bytes(),
+            ]))?;
+            let reply = read_value(&mut replies)?;
+            if let Value::Error(err) = reply {
+                return Err(IoError::new(ErrorKind::Other, err));
+            }
+            // The reply has the entries of each stream: `[[stream, [[id, [field, value, …]]]]]`.
+            for stream in array(reply) {
+                let entries = array(stream).pop().map(array).unwrap_or_default();
+                for entry in entries {
+                    let mut entry = array(entry).into_iter();
+                    let id = match entry.next().and_then(bulk) {
+                        Some(id) => id,
+                        None => continue,
+                    };
+                    last = String::from_utf8_lossy(&id).into_owned();
+                    let fields: Vec<_> = entry
+                        .next()
+                        .map(array)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(bulk)
+                        .collect();
+                    let field = |name: &[u8]| {
+                        fields
+                            .chunks(2)
+                            .find(|pair| pair.len() == 2 && pair[0] == name)
+                            .map(|pair| pair[1].clone())
+                    };
+                    if field(b"origin").map(|o| o == origin.as_bytes()).unwrap_or(false) {
+                        continue;
+                    }
+                    if let Some(envelope) = field(b"envelope") {
+                        if incoming.unbounded_send(Ok(envelope)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// A reply of Redis.
+enum Value {
+    /// A status or an integer, which we don't need.
+    Other,
+    Error(String),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Value>>),
+}
+
+/// Encodes a command as an array of bulk strings.
+fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        bytes.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        bytes.extend_from_slice(arg);
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes
+}
+
+fn read_value<R: BufRead>(reader: &mut R) -> Result<Value, IoError> {
+    let invalid = || IoError::new(ErrorKind::InvalidData, "invalid reply from Redis");
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(IoError::new(ErrorKind::UnexpectedEof, "the server closed the connection"));
+    }
+    let line = line.trim_right_matches("\r\n");
+    if line.is_empty() {
+        return Err(invalid());
+    }
+    let (kind, rest) = line.split_at(1);
+    match kind {
+        "+" | ":" => Ok(Value::Other),
+        "-" => Ok(Value::Error(rest.to_owned())),
+        "$" => {
+            let len: i64 = rest.parse().map_err(|_| invalid())?;
+            if len < 0 {
+                return Ok(Value::Bulk(None));
+            }
+            let mut data = vec![0; len as usize + 2];
+            reader.read_exact(&mut data)?;
+            data.truncate(len as usize);
+            Ok(Value::Bulk(Some(data)))
+        }
+        "*" => {
+            let len: i64 = rest.parse().map_err(|_| invalid())?;
+            if len < 0 {
+                return Ok(Value::Array(None));
+            }
+            let values = (0..len)
+                .map(|_| read_value(&mut *reader))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Array(Some(values)))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// The elements of an array, or nothing if `value` isn't one.
+fn array(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(Some(values)) => values,
+        _ => Vec::new(),
+    }
+}
+
+fn bulk(value: Value) -> Option<Vec<u8>> {
+    match value {
+        Value::Bulk(Some(data)) => Some(data),
+        _ => None,
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The direct protocols that the peers we dial turned out to support, or not.
+//!
+//! Nothing tells us in advance whether a peer accepts tic-tac-toe, voice calls or screen sharing:
+//! an older client, or one built without them, simply refuses the protocol during the
+//! negotiation, after which the swarm drops the connection without a word. `Watch` wraps each of
+//! the dialers of `upgrade::Dialers` and records the outcome of every negotiation. The chat
+//! checks it before dialing, so that `/ttt`, `/call` and `/sharescreen` fail right away for an
+//! address, or any address advertised by the same peer, known not to support the protocol.
+//!
+//! Only the refusals of the remote count: a dial that times out or can't connect says nothing
+//! about the protocols. A refusal is forgotten after `FORGET_AFTER`, since the peer may have
+//! upgraded its client in the meantime.
+
+use futures::Future;
+use libp2p::core::Transport;
+use libp2p::Multiaddr;
+use multistream_select::ProtocolChoiceError;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Error as IoError;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use upgrade::Protocol;
+
+/// How long a refusal is remembered.
+const FORGET_AFTER: Duration = Duration::from_secs(10 * 60);
+
+/// What we learned about each address, shared between the dialers and the chat.
+#[derive(Clone, Default)]
+pub struct Capabilities {
+    inner: Rc<RefCell<HashMap<Multiaddr, HashMap<Protocol, Outcome>>>>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Outcome {
+    supported: bool,
+    when: Instant,
+}
+
+impl Capabilities {
+    /// Records that negotiating `protocol` with `address` succeeded, or was refused.
+    pub fn learned(&self, address: Multiaddr, protocol: Protocol, supported: bool) {
+        let outcome = Outcome {
+            supported,
+            when: Instant::now(),
+        };
+        let mut inner = self.inner.borrow_mut();
+        inner.entry(address).or_insert_with(HashMap::new).insert(protocol, outcome);
+    }
+
+    /// Returns whether `protocol` is supported at `addresses`, which belong to a single peer, or
+    /// `None` if we don't know. The latest outcome wins.
+    pub fn supports(&self, addresses: &[Multiaddr], protocol: Protocol) -> Option<bool> {
+        let inner = self.inner.borrow();
+        addresses
+            .iter()
+            .filter_map(|address| inner.get(address).and_then(|known| known.get(&protocol)))
+            .filter(|outcome| outcome.supported || outcome.when.elapsed() < FORGET_AFTER)
+            .max_by_key(|outcome| outcome.when)
+            .map(|outcome| outcome.supported)
+    }
+
+    /// Wraps `inner`, a dialer that only proposes `protocol`, to learn from its dials.
+    pub fn watch<T>(&self, inner: T, protocol: Protocol) -> Watch<T> {
+        Watch {
+            inner,
+            protocol,
+            capabilities: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Watch<T> {
+    inner: T,
+    protocol: Protocol,
+    capabilities: Capabilities,
+}
+
+impl<T> Transport for Watch<T>
+where
+    T: Transport + 'static,
+    T::Output: 'static,
+    T::Dial: 'static,
+{
+    type Output = T::Output;
+    type Listener = T::Listener;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = Box<Future<Item = (T::Output, Multiaddr), Error = IoError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let Watch {
+            inner,
+            protocol,
+            capabilities,
+        } = self;
+        inner.listen_on(addr).map_err(|(inner, addr)| {
+            let transport = Watch {
+                inner,
+                protocol,
+                capabilities,
+            };
+            (transport, addr)
+        })
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let Watch {
+            inner,
+            protocol,
+            capabilities,
+        } = self;
+        let dial = match inner.dial(addr.clone()) {
+            Ok(dial) => dial,
+            Err((inner, addr)) => {
+                let transport = Watch {
+                    inner,
+                    protocol,
+                    capabilities,
+                };
+                return Err((transport, addr));
+            }
+        };
+        Ok(Box::new(dial.then(move |result| {
+            match result {
+                Ok(_) => capabilities.learned(addr, protocol, true),
+                Err(ref err) if is_refusal(err) => capabilities.learned(addr, protocol, false),
+                Err(_) => {}
+            }
+            result
+        })))
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+/// Returns true if `err` means that the remote has none of the protocols we proposed. The
+/// upgrade reports the failures of the negotiation as errors wrapping a `ProtocolChoiceError`.
+fn is_refusal(err: &IoError) -> bool {
+    match err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<ProtocolChoiceError>())
+    {
+        Some(&ProtocolChoiceError::NoProtocolFound) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Capturing the raw bytes exchanged with the other nodes, to debug the protocols.
+//!
+//! With `--capture <file>`, `Capture` wraps the transport and writes to the file every chunk of
+//! bytes that a connection reads or writes, before any protocol is negotiated. This includes the
+//! multistream-select negotiation, then the floodsub or direct protocol frames. The `decode`
+//! subcommand pretty-prints such a file, see the `dissect` module.
+//!
+//! The file starts with the 8 bytes `RFCAP\0\0\x01`, then contains one record per chunk:
+//!
+//! - the time, in milliseconds since the UNIX epoch, as a big-endian `u64`;
+//! - the connection number, counted from 0 in the order the connections opened, as a
+//!   big-endian `u32`;
+//! - the direction, as a `u8`: 0 for received, 1 for sent;
+//! - the length of the remote address as a big-endian `u16`, then the address in UTF-8;
+//! - the length of the chunk as a big-endian `u32`, then the chunk itself.
+//!
+//! Records of all the connections are interleaved in the order the chunks were read or written.
+//!
+//! Like `decode`, `doctor` and `probe`, only compiled with the `diagnostics` feature, which is on
+//! by default.
+
+use futures::{Future, IntoFuture, Stream};
+use libp2p::core::Transport;
+use libp2p::Multiaddr;
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// First bytes of a capture file; the last one is the version of the format.
+pub const MAGIC: &[u8; 8] = b"RFCAP\0\0\x01";
+
+const RECEIVED: u8 = 0;
+const SENT: u8 = 1;
+
+/// The file that the records are appended to, shared by all the connections.
+#[derive(Clone)]
+pub struct Writer {
+    file: Rc<RefCell<Option<File>>>,
+    next_id: Rc<Cell<u32>>,
+}
+
+impl Writer {
+    pub fn create(path: &str) -> Result<Writer, IoError> {
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+        Ok(Writer {
+            file: Rc::new(RefCell::new(Some(file))),
+            next_id: Rc::new(Cell::new(0)),
+        })
+    }
+
+    fn record(&self, id: u32, direction: u8, address: &str, data: &[u8]) {
+        let mut file = self.file.borrow_mut();
+        let result = match *file {
+            Some(ref mut file) => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_nanos() / 1_000_000))
+                    .unwrap_or(0);
+                let address = &address.as_bytes()[..address.len().min(u16::max_value() as usize)];
+                let mut record = Vec::with_capacity(19 + address.len() + data.len());
+                record.extend_from_slice(&be(millis, 8));
+                record.extend_from_slice(&be(u64::from(id), 4));
+                record.push(direction);
+                record.extend_from_slice(&be(address.len() as u64, 2));
+                record.extend_from_slice(address);
+                record.extend_from_slice(&be(data.len() as u64, 4));
+                record.extend_from_slice(data);
+                // A single write keeps the records whole, whatever the other connections do.
+                file.write_all(&record)
+            }
+            None => return,
+        };
+        if let Err(err) = result {
+            eprintln!("{}", tr!("* Stopped capturing: {}", err));
+            *file = None;
+        }
+    }
+}
+
+/// Encodes the `len` low bytes of `value` in big-endian order.
+fn be(value: u64, len: usize) -> Vec<u8> {
+    (0..len).rev().map(|i| (value >> (8 * i)) as u8).collect()
+}
+
+/// Transport that records what its connections exchange, if it has a `Writer`.
+#[derive(Clone)]
+pub struct Capture<T> {
+    inner: T,
+    writer: Option<Writer>,
+}
+
+impl<T> Capture<T> {
+    pub fn new(inner: T, writer: Option<Writer>) -> Capture<T> {
+        Capture { inner, writer }
+    }
+}
+
+impl<T> Transport for Capture<T>
+where
+    T: Transport + 'static,
+    T::Output: 'static,
+    T::Listener: 'static,
+    T::ListenerUpgrade: 'static,
+    T::Dial: 'static,
+{
+    type Output = Captured<T::Output>;
+    type Listener = Box<Stream<Item = Self::ListenerUpgrade, Error = IoError>>;
+    type ListenerUpgrade = Box<Future<Item = (Self::Output, Multiaddr), Error = IoError>>;
+    type Dial = Box<Future<Item = (Self::Output, Multiaddr), Error = IoError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let Capture { inner, writer } = self;
+        match inner.listen_on(addr) {
+            Ok((listener, addr)) => {
+                let listener = listener.map(move |upgrade| {
+                    let writer = writer.clone();
+                    let upgrade = upgrade.map(move |(socket, addr)| {
+                        (Captured::new(socket, writer, &addr), addr)
+                    });
+                    Box::new(upgrade) as Box<Future<Item = _, Error = _>>
+                });
+                Ok((Box::new(listener), addr))
+            }
+            Err((inner, addr)) => Err((Capture { inner, writer }, addr)),
+        }
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let Capture { inner, writer } = self;
+        match inner.dial(addr) {
+            Ok(dial) => {
+                let dial = dial.into_future().map(move |(socket, addr)| {
+                    (Captured::new(socket, writer, &addr), addr)
+                });
+                Ok(Box::new(dial))
+            }
+            Err((inner, addr)) => Err((Capture { inner, writer }, addr)),
+        }
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+/// Socket that copies the chunks it reads and writes to the capture file.
+pub struct Captured<S> {
+    inner: S,
+    /// The writer, with the number and the address of the connection.
+    writer: Option<(Writer, u32, String)>,
+}
+
+impl<S> Captured<S> {
+    fn new(inner: S, writer: Option<Writer>, address: &Multiaddr) -> Captured<S> {
+        let writer = writer.map(|writer| {
+            let id = writer.next_id.get();
+            writer.next_id.set(id.wrapping_add(1));
+            (writer, id, address.to_string())
+        });
+        Captured { inner, writer }
+    }
+
+    fn record(&self, direction: u8, data: &[u8]) {
+        if let Some((ref writer, id, ref address)) = self.writer {
+            if !data.is_empty() {
+                writer.record(id, direction, address, data);
+            }
+        }
+    }
+}
+
+impl<S: Read> Read for Captured<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let len = self.inner.read(buf)?;
+        self.record(RECEIVED, &buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<S: Write> Write for Captured<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        let len = self.inner.write(buf)?;
+        self.record(SENT, &buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Captured<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for Captured<S> {
+    fn shutdown(&mut self) -> ::futures::Poll<(), IoError> {
+        self.inner.shutdown()
+    }
+}
+
+/// A record read back from a capture file.
+pub struct Record {
+    pub millis: u64,
+    pub connection: u32,
+    pub sent: bool,
+    pub address: String,
+    pub data: Vec<u8>,
+}
+
+/// Parses the content of a capture file.
+pub fn parse(content: &[u8]) -> Result<Vec<Record>, IoError> {
+    if !content.starts_with(MAGIC) {
+        return Err(IoError::new(ErrorKind::InvalidData, "not a capture file"));
+    }
+    let mut records = Vec::new();
+    let mut rest = &content[MAGIC.len()..];
+    while !rest.is_empty() {
+        let millis = take(&mut rest, 8)?;
+        let connection = take(&mut rest, 4)? as u32;
+        let direction = take(&mut rest, 1)?;
+        let address_len = take(&mut rest, 2)? as usize;
+        let address = String::from_utf8_lossy(take_bytes(&mut rest, address_len)?).into_owned();
+        let data_len = take(&mut rest, 4)? as usize;
+        let data = take_bytes(&mut rest, data_len)?.to_vec();
+        records.push(Record {
+            millis,
+            connection,
+            sent: direction == SENT,
+            address,
+            data,
+        });
+    }
+    Ok(records)
+}
+
+/// Reads a big-endian integer of `len` bytes.
+fn take(rest: &mut &[u8], len: usize) -> Result<u64, IoError> {
+    let bytes = take_bytes(rest, len)?;
+    Ok(bytes.iter().fold(0, |value, &byte| (value << 8) | u64::from(byte)))
+}
+
+fn take_bytes<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8], IoError> {
+    if rest.len() < len {
+        return Err(IoError::new(ErrorKind::UnexpectedEof, "truncated capture record"));
+    }
+    let (bytes, remaining) = rest.split_at(len);
+    *rest = remaining;
+    Ok(bytes)
+}
@@ -0,0 +1,102 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Fault injection of `/chaos on`, to show that the chat copes with a bad network.
+//!
+//! While it is on, each heartbeat may drop one of the connections, the outbox holds back some of
+//! the messages we publish, and some of the envelopes we receive are handled twice.
+//! Deduplication, reconnection and the ordering of the outbox should hide all of it.
+
+use futures::future::{self, Future};
+use futures::sync::oneshot;
+use rand::{self, Rng};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Error as IoError;
+use std::rc::Rc;
+
+/// The odds, one in this many, of each kind of fault.
+const DROP_ODDS: u32 = 3;
+const DELAY_ODDS: u32 = 2;
+const DUPLICATE_ODDS: u32 = 4;
+
+#[derive(Default)]
+struct Inner {
+    enabled: bool,
+    /// Completes the future of each open connection, to drop it.
+    connections: HashMap<u64, oneshot::Sender<()>>,
+}
+
+#[derive(Clone, Default)]
+pub struct Chaos {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Chaos {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.inner.borrow_mut().enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.borrow().enabled
+    }
+
+    /// Returns a future that completes if we decide to drop the connection `id`, which is the
+    /// identifier of the `peers` module.
+    pub fn register(&self, id: u64) -> impl Future<Item = (), Error = IoError> {
+        let (tx, rx) = oneshot::channel();
+        self.inner.borrow_mut().connections.insert(id, tx);
+        rx.or_else(|_| future::empty())
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.inner.borrow_mut().connections.remove(&id);
+    }
+
+    /// Drops one of the connections, sometimes. Returns its identifier.
+    pub fn drop_connection(&self) -> Option<u64> {
+        if !self.fault(DROP_ODDS) {
+            return None;
+        }
+        let mut inner = self.inner.borrow_mut();
+        let ids: Vec<u64> = inner.connections.keys().cloned().collect();
+        let id = *rand::thread_rng().choose(&ids)?;
+        let _ = inner.connections.remove(&id)?.send(());
+        Some(id)
+    }
+
+    /// Returns true if the next message of the outbox should wait.
+    pub fn delay(&self) -> bool {
+        self.fault(DELAY_ODDS)
+    }
+
+    /// Returns how many times to handle the envelope we just received.
+    pub fn copies(&self) -> usize {
+        if self.fault(DUPLICATE_ODDS) {
+            2
+        } else {
+            1
+        }
+    }
+
+    fn fault(&self, odds: u32) -> bool {
+        self.is_enabled() && rand::thread_rng().gen_weighted_bool(odds)
+    }
+}
@@ -0,0 +1,3749 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! State of the chat, shared between the stream of messages coming from the network and the
+//! stream of lines coming from stdin.
+
+use addresses;
+#[cfg(feature = "calls")]
+use audio::Calls;
+use backfill::{self, Archived, Backfill};
+use batch::Batcher;
+use bytes::Bytes;
+use capabilities::Capabilities;
+use chaos::Chaos;
+use clipboard;
+use clocks::{self, Clocks};
+#[cfg(feature = "pads")]
+use command::PadAction;
+use command::{self, Command, FilterAction, PurgeTarget};
+use config::{self, Config, Session};
+use compose::{self, Composer};
+use damping::Damper;
+use demo::Demo;
+use directory::{self, Directory};
+use display::{self, Verbosity};
+use drain::Drain;
+use echo::Echo;
+use election::Election;
+use emoji;
+use filter::{Filter, Verdict};
+use graph::Graph;
+use handoff::{self, Handoff};
+use envelope::{self, Body, Kind, OpenError, Received};
+use error::Error;
+use export;
+use futures::sync::mpsc;
+use futures::Async;
+use history::{self, Entry, History};
+#[cfg(all(feature = "http-server", not(target_os = "emscripten")))]
+use http;
+use identity::{self, Identity};
+use idle::Idle;
+use inputs::InputHistory;
+#[cfg(feature = "dht")]
+use kv::{self, KvStore};
+use libp2p::core::Endpoint;
+use libp2p::floodsub::{FloodSubController, Topic, TopicHash};
+use libp2p::{Multiaddr, PeerId};
+use links::{self, Previewer};
+use markdown;
+use mentions::{self, Mentions};
+use metadata::{self, Description, Metadata};
+use migration;
+use names::ShortIds;
+use moderation::{self, Entry as BanEntry, Moderation, SignedEntry};
+use notifier::{Level, Notifier};
+use options::Options;
+use outbox::{self, Outbox, Priority};
+#[cfg(feature = "pads")]
+use pad::Pad;
+use pad::PadOp;
+use peers::PeerTable;
+use personas::{self, Personas};
+use pins::{Pin, Pins};
+use platform;
+use plugins::{self, Plugins};
+use poll::{self, Poll, Polls};
+use presence::{self, Presence};
+use purge;
+use relays::Relays;
+use redact::Redactor;
+use reorder::Reorder;
+use reports::{self, Report, Reports, SignedReport};
+use schedule::{self, Schedule};
+use scores::{self, Scores};
+use screening::Bans;
+use screen::Screens;
+use stack;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use template;
+use tofu::{self, KnownKeys, Warning};
+use topics::TopicNaming;
+use transcript::{self, Difference, Outcome, Range, Verification};
+#[cfg(feature = "games")]
+use ttt::Games;
+use upgrade::{DialRequest, Protocol};
+use usage::{format_bytes, Counters, Usage};
+use version;
+use watches::Watches;
+
+/// Number of messages printed by `/history`.
+const HISTORY_LINES: usize = 20;
+
+/// Number of IDs of our own messages that we remember.
+const MAX_SENT_IDS: usize = 1024;
+
+/// Number of peers listed by `/usage`, the most expensive first.
+const MAX_USAGE_PEERS: usize = 10;
+
+/// Minimum number of seconds between two syncs of the bans of a room. When we join, all the
+/// members are new to us, and they get the bans once.
+const BANS_SYNC_SECS: u64 = 30;
+
+pub struct Chat {
+    identity: Identity,
+    nick: Option<String>,
+    notifier: Notifier,
+    /// Rooms that always notify, whatever the configuration file says.
+    notify_rooms: Vec<String>,
+    /// The configuration as last loaded, to find out what changed on reload.
+    config: Config,
+    previewer: Previewer,
+    floodsub: FloodSubController,
+    /// How the names of the topics are built.
+    naming: TopicNaming,
+    /// The rooms we are in, with their topic.
+    rooms: Vec<(String, Topic)>,
+    /// Name of the current room, in which plain messages are published. Only meaningful if
+    /// `rooms` isn't empty.
+    room: String,
+    moderation: Moderation,
+    /// The banned peers, as the floodsub connections see them. See the `screening` module.
+    bans: Bans,
+    /// When we last sent the bans of each room to a peer who joined it.
+    bans_synced: HashMap<String, Instant>,
+    mentions: Mentions,
+    filter: Filter,
+    /// Masks the secrets in what we send.
+    redactor: Redactor,
+    composer: Composer,
+    /// The last messages displayed or sent.
+    history: History,
+    /// How far `/history more` went back in each room.
+    backfill: Backfill,
+    /// The `/verify` in progress, if any.
+    verification: Option<Verification>,
+    /// The lines typed by the user, for `/again`.
+    inputs: InputHistory,
+    pins: Pins,
+    metadata: Metadata,
+    /// Number of calls to `tick` so far.
+    ticks: u64,
+    /// Slows the ticks down while nothing happens, on battery.
+    idle: Idle,
+    /// What the last `/purge` asked to delete, waiting for confirmation. `None` inside means
+    /// everything.
+    pending_purge: Option<Option<String>>,
+    /// A large message waiting for confirmation. See `publish_text`.
+    pending_send: Option<String>,
+    /// Lines and bytes beyond which a message must be confirmed, if not 0.
+    confirm_lines: usize,
+    confirm_bytes: usize,
+    /// The lines of the last message we sent, its room and when we sent it, for `/undo`.
+    last_sent: Option<(Vec<u64>, String, Instant)>,
+    undo_window: Duration,
+    /// File from which the identity was loaded, if any.
+    identity_file: Option<String>,
+    /// If true, the identity file must be encrypted.
+    encrypt_identity: bool,
+    /// The keys of the other nodes, trusted on first use. See the `tofu` module.
+    known_keys: KnownKeys,
+    /// The messages of `/schedule`, waiting for their time.
+    schedule: Schedule,
+    /// The reports we made, and those we received as a moderator.
+    reports: Reports,
+    /// The reminders we were sent, with their author, so that we only show each one once.
+    reminded: HashSet<(PeerId, u32)>,
+    /// File in which the settings are saved, if any.
+    config_file: Option<String>,
+    /// Profile under which the session is saved in `config_file`.
+    profile: String,
+    watches: Watches,
+    /// If true, a status line is shown. See `refresh_status`.
+    status_line: bool,
+    /// Number of messages received in the rooms other than the current one since we last
+    /// switched to them.
+    unread: HashMap<String, usize>,
+    emoji_on_send: bool,
+    emoji_on_display: bool,
+    /// Time to live of the messages we publish in the room.
+    ttl: Option<u64>,
+    /// Maximum size of a serialized envelope that we send.
+    max_message_size: usize,
+    /// Messages waiting to be published.
+    outbox: Outbox,
+    /// IDs of the last messages we published, to recognize them if they come back to us.
+    sent: VecDeque<u64>,
+    /// True once we have warned that another node uses our identity.
+    warned_duplicate: bool,
+    /// Lines waiting to be packed in a batch, if batching is enabled.
+    batcher: Option<Batcher>,
+    /// With `--reorder-delay`, the texts waiting to be displayed, with their ID and room.
+    reorder: Option<Reorder<(Received, u64, Option<String>, String)>>,
+    /// With `--echo`, publishes back the texts we receive.
+    echo: Option<Echo>,
+    /// With `--demo-traffic`, the personas of the canned conversation.
+    demo: Option<Demo>,
+    /// The identities of `--persona` that we aren't using.
+    personas: Personas,
+    polls: Polls,
+    #[cfg(feature = "games")]
+    games: Rc<RefCell<Games>>,
+    #[cfg(feature = "calls")]
+    calls: Rc<RefCell<Calls>>,
+    screens: Rc<RefCell<Screens>>,
+    /// The bots of `--plugins`.
+    plugins: Plugins,
+    /// The connections that are currently open.
+    peers: Rc<RefCell<PeerTable>>,
+    chaos: Chaos,
+    relays: Relays,
+    /// The direct protocols that the addresses we dialed support, or not.
+    capabilities: Capabilities,
+    drain: Drain,
+    /// Relay to send the others to when we drain, from `--alternate`.
+    alternate: Option<Multiaddr>,
+    /// Set while we follow a relay that drains to its alternate. See the `handoff` module.
+    handoff: Option<Handoff>,
+    /// True if we dial through a SOCKS5 proxy.
+    proxied: bool,
+    usage: Usage,
+    damper: Damper,
+    counters: Counters,
+    presence: Presence,
+    /// How far the clock of each peer is from ours. See the `clocks` module.
+    clocks: Clocks,
+    scores: Scores,
+    /// Addresses at which the others can dial us, advertised with the heartbeats.
+    external_addresses: Vec<Multiaddr>,
+    /// File in which the topology of the mesh is written every tick, if any.
+    graph_file: Option<String>,
+    /// Envelopes received and sent in each of our rooms since the start, for the dashboard.
+    traffic: HashMap<String, (u64, u64)>,
+    short_ids: ShortIds,
+    /// Protocol version of the last message of each peer, `None` if it predates versioning.
+    versions: HashMap<PeerId, Option<String>>,
+    /// Rooms advertised by the nodes of the network.
+    directory: Directory,
+    /// Topic on which the rooms are advertised.
+    directory_topic: Topic,
+    /// `None` if we don't take part in the election of a coordinator.
+    election: Option<Election>,
+    #[cfg(feature = "dht")]
+    kv: KvStore,
+    /// Topic on which the requests to the key-value store of the current room are published.
+    /// Always `None` without the `dht` feature.
+    kv_topic: Option<Topic>,
+    /// The notepads we have joined, and their topic.
+    #[cfg(feature = "pads")]
+    pads: HashMap<String, (Topic, Pad)>,
+    /// Used to ask for new connections to be opened.
+    dial: mpsc::UnboundedSender<DialRequest>,
+}
+
+/// What a `Chat` is built from, besides the options: the identity, the rooms, and the parts of
+/// the node that `main` sets up and shares with the chat.
+pub struct Parts {
+    pub identity: Identity,
+    pub floodsub: FloodSubController,
+    pub naming: TopicNaming,
+    /// The rooms to join, the first one being the current room.
+    pub rooms: Vec<(String, Topic)>,
+    pub config: Config,
+    pub previewer: Previewer,
+    #[cfg(feature = "games")]
+    pub games: Rc<RefCell<Games>>,
+    #[cfg(feature = "calls")]
+    pub calls: Rc<RefCell<Calls>>,
+    pub screens: Rc<RefCell<Screens>>,
+    pub plugins: Plugins,
+    pub peers: Rc<RefCell<PeerTable>>,
+    pub chaos: Chaos,
+    pub relays: Relays,
+    pub capabilities: Capabilities,
+    pub drain: Drain,
+    pub counters: Counters,
+    pub dial: mpsc::UnboundedSender<DialRequest>,
+    pub bans: Bans,
+}
+
+impl Chat {
+    pub fn new(parts: Parts, options: &Options) -> Result<Chat, Error> {
+        let Parts {
+            identity,
+            floodsub,
+            naming,
+            rooms,
+            config,
+            previewer,
+            #[cfg(feature = "games")]
+            games,
+            #[cfg(feature = "calls")]
+            calls,
+            screens,
+            plugins,
+            peers,
+            chaos,
+            relays,
+            capabilities,
+            drain,
+            counters,
+            dial,
+            bans,
+        } = parts;
+        let room = rooms[0].0.clone();
+        #[cfg(feature = "dht")]
+        let kv_topic = {
+            let topic = naming.topic(&format!("{}/kv", room));
+            floodsub.subscribe(&topic);
+            Some(topic)
+        };
+        #[cfg(not(feature = "dht"))]
+        let kv_topic = None;
+        let directory_topic = naming.topic(directory::TOPIC);
+        floodsub.subscribe(&directory_topic);
+        let timeout = options.heartbeat_interval * options.missed_heartbeats;
+        // Nobody is there to confirm the large lines of `--input-file` or `--input-fifo`.
+        let typed = options.input_file.is_none() && options.input_fifo.is_none();
+
+        // The moderators of the room are the owners of the public keys passed on the command line.
+        let moderators = options
+            .moderators
+            .iter()
+            .map(|key| {
+                identity::decode_key(key).ok_or_else(|| {
+                    Error::Config(format!("--moderator expects a public key, not {}", key))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let election = if options.election {
+            Some(Election::new(identity.peer_id().clone()))
+        } else {
+            None
+        };
+        #[cfg(feature = "dht")]
+        let kv = KvStore::new(identity.peer_id());
+        let personas = Personas::load(&options.personas, options.encrypt_identity, &rooms)
+            .map_err(|err| Error::config("can't load the identity file of a persona", err))?;
+        if rooms.len() > 1 {
+            display::set_prompt(Some(room.clone()));
+        }
+        let mut chat = Chat {
+            identity,
+            nick: options.nick.clone(),
+            notifier: Notifier::new(HashMap::new()),
+            notify_rooms: options.notify_rooms.clone(),
+            config: Config::default(),
+            previewer,
+            floodsub,
+            naming,
+            rooms,
+            room,
+            moderation: Moderation::load(moderators, options.bans_file.clone()),
+            bans,
+            bans_synced: HashMap::new(),
+            mentions: Mentions::new(),
+            filter: Filter::new(&options.filters, options.max_repeats)
+                .map_err(|err| Error::config("--filter expects a regular expression", err))?,
+            redactor: Redactor::new(&options.redactions, options.default_redactions)
+                .map_err(|err| Error::config("--redact expects a regular expression", err))?,
+            composer: Composer::new(),
+            history: History::new(),
+            backfill: Backfill::new(),
+            verification: None,
+            inputs: InputHistory::load(
+                options.input_history.clone(),
+                options.input_history_size,
+                &options.input_history_exclude,
+            ).map_err(|err| {
+                Error::config("--input-history-exclude expects a regular expression", err)
+            })?,
+            pins: Pins::new(),
+            metadata: Metadata::new(
+                options.heartbeat_interval * (2 * metadata::SNAPSHOT_TICKS),
+            ),
+            ticks: 0,
+            idle: Idle::new(options.heartbeat_interval),
+            pending_purge: None,
+            pending_send: None,
+            confirm_lines: if typed { options.confirm_lines } else { 0 },
+            confirm_bytes: if typed { options.confirm_bytes } else { 0 },
+            last_sent: None,
+            undo_window: options.undo_window,
+            identity_file: options.identity.clone(),
+            encrypt_identity: options.encrypt_identity,
+            known_keys: KnownKeys::load(options.known_keys.clone()),
+            schedule: Schedule::load(options.schedule_file.clone()),
+            reminded: HashSet::new(),
+            reports: Reports::load(options.reports.clone()),
+            config_file: options.config.clone(),
+            profile: options.profile.clone(),
+            watches: Watches::new(),
+            status_line: options.status_line,
+            unread: HashMap::new(),
+            emoji_on_send: options.emoji_on_send,
+            emoji_on_display: options.emoji_on_display,
+            ttl: options.ttl,
+            max_message_size: options.max_message_size,
+            outbox: Outbox::new(options.queue_size, options.queue_policy),
+            sent: VecDeque::new(),
+            warned_duplicate: false,
+            batcher: options.batch_delay.map(|_| Batcher::new(options.batch_size)),
+            reorder: options
+                .reorder_delay
+                .map(|delay| Reorder::new(Duration::from_millis(delay))),
+            echo: Some(Echo::default()).filter(|_| options.echo),
+            demo: if options.demo_traffic {
+                Some(Demo::new())
+            } else {
+                None
+            },
+            personas,
+            polls: Polls::new(),
+            #[cfg(feature = "games")]
+            games,
+            #[cfg(feature = "calls")]
+            calls,
+            screens,
+            plugins,
+            peers,
+            chaos,
+            relays,
+            capabilities,
+            drain,
+            alternate: options.alternate.clone(),
+            handoff: None,
+            proxied: options.proxy.is_some(),
+            usage: Usage::new(),
+            damper: Damper::new(options.flood_threshold),
+            counters,
+            presence: Presence::new(timeout),
+            clocks: Clocks::default(),
+            scores: Scores::load(options.scores_file.clone(), options.heartbeat_interval),
+            external_addresses: options.external_addresses.clone(),
+            graph_file: options.graph_file.clone(),
+            traffic: HashMap::new(),
+            short_ids: ShortIds::new(),
+            directory: Directory::new(timeout),
+            directory_topic,
+            election,
+            #[cfg(feature = "dht")]
+            kv,
+            kv_topic,
+            #[cfg(feature = "pads")]
+            pads: HashMap::new(),
+            versions: HashMap::new(),
+            dial,
+        };
+        chat.apply_config(config);
+        chat.update_screening();
+        Ok(chat)
+    }
+
+    /// Applies the changes made to the configuration file since it was loaded. Called on
+    /// `SIGHUP` and by `/reload`, without touching the connections.
+    pub fn reload(&mut self) {
+        let config = match self.config_file {
+            Some(ref path) => Config::load(path),
+            None => {
+                say!("* There is no configuration file to reload");
+                return;
+            }
+        };
+        match config {
+            Ok(config) => {
+                self.apply_config(config);
+                say!("* Configuration reloaded");
+            }
+            Err(err) => say!("* Can't reload the configuration: {}", err),
+        }
+    }
+
+    /// Applies `config`. For the filters and the addresses to dial, only what changed since the
+    /// previous configuration is taken into account.
+    fn apply_config(&mut self, config: Config) {
+        let mut levels = config.notify.clone();
+        if let Some(session) = config.sessions.get(&self.profile) {
+            levels.extend(session.notify.iter().map(|(room, &level)| (room.clone(), level)));
+        }
+        let mut notifier = Notifier::new(levels);
+        for room in &self.notify_rooms {
+            notifier.set_level(room.clone(), Level::All);
+        }
+        self.notifier = notifier;
+
+        let mut watches = Watches::new();
+        let session = config.sessions.get(&self.profile);
+        for pattern in session.into_iter().flat_map(|session| session.watches.iter()) {
+            if let Err(err) = watches.add(pattern) {
+                say!("* Invalid pattern {} in the configuration: {}", pattern, err);
+            }
+        }
+        self.watches = watches;
+
+        for pattern in &self.config.filters {
+            if !config.filters.contains(pattern) {
+                self.filter.remove(pattern);
+            }
+        }
+        for pattern in &config.filters {
+            if !self.config.filters.contains(pattern) {
+                if let Err(err) = self.filter.add(pattern) {
+                    say!("* Invalid pattern {} in the configuration: {}", pattern, err);
+                }
+            }
+        }
+
+        for address in &config.dial {
+            if self.config.dial.contains(address) {
+                continue;
+            }
+            match addresses::parse(address) {
+                Ok(address) => {
+                    let _ = self.dial.unbounded_send(DialRequest {
+                        address,
+                        protocol: Protocol::FloodSub,
+                    });
+                }
+                Err(err) => say!("* Invalid address in the configuration: {}", err),
+            }
+        }
+        self.config = config;
+    }
+
+    /// Called for each message received from floodsub, once `envelope::open` has been called on
+    /// it. `topics` are the topics the message was published on, and `source` is the node that
+    /// floodsub says it comes from.
+    pub fn handle_message(
+        &mut self,
+        topics: &[TopicHash],
+        source: &PeerId,
+        opened: Result<Received, OpenError>,
+    ) {
+        if self.scores.is_ignored(source) {
+            return;
+        }
+        let mut received = match opened {
+            Ok(received) => received,
+            // Warn once per peer, since an incompatible peer keeps sending heartbeats.
+            Err(OpenError::Incompatible { sender, version }) => {
+                // The beacons of the peers we talk with are in the format of the previous version.
+                if Some(&version[..]) == migration::previous() && self.presence.is_alive(&sender) {
+                    return;
+                }
+                let known = Some(version.clone());
+                if self.versions.insert(sender.clone(), known.clone()) != Some(known) {
+                    display::chatter(&tr!(
+                        "* {} uses protocol version {}, which is incompatible with ours ({}); \
+                         ignoring their messages",
+                        self.short_ids.get(&sender),
+                        version,
+                        version::PROTOCOL
+                    ));
+                }
+                return;
+            }
+            Err(err) => {
+                display::chatter(&tr!("Dropped message: {}", err));
+                if err == OpenError::BadSignature {
+                    self.penalize(source, |scores| scores.invalid(source));
+                }
+                return;
+            }
+        };
+
+        display::event(
+            Verbosity::Debug,
+            &format!("Received from {}: {:?}", received.sender.to_base58(), received.body.kind),
+        );
+        if received.body.is_expired() {
+            return;
+        }
+        self.versions
+            .insert(received.sender.clone(), received.version.clone());
+        // A message signed with our key is either one of ours that the mesh sent back to us,
+        // which we ignore, or was sent by another node running with the same identity.
+        if received.sender == *self.identity.peer_id() {
+            if self.sent.contains(&received.body.id) {
+                return;
+            }
+            if !self.warned_duplicate {
+                self.warned_duplicate = true;
+                say!(
+                    "* Another node is using our identity. If it isn't you on another device, \
+                     someone has a copy of the identity file: stop that node, or restart with \
+                     another --identity"
+                );
+            }
+            received.body.nick = Some(match received.body.nick.take() {
+                Some(nick) => format!("{}, you elsewhere", nick),
+                None => "you elsewhere".to_owned(),
+            });
+        }
+        let (sender, id) = (received.sender.clone(), received.body.id);
+        if !self.penalize(&sender, |scores| scores.received(&sender, id)) {
+            return;
+        }
+        match received.body.kind {
+            Kind::Heartbeat
+            | Kind::Addresses(_)
+            | Kind::Coordinator
+            | Kind::Topics(_)
+            | Kind::RoomState { .. } => {}
+            _ => self.idle.activity(),
+        }
+        // The room the message was published in, if it is one of ours.
+        let room = self
+            .rooms
+            .iter()
+            .find(|&&(_, ref topic)| topics.contains(topic.hash()))
+            .map(|&(ref room, _)| room.clone());
+        let label = self.naming.label(room.as_ref().unwrap_or(&self.room));
+        if self.moderation.is_banned(&label, &received.sender) {
+            return;
+        }
+        if let Some(ref room) = room {
+            self.traffic.entry(room.clone()).or_insert((0, 0)).0 += 1;
+        }
+        // What others wrote mustn't drive our terminal.
+        received.body.nick = received.body.nick.take().map(|nick| markdown::strip_controls(&nick));
+        let warning = {
+            let nick = received.body.nick.as_ref().map(|nick| nick.as_str());
+            self.known_keys.check(nick, &received.public_key)
+        };
+        match warning {
+            Some(Warning::Changed { nick, trusted }) => say!(
+                "* Careful: {} now signs with an unknown key instead of {}; it may be an impostor",
+                nick,
+                trusted
+            ),
+            Some(Warning::Revoked { successor }) => say!(
+                "* Careful: {} signed with a key that was replaced by {}; it may have leaked",
+                self.sender_name(&received),
+                successor
+            ),
+            None => {}
+        }
+        // Advertisements come from all the rooms of the network, not only ours.
+        let in_room = match received.body.kind {
+            Kind::Topics(_) => false,
+            _ => true,
+        };
+        if in_room {
+            self.presence.seen(&received.sender, received.body.nick.clone());
+            self.short_ids.add(&received.sender);
+            #[cfg(feature = "dht")]
+            self.kv.add_peer(&received.sender);
+            if let Some(ref room) = room {
+                if self.metadata.seen(room, received.sender.clone()) {
+                    self.sync_bans(room);
+                }
+            }
+        }
+
+        // The kind is moved out rather than cloned, as it can contain a large text. The handlers
+        // below only look at the other fields of `received`.
+        let kind = strip_controls(mem::replace(&mut received.body.kind, Kind::Heartbeat));
+        match kind {
+            Kind::Text(text) => {
+                let id = received.body.id;
+                self.echo(&received, room.as_ref(), &text);
+                self.pass_to_plugins(&received, room.as_ref(), &text);
+                self.display_in_order(&received, id, room, text)
+            }
+            Kind::Batch(texts) => {
+                for (index, text) in texts.into_iter().enumerate() {
+                    self.echo(&received, room.as_ref(), &text);
+                    self.pass_to_plugins(&received, room.as_ref(), &text);
+                    let id = history::line_id(received.body.id, index);
+                    self.display_in_order(&received, id, room.clone(), text);
+                }
+            }
+            Kind::Action(action) => {
+                let action = self.display_emoji(action);
+                let name = self.sender_name(&received);
+                let line = format!("* {} {}", name, markdown::render(&action));
+                let id = received.body.id;
+                self.display_text(&received, id, room, line, &action, false)
+            }
+            Kind::Edit { message, text } => {
+                let text = self.display_emoji(text);
+                let (room, line) = match self.history.edit(message, &received.sender, text) {
+                    Some(entry) => (entry.room.clone(), format!("* {}", entry.render())),
+                    None => return,
+                };
+                self.print_in_room(&room, &line);
+            }
+            Kind::React { message, reaction } => {
+                if reaction.chars().count() > history::MAX_REACTION_LEN {
+                    return;
+                }
+                let reaction = self.display_emoji(reaction);
+                let (room, line) = match self.history.react(message, &received.sender, reaction) {
+                    Some(entry) => (
+                        entry.room.clone(),
+                        format!("*   ↳ {}  {}", entry.snippet(), entry.render_reactions()),
+                    ),
+                    None => return,
+                };
+                self.print_in_room(&room, &line);
+            }
+            // Pins only count in the room whose topic they were published on.
+            Kind::Pin {
+                room: pin_room,
+                message,
+                text,
+            } => {
+                let pin_room = self.room_of_label(pin_room);
+                if room.as_ref() != Some(&pin_room) {
+                    return;
+                }
+                let pin = Pin {
+                    message,
+                    text,
+                    pinner: received.sender.clone(),
+                    pinner_name: self.sender_name(&received),
+                };
+                let line = format!("* {} pinned {}", pin.pinner_name, pin.text);
+                if self.pins.pin(&pin_room, pin) {
+                    self.print_in_room(&pin_room, &line);
+                }
+            }
+            Kind::RoomState {
+                room: state_room,
+                description,
+                members,
+            } => {
+                let state_room = self.room_of_label(state_room);
+                if room.as_ref() != Some(&state_room) {
+                    return;
+                }
+                let (described, members) =
+                    self.metadata.apply_snapshot(&state_room, description, &members);
+                if let Some(text) = described {
+                    let line = format!("* The room is now described as: {}", text);
+                    self.print_in_room(&state_room, &line);
+                }
+                for peer in &members {
+                    self.short_ids.add(peer);
+                }
+            }
+            Kind::Unpin {
+                room: pin_room,
+                message,
+            } => {
+                let pin_room = self.room_of_label(pin_room);
+                if room.as_ref() != Some(&pin_room) {
+                    return;
+                }
+                if let Some(pin) = self.pins.unpin(&pin_room, message, &received.sender) {
+                    let line = format!("* {} unpinned {}", pin.pinner_name, pin.text);
+                    self.print_in_room(&pin_room, &line);
+                }
+            }
+            Kind::Delete { message } => {
+                let (room, name) = match self.history.delete(message, &received.sender) {
+                    Some(entry) => (entry.room.clone(), entry.name.clone()),
+                    None => return,
+                };
+                let line = format!("* {} deleted a message", name);
+                self.print_in_room(&room, &line);
+            }
+            Kind::Ban { room, peer } => self.handle_ban(&received, &room, &peer, true),
+            Kind::Unban { room, peer } => self.handle_ban(&received, &room, &peer, false),
+            Kind::Bans { room, entries } => self.handle_bans(&room, entries),
+            Kind::Poll { question, options } => {
+                let poll = Poll {
+                    question,
+                    options,
+                    author: self.sender_name(&received),
+                };
+                let id = received.body.id;
+                if self.polls.add(id, poll) {
+                    self.print_poll(id, self.polls.get(id).unwrap());
+                }
+            }
+            Kind::Vote { poll, choice } => {
+                self.polls.vote(poll, received.public_key, choice);
+            }
+            #[cfg(feature = "pads")]
+            Kind::Pad { name, op } => self.handle_pad_op(&received, &name, op),
+            #[cfg(not(feature = "pads"))]
+            Kind::Pad { .. } => {}
+            #[cfg(feature = "dht")]
+            Kind::KvPut { key, value } => {
+                if value.len() <= kv::MAX_VALUE_LEN {
+                    self.kv.put(key, value);
+                }
+            }
+            #[cfg(feature = "dht")]
+            Kind::KvGet { key, request } => {
+                let value = self.kv.get(&key).cloned();
+                if let Some(value) = value {
+                    self.publish_on_kv(Kind::KvValue { request, value });
+                }
+            }
+            #[cfg(feature = "dht")]
+            Kind::KvValue { request, value } => {
+                if let Some(key) = self.kv.complete(request) {
+                    say!("* {} = {} (from {})", key, value, self.sender_name(&received));
+                }
+            }
+            #[cfg(not(feature = "dht"))]
+            Kind::KvPut { .. } | Kind::KvGet { .. } | Kind::KvValue { .. } => {}
+            Kind::Heartbeat => {
+                let timestamp = received.body.timestamp;
+                if let Some(offset) = self.clocks.sample(&sender, timestamp, envelope::now()) {
+                    display::chatter(&clocks::describe(&self.sender_name(&received), offset));
+                }
+            }
+            Kind::Addresses(addresses) => self.presence.advertised(&received.sender, &addresses),
+            Kind::Topics(rooms) => self.directory.advertised(&received.sender, &rooms),
+            Kind::Rotate { new_key, proof } => self.handle_rotation(&received, &new_key, &proof),
+            Kind::Report(report) => {
+                // Only the moderators keep the reports, which must come from their signer.
+                if !self.moderation.is_moderator(self.identity.public_key())
+                    || !report.is_from(&received.public_key)
+                {
+                    return;
+                }
+                let author = identity::parse_peer_id(&report.report.author)
+                    .map(|peer| self.short_ids.get(&peer))
+                    .unwrap_or_else(|| report.report.author.clone());
+                display::chatter(&tr!(
+                    "* {} reported a message of {} in {}: {} ({})",
+                    self.sender_name(&received),
+                    author,
+                    self.room_of_label(report.report.room.clone()),
+                    report.report.text,
+                    report.report.reason
+                ));
+                self.reports.push(report);
+            }
+            Kind::Reminder { to, reminder, text } => {
+                self.handle_reminder(&received, &to, reminder, text)
+            }
+            Kind::ReminderAck { to, reminder } => {
+                if to != self.identity.peer_id().to_base58() {
+                    return;
+                }
+                let peer = received.sender.to_base58();
+                if let Some(reminder) = self.schedule.acknowledged(reminder, &peer) {
+                    display::chatter(&tr!("* {} got reminder {}", reminder.to, reminder.id));
+                }
+            }
+            Kind::Draining { alternate: None } => {
+                display::chatter(&tr!("* {} is restarting", self.sender_name(&received)))
+            }
+            Kind::Draining {
+                alternate: Some(alternate),
+            } => {
+                let name = self.sender_name(&received);
+                display::chatter(&tr!("* {} is restarting; reconnect to {}", name, alternate));
+                if let Ok(alternate) = alternate.parse() {
+                    self.start_handoff(alternate);
+                }
+            }
+            Kind::HistoryQuery {
+                request,
+                before,
+                limit,
+            } => {
+                if let Some(ref room) = room {
+                    self.answer_history_query(&received, room, request, before, limit);
+                }
+            }
+            Kind::HistoryPage {
+                to,
+                request,
+                messages,
+                more,
+            } => {
+                if to == self.identity.peer_id().to_base58() {
+                    self.print_history_page(&received, request, messages, more);
+                }
+            }
+            Kind::VerifyQuery {
+                request,
+                peer,
+                ranges,
+            } => {
+                let own = self.identity.peer_id().to_base58();
+                if peer.map(|peer| peer == own).unwrap_or(true) {
+                    if let Some(ref room) = room {
+                        self.answer_verify_query(&received, room, request, ranges);
+                    }
+                }
+            }
+            Kind::VerifyAnswer {
+                to,
+                request,
+                digests,
+            } => {
+                if to == self.identity.peer_id().to_base58() {
+                    self.continue_verification(&received, request, digests);
+                }
+            }
+            Kind::Coordinator => {
+                let contest = match self.election {
+                    Some(ref mut election) => election.handle_announcement(received.sender.clone()),
+                    None => false,
+                };
+                if contest {
+                    self.publish(Kind::Coordinator);
+                }
+            }
+        }
+    }
+
+    /// Returns a snapshot of the internal state, for debugging a node that seems stuck.
+    pub fn diagnostics(&self) -> String {
+        let mut dump = String::new();
+        {
+            let mut line = |text: String| {
+                dump.push_str(&text);
+                dump.push('\n');
+            };
+            line(format!("== Diagnostics of {}", self.identity.peer_id().to_base58()));
+            line("Connections:".to_owned());
+            for connection in self.peers.borrow().iter() {
+                line(format!(
+                    "  {} ({:?}, {:?}, open for {}s)",
+                    connection.address,
+                    connection.endpoint,
+                    connection.protocol,
+                    connection.age().as_secs()
+                ));
+            }
+            let rooms: Vec<_> = self.rooms.iter().map(|&(ref room, _)| room.as_str()).collect();
+            #[cfg(feature = "pads")]
+            let pads: Vec<_> = self.pads.keys().map(|pad| pad.as_str()).collect();
+            #[cfg(not(feature = "pads"))]
+            let pads: Vec<&str> = Vec::new();
+            line(format!(
+                "Subscriptions: directory, rooms [{}], pads [{}], key-value store {}",
+                rooms.join(", "),
+                pads.join(", "),
+                if self.kv_topic.is_some() { "yes" } else { "no" }
+            ));
+            line(format!("Current room: {}", self.room));
+            line(format!(
+                "Outbox: {}/{} messages queued, {} dropped",
+                self.outbox.len(),
+                self.outbox.capacity(),
+                self.outbox.dropped()
+            ));
+            let (queued, sent, dropped) = self.outbox.direct();
+            line(format!(
+                "Direct messages: {}/{} queued, {} sent, {} dropped",
+                queued,
+                outbox::DIRECT_CAPACITY,
+                sent,
+                dropped
+            ));
+            if let Some(ref batcher) = self.batcher {
+                line(format!("Batch: {} lines waiting", batcher.len()));
+            }
+            line(format!(
+                "Deduplication: {}/{} of our message IDs, {} received message IDs",
+                self.sent.len(),
+                MAX_SENT_IDS,
+                self.scores.remembered()
+            ));
+            line(format!(
+                "Peers: {} alive, {} ignored",
+                self.presence.alive().count(),
+                self.scores.ignored()
+            ));
+            line(format!("History: {} messages", self.history.len()));
+        }
+        dump
+    }
+
+    /// Returns the topology of the mesh that we know of, in the DOT language. See `graph`.
+    fn graph(&self) -> String {
+        let local = self
+            .nick
+            .clone()
+            .unwrap_or_else(|| self.identity.peer_id().to_base58());
+        let mut graph = Graph::new(&format!("{} (us)", local));
+        for connection in self.peers.borrow().iter() {
+            let outbound = match connection.endpoint {
+                Endpoint::Dialer => true,
+                Endpoint::Listener => false,
+            };
+            graph.connection(&connection.address, outbound);
+        }
+        for (peer, info) in self.presence.roster() {
+            graph.peer(&peer.to_base58(), &self.peer_label(peer, info), &info.addresses);
+        }
+        for &(ref room, _) in &self.rooms {
+            graph.member(room, None);
+            for peer in self.metadata.members(room) {
+                graph.member(room, Some(&peer.to_base58()));
+            }
+        }
+        graph.to_dot()
+    }
+
+    /// The name of `peer` in the graphs: its nickname if we know it, and its short ID.
+    fn peer_label(&self, peer: &PeerId, info: &presence::Peer) -> String {
+        match info.nick {
+            Some(ref nick) => format!("{} ({})", nick, self.short_ids.get(peer)),
+            None => self.short_ids.get(peer),
+        }
+    }
+
+    /// Updates the score of `peer` with `update`, and tells the user if it is now ignored.
+    fn penalize<T, F>(&mut self, peer: &PeerId, update: F) -> T
+    where
+        F: FnOnce(&mut Scores) -> T,
+    {
+        let ignored = self.scores.is_ignored(peer);
+        let result = update(&mut self.scores);
+        if !ignored && self.scores.is_ignored(peer) {
+            display::chatter(&tr!(
+                "* Ignoring {}, whose score dropped below {}",
+                self.short_ids.get(peer),
+                scores::THRESHOLD
+            ));
+            self.scores.save();
+        }
+        result
+    }
+
+    /// Adds an address at which the others can dial us, as discovered by the `stun` module.
+    pub fn add_external_address(&mut self, address: Multiaddr) {
+        if !self.external_addresses.contains(&address) {
+            display::chatter(&tr!("* The others can dial us at {}", address));
+            self.external_addresses.push(address);
+        }
+    }
+
+    /// Called periodically, every `--heartbeat-interval`.
+    pub fn tick(&mut self) {
+        if self.idle.skip_tick() {
+            return;
+        }
+        // Hashed topics are meant to hide the names of the rooms.
+        if !self.naming.is_hashed() {
+            let rooms = self.rooms.iter().map(|&(ref room, _)| room.clone()).collect();
+            let body = self.new_body(Kind::Topics(rooms));
+            let topic = self.directory_topic.clone();
+            self.send(&topic, &body);
+        }
+        if self.rooms.is_empty() {
+            return;
+        }
+
+        self.publish(Kind::Heartbeat);
+        if let Some(id) = self.chaos.drop_connection() {
+            display::chatter(&tr!("* Chaos dropped connection {}", id));
+        }
+        for peer in self.scores.tick() {
+            display::chatter(&tr!("* No longer ignoring {}", self.short_ids.get(&peer)));
+        }
+        if let Some(ref path) = self.graph_file {
+            if let Err(err) = fs::write(path, self.graph()) {
+                say!("* Couldn't write the graph to {}: {}", path, err);
+            }
+        }
+        if !self.external_addresses.is_empty() {
+            let addresses = self.external_addresses.iter().map(|a| a.to_string()).collect();
+            self.publish(Kind::Addresses(addresses));
+        }
+        for (peer, info) in self.presence.expire() {
+            self.clocks.remove(&peer);
+            display::chatter(&tr!(
+                "* {} left (no news for {}s)",
+                info.nick.unwrap_or_else(|| peer.to_base58()),
+                self.presence.timeout().as_secs()
+            ));
+        }
+        let announce = match self.election {
+            Some(ref mut election) => election.tick(&self.presence),
+            None => false,
+        };
+        if announce {
+            self.publish(Kind::Coordinator);
+        }
+        self.ticks += 1;
+        if self.ticks % migration::BEACON_TICKS == 0 {
+            self.publish_beacons();
+        }
+        if self.ticks % u64::from(metadata::SNAPSHOT_TICKS) == 0 {
+            self.publish_room_state();
+        }
+        // Connections are opened and closed without us knowing, so we refresh regularly.
+        self.refresh_status();
+    }
+
+    /// Name under which the author of a message is displayed: their short ID, preceded by their
+    /// nickname if they have one.
+    fn sender_name(&self, received: &Received) -> String {
+        let short_id = self.short_ids.get(&received.sender);
+        match received.body.nick {
+            Some(ref nick) => format!("{} ({})", nick, short_id),
+            None => short_id,
+        }
+    }
+
+    fn display_emoji(&self, text: String) -> String {
+        if self.emoji_on_display {
+            emoji::expand(&text)
+        } else {
+            text
+        }
+    }
+
+    /// With `--echo`, publishes `text` back in `room`.
+    fn echo(&mut self, received: &Received, room: Option<&String>, text: &str) {
+        if self.echo.is_none() || received.sender == *self.identity.peer_id() {
+            return;
+        }
+        let topic = match room.and_then(|room| self.rooms.iter().find(|&&(ref r, _)| r == room)) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => return,
+        };
+        let reply = match self.echo {
+            Some(ref mut echo) => echo.reply(text),
+            None => None,
+        };
+        if let Some(reply) = reply {
+            let body = self.new_body(Kind::Text(reply));
+            self.send(&topic, &body);
+        }
+    }
+
+    /// Passes `text`, received in `room`, to the bots of `--plugins`.
+    fn pass_to_plugins(&mut self, received: &Received, room: Option<&String>, text: &str) {
+        if let Some(room) = room {
+            let sender = self.sender_name(received);
+            self.plugins.message(room, &sender, text);
+        }
+    }
+
+    /// Displays a text now, or with `--reorder-delay`, once the texts written before it had the
+    /// time to arrive.
+    fn display_in_order(
+        &mut self,
+        received: &Received,
+        id: u64,
+        room: Option<String>,
+        text: String,
+    ) {
+        let timestamp = self.clocks.correct(&received.sender, received.body.timestamp);
+        if let Some(ref mut reorder) = self.reorder {
+            reorder.push(timestamp, (received.clone(), id, room, text));
+            return;
+        }
+        self.display_message(received, id, room, text)
+    }
+
+    /// Displays the texts of `--reorder-delay` whose turn came.
+    pub fn flush_reorder(&mut self) {
+        let ready = match self.reorder {
+            Some(ref mut reorder) => reorder.take_ready(Instant::now()),
+            None => return,
+        };
+        for (received, id, room, text) in ready {
+            self.display_message(&received, id, room, text);
+        }
+    }
+
+    fn display_message(
+        &mut self,
+        received: &Received,
+        id: u64,
+        room: Option<String>,
+        text: String,
+    ) {
+        let text = self.display_emoji(text);
+        let mut body = String::new();
+        if let Some(parent) = received.body.reply_to.and_then(|id| self.history.find(id)) {
+            body.push_str(&format!("[re {}] ", parent.snippet()));
+        }
+        body.push_str(&markdown::render(&text));
+        let name = self.sender_name(received);
+        // A room with a format of its own also chooses where the time and the room go.
+        let line = {
+            let room = room.as_ref().unwrap_or(&self.room);
+            self.config
+                .formats
+                .get(room)
+                .map(|format| config::render_format(format, &display::clock(), room, &name, &body))
+        };
+        match line {
+            Some(line) => self.display_text(received, id, room, line, &text, true),
+            None => {
+                let line = format!("{}: {}", name, body);
+                self.display_text(received, id, room, line, &text, false)
+            }
+        }
+    }
+
+    /// Prints `line`, which displays the user-written `text`, highlighting it if we are mentioned.
+    /// Unless it is `formatted` with the format of its room, the line is tagged with the name of
+    /// `room` when we are in several rooms. The text is remembered in the history under `id`.
+    fn display_text(
+        &mut self,
+        received: &Received,
+        id: u64,
+        room: Option<String>,
+        line: String,
+        text: &str,
+        formatted: bool,
+    ) {
+        match self.filter.check(&received.sender, text) {
+            Verdict::Show(None) => {}
+            Verdict::Show(Some(repeated)) => display::chatter(&tr!(
+                "* {} sent the same message {}{}",
+                self.sender_name(received),
+                repeated,
+                tr!(display::symbol("×", " times"))
+            )),
+            Verdict::Drop | Verdict::Collapse => return,
+        }
+
+        let room = room.unwrap_or_else(|| self.room.clone());
+        let mentioned = match self.nick {
+            Some(ref nick) => mentions::is_mentioned(text, nick),
+            None => false,
+        };
+        let watched = self.watches.matching(text).map(|pattern| pattern.to_owned());
+        // In a flooded room, only a sample of the messages is displayed, but we still show the
+        // ones that mention us or that we watch, and keep all of them in the history.
+        let damping = self.damper.check(&room, text);
+        if damping.started {
+            display::chatter(&tr!("* {} is flooded; showing only a sample of its messages", room));
+        }
+        if let Some(hidden) = damping.repeated {
+            display::chatter(&tr!(
+                "* The previous message was repeated {}{}",
+                hidden,
+                tr!(display::symbol("×", " times"))
+            ));
+        }
+        let shown = damping.show || mentioned || watched.is_some();
+        if shown {
+            let line = if formatted {
+                line
+            } else if self.rooms.len() > 1 {
+                format!("{}[{}] {}", display::timestamp(), room, line)
+            } else {
+                format!("{}{}", display::timestamp(), line)
+            };
+            let sender = self.sender_name(received);
+            match watched {
+                Some(ref pattern) if !mentioned => {
+                    self.notifier.watched(&room, &sender, text, pattern)
+                }
+                _ => self.notifier.message(&room, &sender, text, mentioned),
+            }
+            if mentioned {
+                display::message(&display::highlight(&links::render(&line)));
+                self.mentions.push(room.clone(), line);
+            } else if watched.is_some() {
+                display::message(&display::highlight(&links::render(&line)));
+            } else {
+                display::message(&links::render(&line));
+            }
+            if room != self.room {
+                *self.unread.entry(room.clone()).or_insert(0) += 1;
+            }
+        }
+        self.history.push(Entry {
+            id,
+            room,
+            author: received.sender.clone(),
+            name: self.sender_name(received),
+            timestamp: self.clocks.correct(&received.sender, received.body.timestamp),
+            sent: received.body.timestamp,
+            text: text.to_owned(),
+            reply_to: received.body.reply_to,
+            reactions: BTreeMap::new(),
+            edited: false,
+            deleted: false,
+        });
+        if shown {
+            for url in links::find_urls(text) {
+                self.previewer.preview(url);
+            }
+        }
+        self.refresh_status();
+    }
+
+    /// Gossips what we know about the current room.
+    fn publish_room_state(&mut self) {
+        let own = self.identity.peer_id().clone();
+        self.metadata.seen(&self.room, own);
+        let members = self
+            .metadata
+            .members(&self.room)
+            .into_iter()
+            .take(metadata::MAX_SNAPSHOT_MEMBERS)
+            .map(|peer| peer.to_base58())
+            .collect();
+        let description = self.metadata.description(&self.room).cloned();
+        let room = self.naming.label(&self.room);
+        self.publish(Kind::RoomState {
+            room,
+            description,
+            members,
+        });
+    }
+
+    /// Prints a line about a message of `room`, tagged like the messages themselves.
+    fn print_in_room(&self, room: &str, line: &str) {
+        if self.rooms.len() > 1 {
+            display::message(&format!("[{}] {}", room, line));
+        } else {
+            display::message(line);
+        }
+    }
+
+    /// Prints the status line again, if enabled, after something it shows has changed.
+    fn refresh_status(&self) {
+        if !self.status_line {
+            return;
+        }
+        let peers = self.peers.borrow().iter().count();
+        let plural = if peers == 1 { "" } else { "s" };
+        let mut status = format!("{} peer{} | {}", peers, plural, self.room);
+        let mut unread: Vec<_> = self.unread.iter().filter(|&(_, &n)| n > 0).collect();
+        unread.sort();
+        for (room, n) in unread {
+            status.push_str(&format!(" | {}: {} unread", room, n));
+        }
+        display::set_status(status);
+    }
+
+    fn print_poll(&self, id: u64, poll: &Poll) {
+        say!("* {} asks: {}", poll.author, poll.question);
+        for (n, option) in poll.options.iter().enumerate() {
+            display::reply(&format!("*   {}. {}", n + 1, option));
+        }
+        say!("* Vote with `/vote {} <n>`", poll::format_id(id));
+    }
+
+    #[cfg(feature = "pads")]
+    fn handle_pad_op(&mut self, received: &Received, name: &str, op: PadOp) {
+        let snapshot = match self.pads.get_mut(name) {
+            Some(&mut (_, ref mut pad)) => match op {
+                PadOp::SyncRequest => Some(pad.snapshot()),
+                op => {
+                    pad.apply(op);
+                    say!("* {} updated pad {}:", self.sender_name(received), name);
+                    print_pad(pad);
+                    None
+                }
+            },
+            None => None,
+        };
+        if let Some(snapshot) = snapshot {
+            self.publish_pad_op(name, snapshot);
+        }
+    }
+
+    #[cfg(feature = "pads")]
+    fn handle_pad_command(&mut self, name: String, action: PadAction) {
+        if let PadAction::Close = action {
+            match self.pads.remove(&name) {
+                Some((topic, _)) => {
+                    self.floodsub.unsubscribe(&topic);
+                    say!("* Closed pad {}", name);
+                }
+                None => say!("* You haven't joined pad {}", name),
+            }
+            return;
+        }
+        if let PadAction::Open = action {
+            if !self.pads.contains_key(&name) {
+                let topic = self.naming.topic(&format!("{}/pad/{}", self.room, name));
+                self.floodsub.subscribe(&topic);
+                self.pads.insert(name.clone(), (topic, Pad::new(::rand::random())));
+                // Ask the other participants for the current content of the pad.
+                self.publish_pad_op(&name, PadOp::SyncRequest);
+            }
+        }
+
+        let targets_line = match action {
+            PadAction::Edit(..) | PadAction::Delete(..) => true,
+            _ => false,
+        };
+        let op = match self.pads.get_mut(&name) {
+            Some(&mut (_, ref mut pad)) => match action {
+                PadAction::Close => unreachable!("handled above"),
+                PadAction::Open | PadAction::Show => {
+                    say!("* Pad {}:", name);
+                    print_pad(pad);
+                    None
+                }
+                PadAction::Append(text) => Some(pad.append(text)),
+                PadAction::Edit(n, text) => pad.edit(n, text),
+                PadAction::Delete(n) => pad.delete(n),
+            },
+            None => {
+                say!("* You haven't joined pad {}; use `/pad {}` first", name, name);
+                return;
+            }
+        };
+
+        match op {
+            Some(op) => {
+                self.publish_pad_op(&name, op);
+                print_pad(&self.pads[&name].1);
+            }
+            None if targets_line => say!("* Pad {} has no such line", name),
+            None => {}
+        }
+    }
+
+    #[cfg(feature = "dht")]
+    fn publish_on_kv(&mut self, kind: Kind) {
+        let topic = match self.kv_topic {
+            Some(ref topic) => topic.clone(),
+            None => return say!("* You aren't in any room; use `/join <room>`"),
+        };
+        let body = self.new_body(kind);
+        self.send(&topic, &body);
+    }
+
+    #[cfg(feature = "pads")]
+    fn publish_pad_op(&mut self, name: &str, op: PadOp) {
+        let body = self.new_body(Kind::Pad {
+            name: name.to_owned(),
+            op,
+        });
+        let topic = self.pads[name].0.clone();
+        self.send(&topic, &body);
+    }
+
+    /// Handles the announcement that the author replaced their key. See the `tofu` module.
+    fn handle_rotation(&mut self, received: &Received, new_key: &[u8], proof: &[u8]) {
+        // Whoever has a copy of a revoked key mustn't be able to rotate it again, to their key.
+        if self.known_keys.is_revoked(&received.public_key) {
+            return;
+        }
+        if !identity::verify(new_key, &tofu::rotation_proof(&received.public_key), proof) {
+            display::chatter(&tr!("Dropped message: {}", "invalid key rotation"));
+            let sender = &received.sender;
+            self.penalize(sender, |scores| scores.invalid(sender));
+            return;
+        }
+        self.known_keys.rotate(&received.public_key, new_key);
+        say!(
+            "* {} replaced their key; their new public key is {}",
+            self.sender_name(received),
+            identity::encode_key(new_key)
+        );
+    }
+
+    /// Replaces our key with a new one, and announces it signed with the old one.
+    fn rotate_key(&mut self) {
+        let new = match self.identity_file {
+            Some(ref path) => match Identity::regenerate(path, self.encrypt_identity) {
+                Ok(identity) => identity,
+                Err(err) => return say!("* Can't replace the key in {}: {}", path, err),
+            },
+            None => Identity::generate(),
+        };
+        let old_key = self.identity.public_key().to_vec();
+        let proof = new.sign(&tofu::rotation_proof(&old_key));
+        let body = self.new_body(Kind::Rotate {
+            new_key: new.public_key().to_vec(),
+            proof,
+        });
+        let topic = self.directory_topic.clone();
+        self.send(&topic, &body);
+        self.known_keys.rotate(&old_key, new.public_key());
+
+        self.identity = new;
+        #[cfg(feature = "dht")]
+        {
+            self.kv = KvStore::new(self.identity.peer_id());
+        }
+        if self.election.is_some() {
+            self.election = Some(Election::new(self.identity.peer_id().clone()));
+        }
+        say!(
+            "* Replaced our key; our public key is now {}",
+            identity::encode_key(self.identity.public_key())
+        );
+    }
+
+    /// The room named `label` in a message, as written by `TopicNaming::label`: one of ours, or
+    /// the label itself if we aren't in that room.
+    fn room_of_label(&self, label: String) -> String {
+        let room = self
+            .rooms
+            .iter()
+            .map(|&(ref room, _)| room)
+            .find(|room| self.naming.label(room) == label)
+            .cloned();
+        room.unwrap_or(label)
+    }
+
+    /// Applies a ban or an unban of the room that `label` names, from a node that predates the
+    /// signed entries of `Kind::Bans`.
+    fn handle_ban(&mut self, received: &Received, label: &str, peer: &str, ban: bool) {
+        let peer = match identity::parse_peer_id(peer) {
+            Some(peer) => peer,
+            None => return,
+        };
+        let applied = if ban {
+            self.moderation.ban(&received.public_key, label, peer.clone())
+        } else {
+            self.moderation.unban(&received.public_key, label, &peer)
+        };
+        if applied {
+            self.update_screening();
+            self.print_ban(label, &peer);
+        }
+    }
+
+    /// Applies the signed entries about the room that `label` names.
+    fn handle_bans(&mut self, label: &str, entries: Vec<SignedEntry>) {
+        let mut changed = false;
+        for entry in entries.into_iter().take(moderation::MAX_SYNCED_ENTRIES) {
+            if entry.entry.room != label {
+                continue;
+            }
+            if let Some(peer) = self.moderation.apply(entry) {
+                changed = true;
+                self.print_ban(label, &peer);
+            }
+        }
+        if changed {
+            self.update_screening();
+        }
+    }
+
+    /// Tells whether `peer` is banned from the room that `label` names, if it is the current one.
+    fn print_ban(&self, label: &str, peer: &PeerId) {
+        if label != self.naming.label(&self.room) {
+            return;
+        }
+        let verb = if self.moderation.is_banned(label, peer) {
+            tr!("banned")
+        } else {
+            tr!("unbanned")
+        };
+        display::chatter(&tr!("* {} was {} by a moderator", peer.to_base58(), verb));
+    }
+
+    /// Sends the signed entries about `room` that we know of, for a peer who just joined it.
+    fn sync_bans(&mut self, room: &str) {
+        let label = self.naming.label(room);
+        let entries = self.moderation.entries(&label);
+        let recent = self
+            .bans_synced
+            .get(room)
+            .map(|at| at.elapsed() < Duration::from_secs(BANS_SYNC_SECS))
+            .unwrap_or(false);
+        if entries.is_empty() || recent {
+            return;
+        }
+        let topic = match self.rooms.iter().find(|&&(ref r, _)| r == room) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => return,
+        };
+        self.bans_synced.insert(room.to_owned(), Instant::now());
+        let body = self.new_body(Kind::Bans { room: label, entries });
+        self.send(&topic, &body);
+    }
+
+    /// Tells the floodsub connections which peers are banned from which topics.
+    fn update_screening(&self) {
+        let banned = self
+            .moderation
+            .all_banned()
+            .map(|(label, peer)| (self.naming.topic_of_label(label).hash().clone(), peer.clone()))
+            .collect();
+        self.bans.set(banned);
+    }
+
+    /// Called for each line typed by the user.
+    pub fn handle_input(&mut self, line: &str) {
+        self.idle.activity();
+        display::line_typed(line);
+        // Running a line again doesn't shift the numbers of the others.
+        match command::parse(line) {
+            Command::Again(_) if !self.composer.is_composing() => (),
+            _ => self.inputs.push(line),
+        }
+        self.handle_line(line);
+        display::restore_prompt();
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        // A purge is only confirmed by the line that immediately follows it.
+        let mut pending_purge = self.pending_purge.take();
+        // Likewise for a large message, which anything but yes cancels, in English or French.
+        if let Some(text) = self.pending_send.take() {
+            let answer = line.trim().to_lowercase();
+            if ["y", "yes", "o", "oui"].contains(&&answer[..]) {
+                self.send_text(text);
+            } else {
+                say!("* Not sent");
+            }
+            return;
+        }
+        if self.composer.is_composing() {
+            if let Some(text) = self.composer.feed(line) {
+                self.publish_text(text);
+            }
+            return;
+        }
+
+        for line in command::expand(line, &self.config.aliases, &self.config.macros) {
+            // A macro can start a multi-line message and continue with its lines.
+            if self.composer.is_composing() {
+                if let Some(text) = self.composer.feed(&line) {
+                    self.publish_text(text);
+                }
+            } else {
+                self.handle_command(&line, pending_purge.take());
+            }
+        }
+    }
+
+    fn handle_command(&mut self, line: &str, pending_purge: Option<Option<String>>) {
+        match command::parse(line) {
+            // While we share our terminal, what we type goes to its shell.
+            Command::Message(ref text) if self.screens.borrow().is_sharing() => {
+                self.screens.borrow_mut().type_line(text)
+            }
+            Command::Message(text) => self.publish_text(text),
+            Command::Me(action) => {
+                let action = self.redact(action);
+                let action = self.send_emoji(action);
+                self.publish(Kind::Action(action))
+            }
+            Command::Ban(peer) => self.moderate(&peer, true),
+            Command::Unban(peer) => self.moderate(&peer, false),
+            Command::Bans => {
+                for peer in self.moderation.banned(&self.naming.label(&self.room)) {
+                    say!("* banned: {}", peer.to_base58());
+                }
+            }
+            Command::Mentions => {
+                for line in self.mentions.iter() {
+                    display::reply(&format!("* {}", line));
+                }
+            }
+            Command::Multiline => {
+                say!(
+                    "* Composing a multi-line message; type `{}` on its own line to send it",
+                    compose::END_MARKER
+                );
+                self.composer.start();
+            }
+            Command::Emoji(enabled) => {
+                self.emoji_on_send = enabled;
+                say!(
+                    "* Emoji shortcodes are now {} in your messages",
+                    if enabled { tr!("expanded") } else { tr!("left as-is") }
+                );
+            }
+            Command::Poll { question, options } => {
+                if !envelope::poll_fits(&question, &options) {
+                    return say!(
+                        "* Polls have at most {} options, and {} characters per text",
+                        envelope::MAX_POLL_OPTIONS,
+                        envelope::MAX_POLL_TEXT_LEN
+                    );
+                }
+                let poll = Poll {
+                    question: question.clone(),
+                    options: options.clone(),
+                    author: self.nick.clone().unwrap_or_else(|| "you".to_owned()),
+                };
+                let body = self.new_body(Kind::Poll { question, options });
+                self.print_poll(body.id, &poll);
+                self.polls.add(body.id, poll);
+                self.publish_body(&body);
+            }
+            Command::Vote { poll, choice } => {
+                let id = match self.polls.find(&poll) {
+                    Some(id) => id,
+                    None => return say!("No such poll: {}", poll),
+                };
+                if choice > self.polls.get(id).map(|p| p.options.len()).unwrap_or(0) {
+                    return say!("This poll doesn't have an option {}", choice);
+                }
+                let public_key = self.identity.public_key().to_vec();
+                self.polls.vote(id, public_key, choice - 1);
+                self.publish(Kind::Vote { poll: id, choice: choice - 1 });
+            }
+            Command::PollResults(poll) => match self.polls.find(&poll) {
+                Some(id) => {
+                    display::reply(&format!("* {}", self.polls.get(id).unwrap().question));
+                    for (option, count) in self.polls.results(id).unwrap_or_default() {
+                        display::reply(&format!("*   {}: {}", option, count));
+                    }
+                }
+                None => say!("No such poll: {}", poll),
+            },
+            #[cfg(feature = "games")]
+            Command::Ttt(address) => self.dial_direct(&address, Protocol::Ttt),
+            #[cfg(not(feature = "games"))]
+            Command::Ttt(_) => say!("* Tic-tac-toe requires the games feature"),
+            #[cfg(feature = "calls")]
+            Command::Call(address) => self.dial_direct(&address, Protocol::Audio),
+            #[cfg(feature = "calls")]
+            Command::HangUp => self.calls.borrow_mut().hang_up(),
+            #[cfg(not(feature = "calls"))]
+            Command::Call(_) | Command::HangUp => say!("* Voice calls require the calls feature"),
+            Command::ShareScreen(address) => self.dial_direct(&address, Protocol::Screen),
+            Command::Unshare => self.screens.borrow_mut().unshare(),
+            Command::Schedule { delay, text } => {
+                let room = self.room.clone();
+                let text = self.redact(text);
+                let id = self.schedule.add(envelope::now() + delay.as_secs(), room, text);
+                say!(
+                    "* Scheduled message {} for {} from now",
+                    id,
+                    schedule::format_delay(delay.as_secs())
+                );
+            }
+            Command::Scheduled => {
+                let now = envelope::now();
+                let queue = self.schedule.queue();
+                let reminders = self.schedule.reminders();
+                if queue.is_empty() && reminders.is_empty() {
+                    say!("* No message is scheduled");
+                }
+                for scheduled in queue {
+                    let left = schedule::format_delay(scheduled.at.saturating_sub(now));
+                    say!(
+                        "* {}. in {}, in {}: {}",
+                        scheduled.id,
+                        left,
+                        scheduled.room,
+                        scheduled.text
+                    );
+                }
+                for reminder in reminders {
+                    if reminder.sent.is_some() {
+                        say!(
+                            "* {}. for {}, waiting for them to acknowledge it: {}",
+                            reminder.id,
+                            reminder.to,
+                            reminder.text
+                        );
+                    } else {
+                        let left = schedule::format_delay(reminder.at.saturating_sub(now));
+                        say!(
+                            "* {}. for {}, in {} at the earliest: {}",
+                            reminder.id,
+                            reminder.to,
+                            left,
+                            reminder.text
+                        );
+                    }
+                }
+            }
+            Command::CancelScheduled(id) => {
+                if self.schedule.cancel(id) {
+                    say!("* Cancelled scheduled message {}", id);
+                } else {
+                    say!("* There is no scheduled message {}", id);
+                }
+            }
+            Command::Report { n, reason } => self.report(n, reason),
+            Command::Undo => self.undo(),
+            Command::Conn(peer) => self.print_connections(&peer),
+            Command::Reports => {
+                if self.reports.iter().next().is_none() {
+                    say!("* No report yet");
+                }
+                let own = identity::encode_key(self.identity.public_key());
+                for signed in self.reports.iter() {
+                    let report = &signed.report;
+                    let reporter = if signed.reporter == own {
+                        tr!("you")
+                    } else {
+                        signed.reporter.clone()
+                    };
+                    say!(
+                        "* By {}, about {} in {}: {} ({})",
+                        reporter,
+                        report.author,
+                        self.room_of_label(report.room.clone()),
+                        report.text,
+                        report.reason
+                    );
+                }
+            }
+            Command::Remind { to, delay, text } => {
+                let text = self.redact(text);
+                let id = self.schedule.remind(envelope::now() + delay.as_secs(), to.clone(), text);
+                say!(
+                    "* Reminder {} for {} is due in {}, and will be delivered once they are around",
+                    id,
+                    to,
+                    schedule::format_delay(delay.as_secs())
+                );
+            }
+            #[cfg(feature = "games")]
+            Command::Move(cell) => self.games.borrow_mut().play(cell),
+            #[cfg(feature = "games")]
+            Command::Resign => self.games.borrow_mut().resign(),
+            #[cfg(not(feature = "games"))]
+            Command::Move(_) | Command::Resign => say!("* Tic-tac-toe requires the games feature"),
+            #[cfg(feature = "pads")]
+            Command::Pad { name, action } => self.handle_pad_command(name, action),
+            #[cfg(not(feature = "pads"))]
+            Command::Pad { .. } => say!("* Notepads require the pads feature"),
+            #[cfg(feature = "dht")]
+            Command::Put { key, value } => {
+                if value.len() > kv::MAX_VALUE_LEN {
+                    return say!("* Values are limited to {} bytes", kv::MAX_VALUE_LEN);
+                }
+                if self.kv.put(key.clone(), value.clone()) {
+                    say!("* Stored {} locally, as we are one of the closest nodes", key);
+                }
+                self.publish_on_kv(Kind::KvPut { key, value });
+            }
+            #[cfg(feature = "dht")]
+            Command::Get(key) => {
+                let local = self.kv.get(&key).cloned();
+                match local {
+                    Some(value) => say!("* {} = {} (stored locally)", key, value),
+                    None => {
+                        let request = ::rand::random();
+                        self.kv.add_pending(request, key.clone());
+                        self.publish_on_kv(Kind::KvGet { key, request });
+                    }
+                }
+            }
+            #[cfg(not(feature = "dht"))]
+            Command::Put { .. } | Command::Get(_) => {
+                say!("* The key-value store requires the dht feature")
+            }
+            Command::Who => {
+                for (peer, info) in self.presence.roster() {
+                    let addresses: Vec<_> = info.addresses.iter().map(|a| a.to_string()).collect();
+                    let reachable = if addresses.is_empty() {
+                        String::new()
+                    } else {
+                        format!(", reachable at {}", addresses.join(" "))
+                    };
+                    // Within a couple of seconds, the offset is only the resolution of the
+                    // timestamps.
+                    let clock = match self.clocks.offset(peer) {
+                        Some(offset) if offset.abs() > 2 => format!(", clock {:+}s off", offset),
+                        _ => String::new(),
+                    };
+                    match info.nick {
+                        Some(ref nick) => display::reply(&format!(
+                            "* {} ({}){}{}",
+                            nick,
+                            peer.to_base58(),
+                            reachable,
+                            clock
+                        )),
+                        None => {
+                            display::reply(&format!("* {}{}{}", peer.to_base58(), reachable, clock))
+                        }
+                    }
+                }
+            }
+            Command::Topics => {
+                // We don't receive our own advertisements, so we count ourselves in our rooms.
+                let rooms = self.directory.rooms();
+                let ours = |room: &str| self.rooms.iter().any(|&(ref r, _)| r == room);
+                for &(ref room, _) in &self.rooms {
+                    if !rooms.iter().any(|&(r, _)| r == room) {
+                        say!("* {} (1 member)", room);
+                    }
+                }
+                for (room, members) in rooms {
+                    let members = if ours(room) { members + 1 } else { members };
+                    let plural = if members == 1 { "" } else { "s" };
+                    say!("* {} ({} member{})", room, members, plural);
+                }
+            }
+            Command::Watch(None) => {
+                if self.watches.patterns().next().is_none() {
+                    return say!("* You aren't watching anything");
+                }
+                for pattern in self.watches.patterns() {
+                    say!("* watch: {}", pattern);
+                }
+            }
+            Command::Watch(Some(pattern)) => match self.watches.add(&pattern) {
+                Ok(true) => {
+                    say!("* Messages matching {} will be highlighted in every room", pattern);
+                    self.save_config();
+                }
+                Ok(false) => say!("* Already watching {}", pattern),
+                Err(err) => say!("* Invalid pattern: {}", err),
+            },
+            Command::Unwatch(pattern) => {
+                if self.watches.remove(&pattern) {
+                    say!("* Stopped watching {}", pattern);
+                    self.save_config();
+                } else {
+                    say!("* You aren't watching {}", pattern);
+                }
+            }
+            Command::Filter(FilterAction::List) => {
+                for pattern in self.filter.patterns() {
+                    say!("* filter: {}", pattern);
+                }
+            }
+            Command::Filter(FilterAction::Add(pattern)) => match self.filter.add(&pattern) {
+                Ok(()) => say!("* Messages matching {} will be dropped", pattern),
+                Err(err) => say!("* Invalid pattern: {}", err),
+            },
+            Command::Filter(FilterAction::Remove(pattern)) => {
+                if !self.filter.remove(&pattern) {
+                    say!("* There is no filter {}", pattern);
+                }
+            }
+            Command::Join(room) => {
+                self.join(room);
+                self.save_config();
+            }
+            Command::Switch(room) => {
+                if self.rooms.iter().any(|&(ref r, _)| *r == room) {
+                    self.switch(Some(room));
+                    self.save_config();
+                } else {
+                    say!("* You aren't in {}; use `/join {}`", room, room);
+                }
+            }
+            Command::Leave => {
+                self.leave();
+                self.save_config();
+            }
+            Command::Connections => {
+                for connection in self.peers.borrow().iter() {
+                    let direction = match connection.endpoint {
+                        Endpoint::Dialer => tr!("outbound"),
+                        Endpoint::Listener => tr!("inbound"),
+                    };
+                    say!(
+                        "* {} ({}, {}, open for {}s)",
+                        connection.address,
+                        direction,
+                        format!("{:?}", connection.protocol),
+                        connection.age().as_secs()
+                    );
+                }
+            }
+            Command::Purge(target) => {
+                let room = match target {
+                    PurgeTarget::CurrentRoom => Some(self.room.clone()),
+                    PurgeTarget::Room(room) => Some(room),
+                    PurgeTarget::All => None,
+                };
+                if pending_purge.as_ref() == Some(&room) {
+                    return self.purge(room.as_ref().map(|room| room.as_str()));
+                }
+                match room {
+                    Some(ref room) => say!("* This forgets the messages and data of {}", room),
+                    None => say!(
+                        "* This forgets everything and deletes the files we keep, the identity \
+                         included"
+                    ),
+                }
+                say!("* Type the same command again to confirm");
+                self.pending_purge = Some(room);
+            }
+            Command::Notify(None) => say!(
+                "* Notifications in {}: {}",
+                self.room,
+                self.notifier.level(&self.room).name()
+            ),
+            Command::Notify(Some(level)) => {
+                self.notifier.set_level(self.room.clone(), level);
+                say!("* Notifications in {}: {}", self.room, level.name());
+                self.save_config();
+            }
+            Command::Paste => match clipboard::get() {
+                Ok(ref text) if text.trim().is_empty() => say!("* The clipboard is empty"),
+                Ok(text) => self.publish_text(text),
+                Err(err) => say!("* Can't read the clipboard: {}", err),
+            },
+            Command::Copy(n) => {
+                let text = match self.history.get(n) {
+                    Some(entry) if entry.deleted => return say!("* That message was deleted"),
+                    Some(entry) => entry.text.clone(),
+                    None => return say!("* Only {} messages to copy from", self.history.len()),
+                };
+                match clipboard::set(text) {
+                    Ok(()) => say!("* Copied to the clipboard"),
+                    Err(err) => say!("* Can't write to the clipboard: {}", err),
+                }
+            }
+            Command::History => {
+                let skip = self.history.len().saturating_sub(HISTORY_LINES);
+                for (n, entry) in self.history.iter().skip(skip) {
+                    if self.rooms.len() > 1 {
+                        display::reply(&format!("* {}. [{}] {}", n, entry.room, entry.render()));
+                    } else {
+                        display::reply(&format!("* {}. {}", n, entry.render()));
+                    }
+                }
+            }
+            Command::HistoryMore => {
+                let topic = match self.rooms.iter().find(|&&(ref room, _)| *room == self.room) {
+                    Some(&(_, ref topic)) => topic.clone(),
+                    None => return say!("* You aren't in any room; use `/join <room>`"),
+                };
+                let request = ::rand::random();
+                let before = self.backfill.cursor(&self.room, &self.history);
+                self.backfill.add_pending(request, self.room.clone());
+                let body = self.new_body(Kind::HistoryQuery {
+                    request,
+                    before,
+                    limit: backfill::PAGE_SIZE,
+                });
+                if self.send(&topic, &body) {
+                    say!("* Asking {} for older messages", self.room);
+                }
+            }
+            Command::Verify(delay) => {
+                let topic = match self.rooms.iter().find(|&&(ref room, _)| *room == self.room) {
+                    Some(&(_, ref topic)) => topic.clone(),
+                    None => return say!("* You aren't in any room; use `/join <room>`"),
+                };
+                let range = match delay {
+                    Some(delay) => {
+                        let now = envelope::now();
+                        Range {
+                            from: now.saturating_sub(delay.as_secs()),
+                            to: now + 1,
+                        }
+                    }
+                    None => match transcript::everything(&self.history, &self.room) {
+                        Some(range) => range,
+                        None => return say!("* There are no messages of {} to verify", self.room),
+                    },
+                };
+                self.verification = Some(Verification::new(self.room.clone()));
+                self.send_verify_query(&topic, None, vec![range]);
+                say!("* Comparing our history of {} with the room", self.room);
+            }
+            Command::Reply { n, text } => {
+                let parent = match self.history_entry(n) {
+                    Some(entry) => entry.id,
+                    None => return,
+                };
+                // Replies are never batched, since a batch has a single `reply_to`.
+                let text = self.redact(text);
+                let text = self.send_emoji(text);
+                let mut body = self.new_body(Kind::Text(text));
+                body.reply_to = Some(parent);
+                if self.publish_body(&body) {
+                    self.remember_sent(&body);
+                }
+            }
+            Command::React { n, reaction } => {
+                let reaction = self.send_emoji(reaction);
+                if reaction.chars().count() > history::MAX_REACTION_LEN {
+                    let max = history::MAX_REACTION_LEN;
+                    return say!("* Reactions are at most {} characters long", max);
+                }
+                let message = match self.history_entry(n) {
+                    Some(entry) if entry.room != self.room => {
+                        let room = &entry.room;
+                        return say!("* That message is in {0}; use `/switch {0}` first", room);
+                    }
+                    Some(entry) => entry.id,
+                    None => return,
+                };
+                let own = self.identity.peer_id().clone();
+                self.history.react(message, &own, reaction.clone());
+                self.publish(Kind::React { message, reaction });
+            }
+            Command::Pin(n) => {
+                let (message, text) = match self.history_entry(n) {
+                    Some(entry) if entry.deleted => return say!("* That message was deleted"),
+                    Some(entry) if entry.room != self.room => {
+                        let room = &entry.room;
+                        return say!("* That message is in {0}; use `/switch {0}` first", room);
+                    }
+                    Some(entry) => (entry.id, format!("{}: {}", entry.name, entry.text)),
+                    None => return,
+                };
+                let pin = Pin {
+                    message,
+                    text: text.clone(),
+                    pinner: self.identity.peer_id().clone(),
+                    pinner_name: "you".to_owned(),
+                };
+                if !self.pins.pin(&self.room, pin) {
+                    return say!("* That message is already pinned");
+                }
+                let room = self.naming.label(&self.room);
+                self.publish(Kind::Pin {
+                    room,
+                    message,
+                    text,
+                });
+            }
+            Command::Unpin(n) => {
+                let message = n
+                    .checked_sub(1)
+                    .and_then(|i| self.pins.get(&self.room).get(i))
+                    .map(|pin| pin.message);
+                let message = match message {
+                    Some(message) => message,
+                    None => return say!("* There is no pin {} in {}", n, self.room),
+                };
+                let own = self.identity.peer_id().clone();
+                if self.pins.unpin(&self.room, message, &own).is_none() {
+                    return say!("* Only the peer who pinned a message can unpin it");
+                }
+                let room = self.naming.label(&self.room);
+                self.publish(Kind::Unpin { room, message });
+            }
+            Command::Pins => {
+                for (n, pin) in self.pins.get(&self.room).iter().enumerate() {
+                    say!("* {}. {} (pinned by {})", n + 1, pin.text, pin.pinner_name);
+                }
+            }
+            Command::RoomInfo => {
+                match self.metadata.description(&self.room) {
+                    Some(description) => {
+                        let author = identity::parse_peer_id(&description.author)
+                            .map(|peer| self.short_ids.get(&peer))
+                            .unwrap_or_else(|| description.author.clone());
+                        say!("* {}: {} (set by {})", self.room, description.text, author);
+                    }
+                    None => say!(
+                        "* {} has no description; set one with `/room describe`",
+                        self.room
+                    ),
+                }
+                let members: Vec<String> = self
+                    .metadata
+                    .members(&self.room)
+                    .into_iter()
+                    .map(|peer| self.short_ids.get(peer))
+                    .collect();
+                say!("* Recently seen members: {}", members.join(", "));
+            }
+            Command::Describe(text) => {
+                if text.chars().count() > metadata::MAX_DESCRIPTION_LEN {
+                    let max = metadata::MAX_DESCRIPTION_LEN;
+                    return say!("* Descriptions are at most {} characters long", max);
+                }
+                let description = Description {
+                    text,
+                    author: self.identity.peer_id().to_base58(),
+                    timestamp: envelope::now(),
+                };
+                let room = self.room.clone();
+                self.metadata.describe(&room, description);
+                self.publish_room_state();
+            }
+            Command::Thread(n) => {
+                if self.history_entry(n).is_none() {
+                    return;
+                }
+                for (depth, entry) in self.history.thread(n) {
+                    display::reply(&format!("* {}{}", "  ".repeat(depth), entry.render()));
+                }
+            }
+            Command::Edit { n, text } => {
+                if let Some(id) = self.own_message(n) {
+                    let text = self.redact(text);
+                    let text = self.send_emoji(text);
+                    let own = self.identity.peer_id().clone();
+                    self.history.edit(id, &own, text.clone());
+                    self.publish(Kind::Edit { message: id, text });
+                }
+            }
+            Command::Delete(n) => {
+                if let Some(id) = self.own_message(n) {
+                    let own = self.identity.peer_id().clone();
+                    self.history.delete(id, &own);
+                    self.publish(Kind::Delete { message: id });
+                }
+            }
+            Command::Identities => {
+                say!("* You are {}", self.personas.current());
+                for persona in self.personas.parked() {
+                    say!(
+                        "* {}: {}",
+                        persona.name,
+                        identity::encode_key(persona.identity.public_key())
+                    );
+                }
+                if self.personas.parked().is_empty() {
+                    say!("* Pass --persona <name> to play other identities");
+                }
+            }
+            Command::UseIdentity(name) => self.use_persona(&name),
+            Command::Rotate => self.rotate_key(),
+            Command::Chaos(enabled) => {
+                self.chaos.set_enabled(enabled);
+                if enabled {
+                    say!("* Chaos is on: connections drop, messages are delayed and duplicated");
+                } else {
+                    say!("* Chaos is off");
+                }
+            }
+            Command::Version => {
+                say!("* You: {}", version::agent());
+                let own = self.identity.peer_id();
+                for (peer, version) in self.versions.iter().filter(|&(peer, _)| peer != own) {
+                    let version = version.as_ref().map(|v| v.as_str()).unwrap_or("unknown");
+                    say!("* {}: protocol {}", self.short_ids.get(peer), version);
+                }
+            }
+            Command::Stats => {
+                say!(
+                    "* Outbox: {}/{} messages queued, {} dropped",
+                    self.outbox.len(),
+                    self.outbox.capacity(),
+                    self.outbox.dropped()
+                );
+                let (queued, sent, dropped) = self.outbox.direct();
+                say!(
+                    "* Direct messages: {}/{} queued, {} sent, {} dropped",
+                    queued,
+                    outbox::DIRECT_CAPACITY,
+                    sent,
+                    dropped
+                );
+                say!("* Open connections: {}", self.peers.borrow().iter().count());
+                say!("* Peers seen recently: {}", self.presence.alive().count());
+                #[cfg(feature = "dht")]
+                say!("* Key-value records stored here: {}", self.kv.len());
+                for line in self.relays.describe() {
+                    say!("* {}", line);
+                }
+            }
+            Command::Usage => {
+                say!(
+                    "* Sockets: {} sent, {} received",
+                    format_bytes(self.counters.sent()),
+                    format_bytes(self.counters.received())
+                );
+                for (room, &(received, sent)) in self.usage.rooms() {
+                    say!(
+                        "* Room {}: {} messages ({}) received, {} messages ({}) sent",
+                        room,
+                        received.messages,
+                        format_bytes(received.bytes),
+                        sent.messages,
+                        format_bytes(sent.bytes)
+                    );
+                }
+                for (peer, tally) in self.usage.peers().into_iter().take(MAX_USAGE_PEERS) {
+                    say!(
+                        "* From {}: {} messages ({})",
+                        self.short_ids.get(peer),
+                        tally.messages,
+                        format_bytes(tally.bytes)
+                    );
+                }
+                for (hour, tally) in self.usage.hours().iter().enumerate() {
+                    say!(
+                        "* Hour {}: {} messages ({})",
+                        hour + 1,
+                        tally.messages,
+                        format_bytes(tally.bytes)
+                    );
+                }
+            }
+            Command::Graph => display::reply(self.graph().trim_right()),
+            Command::Reload => self.reload(),
+            Command::Export { path, room, range } => {
+                let room = room.unwrap_or_else(|| self.room.clone());
+                let in_range = |n: usize| match range {
+                    Some((from, to)) => n >= from.min(to) && n <= from.max(to),
+                    None => true,
+                };
+                let entries: Vec<&Entry> = self
+                    .history
+                    .iter()
+                    .filter(|&(n, entry)| entry.room == room && in_range(n))
+                    .map(|(_, entry)| entry)
+                    .collect();
+                let format = match export::Format::from_path(&path) {
+                    Some(format) => format,
+                    None => return say!("* Export to a .md or a .html file"),
+                };
+                let document = export::render(&room, &entries, &self.history, format);
+                match fs::write(&path, document) {
+                    Ok(()) => say!("* Exported {} messages of {} to {}", entries.len(), room, path),
+                    Err(err) => say!("* Can't write {}: {}", path, err),
+                }
+            }
+            Command::Recent => {
+                for (n, line) in self.inputs.recent(HISTORY_LINES) {
+                    display::reply(&format!("* {}. {}", n, line));
+                }
+            }
+            Command::Again(n) => match self.inputs.get(n).map(|line| line.to_owned()) {
+                Some(line) => self.handle_line(&line),
+                None => say!("* There is no line {} in the input history", n),
+            },
+            Command::Forgive(name) => {
+                let peer = self
+                    .scores
+                    .iter()
+                    .into_iter()
+                    .map(|(peer, _)| peer.clone())
+                    .find(|peer| self.short_ids.get(peer) == name || peer.to_base58() == name)
+                    .or_else(|| self.find_around(&name));
+                match peer {
+                    Some(ref peer) if self.scores.is_scored(peer) => {
+                        self.scores.forgive(peer);
+                        say!("* Forgave {}", self.short_ids.get(peer));
+                    }
+                    _ => say!("* {} has no score to forgive", name),
+                }
+            }
+            Command::Broadcast { rooms, text } => self.broadcast(&rooms, text),
+            Command::Drain(None) => self.drain(None),
+            Command::Drain(Some(address)) => match addresses::parse(&address) {
+                Ok(address) => self.drain(Some(address)),
+                Err(invalid) => say!("* Can't drain to {}: {}", address, invalid),
+            },
+            Command::Scores => {
+                for (peer, score) in self.scores.iter() {
+                    say!(
+                        "* {}: {} ({} duplicates, {} invalid signatures, {} over the rate limit)",
+                        self.short_ids.get(peer),
+                        score.value,
+                        score.duplicates,
+                        score.invalid,
+                        score.flooded
+                    );
+                }
+            }
+            Command::Help(None) => {
+                for spec in command::COMMANDS {
+                    display::reply(&format!("* {:<24} {}", spec.usage(), tr!(spec.description)));
+                }
+            }
+            Command::Help(Some(name)) => match command::find(&name) {
+                Some(spec) => {
+                    say!("* Usage: {}", spec.usage());
+                    display::reply(&format!("* {}", tr!(spec.description)));
+                    if !spec.aliases.is_empty() {
+                        say!("* Aliases: /{}", spec.aliases.join(", /"));
+                    }
+                }
+                None => self.unknown_command(&name),
+            },
+            Command::Invalid(line) => {
+                let name = line[1..].split_whitespace().next().unwrap_or("");
+                match command::find(name) {
+                    Some(spec) => say!("* Usage: {}", spec.usage()),
+                    None => self.unknown_command(name),
+                }
+            }
+        }
+    }
+
+    fn unknown_command(&self, name: &str) {
+        match command::closest(name) {
+            Some(closest) => say!("* Unknown command /{}, did you mean /{}?", name, closest),
+            None => say!("* Unknown command /{}, type /help for the list", name),
+        }
+    }
+
+    /// Joins `room`, if we aren't in it yet, and makes it the current room.
+    fn join(&mut self, room: String) {
+        if !self.rooms.iter().any(|&(ref r, _)| *r == room) {
+            let topic = self.naming.topic(&room);
+            self.floodsub.subscribe(&topic);
+            if let Some(legacy) = migration::legacy_topic(&self.naming, &room) {
+                self.floodsub.subscribe(&legacy);
+            }
+            self.rooms.push((room.clone(), topic));
+            say!("* Joined {}", room);
+        }
+        self.switch(Some(room));
+    }
+
+    /// Unsubscribes from the topics of the current room, including its pads, and switches to
+    /// one of the remaining rooms.
+    ///
+    /// Unsubscribing sends an announcement to our peers, so that they stop forwarding us the
+    /// messages of these topics.
+    fn leave(&mut self) {
+        let position = match self.rooms.iter().position(|&(ref r, _)| *r == self.room) {
+            Some(position) => position,
+            None => return say!("* You aren't in any room"),
+        };
+        let (room, topic) = self.rooms.remove(position);
+        self.floodsub.unsubscribe(&topic);
+        if let Some(legacy) = migration::legacy_topic(&self.naming, &room) {
+            self.floodsub.unsubscribe(&legacy);
+        }
+        #[cfg(feature = "pads")]
+        self.close_pads(Some(&room));
+        self.unread.remove(&room);
+        say!("* Left {}", room);
+
+        let next = self.rooms.first().map(|&(ref room, _)| room.clone());
+        self.switch(next);
+    }
+
+    /// Saves where we are in the session of our profile. See `config::Session`.
+    fn save_config(&self) {
+        let path = match self.config_file {
+            Some(ref path) => path,
+            None => return,
+        };
+        // The file is read again so that we keep what was edited by hand, such as the profiles.
+        let mut config = match Config::load(path) {
+            Ok(config) => config,
+            Err(err) => {
+                say!("* Not saving the configuration, {} is invalid: {}", path, err);
+                return;
+            }
+        };
+        {
+            let session = config
+                .sessions
+                .entry(self.profile.clone())
+                .or_insert_with(Session::default);
+            // A room set back to the default must not get the level of `notify` again.
+            let mut notify = self.notifier.levels().clone();
+            for room in config.notify.keys() {
+                notify.entry(room.clone()).or_insert(Level::Mentions);
+            }
+            session.notify = notify;
+            // The rooms and the nickname of the other personas aren't restored.
+            if self.personas.current() == personas::MAIN {
+                let mut rooms: Vec<String> =
+                    self.rooms.iter().map(|&(ref room, _)| room.clone()).collect();
+                if let Some(position) = rooms.iter().position(|room| *room == self.room) {
+                    let current = rooms.remove(position);
+                    rooms.insert(0, current);
+                }
+                session.rooms = rooms;
+                session.nick = self.nick.clone();
+            }
+            session.watches = self.watches.patterns().map(|p| p.to_owned()).collect();
+        }
+        if let Err(err) = config.save(path) {
+            say!("* Can't save the configuration to {}: {}", path, err);
+        }
+    }
+
+    /// Leaves the notepads of `room`, or all of them if `None`.
+    #[cfg(feature = "pads")]
+    fn close_pads(&mut self, room: Option<&str>) {
+        let pads: Vec<String> = self
+            .pads
+            .iter()
+            .filter(|&(name, &(ref topic, _))| match room {
+                Some(room) => {
+                    topic.hash() == self.naming.topic(&format!("{}/pad/{}", room, name)).hash()
+                }
+                None => true,
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in pads {
+            if let Some((topic, _)) = self.pads.remove(&name) {
+                self.floodsub.unsubscribe(&topic);
+            }
+        }
+    }
+
+    /// Forgets what we know locally about `room`, or about everything if `None`, on disk too. See
+    /// the `purge` module.
+    fn purge(&mut self, room: Option<&str>) {
+        self.history.purge(room);
+        self.backfill.purge(room);
+        self.pins.purge(room);
+        self.metadata.purge(room);
+        self.mentions.purge(room);
+        #[cfg(feature = "pads")]
+        self.close_pads(room);
+        #[cfg(feature = "dht")]
+        {
+            let in_room = |r: &str| room.map(|room| room == r).unwrap_or(true);
+            if in_room(&self.room) {
+                self.kv = KvStore::new(self.identity.peer_id());
+            }
+        }
+        let stores = purge::Stores {
+            inputs: &mut self.inputs,
+            schedule: &mut self.schedule,
+            reports: &mut self.reports,
+            scores: &mut self.scores,
+            known_keys: &mut self.known_keys,
+            moderation: &mut self.moderation,
+        };
+        for err in stores.purge(room, &self.naming) {
+            say!("* Can't delete {}", err);
+        }
+        self.update_screening();
+        if let Some(room) = room {
+            return say!("* Purged the local data of {}", room);
+        }
+
+        self.versions.clear();
+        self.polls = Polls::new();
+        self.directory = Directory::new(self.presence.timeout());
+        for bot in self.plugins.purge() {
+            say!("* The bot {} doesn't keep up, so it still has its values", bot);
+        }
+        self.purge_sessions();
+        let mut files = self.personas.take_files();
+        files.extend(self.identity_file.take());
+        for path in files {
+            match identity::remove(&path) {
+                Ok(()) => say!("* Deleted {}; your identity only lives until you quit", path),
+                Err(err) => say!("* Can't delete {}: {}", path, err),
+            }
+        }
+        say!("* Purged all the local data");
+    }
+
+    /// Removes the sessions of every profile from the configuration file, which keeps the rest.
+    fn purge_sessions(&self) {
+        let path = match self.config_file {
+            Some(ref path) => path,
+            None => return,
+        };
+        let mut config = match Config::load(path) {
+            Ok(config) => config,
+            Err(err) => return say!("* Can't remove the sessions from {}: {}", path, err),
+        };
+        if config.sessions.is_empty() {
+            return;
+        }
+        config.sessions.clear();
+        if let Err(err) = config.save(path) {
+            say!("* Can't remove the sessions from {}: {}", path, err);
+        }
+    }
+
+    /// Makes the persona called `name` the current one: its key signs what we publish, and we
+    /// are only subscribed to its rooms. See the `personas` module.
+    fn use_persona(&mut self, name: &str) {
+        if name == self.personas.current() {
+            return say!("* You are already {}", name);
+        }
+        let previous = self.personas.current().to_owned();
+        let mut persona = match self.personas.take(name) {
+            Some(persona) => persona,
+            None => return say!("* There is no persona named {}; see /identity", name),
+        };
+
+        let in_rooms = |rooms: &[(String, Topic)], topic: &Topic| {
+            rooms.iter().any(|&(_, ref t)| t.hash() == topic.hash())
+        };
+        for &(_, ref topic) in &self.rooms {
+            if !in_rooms(&persona.rooms, topic) {
+                self.floodsub.unsubscribe(topic);
+            }
+        }
+        for &(_, ref topic) in &persona.rooms {
+            if !in_rooms(&self.rooms, topic) {
+                self.floodsub.subscribe(topic);
+            }
+        }
+        #[cfg(feature = "pads")]
+        self.close_pads(None);
+
+        mem::swap(&mut self.identity, &mut persona.identity);
+        mem::swap(&mut self.identity_file, &mut persona.file);
+        mem::swap(&mut self.nick, &mut persona.nick);
+        mem::swap(&mut self.rooms, &mut persona.rooms);
+        let room = mem::replace(&mut persona.room, self.room.clone());
+        persona.name = previous;
+        self.personas.park(persona);
+        say!(
+            "* You are now {}, whose public key is {}",
+            name,
+            identity::encode_key(self.identity.public_key())
+        );
+
+        // The key-value store and the election depend on our identity, so they start over even
+        // if the room doesn't change.
+        self.room.clear();
+        let room = if self.rooms.iter().any(|&(ref r, _)| *r == room) {
+            Some(room)
+        } else {
+            self.rooms.first().map(|&(ref room, _)| room.clone())
+        };
+        self.switch(room);
+    }
+
+    /// Makes `room`, which we must be in, the current room. The key-value store and the election
+    /// of a coordinator follow the current room. `None` if we aren't in any room anymore.
+    fn switch(&mut self, room: Option<String>) {
+        if let Some(topic) = self.kv_topic.take() {
+            self.floodsub.unsubscribe(&topic);
+        }
+        let room = match room {
+            Some(room) => room,
+            None => {
+                display::set_prompt(None);
+                return say!("* You aren't in any room anymore; use `/join <room>`");
+            }
+        };
+
+        #[cfg(feature = "dht")]
+        {
+            let kv_topic = self.naming.topic(&format!("{}/kv", room));
+            self.floodsub.subscribe(&kv_topic);
+            self.kv_topic = Some(kv_topic);
+        }
+        if room != self.room {
+            #[cfg(feature = "dht")]
+            {
+                self.kv = KvStore::new(self.identity.peer_id());
+            }
+            if self.election.is_some() {
+                self.election = Some(Election::new(self.identity.peer_id().clone()));
+            }
+        }
+        if self.rooms.len() > 1 {
+            say!("* Now talking in {}", room);
+            display::set_prompt(Some(room.clone()));
+        } else {
+            display::set_prompt(None);
+        }
+        self.unread.remove(&room);
+        self.room = room;
+        self.refresh_status();
+    }
+
+    fn moderate(&mut self, peer: &str, ban: bool) {
+        if !self.moderation.is_moderator(self.identity.public_key()) {
+            say!("Only the moderators of the room can do that");
+            return;
+        }
+        let peer_id = match identity::parse_peer_id(peer) {
+            Some(peer_id) => peer_id,
+            None => {
+                say!("Not a valid peer ID: {}", peer);
+                return;
+            }
+        };
+
+        // Floodsub doesn't send our own messages back to us, so we apply the entry locally.
+        let entry = BanEntry {
+            room: self.naming.label(&self.room),
+            peer: peer_id.to_base58(),
+            banned: ban,
+            timestamp: envelope::now(),
+        };
+        let signed = SignedEntry::sign(entry.clone(), &self.identity);
+        self.moderation.apply(signed.clone());
+        self.update_screening();
+        self.publish(Kind::Bans {
+            room: entry.room,
+            entries: vec![signed],
+        });
+    }
+
+    /// Masks the secrets of a text we are about to send, and says which ones were masked.
+    fn redact(&self, text: String) -> String {
+        let (text, masked) = self.redactor.redact(text);
+        if !masked.is_empty() {
+            say!("* Masked in your message: {}", masked.join(", "));
+        }
+        text
+    }
+
+    fn send_emoji(&self, text: String) -> String {
+        if self.emoji_on_send {
+            emoji::expand(&text)
+        } else {
+            text
+        }
+    }
+
+    /// Publishes a text that we wrote. In a terminal, a large one is only sent once the next
+    /// line confirms it, in case it was pasted by mistake.
+    fn publish_text(&mut self, text: String) {
+        let lines = text.lines().count();
+        let too_many = self.confirm_lines > 0 && lines > self.confirm_lines;
+        let too_large = self.confirm_bytes > 0 && text.len() > self.confirm_bytes;
+        if (too_many || too_large) && platform::is_terminal() {
+            say!(
+                "* Send {} lines ({}) to {}? [y/N]",
+                lines,
+                format_bytes(text.len() as u64),
+                self.room
+            );
+            self.pending_send = Some(text);
+            return;
+        }
+        self.send_text(text)
+    }
+
+    fn send_text(&mut self, text: String) {
+        let text = self.redact(text);
+        let text = self.send_emoji(text);
+        // Half of the limit leaves room for the escaping and the signature, as for the pages of
+        // the history.
+        let parts = compose::split(&text, self.max_message_size / 2);
+        if parts.len() > 1 {
+            say!(
+                "* The message is too large for a single envelope; sending it in {} parts",
+                parts.len()
+            );
+            // Together, the parts wouldn't fit in a batch either.
+            self.flush_batch();
+            for part in parts {
+                self.publish(Kind::Text(part));
+            }
+            return;
+        }
+        let full = match self.batcher {
+            Some(ref mut batcher) => batcher.push(text),
+            None => return self.publish(Kind::Text(text)),
+        };
+        if let Some(lines) = full {
+            self.publish_batch(lines);
+        }
+    }
+
+    /// Publishes the lines waiting in the batch. Called every `--batch-delay`.
+    pub fn flush_batch(&mut self) {
+        let lines = match self.batcher {
+            Some(ref mut batcher) => batcher.take(),
+            None => return,
+        };
+        self.publish_batch(lines);
+    }
+
+    fn publish_batch(&mut self, mut lines: Vec<String>) {
+        match lines.len() {
+            0 => {}
+            1 => self.publish(Kind::Text(lines.remove(0))),
+            _ => self.publish(Kind::Batch(lines)),
+        }
+    }
+
+    fn publish(&mut self, kind: Kind) {
+        let body = self.new_body(kind);
+        if self.publish_body(&body) {
+            self.remember_sent(&body);
+        }
+    }
+
+    /// Returns the `n`th message of the history, or explains that there isn't one.
+    fn history_entry(&self, n: usize) -> Option<&Entry> {
+        let entry = self.history.get(n);
+        if entry.is_none() {
+            say!("* There are only {} messages in the history", self.history.len());
+        }
+        entry
+    }
+
+    /// Returns the ID of the `n`th message of the history if we can edit or delete it. Otherwise
+    /// explains why not.
+    fn own_message(&self, n: usize) -> Option<u64> {
+        let entry = self.history_entry(n)?;
+        if entry.author != *self.identity.peer_id() {
+            say!("* You can only change your own messages");
+            return None;
+        }
+        if entry.deleted {
+            say!("* That message was deleted");
+            return None;
+        }
+        // Changes are published in the current room, which is where the others will look.
+        if entry.room != self.room {
+            say!("* That message is in {}; use `/switch {}` first", entry.room, entry.room);
+            return None;
+        }
+        Some(entry.id)
+    }
+
+    /// Dials `address` for one of the direct protocols, unless we know that the peer there
+    /// doesn't support it. See the `capabilities` module.
+    fn dial_direct(&self, address: &str, protocol: Protocol) {
+        let address = match addresses::parse(address) {
+            Ok(address) => address,
+            Err(err) => return say!("Not a valid multiaddress: {}", err),
+        };
+        // What we learned at another address of the same peer is just as true.
+        let owner = self
+            .presence
+            .roster()
+            .find(|&(_, info)| info.addresses.contains(&address));
+        let (name, addresses) = match owner {
+            Some((peer, info)) => {
+                let name = info.nick.clone().unwrap_or_else(|| peer.to_base58());
+                if self.scores.is_ignored(peer) {
+                    return say!("* Not dialing {}, who is ignored; see /forgive", name);
+                }
+                (name, info.addresses.clone())
+            }
+            None => (address.to_string(), vec![address.clone()]),
+        };
+        if self.capabilities.supports(&addresses, protocol) == Some(false) {
+            return say!("* {} doesn't support {}", name, tr!(protocol.describe()));
+        }
+        let _ = self.dial.unbounded_send(DialRequest { address, protocol });
+    }
+
+    /// Prints the layers of the connections to `peer`, and how long each of them took. See the
+    /// `stack` module.
+    fn print_connections(&self, peer: &str) {
+        // The advertised addresses of a peer are the ones we dial.
+        let advertised: Vec<Multiaddr> = self
+            .presence
+            .roster()
+            .filter(|&(id, info)| {
+                id.to_base58() == peer || info.nick.as_ref().map(|nick| &nick[..]) == Some(peer)
+            })
+            .flat_map(|(_, info)| info.addresses.clone())
+            .collect();
+        let peers = self.peers.borrow();
+        let connections: Vec<_> = peers
+            .iter()
+            .filter(|c| advertised.contains(&c.address) || c.address.to_string().contains(peer))
+            .collect();
+        if connections.is_empty() {
+            return say!("* No connection to {}", peer);
+        }
+        for connection in connections {
+            let age = connection.age().as_secs();
+            let proxied = match connection.endpoint {
+                Endpoint::Dialer => {
+                    say!("* Connection to {}, open for {}s", connection.address, age);
+                    self.proxied
+                }
+                Endpoint::Listener => {
+                    say!("* Connection from {}, open for {}s", connection.address, age);
+                    false
+                }
+            };
+            let steps = &connection.steps;
+            let unknown = || tr!("unknown");
+            say!(
+                "*   transport: {}, {}",
+                stack::transport(&connection.address, proxied),
+                steps.transport.map(stack::millis).unwrap_or_else(unknown)
+            );
+            say!("*   security: none, the messages are signed instead");
+            say!("*   muxer: none, one connection per protocol");
+            say!(
+                "*   negotiation: multistream-select, {}",
+                steps.negotiation.map(stack::millis).unwrap_or_else(unknown)
+            );
+            say!(
+                "*   protocol: {}, handshake {}",
+                connection.protocol.name(),
+                stack::millis(steps.handshake)
+            );
+        }
+    }
+
+    /// Retracts the last message we sent, if it is recent enough. The others' clients hide the
+    /// message once they receive the `Delete`, but nothing can unsend what they already read, or
+    /// what a client ignoring deletions keeps.
+    fn undo(&mut self) {
+        let (lines, room, sent) = match self.last_sent.take() {
+            Some(last) => last,
+            None => return say!("* You haven't sent anything to undo"),
+        };
+        if sent.elapsed() > self.undo_window {
+            return say!(
+                "* Your last message is older than {} seconds; use `/delete` instead",
+                self.undo_window.as_secs()
+            );
+        }
+        // Like the other changes, the retraction must be published where the message is.
+        if room != self.room {
+            say!("* That message is in {}; use `/switch {}` first", room, room);
+            self.last_sent = Some((lines, room, sent));
+            return;
+        }
+        let own = self.identity.peer_id().clone();
+        for id in lines {
+            if self.history.delete(id, &own).is_some() {
+                self.publish(Kind::Delete { message: id });
+            }
+        }
+        say!(
+            "* Retracted; whoever already read your message or ignores retractions still has it"
+        );
+    }
+
+    /// Adds the messages we published to the history, so that we can refer to them as well.
+    fn remember_sent(&mut self, body: &Body) {
+        let texts = match body.kind {
+            Kind::Text(ref text) | Kind::Action(ref text) => vec![text.clone()],
+            Kind::Batch(ref texts) => texts.clone(),
+            _ => return,
+        };
+        let name = match self.nick {
+            Some(ref nick) => format!("{} (you)", nick),
+            None => "you".to_owned(),
+        };
+        let lines = (0..texts.len()).map(|index| history::line_id(body.id, index)).collect();
+        self.last_sent = Some((lines, self.room.clone(), Instant::now()));
+        for (index, text) in texts.into_iter().enumerate() {
+            self.history.push(Entry {
+                id: history::line_id(body.id, index),
+                room: self.room.clone(),
+                author: self.identity.peer_id().clone(),
+                name: name.clone(),
+                timestamp: body.timestamp,
+                sent: body.timestamp,
+                text,
+                reply_to: body.reply_to,
+                reactions: BTreeMap::new(),
+                edited: false,
+                deleted: false,
+            });
+        }
+    }
+
+    /// Builds the body of a message we are about to publish.
+    ///
+    /// Each topic has its own time to live: heartbeats and key-value requests are only useful
+    /// for a short while, pads must stay replayable to converge, and the room uses `--ttl`.
+    fn new_body(&self, kind: Kind) -> Body {
+        let ttl = match kind {
+            Kind::Heartbeat
+            | Kind::Addresses(_)
+            | Kind::Coordinator
+            | Kind::Topics(_)
+            | Kind::RoomState { .. }
+            | Kind::Reminder { .. }
+            | Kind::ReminderAck { .. }
+            | Kind::Draining { .. } => Some(self.presence.timeout().as_secs()),
+            Kind::KvPut { .. } => None,
+            #[cfg(feature = "dht")]
+            Kind::KvGet { .. } | Kind::KvValue { .. } => Some(kv::REQUEST_TTL_SECS),
+            // Without the key-value store, we never send them.
+            #[cfg(not(feature = "dht"))]
+            Kind::KvGet { .. } | Kind::KvValue { .. } => None,
+            Kind::HistoryQuery { .. } | Kind::HistoryPage { .. } => {
+                Some(backfill::REQUEST_TTL_SECS)
+            }
+            Kind::VerifyQuery { .. } | Kind::VerifyAnswer { .. } => {
+                Some(transcript::REQUEST_TTL_SECS)
+            }
+            Kind::Pad { .. } => None,
+            _ => self.ttl,
+        };
+        let mut body = Body::new(self.nick.clone(), kind);
+        body.ttl = ttl;
+        body
+    }
+
+    /// Publishes `body` in the current room. Returns false if it couldn't be sent.
+    fn publish_body(&mut self, body: &Body) -> bool {
+        let topic = match self.rooms.iter().find(|&&(ref room, _)| *room == self.room) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => {
+                say!("* You aren't in any room; use `/join <room>`");
+                return false;
+            }
+        };
+        self.send(&topic, body)
+    }
+
+    /// Counts an envelope that we received, before verifying it, for `/usage`.
+    pub fn count_received(&mut self, topics: &[TopicHash], source: &PeerId, bytes: usize) {
+        let room = self
+            .rooms
+            .iter()
+            .find(|&&(_, ref topic)| topics.contains(topic.hash()))
+            .map(|&(ref room, _)| room.as_str());
+        self.usage.received(room, source, bytes);
+    }
+
+    /// Signs `body` and queues it for publication on `topic`, unless it is too large. Returns
+    /// false if it is.
+    fn send(&mut self, topic: &Topic, body: &Body) -> bool {
+        self.send_many(&[topic.clone()], body)
+    }
+
+    /// Like `send`, but publishes on all of `topics`. The envelope is signed and serialized once.
+    fn send_many(&mut self, topics: &[Topic], body: &Body) -> bool {
+        let data = Bytes::from(envelope::seal(&self.identity, body));
+        if data.len() > self.max_message_size {
+            say!(
+                "* Not sent: the message is {} bytes long, but the limit is {} bytes",
+                data.len(),
+                self.max_message_size
+            );
+            return false;
+        }
+        if self.sent.len() >= MAX_SENT_IDS {
+            self.sent.pop_front();
+        }
+        self.sent.push_back(body.id);
+        // The bytes go out once, so they are counted in the first of the rooms.
+        let room = self
+            .rooms
+            .iter()
+            .find(|&&(_, ref other)| topics.iter().any(|topic| other.hash() == topic.hash()))
+            .map(|&(ref room, _)| room.as_str());
+        if let Some(room) = room {
+            self.traffic.entry(room.to_owned()).or_insert((0, 0)).1 += 1;
+        }
+        self.usage.sent(room, data.len());
+        display::event(Verbosity::Debug, &format!("Sending: {:?}", body.kind));
+        // Presence and requests mustn't wait behind a large paste.
+        let priority = match body.kind {
+            Kind::Heartbeat
+            | Kind::Addresses(_)
+            | Kind::Coordinator
+            | Kind::Topics(_)
+            | Kind::RoomState { .. }
+            | Kind::KvGet { .. }
+            | Kind::Ban { .. }
+            | Kind::Unban { .. }
+            | Kind::Bans { .. }
+            | Kind::Rotate { .. }
+            | Kind::Draining { .. }
+            | Kind::HistoryQuery { .. }
+            | Kind::VerifyQuery { .. } => Priority::Control,
+            // Meant for a single node, which is often waiting for an answer to its query.
+            Kind::Reminder { .. }
+            | Kind::ReminderAck { .. }
+            | Kind::KvValue { .. }
+            | Kind::HistoryPage { .. }
+            | Kind::VerifyAnswer { .. } => Priority::Direct,
+            _ => Priority::Bulk,
+        };
+        if !self.outbox.push_many(topics.to_vec(), data, priority) {
+            say!(
+                "* Not sent: the outbox is full, with {} messages waiting",
+                self.outbox.len()
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Tells the clients of the previous version of the protocol, in our rooms, that they must
+    /// upgrade. See the `migration` module.
+    fn publish_beacons(&mut self) {
+        let beacon = match migration::beacon(&self.identity, self.nick.clone()) {
+            Some(beacon) => Bytes::from(beacon),
+            None => return,
+        };
+        for &(ref room, _) in &self.rooms {
+            if let Some(legacy) = migration::legacy_topic(&self.naming, room) {
+                self.outbox.push(legacy, beacon.clone(), Priority::Bulk);
+            }
+        }
+    }
+
+    /// With `--demo-traffic`, sometimes publishes the next line of the canned conversation in
+    /// the current room. Floodsub doesn't show us our own messages, so we also handle it as if
+    /// we had received it.
+    pub fn demo_tick(&mut self) {
+        let (source, data) = match self.demo.as_mut().and_then(|demo| demo.tick()) {
+            Some((source, data)) => (source, Bytes::from(data)),
+            None => return,
+        };
+        let topic = match self.rooms.iter().find(|&&(ref room, _)| *room == self.room) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => return,
+        };
+        self.outbox.push(topic.clone(), data.clone(), Priority::Bulk);
+        self.handle_message(&[topic.hash().clone()], &source, envelope::open(&data));
+    }
+
+    /// Publishes `text` in all of `rooms`, joined or not, as a single floodsub message. A node in
+    /// several of them receives it once, and shows it in the first of its rooms.
+    fn broadcast(&mut self, rooms: &[String], text: String) {
+        let text = self.redact(text);
+        let text = self.send_emoji(text);
+        let topics: Vec<Topic> = rooms.iter().map(|room| self.naming.topic(room)).collect();
+        let body = self.new_body(Kind::Text(text));
+        if self.send_many(&topics, &body) {
+            say!("* Broadcast in {}", rooms.join(", "));
+        }
+    }
+
+    /// Publishes the `text` of a bot in `room`. Returns false if it wasn't published, because we
+    /// left the room or the text is too large.
+    pub fn announce(&mut self, room: &str, text: String) -> bool {
+        let topic = match self.rooms.iter().find(|&&(ref r, _)| r == room) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => {
+                display::chatter(&tr!("* Not announcing in {}, which we left", room));
+                return false;
+            }
+        };
+        let body = self.new_body(Kind::Text(text));
+        self.send(&topic, &body)
+    }
+
+    /// Publishes in `room` a text that a bot or `/schedule` wrote in advance, with its
+    /// placeholders filled in. See the `template` module.
+    fn announce_canned(&mut self, room: &str, text: String) {
+        let vars = template::Vars {
+            nick: match self.nick {
+                Some(ref nick) => nick.clone(),
+                None => self.identity.peer_id().to_base58(),
+            },
+            time: display::clock(),
+            peer_count: self.presence.alive().count(),
+        };
+        let text = template::render(&text, &vars);
+        self.announce(room, text);
+    }
+
+    /// Publishes the messages of `/schedule` whose time came, and sends the reminders of
+    /// `/remind` that are due to those who are around. Called every second.
+    pub fn publish_scheduled(&mut self) {
+        let now = envelope::now();
+        for reminder in self.schedule.reminders_to_send(now) {
+            let peer = match self.find_around(&reminder.to) {
+                Some(peer) => peer.to_base58(),
+                None => continue,
+            };
+            let body = self.new_body(Kind::Reminder {
+                to: peer.clone(),
+                reminder: reminder.id,
+                text: reminder.text,
+            });
+            let topic = self.directory_topic.clone();
+            if self.send(&topic, &body) {
+                self.schedule.sent(reminder.id, peer, now);
+            }
+        }
+        for scheduled in self.schedule.take_due(now) {
+            let text = self.send_emoji(scheduled.text);
+            display::chatter(&tr!(
+                "* Publishing scheduled message {} in {}",
+                scheduled.id,
+                scheduled.room
+            ));
+            self.announce_canned(&scheduled.room, text);
+        }
+    }
+
+    /// Returns the peer that `name` designates among those who are around: by nickname, short ID
+    /// or base58 `PeerId`.
+    fn find_around(&self, name: &str) -> Option<PeerId> {
+        self.presence
+            .roster()
+            .find(|&(peer, info)| {
+                info.nick.as_ref().map(|nick| nick == name).unwrap_or(false)
+                    || self.short_ids.get(peer) == name
+                    || peer.to_base58() == name
+            })
+            .map(|(peer, _)| peer.clone())
+    }
+
+    /// Sends the page of our history that `received` asks for, if we remember any of it.
+    fn answer_history_query(
+        &mut self,
+        received: &Received,
+        room: &str,
+        request: u64,
+        before: Option<backfill::Cursor>,
+        limit: usize,
+    ) {
+        let topic = match self.rooms.iter().find(|&&(ref r, _)| r == room) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => return,
+        };
+        let limit = limit.min(backfill::PAGE_SIZE);
+        // Half of the limit leaves room for the names, the escaping and the signature.
+        let max_bytes = self.max_message_size / 2;
+        let (messages, more) = backfill::page(&self.history, room, before, limit, max_bytes);
+        if messages.is_empty() {
+            return;
+        }
+        let body = self.new_body(Kind::HistoryPage {
+            to: received.sender.to_base58(),
+            request,
+            messages,
+            more,
+        });
+        self.send(&topic, &body);
+    }
+
+    /// Displays the page that answers our `/history more`, unless another peer was first.
+    fn print_history_page(
+        &mut self,
+        received: &Received,
+        request: u64,
+        messages: Vec<Archived>,
+        more: bool,
+    ) {
+        let room = match self.backfill.complete(request, &messages) {
+            Some(room) => room,
+            None => return,
+        };
+        let name = self.sender_name(received);
+        say!("* Older messages of {}, from the history of {}:", room, name);
+        for message in messages {
+            let time = export::format_time(message.timestamp);
+            display::reply(&format!("* [{}] {}: {}", time, message.name, message.text));
+        }
+        if more {
+            say!("* Type `/history more` again for older messages");
+        } else {
+            say!("* That's the beginning of {} as far as {} remembers", room, name);
+        }
+    }
+
+    /// Publishes a query of the `/verify` in progress, for `peer` or for everyone in the room.
+    fn send_verify_query(&mut self, topic: &Topic, peer: Option<String>, ranges: Vec<Range>) {
+        let request = ::rand::random();
+        if let Some(ref mut verification) = self.verification {
+            verification.add_pending(request);
+        }
+        let body = self.new_body(Kind::VerifyQuery {
+            request,
+            peer,
+            ranges,
+        });
+        self.send(topic, &body);
+    }
+
+    /// Sends our digests of the ranges that `received` asks for.
+    fn answer_verify_query(
+        &mut self,
+        received: &Received,
+        room: &str,
+        request: u64,
+        ranges: Vec<Range>,
+    ) {
+        let topic = match self.rooms.iter().find(|&&(ref r, _)| r == room) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => return,
+        };
+        let digests = ranges
+            .into_iter()
+            .filter(|range| range.is_valid())
+            .take(transcript::MAX_RANGES)
+            .map(|range| transcript::digest(&self.history, room, range))
+            .collect();
+        let body = self.new_body(Kind::VerifyAnswer {
+            to: received.sender.to_base58(),
+            request,
+            digests,
+        });
+        self.send(&topic, &body);
+    }
+
+    /// Compares the digests that answer our query with ours: displays the messages that diverge,
+    /// and asks for the halves of the ranges whose roots differ.
+    fn continue_verification(
+        &mut self,
+        received: &Received,
+        request: u64,
+        digests: Vec<transcript::Digest>,
+    ) {
+        let room = match self.verification {
+            Some(ref mut verification) => {
+                if !verification.answered(request, &received.sender) {
+                    return;
+                }
+                verification.room.clone()
+            }
+            None => return,
+        };
+        let topic = match self.rooms.iter().find(|&&(ref r, _)| *r == room) {
+            Some(&(_, ref topic)) => topic.clone(),
+            None => {
+                self.verification = None;
+                return;
+            }
+        };
+        let name = self.sender_name(received);
+        let mut differences = 0;
+        let mut splits = Vec::new();
+        for theirs in digests.iter().filter(|digest| digest.range.is_valid()) {
+            let ours = transcript::digest(&self.history, &room, theirs.range);
+            match transcript::compare(&ours, theirs) {
+                Outcome::Agree => {}
+                Outcome::Split(first, second) => {
+                    splits.push(first);
+                    splits.push(second);
+                }
+                Outcome::Differ(ref found) if found.is_empty() => {
+                    differences += 1;
+                    say!(
+                        "* {} has messages between {} and {} that differ from ours",
+                        name,
+                        export::format_time(theirs.range.from),
+                        export::format_time(theirs.range.to)
+                    );
+                }
+                Outcome::Differ(found) => {
+                    differences += found.len();
+                    for difference in found {
+                        self.print_difference(&name, difference);
+                    }
+                }
+            }
+        }
+
+        let peer = received.sender.to_base58();
+        for ranges in splits.chunks(transcript::MAX_RANGES) {
+            self.send_verify_query(&topic, Some(peer.clone()), ranges.to_vec());
+        }
+        let finished = match self.verification {
+            Some(ref mut verification) => {
+                verification.differences += differences;
+                verification.is_done()
+            }
+            None => false,
+        };
+        if !finished {
+            return;
+        }
+        let verification = self.verification.take().expect("the verification is in progress");
+        if verification.differences == 0 {
+            say!("* Our history of {} matches the one of {}", room, name);
+        } else {
+            say!(
+                "* Found {} diverging messages between our history of {} and the one of {}",
+                verification.differences,
+                room,
+                name
+            );
+        }
+    }
+
+    fn print_difference(&self, name: &str, difference: Difference) {
+        let line = |entry: &Entry| {
+            format!("[{}] {}", export::format_time(entry.timestamp), entry.render())
+        };
+        match difference {
+            Difference::Missing(id) => {
+                if let Some(entry) = self.history.find(id) {
+                    say!("* {} doesn't have: {}", name, line(entry));
+                }
+            }
+            Difference::Changed(id) => {
+                if let Some(entry) = self.history.find(id) {
+                    say!("* {} has another version of: {}", name, line(entry));
+                }
+            }
+            Difference::Unknown(leaf) => say!(
+                "* {} has a message sent at {} that we don't",
+                name,
+                export::format_time(leaf.sent)
+            ),
+        }
+    }
+
+    /// Records a report about the `n`th message of the history, and sends it to the moderators
+    /// of its room, if it has any.
+    fn report(&mut self, n: usize, reason: String) {
+        if reason.chars().count() > reports::MAX_REASON_LEN {
+            let max = reports::MAX_REASON_LEN;
+            return say!("* The reason can't be longer than {} characters", max);
+        }
+        let report = match self.history_entry(n) {
+            Some(entry) => Report {
+                message: entry.id,
+                room: self.naming.label(&entry.room),
+                author: entry.author.to_base58(),
+                text: entry.text.clone(),
+                reason,
+                timestamp: envelope::now(),
+            },
+            None => return,
+        };
+        let signed = SignedReport::sign(report, &self.identity);
+        if self.moderation.has_moderators() {
+            let room = self.room_of_label(signed.report.room.clone());
+            let topic = self
+                .rooms
+                .iter()
+                .find(|&&(ref r, _)| *r == room)
+                .map(|&(_, ref topic)| topic.clone());
+            match topic {
+                Some(topic) => {
+                    let body = self.new_body(Kind::Report(signed.clone()));
+                    if self.send(&topic, &body) {
+                        say!("* Sent the report to the moderators of {}", room);
+                    }
+                }
+                None => say!("* Not sending the report to {}, which we left", room),
+            }
+        }
+        say!("* Recorded the report about message {}", n);
+        self.reports.push(signed);
+    }
+
+    /// Shows a reminder of `/remind` if it is for us, and acknowledges it.
+    fn handle_reminder(&mut self, received: &Received, to: &str, reminder: u32, text: String) {
+        if to != self.identity.peer_id().to_base58() {
+            return;
+        }
+        // The author sends it again until it gets the acknowledgement, which may have been lost.
+        if self.reminded.insert((received.sender.clone(), reminder)) {
+            let sender = self.sender_name(received);
+            display::chatter(&tr!("* Reminder from {}: {}", sender, text));
+            self.notifier.message(&self.room, &sender, &text, true);
+        }
+        let body = self.new_body(Kind::ReminderAck {
+            to: received.sender.to_base58(),
+            reminder,
+        });
+        let topic = self.directory_topic.clone();
+        self.send(&topic, &body);
+    }
+
+    /// Does what a bot of `--plugins` asked.
+    pub fn handle_plugin_action(&mut self, action: plugins::Action) {
+        match action {
+            plugins::Action::Publish { room, text } => self.announce_canned(&room, text),
+            plugins::Action::Log { bot, text } => display::chatter(&format!("[{}] {}", bot, text)),
+            plugins::Action::Failed { bot, error } => {
+                display::chatter(&tr!("* The bot {} stopped: {}", bot, error))
+            }
+        }
+    }
+
+    /// With `--digest-to`, renders as HTML the messages of the current room from the last `period`,
+    /// if there are any.
+    pub fn digest(&self, period: Duration) -> Option<(String, String)> {
+        let since = envelope::now().saturating_sub(period.as_secs());
+        let entries: Vec<&Entry> = self
+            .history
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.room == self.room && entry.timestamp >= since && !entry.deleted)
+            .collect();
+        if entries.is_empty() {
+            return None;
+        }
+        let html = export::render(&self.room, &entries, &self.history, export::Format::Html);
+        Some((self.room.clone(), html))
+    }
+
+    /// With `--bridge`, publishes in `topic` an envelope that the broker sent us, and handles it
+    /// as if we had received it.
+    pub fn inject(&mut self, topic: &Topic, data: Bytes) {
+        let opened = envelope::open(&data);
+        let source = match opened {
+            Ok(ref received) => received.sender.clone(),
+            Err(ref err) => {
+                display::chatter(&tr!("* Dropped an envelope from the bridge: {}", err));
+                return;
+            }
+        };
+        self.outbox.push(topic.clone(), data, Priority::Bulk);
+        self.handle_message(&[topic.hash().clone()], &source, opened);
+    }
+
+    /// Publishes the oldest message of the outbox, if any.
+    pub fn flush_one(&mut self) {
+        if self.chaos.delay() {
+            return;
+        }
+        self.poll_handoff();
+        if let Some((topics, data)) = self.outbox.pop() {
+            let connected = self
+                .peers
+                .borrow()
+                .iter()
+                .any(|connection| connection.protocol == Protocol::FloodSub);
+            if let Some(ref mut handoff) = self.handoff {
+                if !connected {
+                    handoff.unsent(topics.clone(), data.clone());
+                }
+            }
+            // Floodsub wants a buffer of its own. Until here, the envelope was shared by the
+            // outbox and the handoff.
+            self.floodsub.publish_many(&topics, data.to_vec());
+        }
+    }
+
+    /// Returns the addresses of the floodsub connections that we dialed and are still open.
+    fn floodsub_outbound(&self) -> Vec<Multiaddr> {
+        self.peers
+            .borrow()
+            .iter()
+            .filter(|connection| connection.protocol == Protocol::FloodSub)
+            .filter(|connection| match connection.endpoint {
+                Endpoint::Dialer => true,
+                Endpoint::Listener => false,
+            })
+            .map(|connection| connection.address.clone())
+            .collect()
+    }
+
+    /// Follows a relay that drains to `alternate`, if we may have joined through it.
+    fn start_handoff(&mut self, alternate: Multiaddr) {
+        if self.drain.is_draining() || self.handoff.is_some() {
+            return;
+        }
+        let outbound = self.floodsub_outbound();
+        if outbound.is_empty() || outbound.contains(&alternate) {
+            return;
+        }
+        self.handoff = Some(Handoff::new(alternate, outbound));
+    }
+
+    /// Dials the alternate relay once the drainer is gone, and then publishes again what nobody
+    /// received in the meantime.
+    fn poll_handoff(&mut self) {
+        if self.handoff.is_none() {
+            return;
+        }
+        let outbound = self.floodsub_outbound();
+        let step = match self.handoff {
+            Some(ref mut handoff) => handoff.poll(&outbound),
+            None => None,
+        };
+        match step {
+            Some(handoff::Step::Dial(address)) => {
+                display::chatter(&tr!("* Our relay left; dialing {}", address));
+                let _ = self.dial.unbounded_send(DialRequest {
+                    address,
+                    protocol: Protocol::FloodSub,
+                });
+            }
+            Some(handoff::Step::Done(address, unsent)) => {
+                self.handoff = None;
+                display::chatter(&tr!(
+                    "* Handed off to {}; resending {} messages",
+                    address,
+                    unsent.len()
+                ));
+                let mut dropped = 0;
+                for (topics, data) in unsent {
+                    if !self.outbox.push_many(topics, data, Priority::Bulk) {
+                        dropped += 1;
+                    }
+                }
+                if dropped > 0 {
+                    say!("* The outbox is full; {} of them weren't resent", dropped);
+                }
+            }
+            Some(handoff::Step::Expired(address)) => {
+                self.handoff = None;
+                display::chatter(&tr!("* Couldn't hand off to {}", address));
+            }
+            None => {}
+        }
+    }
+
+    /// Starts draining: the nodes that dial us are turned away from now on, and the others are
+    /// told to reconnect to `alternate`, or to `--alternate`.
+    pub fn drain(&mut self, alternate: Option<Multiaddr>) {
+        let alternate = alternate.or_else(|| self.alternate.clone());
+        if !self.drain.start(alternate.clone()) {
+            say!("* Already draining");
+            return;
+        }
+        match alternate {
+            Some(ref alternate) => say!(
+                "* Draining: turning away new nodes, sending the others to {}, and exiting",
+                alternate
+            ),
+            None => say!("* Draining: turning away new nodes, and exiting"),
+        }
+        let body = self.new_body(Kind::Draining {
+            alternate: alternate.map(|alternate| alternate.to_string()),
+        });
+        let topic = self.directory_topic.clone();
+        self.send(&topic, &body);
+    }
+
+    /// Returns true once we are draining and have published all that we had to.
+    pub fn drained(&self) -> bool {
+        let (direct, _, _) = self.outbox.direct();
+        self.drain.grace_elapsed() && self.outbox.len() == 0 && direct == 0
+    }
+
+    /// Returns `Ready` if the outbox can accept the messages produced by another line of input.
+    pub fn poll_input_ready(&mut self) -> Async<()> {
+        self.outbox.poll_ready()
+    }
+}
+
+#[cfg(all(feature = "http-server", not(target_os = "emscripten")))]
+impl http::Node for Chat {
+    fn connections(&self) -> usize {
+        self.peers.borrow().iter().count()
+    }
+
+    fn is_draining(&self) -> bool {
+        self.drain.is_draining()
+    }
+
+    fn status(&self) -> http::Status {
+        let peers = self.presence.roster().map(|(peer, info)| http::PeerStatus {
+            id: peer.to_base58(),
+            label: self.peer_label(peer, info),
+            addresses: info.addresses.iter().map(|address| address.to_string()).collect(),
+        });
+        let connections = self
+            .peers
+            .borrow()
+            .iter()
+            .map(|connection| http::ConnectionStatus {
+                address: connection.address.to_string(),
+                outbound: match connection.endpoint {
+                    Endpoint::Dialer => true,
+                    Endpoint::Listener => false,
+                },
+                age: connection.age().as_secs(),
+            })
+            .collect();
+        let rooms = self.rooms.iter().map(|&(ref room, _)| {
+            let (received, sent) = self.traffic.get(room).cloned().unwrap_or((0, 0));
+            http::RoomStatus {
+                name: room.clone(),
+                members: self.metadata.members(room).iter().map(|peer| peer.to_base58()).collect(),
+                received,
+                sent,
+            }
+        });
+        http::Status {
+            local: match self.nick {
+                Some(ref nick) => nick.clone(),
+                None => self.identity.peer_id().to_base58(),
+            },
+            peers: peers.collect(),
+            connections,
+            rooms: rooms.collect(),
+        }
+    }
+
+    fn feed(&self, room: &str, count: usize) -> String {
+        let entries: Vec<&Entry> = self
+            .history
+            .iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.room == room && !entry.deleted)
+            .collect();
+        let skipped = entries.len().saturating_sub(count);
+        export::render(room, &entries[skipped..], &self.history, export::Format::Atom)
+    }
+
+    fn announce(&mut self, room: &str, text: String) -> bool {
+        Chat::announce(self, room, text)
+    }
+}
+
+/// Strips the control characters from the texts of `kind` that we display, see
+/// `markdown::strip_controls`.
+fn strip_controls(kind: Kind) -> Kind {
+    let strip = |text: String| markdown::strip_controls(&text);
+    match kind {
+        Kind::Text(text) => Kind::Text(strip(text)),
+        Kind::Batch(texts) => Kind::Batch(texts.into_iter().map(&strip).collect()),
+        Kind::Action(text) => Kind::Action(strip(text)),
+        Kind::Edit { message, text } => Kind::Edit {
+            message,
+            text: strip(text),
+        },
+        Kind::React { message, reaction } => Kind::React {
+            message,
+            reaction: strip(reaction),
+        },
+        Kind::Pin {
+            room,
+            message,
+            text,
+        } => Kind::Pin {
+            room,
+            message,
+            text: strip(text),
+        },
+        Kind::Poll { question, options } => Kind::Poll {
+            question: strip(question),
+            options: options.into_iter().map(&strip).collect(),
+        },
+        Kind::Pad { name, op } => Kind::Pad {
+            name,
+            op: strip_pad_controls(op),
+        },
+        Kind::Reminder { to, reminder, text } => Kind::Reminder {
+            to,
+            reminder,
+            text: strip(text),
+        },
+        Kind::HistoryPage {
+            to,
+            request,
+            messages,
+            more,
+        } => Kind::HistoryPage {
+            to,
+            request,
+            messages: messages
+                .into_iter()
+                .map(|archived| Archived {
+                    name: strip(archived.name),
+                    text: strip(archived.text),
+                    ..archived
+                })
+                .collect(),
+            more,
+        },
+        kind => kind,
+    }
+}
+
+fn strip_pad_controls(op: PadOp) -> PadOp {
+    let strip = |text: String| markdown::strip_controls(&text);
+    match op {
+        PadOp::Insert { id, after, text } => PadOp::Insert {
+            id,
+            after,
+            text: strip(text),
+        },
+        PadOp::Edit { id, version, text } => PadOp::Edit {
+            id,
+            version,
+            text: strip(text),
+        },
+        PadOp::Snapshot(ops) => PadOp::Snapshot(ops.into_iter().map(strip_pad_controls).collect()),
+        op => op,
+    }
+}
+
+#[cfg(feature = "pads")]
+fn print_pad(pad: &Pad) {
+    for (n, line) in pad.text().iter().enumerate() {
+        display::reply(&format!("  {:3} | {}", n + 1, line));
+    }
+}
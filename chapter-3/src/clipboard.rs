@@ -0,0 +1,55 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Access to the system clipboard, for `/paste` and `/copy`.
+//!
+//! Only available outside of the browser, and only if the crate is compiled with the
+//! `system-clipboard` feature, since it needs the X11 libraries on Linux. Otherwise every access
+//! fails with an explanation.
+
+#[cfg(all(feature = "system-clipboard", not(target_os = "emscripten")))]
+use system_clipboard::{ClipboardContext, ClipboardProvider};
+
+/// Returns the text currently in the clipboard.
+#[cfg(all(feature = "system-clipboard", not(target_os = "emscripten")))]
+pub fn get() -> Result<String, String> {
+    let mut context: ClipboardContext = ClipboardProvider::new().map_err(|err| err.to_string())?;
+    context.get_contents().map_err(|err| err.to_string())
+}
+
+/// Replaces the content of the clipboard with `text`.
+#[cfg(all(feature = "system-clipboard", not(target_os = "emscripten")))]
+pub fn set(text: String) -> Result<(), String> {
+    let mut context: ClipboardContext = ClipboardProvider::new().map_err(|err| err.to_string())?;
+    context.set_contents(text).map_err(|err| err.to_string())
+}
+
+#[cfg(not(all(feature = "system-clipboard", not(target_os = "emscripten"))))]
+pub fn get() -> Result<String, String> {
+    Err(UNAVAILABLE.to_owned())
+}
+
+#[cfg(not(all(feature = "system-clipboard", not(target_os = "emscripten"))))]
+pub fn set(_text: String) -> Result<(), String> {
+    Err(UNAVAILABLE.to_owned())
+}
+
+#[cfg(not(all(feature = "system-clipboard", not(target_os = "emscripten"))))]
+const UNAVAILABLE: &str = "this build has no clipboard support, see the `system-clipboard` feature";
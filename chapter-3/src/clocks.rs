@@ -0,0 +1,111 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Estimating how far the clock of each peer is from ours, so that their timestamps can be put on
+//! our clock.
+//!
+//! Each heartbeat carries the time at which its author sent it, so the timestamp minus the time
+//! we received it is a sample of the offset of their clock, minus the time the heartbeat took to
+//! reach us. We keep the last `SAMPLES` samples of each peer and take the largest, the one that
+//! was delayed the least. Timestamps are in seconds, so offsets below a couple of seconds are
+//! noise.
+//!
+//! The timestamps of the history, and so of `/export` and of the digests, are corrected with the
+//! estimate. A peer whose clock is more than `MAX_SKEW_SECS` off is flagged once, since their
+//! messages may also look expired to the nodes that don't correct them.
+
+use libp2p::PeerId;
+use schedule;
+use std::collections::{HashMap, VecDeque};
+
+/// Samples kept for each peer.
+const SAMPLES: usize = 16;
+/// Offset beyond which a clock is badly off.
+pub const MAX_SKEW_SECS: i64 = 60;
+
+#[derive(Default)]
+struct Clock {
+    samples: VecDeque<i64>,
+    /// True while the clock is flagged as badly off.
+    flagged: bool,
+}
+
+#[derive(Default)]
+pub struct Clocks {
+    peers: HashMap<PeerId, Clock>,
+}
+
+impl Clocks {
+    /// Records a heartbeat of `peer` written at `timestamp` and received at `now`. Returns the
+    /// offset of their clock if it just went more than `MAX_SKEW_SECS` off.
+    pub fn sample(&mut self, peer: &PeerId, timestamp: u64, now: u64) -> Option<i64> {
+        let clock = self.peers.entry(peer.clone()).or_insert_with(Clock::default);
+        if clock.samples.len() == SAMPLES {
+            clock.samples.pop_front();
+        }
+        clock.samples.push_back(timestamp as i64 - now as i64);
+        let offset = *clock.samples.iter().max().expect("we just pushed a sample");
+        let badly_off = offset.abs() > MAX_SKEW_SECS;
+        let newly = badly_off && !clock.flagged;
+        clock.flagged = badly_off;
+        if newly {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// The estimated offset of the clock of `peer` from ours, in seconds, if we heard from them.
+    pub fn offset(&self, peer: &PeerId) -> Option<i64> {
+        self.peers
+            .get(peer)
+            .and_then(|clock| clock.samples.iter().max().cloned())
+    }
+
+    /// Puts `timestamp`, written by `peer`, on our clock.
+    pub fn correct(&self, peer: &PeerId, timestamp: u64) -> u64 {
+        match self.offset(peer) {
+            Some(offset) => (timestamp as i64 - offset).max(0) as u64,
+            None => timestamp,
+        }
+    }
+
+    /// Forgets a peer that left.
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+}
+/// Tells that the clock of `name` is `offset` seconds off ours, as returned by `sample`.
+pub fn describe(name: &str, offset: i64) -> String {
+    let by = schedule::format_delay(offset.abs() as u64);
+    if offset > 0 {
+        tr!(
+            "* The clock of {} is {} ahead of ours; correcting its timestamps",
+            name,
+            by
+        )
+    } else {
+        tr!(
+            "* The clock of {} is {} behind ours; correcting its timestamps",
+            name,
+            by
+        )
+    }
+}
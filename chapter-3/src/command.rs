@@ -0,0 +1,1017 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Parsing of the lines typed by the user.
+//!
+//! A line starting with `/` is a command. Everything else is a message to publish in the room.
+//!
+//! `COMMANDS` lists the commands with their aliases and a short description, for `/help`. A
+//! command added to `parse` must be added there too.
+//!
+//! Before being parsed, a line goes through `expand`, which applies the aliases and macros of the
+//! configuration file.
+
+use notifier::Level;
+use schedule;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Publish a text message.
+    Message(String),
+    /// `/me <action>`
+    Me(String),
+    /// `/ban <peer>`
+    Ban(String),
+    /// `/unban <peer>`
+    Unban(String),
+    /// `/bans`
+    Bans,
+    /// `/mentions`
+    Mentions,
+    /// `/multiline`
+    Multiline,
+    /// `/emoji on|off`
+    Emoji(bool),
+    /// `/poll "question" option1 option2...`
+    Poll { question: String, options: Vec<String> },
+    /// `/poll results <id>`
+    PollResults(String),
+    /// `/vote <id> <n>`, where `n` starts at 1.
+    Vote { poll: String, choice: usize },
+    /// `/ttt <multiaddr>`
+    Ttt(String),
+    /// `/move <1-9>`
+    Move(usize),
+    /// `/resign`
+    Resign,
+    /// `/pad <name> [show|close|append <text>|edit <n> <text>|delete <n>]`
+    Pad { name: String, action: PadAction },
+    /// `/put <key> <value>`
+    Put { key: String, value: String },
+    /// `/get <key>`
+    Get(String),
+    /// `/stats`
+    Stats,
+    /// `/scores`
+    Scores,
+    /// `/graph`
+    Graph,
+    /// `/reload`
+    Reload,
+    /// `/export <file.md|file.html> [room] [n-m]`, where `n-m` are positions in the history.
+    Export {
+        path: String,
+        room: Option<String>,
+        range: Option<(usize, usize)>,
+    },
+    /// `/recent`
+    Recent,
+    /// `/again <n>`, where `n` starts at 1 for the last line typed.
+    Again(usize),
+    /// `/connections`
+    Connections,
+    /// `/who`
+    Who,
+    /// `/topics`
+    Topics,
+    /// `/join <room>`
+    Join(String),
+    /// `/leave`
+    Leave,
+    /// `/switch <room>`
+    Switch(String),
+    /// `/version`
+    Version,
+    /// `/chaos on|off`
+    Chaos(bool),
+    /// `/usage`
+    Usage,
+    /// `/identity`
+    Identities,
+    /// `/identity use <name>`
+    UseIdentity(String),
+    /// `/rotate`
+    Rotate,
+    /// `/call <multiaddr>`
+    Call(String),
+    /// `/hangup`
+    HangUp,
+    /// `/sharescreen <multiaddr>`
+    ShareScreen(String),
+    /// `/unshare`
+    Unshare,
+    /// `/schedule <delay> <text>`, or `/in <delay> <text>`.
+    Schedule { delay: Duration, text: String },
+    /// `/scheduled`
+    Scheduled,
+    /// `/scheduled cancel <n>`
+    CancelScheduled(u32),
+    /// `/report <n> <reason>`, where `n` is a position in the history, starting at 1.
+    Report { n: usize, reason: String },
+    /// `/reports`
+    Reports,
+    /// `/watch [<pattern>]`, which lists the patterns without one.
+    Watch(Option<String>),
+    /// `/unwatch <pattern>`
+    Unwatch(String),
+    /// `/undo`
+    Undo,
+    /// `/conn <peer>`, where the peer is a nickname, a `PeerId` or part of an address.
+    Conn(String),
+    /// `/forgive <peer>`
+    Forgive(String),
+    /// `/broadcast <room>,<room>... <text>`
+    Broadcast { rooms: Vec<String>, text: String },
+    /// `/drain [<multiaddr>]`, where the multiaddress is the relay to send the others to.
+    Drain(Option<String>),
+    /// `/verify [<delay>]`, to compare the messages of the last `delay` only.
+    Verify(Option<Duration>),
+    /// `/remind <nick> <delay> <text>`, where the delay can be `now`.
+    Remind {
+        to: String,
+        delay: Duration,
+        text: String,
+    },
+    /// `/notify [all|mentions|silent]`
+    Notify(Option<Level>),
+    /// `/history`
+    History,
+    /// `/history more`, to fetch older messages than we know of from the room.
+    HistoryMore,
+    /// `/edit <n> <text>`, where `n` is a position in the history, starting at 1.
+    Edit { n: usize, text: String },
+    /// `/delete <n>`
+    Delete(usize),
+    /// `/reply <n> <text>`
+    Reply { n: usize, text: String },
+    /// `/thread <n>`
+    Thread(usize),
+    /// `/react <n> <reaction>`
+    React { n: usize, reaction: String },
+    /// `/pin <n>`, where `n` is a position in the history.
+    Pin(usize),
+    /// `/unpin <n>`, where `n` is a position in the list printed by `/pins`, starting at 1.
+    Unpin(usize),
+    /// `/pins`
+    Pins,
+    /// `/room info`
+    RoomInfo,
+    /// `/room describe <text>`
+    Describe(String),
+    /// `/paste`
+    Paste,
+    /// `/copy <n>`, where `n` starts at 1 for the last message.
+    Copy(usize),
+    /// `/purge [room|all]`
+    Purge(PurgeTarget),
+    /// `/filter [add <regex>|remove <regex>]`
+    Filter(FilterAction),
+    /// `/help [command]`
+    Help(Option<String>),
+    /// A command we don't know about, or with the wrong arguments.
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PurgeTarget {
+    CurrentRoom,
+    Room(String),
+    All,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterAction {
+    List,
+    Add(String),
+    Remove(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PadAction {
+    /// Joins the pad and shows it.
+    Open,
+    Show,
+    /// Leaves the pad.
+    Close,
+    Append(String),
+    /// Replaces the `n`th line, starting at 1.
+    Edit(usize, String),
+    /// Deletes the `n`th line, starting at 1.
+    Delete(usize),
+}
+
+pub fn parse(line: &str) -> Command {
+    // Two slashes escape a message that starts with a slash.
+    if line.starts_with("//") || !line.starts_with('/') {
+        let text = if line.starts_with("//") { &line[1..] } else { line };
+        return Command::Message(text.to_owned());
+    }
+
+    // Commands whose argument is free text rather than a list of words.
+    if line.starts_with("/me ") {
+        return Command::Me(line[4..].trim().to_owned());
+    }
+
+    let words = split_words(&line[1..]);
+    let name = words.first().map(|s| s.as_str()).unwrap_or("");
+    let name = find(name).map(|spec| spec.name).unwrap_or(name);
+    let args: Vec<&str> = words.iter().skip(1).map(|s| s.as_str()).collect();
+
+    match (name, args.as_slice()) {
+        ("help", &[]) => Command::Help(None),
+        ("help", &[command]) => Command::Help(Some(command.trim_left_matches('/').to_owned())),
+        ("ban", &[peer]) => Command::Ban(peer.to_owned()),
+        ("unban", &[peer]) => Command::Unban(peer.to_owned()),
+        ("bans", &[]) => Command::Bans,
+        ("mentions", &[]) => Command::Mentions,
+        ("multiline", &[]) => Command::Multiline,
+        ("emoji", &["on"]) => Command::Emoji(true),
+        ("emoji", &["off"]) => Command::Emoji(false),
+        ("poll", &["results", id]) => Command::PollResults(id.to_owned()),
+        ("poll", _) if args.len() >= 3 => Command::Poll {
+            question: args[0].to_owned(),
+            options: args[1..].iter().map(|s| s.to_string()).collect(),
+        },
+        ("vote", &[poll, choice]) => match choice.parse() {
+            Ok(choice) if choice >= 1 => Command::Vote {
+                poll: poll.to_owned(),
+                choice,
+            },
+            _ => Command::Invalid(line.to_owned()),
+        },
+        ("ttt", &[address]) => Command::Ttt(address.to_owned()),
+        ("move", &[cell]) => match cell.parse() {
+            Ok(cell) => Command::Move(cell),
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("resign", &[]) => Command::Resign,
+        ("put", _) if args.len() >= 2 => Command::Put {
+            key: args[0].to_owned(),
+            value: rest_of_line(&line[1..], 2).to_owned(),
+        },
+        ("get", &[key]) => Command::Get(key.to_owned()),
+        ("stats", &[]) => Command::Stats,
+        ("scores", &[]) => Command::Scores,
+        ("graph", &[]) => Command::Graph,
+        ("reload", &[]) => Command::Reload,
+        ("export", &[path]) => Command::Export {
+            path: path.to_owned(),
+            room: None,
+            range: None,
+        },
+        ("export", &[path, arg]) => match parse_range(arg) {
+            Some(range) => Command::Export {
+                path: path.to_owned(),
+                room: None,
+                range: Some(range),
+            },
+            None => Command::Export {
+                path: path.to_owned(),
+                room: Some(arg.to_owned()),
+                range: None,
+            },
+        },
+        ("export", &[path, room, range]) => match parse_range(range) {
+            Some(range) => Command::Export {
+                path: path.to_owned(),
+                room: Some(room.to_owned()),
+                range: Some(range),
+            },
+            None => Command::Invalid(line.to_owned()),
+        },
+        ("recent", &[]) => Command::Recent,
+        ("again", &[n]) => match n.parse() {
+            Ok(n) if n >= 1 => Command::Again(n),
+            _ => Command::Invalid(line.to_owned()),
+        },
+        ("connections", &[]) => Command::Connections,
+        ("who", &[]) => Command::Who,
+        ("topics", &[]) => Command::Topics,
+        ("join", &[room]) => Command::Join(room.to_owned()),
+        ("leave", &[]) => Command::Leave,
+        ("switch", &[room]) => Command::Switch(room.to_owned()),
+        ("version", &[]) => Command::Version,
+        ("usage", &[]) => Command::Usage,
+        ("identity", &[]) => Command::Identities,
+        ("identity", &["use", name]) => Command::UseIdentity(name.to_owned()),
+        ("rotate", &[]) => Command::Rotate,
+        ("call", &[address]) => Command::Call(address.to_owned()),
+        ("hangup", &[]) => Command::HangUp,
+        ("sharescreen", &[address]) => Command::ShareScreen(address.to_owned()),
+        ("unshare", &[]) => Command::Unshare,
+        ("schedule", _) if args.len() >= 2 => match schedule::parse_delay(args[0]) {
+            Some(delay) => Command::Schedule {
+                delay,
+                text: rest_of_line(&line[1..], 2).to_owned(),
+            },
+            None => Command::Invalid(line.to_owned()),
+        },
+        ("scheduled", &[]) => Command::Scheduled,
+        ("scheduled", &["cancel", n]) => match n.parse() {
+            Ok(n) => Command::CancelScheduled(n),
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("report", _) if args.len() >= 2 => match args[0].parse() {
+            Ok(n) => Command::Report {
+                n,
+                reason: rest_of_line(&line[1..], 2).to_owned(),
+            },
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("reports", &[]) => Command::Reports,
+        ("watch", &[]) => Command::Watch(None),
+        ("watch", _) => Command::Watch(Some(rest_of_line(&line[1..], 1).to_owned())),
+        ("unwatch", _) if !args.is_empty() => {
+            Command::Unwatch(rest_of_line(&line[1..], 1).to_owned())
+        }
+        ("undo", &[]) => Command::Undo,
+        ("conn", &[peer]) => Command::Conn(peer.to_owned()),
+        ("forgive", &[peer]) => Command::Forgive(peer.to_owned()),
+        ("broadcast", _) if args.len() >= 2 => {
+            let mut rooms: Vec<String> = Vec::new();
+            for room in args[0].split(',').filter(|room| !room.is_empty()) {
+                if !rooms.iter().any(|other| other == room) {
+                    rooms.push(room.to_owned());
+                }
+            }
+            if rooms.is_empty() {
+                return Command::Invalid(line.to_owned());
+            }
+            Command::Broadcast {
+                rooms,
+                text: rest_of_line(&line[1..], 2).to_owned(),
+            }
+        }
+        ("drain", &[]) => Command::Drain(None),
+        ("drain", &[address]) => Command::Drain(Some(address.to_owned())),
+        ("verify", &[]) => Command::Verify(None),
+        ("verify", &[delay]) => match schedule::parse_delay(delay) {
+            Some(delay) => Command::Verify(Some(delay)),
+            None => Command::Invalid(line.to_owned()),
+        },
+        ("remind", _) if args.len() >= 3 => {
+            let delay = match args[1] {
+                "now" => Some(Duration::from_secs(0)),
+                delay => schedule::parse_delay(delay),
+            };
+            match delay {
+                Some(delay) => Command::Remind {
+                    to: args[0].to_owned(),
+                    delay,
+                    text: rest_of_line(&line[1..], 3).to_owned(),
+                },
+                None => Command::Invalid(line.to_owned()),
+            }
+        }
+        ("chaos", &["on"]) => Command::Chaos(true),
+        ("chaos", &["off"]) => Command::Chaos(false),
+        ("notify", &[]) => Command::Notify(None),
+        ("notify", &[level]) => match Level::parse(level) {
+            Some(level) => Command::Notify(Some(level)),
+            None => Command::Invalid(line.to_owned()),
+        },
+        ("history", &[]) => Command::History,
+        ("history", &["more"]) => Command::HistoryMore,
+        ("edit", _) if args.len() >= 2 => match args[0].parse() {
+            Ok(n) => Command::Edit {
+                n,
+                text: rest_of_line(&line[1..], 2).to_owned(),
+            },
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("delete", &[n]) => match n.parse() {
+            Ok(n) => Command::Delete(n),
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("reply", _) if args.len() >= 2 => match args[0].parse() {
+            Ok(n) => Command::Reply {
+                n,
+                text: rest_of_line(&line[1..], 2).to_owned(),
+            },
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("react", &[n, reaction]) => match n.parse() {
+            Ok(n) => Command::React {
+                n,
+                reaction: reaction.to_owned(),
+            },
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("pin", &[n]) => match n.parse() {
+            Ok(n) => Command::Pin(n),
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("unpin", &[n]) => match n.parse() {
+            Ok(n) => Command::Unpin(n),
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("pins", &[]) => Command::Pins,
+        ("room", &["info"]) => Command::RoomInfo,
+        ("room", _) if args.len() >= 2 && args[0] == "describe" => {
+            Command::Describe(rest_of_line(&line[1..], 2).to_owned())
+        }
+        ("thread", &[n]) => match n.parse() {
+            Ok(n) => Command::Thread(n),
+            Err(_) => Command::Invalid(line.to_owned()),
+        },
+        ("paste", &[]) => Command::Paste,
+        ("purge", &[]) => Command::Purge(PurgeTarget::CurrentRoom),
+        ("purge", &["all"]) => Command::Purge(PurgeTarget::All),
+        ("purge", &[room]) => Command::Purge(PurgeTarget::Room(room.to_owned())),
+        ("copy", &[n]) => match n.parse() {
+            Ok(n) if n >= 1 => Command::Copy(n),
+            _ => Command::Invalid(line.to_owned()),
+        },
+        ("filter", &[]) => Command::Filter(FilterAction::List),
+        ("filter", &["add", _]) => Command::Filter(FilterAction::Add(args[1].to_owned())),
+        ("filter", &["remove", _]) => Command::Filter(FilterAction::Remove(args[1].to_owned())),
+        ("pad", _) if !args.is_empty() => {
+            let action = match (args.get(1).cloned(), args.len()) {
+                (None, _) => Some(PadAction::Open),
+                (Some("show"), 2) => Some(PadAction::Show),
+                (Some("close"), 2) => Some(PadAction::Close),
+                (Some("append"), n) if n >= 3 => {
+                    Some(PadAction::Append(rest_of_line(&line[1..], 3).to_owned()))
+                }
+                (Some("edit"), n) if n >= 4 => args[2]
+                    .parse()
+                    .ok()
+                    .map(|n| PadAction::Edit(n, rest_of_line(&line[1..], 4).to_owned())),
+                (Some("delete"), 3) => args[2].parse().ok().map(PadAction::Delete),
+                _ => None,
+            };
+            match action {
+                Some(action) => Command::Pad {
+                    name: args[0].to_owned(),
+                    action,
+                },
+                None => Command::Invalid(line.to_owned()),
+            }
+        }
+        _ => Command::Invalid(line.to_owned()),
+    }
+}
+
+/// Expands the user-defined aliases and macros of `line` into the lines to run. The commands of
+/// `COMMANDS` can't be redefined, and the lines of a macro can use aliases but not macros.
+pub fn expand(
+    line: &str,
+    aliases: &HashMap<String, String>,
+    macros: &HashMap<String, Vec<String>>,
+) -> Vec<String> {
+    if let Some((name, _)) = user_defined(line) {
+        if let Some(lines) = macros.get(name) {
+            return lines.iter().map(|line| expand_alias(line, aliases)).collect();
+        }
+    }
+    vec![expand_alias(line, aliases)]
+}
+
+/// Replaces the name of the command with what it is an alias for, so that `/j room` becomes
+/// `/join room` with the alias `"j": "join"`.
+fn expand_alias(line: &str, aliases: &HashMap<String, String>) -> String {
+    match user_defined(line) {
+        Some((name, rest)) => match aliases.get(name) {
+            Some(command) => format!("/{}{}", command.trim_left_matches('/'), rest),
+            None => line.to_owned(),
+        },
+        None => line.to_owned(),
+    }
+}
+
+/// Splits `line` into the name of the command and its arguments, unless it isn't a command or
+/// the command is one of `COMMANDS`.
+fn user_defined(line: &str) -> Option<(&str, &str)> {
+    if !line.starts_with('/') || line.starts_with("//") {
+        return None;
+    }
+    let line = &line[1..];
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    let (name, rest) = line.split_at(end);
+    if name.is_empty() || find(name).is_some() {
+        return None;
+    }
+    Some((name, rest))
+}
+
+/// Description of a command, for `/help`.
+pub struct Spec {
+    pub name: &'static str,
+    /// Other names that `parse` accepts for the command.
+    pub aliases: &'static [&'static str],
+    /// The arguments, as shown in the usage line.
+    pub args: &'static str,
+    pub description: &'static str,
+}
+
+impl Spec {
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            format!("/{}", self.name)
+        } else {
+            format!("/{} {}", self.name, self.args)
+        }
+    }
+}
+
+pub const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "help",
+        aliases: &["?"],
+        args: "[command]",
+        description: "List the commands, or show how to use one",
+    },
+    Spec {
+        name: "me",
+        aliases: &[],
+        args: "<action>",
+        description: "Tell the room what you are doing",
+    },
+    Spec {
+        name: "join",
+        aliases: &[],
+        args: "<room>",
+        description: "Join a room and make it the current one",
+    },
+    Spec {
+        name: "leave",
+        aliases: &[],
+        args: "",
+        description: "Leave the current room",
+    },
+    Spec {
+        name: "switch",
+        aliases: &[],
+        args: "<room>",
+        description: "Make a room you joined the current one",
+    },
+    Spec {
+        name: "topics",
+        aliases: &["rooms"],
+        args: "",
+        description: "List the rooms you joined",
+    },
+    Spec {
+        name: "who",
+        aliases: &[],
+        args: "",
+        description: "List the peers seen recently",
+    },
+    Spec {
+        name: "connections",
+        aliases: &[],
+        args: "",
+        description: "List the open connections",
+    },
+    Spec {
+        name: "room",
+        aliases: &[],
+        args: "info|describe <text>",
+        description: "Show or set the description of the current room",
+    },
+    Spec {
+        name: "multiline",
+        aliases: &[],
+        args: "",
+        description: "Compose a message over several lines",
+    },
+    Spec {
+        name: "paste",
+        aliases: &[],
+        args: "",
+        description: "Send the content of the clipboard",
+    },
+    Spec {
+        name: "copy",
+        aliases: &[],
+        args: "<n>",
+        description: "Copy the nth last message to the clipboard",
+    },
+    Spec {
+        name: "history",
+        aliases: &[],
+        args: "[more]",
+        description: "Show the last messages, numbered, or ask the room for older ones",
+    },
+    Spec {
+        name: "edit",
+        aliases: &[],
+        args: "<n> <text>",
+        description: "Replace the text of one of your messages",
+    },
+    Spec {
+        name: "delete",
+        aliases: &[],
+        args: "<n>",
+        description: "Delete one of your messages",
+    },
+    Spec {
+        name: "reply",
+        aliases: &[],
+        args: "<n> <text>",
+        description: "Reply to a message of the history",
+    },
+    Spec {
+        name: "thread",
+        aliases: &[],
+        args: "<n>",
+        description: "Show a message and its replies",
+    },
+    Spec {
+        name: "react",
+        aliases: &[],
+        args: "<n> <reaction>",
+        description: "React to a message of the history",
+    },
+    Spec {
+        name: "pin",
+        aliases: &[],
+        args: "<n>",
+        description: "Pin a message of the history",
+    },
+    Spec {
+        name: "unpin",
+        aliases: &[],
+        args: "<n>",
+        description: "Unpin a message of the list shown by /pins",
+    },
+    Spec {
+        name: "pins",
+        aliases: &[],
+        args: "",
+        description: "List the pinned messages",
+    },
+    Spec {
+        name: "export",
+        aliases: &[],
+        args: "<file.md|file.html> [room] [n-m]",
+        description: "Write the history of a room to a Markdown or HTML file",
+    },
+    Spec {
+        name: "recent",
+        aliases: &[],
+        args: "",
+        description: "Show the last lines you typed, numbered",
+    },
+    Spec {
+        name: "again",
+        aliases: &[],
+        args: "<n>",
+        description: "Run a line shown by /recent again",
+    },
+    Spec {
+        name: "mentions",
+        aliases: &[],
+        args: "",
+        description: "List the messages that mention you",
+    },
+    Spec {
+        name: "notify",
+        aliases: &[],
+        args: "[all|mentions|silent]",
+        description: "Show or set when to be notified",
+    },
+    Spec {
+        name: "emoji",
+        aliases: &[],
+        args: "on|off",
+        description: "Turn the :shortcode: replacement on or off",
+    },
+    Spec {
+        name: "filter",
+        aliases: &[],
+        args: "[add <regex>|remove <regex>]",
+        description: "List, add or remove the patterns of hidden messages",
+    },
+    Spec {
+        name: "ban",
+        aliases: &[],
+        args: "<peer>",
+        description: "Stop displaying and relaying the messages of a peer",
+    },
+    Spec {
+        name: "unban",
+        aliases: &[],
+        args: "<peer>",
+        description: "Show the messages of a peer again",
+    },
+    Spec {
+        name: "bans",
+        aliases: &[],
+        args: "",
+        description: "List the banned peers",
+    },
+    Spec {
+        name: "purge",
+        aliases: &[],
+        args: "[room|all]",
+        description: "Delete the local data of a room, or of everything",
+    },
+    Spec {
+        name: "poll",
+        aliases: &[],
+        args: "\"question\" <option>...|results <id>",
+        description: "Start a poll, or show its results",
+    },
+    Spec {
+        name: "vote",
+        aliases: &[],
+        args: "<id> <n>",
+        description: "Vote in a poll",
+    },
+    Spec {
+        name: "pad",
+        aliases: &[],
+        args: "<name> [show|close|append <text>|edit <n> <text>|delete <n>]",
+        description: "Open or edit a shared pad",
+    },
+    Spec {
+        name: "ttt",
+        aliases: &[],
+        args: "<multiaddr>",
+        description: "Play tic-tac-toe with a peer",
+    },
+    Spec {
+        name: "move",
+        aliases: &[],
+        args: "<1-9>",
+        description: "Play a cell of the tic-tac-toe grid",
+    },
+    Spec {
+        name: "resign",
+        aliases: &[],
+        args: "",
+        description: "Give up the tic-tac-toe game",
+    },
+    Spec {
+        name: "put",
+        aliases: &[],
+        args: "<key> <value>",
+        description: "Store a value in the DHT",
+    },
+    Spec {
+        name: "get",
+        aliases: &[],
+        args: "<key>",
+        description: "Look up a value in the DHT",
+    },
+    Spec {
+        name: "stats",
+        aliases: &[],
+        args: "",
+        description: "Show the outbox and dial counters",
+    },
+    Spec {
+        name: "scores",
+        aliases: &[],
+        args: "",
+        description: "Show the scores of the peers",
+    },
+    Spec {
+        name: "graph",
+        aliases: &[],
+        args: "",
+        description: "Print the known network as a Graphviz graph",
+    },
+    Spec {
+        name: "reload",
+        aliases: &[],
+        args: "",
+        description: "Reload the configuration file",
+    },
+    Spec {
+        name: "version",
+        aliases: &[],
+        args: "",
+        description: "Show the versions of the known peers",
+    },
+    Spec {
+        name: "usage",
+        aliases: &[],
+        args: "",
+        description: "Show the bandwidth used, by room, peer and hour",
+    },
+    Spec {
+        name: "chaos",
+        aliases: &[],
+        args: "on|off",
+        description: "Drop connections, delay and duplicate messages, to test the chat",
+    },
+    Spec {
+        name: "identity",
+        aliases: &[],
+        args: "[use <name>]",
+        description: "List the personas of --persona, or publish as another one",
+    },
+    Spec {
+        name: "rotate",
+        aliases: &[],
+        args: "",
+        description: "Replace our key with a new one, and revoke the old one",
+    },
+    Spec {
+        name: "call",
+        aliases: &[],
+        args: "<multiaddr>",
+        description: "Talk with the node at the address, through the microphone",
+    },
+    Spec {
+        name: "hangup",
+        aliases: &[],
+        args: "",
+        description: "End the call in progress",
+    },
+    Spec {
+        name: "sharescreen",
+        aliases: &[],
+        args: "<multiaddr>",
+        description: "Show a shell to the node at the address, which can't type in it",
+    },
+    Spec {
+        name: "unshare",
+        aliases: &[],
+        args: "",
+        description: "Stop sharing the terminal",
+    },
+    Spec {
+        name: "schedule",
+        aliases: &["in"],
+        args: "<delay> <text>",
+        description: "Publish a message later, after a delay such as 30s, 10m or 2h",
+    },
+    Spec {
+        name: "scheduled",
+        aliases: &[],
+        args: "[cancel <n>]",
+        description: "List the scheduled messages, or cancel one",
+    },
+    Spec {
+        name: "remind",
+        aliases: &[],
+        args: "<nick> <delay|now> <text>",
+        description: "Send a reminder to someone once it is due and they are around",
+    },
+    Spec {
+        name: "report",
+        aliases: &[],
+        args: "<n> <reason>",
+        description: "Report an abusive message to the moderators of the room",
+    },
+    Spec {
+        name: "reports",
+        aliases: &[],
+        args: "",
+        description: "List the reports you made, and those you received as a moderator",
+    },
+    Spec {
+        name: "watch",
+        aliases: &[],
+        args: "[<pattern>]",
+        description: "Highlight the messages that match a pattern in every room, or list them",
+    },
+    Spec {
+        name: "unwatch",
+        aliases: &[],
+        args: "<pattern>",
+        description: "Stop watching a pattern",
+    },
+    Spec {
+        name: "undo",
+        aliases: &[],
+        args: "",
+        description: "Retract the message you just sent, if the others' clients allow it",
+    },
+    Spec {
+        name: "conn",
+        aliases: &[],
+        args: "<peer>",
+        description: "Show how the connections to a peer were built, layer by layer",
+    },
+    Spec {
+        name: "forgive",
+        aliases: &[],
+        args: "<peer>",
+        description: "Reset the score of a peer, to stop ignoring it",
+    },
+    Spec {
+        name: "broadcast",
+        aliases: &[],
+        args: "<room>,<room>... <text>",
+        description: "Send a message to several rooms at once",
+    },
+    Spec {
+        name: "drain",
+        aliases: &[],
+        args: "[<multiaddr>]",
+        description: "Turn away new nodes, send the others to another relay, and exit",
+    },
+    Spec {
+        name: "verify",
+        aliases: &[],
+        args: "[<delay>]",
+        description: "Compare the messages of the room with the history of another peer",
+    },
+];
+
+/// Returns the command called `name`, or with `name` as an alias.
+pub fn find(name: &str) -> Option<&'static Spec> {
+    COMMANDS.iter().find(|spec| spec.name == name || spec.aliases.contains(&name))
+}
+
+/// Returns the name of the command closest to `name`, if one is close enough to be a typo.
+pub fn closest(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .flat_map(|spec| Some(spec.name).into_iter().chain(spec.aliases.iter().cloned()))
+        .map(|candidate| (distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= 2)
+        .min()
+        .and_then(|(_, candidate)| find(candidate))
+        .map(|spec| spec.name)
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Splits `line` into words separated by whitespace. Words can be surrounded with double quotes
+/// in order to contain whitespace.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(current.clone());
+                    current.clear();
+                    in_word = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+/// Parses a range of positions in the history, such as `20-1`.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let mut bounds = range.splitn(2, '-');
+    let from = bounds.next()?.parse().ok()?;
+    let to = bounds.next()?.parse().ok()?;
+    Some((from, to))
+}
+
+/// Returns what remains of `line` once the first `skip` words have been removed.
+fn rest_of_line(line: &str, skip: usize) -> &str {
+    let mut rest = line.trim_left();
+    for _ in 0..skip {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_left();
+    }
+    rest
+}
@@ -0,0 +1,101 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The `/multiline` compose mode.
+//!
+//! Once `/multiline` has been typed, the following lines are accumulated instead of being sent
+//! one by one. A line containing only `.` sends them all as a single message.
+//!
+//! A text too large for a single envelope, composed or pasted, is sent in several parts, cut by
+//! `split`.
+
+/// Line that terminates the message being composed.
+pub const END_MARKER: &str = ".";
+
+pub struct Composer {
+    /// `Some` while we are composing a message.
+    lines: Option<Vec<String>>,
+}
+
+impl Composer {
+    pub fn new() -> Composer {
+        Composer { lines: None }
+    }
+
+    pub fn is_composing(&self) -> bool {
+        self.lines.is_some()
+    }
+
+    pub fn start(&mut self) {
+        self.lines = Some(Vec::new());
+    }
+
+    /// Adds a line to the message being composed. Returns the full message once the end marker
+    /// is reached.
+    ///
+    /// Must only be called while composing.
+    pub fn feed(&mut self, line: &str) -> Option<String> {
+        if line == END_MARKER {
+            return self.lines.take().map(|lines| lines.join("\n"));
+        }
+        if let Some(ref mut lines) = self.lines {
+            lines.push(line.to_owned());
+        }
+        None
+    }
+}
+
+/// Cuts `text` into parts of at most `max_bytes` bytes. A part ends at the last line break that
+/// fits, or in the middle of a line that is too long on its own.
+pub fn split(text: &str, max_bytes: usize) -> Vec<String> {
+    // A part must be able to hold any character.
+    let max_bytes = max_bytes.max(4);
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while rest.len() > max_bytes {
+        let mut end = max_bytes;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some(newline) = rest[..end].rfind('\n') {
+            end = newline + 1;
+        }
+        let part = rest[..end].trim_right_matches('\n');
+        if !part.is_empty() {
+            parts.push(part.to_owned());
+        }
+        rest = &rest[end..];
+    }
+    parts.push(rest.to_owned());
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texts_are_split_at_line_breaks_then_at_characters() {
+        assert_eq!(split("short", 10), vec!["short"]);
+        assert_eq!(split("one\ntwo\nthree", 9), vec!["one\ntwo", "three"]);
+        // "é" takes two bytes, and isn't cut in half.
+        assert_eq!(split("aaaéé", 4), vec!["aaa", "éé"]);
+    }
+}
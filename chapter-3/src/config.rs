@@ -0,0 +1,180 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The configuration file, which keeps the settings changed from within the chat between runs.
+//!
+//! It is a JSON file, rewritten entirely every time a setting changes. A missing file is the
+//! same as an empty one. Changes made by hand are applied by `/reload`, or on `SIGHUP`.
+//!
+//! The file can also hold named profiles, selected with `--profile`, which are edited by hand:
+//!
+//! ```json
+//! {
+//!     "profile": {
+//!         "workshop": { "identity": "workshop.key", "dial": ["/ip4/10.0.0.1/tcp/63204/ws"] },
+//!         "home": { "listen": ["/ip4/127.0.0.1/tcp/63204/ws"], "topics": ["family"] }
+//!     }
+//! }
+//! ```
+//!
+//! Aliases and macros are also edited by hand. An alias is another name for a command, maybe
+//! with some of its arguments, and a macro runs lines one after the other:
+//!
+//! ```json
+//! {
+//!     "aliases": { "j": "join", "w": "join workshop" },
+//!     "macros": { "demo": ["/join demo", "Hello everyone!", "/who"] }
+//! }
+//! ```
+//!
+//! The texts of a room can be displayed with a format of its own, in which `{time}`, `{room}`,
+//! `{nick}` and `{body}` are replaced with the current time in UTC, the room, the author and the
+//! text. The other rooms keep the default, `{nick}: {body}` behind the room when we are in
+//! several:
+//!
+//! ```json
+//! {
+//!     "formats": { "log": "{time} {nick}: {body}", "workshop": "[{time}] {room} {nick}: {body}" }
+//! }
+//! ```
+//!
+//! Where we were is kept for each profile in `sessions`, also rewritten from within the chat:
+//! the rooms we are in, the current one first, the notification levels that `/notify` set, such
+//! as the muted rooms, the patterns of `/watch` and the nickname. The next run with the same
+//! profile starts from there, unless `--topic` or `--nick` are passed. Without `--profile`, the
+//! session of the `default` profile is used.
+
+use notifier::Level;
+use serde_json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Notification level of each room, for the rooms that don't use the default. The levels of
+    /// the session win.
+    #[serde(default)]
+    pub notify: HashMap<String, Level>,
+    /// Patterns of the messages to drop, in addition to those passed with `--filter`.
+    #[serde(default)]
+    pub filters: Vec<String>,
+    /// Addresses to dial at startup, and when they are added to the file later on.
+    #[serde(default)]
+    pub dial: Vec<String>,
+    /// Other names for commands, such as `"j": "join"`.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    /// Lines run by `/<name>`, each a command or a message.
+    #[serde(default)]
+    pub macros: HashMap<String, Vec<String>>,
+    /// Format of the texts of each room, for the rooms that don't use the default. See
+    /// `render_format`.
+    #[serde(default)]
+    pub formats: HashMap<String, String>,
+    /// Named sets of command-line options. See `Profile`.
+    #[serde(default)]
+    pub profile: HashMap<String, Profile>,
+    /// Where we were when the chat last ran with each profile. See `Session`.
+    #[serde(default)]
+    pub sessions: HashMap<String, Session>,
+}
+
+/// Profile used when `--profile` isn't passed, if the file has one.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Default values for some command-line options. The options passed on the command line win.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub identity: Option<String>,
+    #[serde(default)]
+    pub nick: Option<String>,
+    #[serde(default)]
+    pub input_history: Option<String>,
+    #[serde(default)]
+    pub listen: Vec<String>,
+    #[serde(default)]
+    pub dial: Vec<String>,
+    #[serde(default)]
+    pub topics: Vec<String>,
+}
+
+/// The state of the chat restored at startup. It wins over the profile, and the command line
+/// wins over it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Session {
+    /// The rooms we are in, the current one first.
+    #[serde(default)]
+    pub rooms: Vec<String>,
+    /// Notification levels of the rooms, over those of `notify`.
+    #[serde(default)]
+    pub notify: HashMap<String, Level>,
+    /// Patterns of `/watch`.
+    #[serde(default)]
+    pub watches: Vec<String>,
+    #[serde(default)]
+    pub nick: Option<String>,
+}
+
+/// Replaces the fields of `format` with their value. An unknown field is kept as is.
+pub fn render_format(format: &str, time: &str, room: &str, nick: &str, body: &str) -> String {
+    let mut line = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        line.push_str(&rest[..start]);
+        let field = &rest[start..];
+        let end = match field.find('}') {
+            Some(end) => end + 1,
+            None => {
+                rest = field;
+                break;
+            }
+        };
+        match &field[..end] {
+            "{time}" => line.push_str(time),
+            "{room}" => line.push_str(room),
+            "{nick}" => line.push_str(nick),
+            "{body}" => line.push_str(body),
+            other => line.push_str(other),
+        }
+        rest = &field[end..];
+    }
+    // After the last field, or from an opening brace that is never closed.
+    line.push_str(rest);
+    line
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, IoError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        serde_json::from_reader(File::open(path)?)
+            .map_err(|err| IoError::new(ErrorKind::InvalidData, err))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), IoError> {
+        serde_json::to_writer_pretty(File::create(path)?, self)
+            .map_err(|err| IoError::new(ErrorKind::Other, err))
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Reading the Windows console.
+//!
+//! `tokio_stdin` reads stdin byte by byte, which on Windows goes through the code page of the
+//! console: the characters it can't represent are lost, and Ctrl+Z arrives as an ordinary
+//! character rather than as the end of the input. We read the console with `ReadConsoleW`
+//! instead, which gives us UTF-16, and treat a line starting with Ctrl+Z as the end of the
+//! input, like other Windows programs. When stdin is redirected, it is read as UTF-8.
+
+use futures::sync::mpsc;
+use std::io::{self, BufRead};
+use std::ptr;
+use winapi::ctypes::c_void;
+use winapi::shared::minwindef::DWORD;
+use winapi::um::consoleapi::{GetConsoleMode, ReadConsoleW};
+use winapi::um::processenv::GetStdHandle;
+use winapi::um::winbase::STD_INPUT_HANDLE;
+use winapi::um::winnt::HANDLE;
+
+/// Character produced by Ctrl+Z.
+const END_OF_INPUT: u16 = 0x1a;
+
+/// Sends the lines typed by the user to `tx` until the end of the input. Blocks, so this should
+/// run on a thread of its own.
+pub fn read_lines(tx: mpsc::UnboundedSender<String>) {
+    let handle = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+    let mut mode = 0;
+    if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.unbounded_send(line).is_err() {
+                        return;
+                    }
+                }
+                Err(_) => return,
+            }
+        }
+        return;
+    }
+
+    while let Some(line) = read_console_line(handle) {
+        if tx.unbounded_send(line).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads a line from the console, without its line break. Returns `None` at the end of the
+/// input.
+fn read_console_line(handle: HANDLE) -> Option<String> {
+    let mut line: Vec<u16> = Vec::new();
+    let mut buffer = [0u16; 1024];
+    loop {
+        let mut read: DWORD = 0;
+        let ok = unsafe {
+            ReadConsoleW(
+                handle,
+                buffer.as_mut_ptr() as *mut c_void,
+                buffer.len() as DWORD,
+                &mut read,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 || read == 0 {
+            return None;
+        }
+        line.extend_from_slice(&buffer[..read as usize]);
+        // Long lines are returned in several chunks, the last of which ends with `\r\n`.
+        if line.ends_with(&[u16::from(b'\r'), u16::from(b'\n')]) {
+            break;
+        }
+    }
+
+    if line.first() == Some(&END_OF_INPUT) {
+        return None;
+    }
+    let line = String::from_utf16_lossy(&line);
+    Some(line.trim_right_matches(|c| c == '\r' || c == '\n').to_owned())
+}
@@ -0,0 +1,114 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Flood damping, so that a very chatty room doesn't make the others unreadable.
+//!
+//! When more than `--flood-threshold` messages arrive in a room within `WINDOW_SECS`, the room is
+//! considered flooded. Until it calms down, the copies of the same text in a row are hidden and
+//! summarized by a single line once another text arrives, and only a sample of the other
+//! messages is shown. All the messages are kept in the history either way.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+const WINDOW_SECS: u64 = 10;
+
+/// What to do with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verdict {
+    /// True if the message is displayed.
+    pub show: bool,
+    /// The number of hidden copies of the previous text, to summarize before this message.
+    pub repeated: Option<usize>,
+    /// True if the room just became flooded.
+    pub started: bool,
+}
+
+#[derive(Default)]
+struct Room {
+    arrivals: VecDeque<Instant>,
+    flooded: bool,
+    last_text: String,
+    /// Hidden copies of `last_text`.
+    hidden: usize,
+    /// Distinct texts since the last one we displayed.
+    skipped: usize,
+}
+
+pub struct Damper {
+    threshold: usize,
+    rooms: HashMap<String, Room>,
+}
+
+impl Damper {
+    pub fn new(threshold: usize) -> Damper {
+        Damper {
+            threshold: threshold.max(1),
+            rooms: HashMap::new(),
+        }
+    }
+
+    /// Decides whether to display `text`, which just arrived in `room`.
+    pub fn check(&mut self, room: &str, text: &str) -> Verdict {
+        let threshold = self.threshold;
+        let state = self.rooms.entry(room.to_owned()).or_insert_with(Room::default);
+        let now = Instant::now();
+        state.arrivals.push_back(now);
+        while state
+            .arrivals
+            .front()
+            .map_or(false, |&at| now - at > Duration::from_secs(WINDOW_SECS))
+        {
+            state.arrivals.pop_front();
+        }
+
+        let rate = state.arrivals.len();
+        let started = rate > threshold && !state.flooded;
+        state.flooded = rate > threshold;
+        if state.flooded && text == state.last_text {
+            state.hidden += 1;
+            return Verdict {
+                show: false,
+                repeated: None,
+                started,
+            };
+        }
+
+        let repeated = Some(state.hidden).filter(|&hidden| hidden > 0);
+        state.last_text = text.to_owned();
+        state.hidden = 0;
+        // While flooded, one distinct text in `rate / threshold` is displayed.
+        let every = if state.flooded {
+            (rate + threshold - 1) / threshold
+        } else {
+            1
+        };
+        state.skipped += 1;
+        let show = state.skipped >= every;
+        if show {
+            state.skipped = 0;
+        }
+        Verdict {
+            show,
+            repeated,
+            started,
+        }
+    }
+}
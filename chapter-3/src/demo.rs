@@ -0,0 +1,73 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The synthetic conversation of `--demo-traffic`, to show a lively room before anyone joins.
+//!
+//! A few personas, each with its own key pair, take turns in a canned conversation. Their
+//! messages are signed and published like real ones, so the other nodes see them too.
+
+use envelope::{self, Body, Kind};
+use identity::Identity;
+use libp2p::PeerId;
+use rand::{self, Rng};
+
+const PERSONAS: &[&str] = &["Ada", "Grace", "Linus"];
+/// The lines of the conversation, with the index of the persona who says them.
+const SCRIPT: &[(usize, &str)] = &[
+    (0, "Hi everyone! Is this the room for the workshop?"),
+    (1, "It is :wave: I just got my node running"),
+    (2, "Same here. Took me a while to find the right port"),
+    (0, "Did anyone get the browser version to compile?"),
+    (1, "Yes, with the docker image from the README"),
+    (2, "Websockets are the only thing the browser can dial, right?"),
+    (1, "Right, use `/ws` at the end of the address"),
+    (0, "Nice, I see **both** of you now :tada:"),
+    (2, "Who wants a game? `/ttt` is surprisingly fun"),
+    (1, "Later! Still fighting with the borrow checker"),
+];
+/// On average, a message every this many ticks.
+const MEAN_TICKS: u32 = 4;
+
+pub struct Demo {
+    personas: Vec<Identity>,
+    next: usize,
+}
+
+impl Demo {
+    pub fn new() -> Demo {
+        Demo {
+            personas: PERSONAS.iter().map(|_| Identity::generate()).collect(),
+            next: 0,
+        }
+    }
+
+    /// Returns the next line of the conversation, sealed by its persona, along with the `PeerId`
+    /// of the persona. Most ticks return `None`, so that the messages come at irregular intervals.
+    pub fn tick(&mut self) -> Option<(PeerId, Vec<u8>)> {
+        if !rand::thread_rng().gen_weighted_bool(MEAN_TICKS) {
+            return None;
+        }
+        let (speaker, text) = SCRIPT[self.next % SCRIPT.len()];
+        self.next += 1;
+        let identity = &self.personas[speaker];
+        let body = Body::new(Some(PERSONAS[speaker].to_owned()), Kind::Text(text.to_owned()));
+        Some((identity.peer_id().clone(), envelope::seal(identity, &body)))
+    }
+}
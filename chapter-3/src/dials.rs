@@ -0,0 +1,210 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Limits on the outbound dials.
+//!
+//! Without them, dialing an unreachable address hangs until the OS gives up, which can take
+//! minutes, and nothing tells the user. `DialLimit` wraps the transport so that every dial has a
+//! timeout, and so that only a few dials are in progress at the same time. The others wait for a
+//! free slot.
+//!
+//! The dial of the inner transport is only started once a slot is free, since creating the dial
+//! is what opens the socket. As a consequence, addresses that the inner transport doesn't
+//! support are reported when their turn comes rather than immediately. This is fine as long as
+//! the transports wrapping `DialLimit`, such as `race::Race`, don't rely on this error.
+
+use display;
+use futures::future::{self, Either};
+use futures::task::{self, Task};
+use futures::{Async, Future, IntoFuture, Poll};
+use libp2p::core::Transport;
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::io::{Error as IoError, ErrorKind};
+use std::rc::Rc;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Timeout};
+
+#[derive(Clone)]
+pub struct DialLimit<T> {
+    inner: T,
+    slots: Rc<RefCell<Slots>>,
+    timeout: Duration,
+    handle: Handle,
+}
+
+struct Slots {
+    max: usize,
+    free: usize,
+    /// Number of dials waiting for a free slot.
+    queued: usize,
+    /// Tasks waiting for a free slot.
+    waiting: Vec<Task>,
+}
+
+impl<T> DialLimit<T> {
+    /// Allows `max` dials at the same time, each of them lasting at most `timeout`.
+    pub fn new(inner: T, max: usize, timeout: Duration, handle: Handle) -> DialLimit<T> {
+        DialLimit {
+            inner,
+            slots: Rc::new(RefCell::new(Slots {
+                max: max.max(1),
+                free: max.max(1),
+                queued: 0,
+                waiting: Vec::new(),
+            })),
+            timeout,
+            handle,
+        }
+    }
+
+    /// Returns the number of dials in progress and the number of dials waiting for a slot, for
+    /// all the clones of this `DialLimit`.
+    pub fn pending(&self) -> (usize, usize) {
+        let slots = self.slots.borrow();
+        (slots.max - slots.free, slots.queued)
+    }
+}
+
+impl<T> Transport for DialLimit<T>
+where
+    T: Transport + 'static,
+    T::Output: 'static,
+    T::Dial: 'static,
+{
+    type Output = T::Output;
+    type Listener = T::Listener;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = Box<Future<Item = (T::Output, Multiaddr), Error = IoError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let DialLimit {
+            inner,
+            slots,
+            timeout,
+            handle,
+        } = self;
+        inner.listen_on(addr).map_err(|(inner, addr)| {
+            let transport = DialLimit {
+                inner,
+                slots,
+                timeout,
+                handle,
+            };
+            (transport, addr)
+        })
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let DialLimit {
+            inner,
+            slots,
+            timeout,
+            handle,
+        } = self;
+        slots.borrow_mut().queued += 1;
+        let acquire = Acquire {
+            slots,
+            acquired: false,
+        };
+        let future = acquire.and_then(move |slot| {
+            let dial = match inner.dial(addr.clone()) {
+                Ok(dial) => dial.into_future(),
+                Err((_, addr)) => {
+                    let err = IoError::new(ErrorKind::Other, format!("can't dial {}", addr));
+                    return Either::A(future::err(err));
+                }
+            };
+            let timer = match Timeout::new(timeout, &handle) {
+                Ok(timer) => timer,
+                Err(err) => return Either::A(future::err(err)),
+            };
+            Either::B(dial.select2(timer).then(move |result| {
+                // Frees the slot for the next dial.
+                drop(slot);
+                match result {
+                    Ok(Either::A((output, _))) => Ok(output),
+                    Ok(Either::B(((), _))) => {
+                        let secs = timeout.as_secs();
+                        display::chatter(&tr!("* Dialing {} timed out after {}s", addr, secs));
+                        Err(IoError::new(ErrorKind::TimedOut, "dial timed out"))
+                    }
+                    Err(Either::A((err, _))) | Err(Either::B((err, _))) => Err(err),
+                }
+            }))
+        });
+        Ok(Box::new(future))
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+/// Resolves to a `Slot` once one is free.
+struct Acquire {
+    slots: Rc<RefCell<Slots>>,
+    /// True once the slot has been handed out, after which the dial no longer counts as queued.
+    acquired: bool,
+}
+
+impl Future for Acquire {
+    type Item = Slot;
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<Slot, IoError> {
+        let mut slots = self.slots.borrow_mut();
+        if slots.free == 0 {
+            slots.waiting.push(task::current());
+            return Ok(Async::NotReady);
+        }
+        slots.free -= 1;
+        slots.queued -= 1;
+        self.acquired = true;
+        Ok(Async::Ready(Slot {
+            slots: self.slots.clone(),
+        }))
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if !self.acquired {
+            self.slots.borrow_mut().queued -= 1;
+        }
+    }
+}
+
+/// The right to have a dial in progress. Dropping it frees the slot.
+struct Slot {
+    slots: Rc<RefCell<Slots>>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut slots = self.slots.borrow_mut();
+        slots.free += 1;
+        // A waiting dial may have been dropped in the meantime, so we wake all of them up and let
+        // them compete for the slot, rather than waking up one that may never come back.
+        for task in slots.waiting.drain(..) {
+            task.notify();
+        }
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Mailing digests of a room, for the participants who couldn't follow it live.
+//!
+//! With `--digest-to <address>`, a node, typically one running as a service, renders at every
+//! `--digest-hours` the messages of its room from that period as an HTML transcript, like
+//! `/export`, and mails it through the SMTP server of `--smtp`. Nothing is sent for a period
+//! without messages.
+//!
+//! The server must relay our mail without authentication or TLS, as a local MTA does, since we
+//! speak plain SMTP. The conversation is short, so it happens with a blocking socket on a thread
+//! of its own.
+
+use futures::sync::oneshot;
+use futures::Future;
+use std::io::{BufRead, BufReader, Error as IoError, ErrorKind, Write};
+use std::net::TcpStream;
+use std::thread;
+
+pub const DEFAULT_SERVER: &str = "localhost:25";
+pub const DEFAULT_FROM: &str = "rustfest-chat@localhost";
+pub const DEFAULT_HOURS: &str = "24";
+
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    /// Host and port of the SMTP server.
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl Mailer {
+    /// Mails `html`, the digest of `room`. Resolves once the server accepted it.
+    pub fn send(&self, room: &str, html: String) -> Box<Future<Item = (), Error = IoError>> {
+        let (sender, receiver) = oneshot::channel();
+        let mailer = self.clone();
+        let subject = format!("Digest of {}", room);
+        thread::spawn(move || {
+            let _ = sender.send(mailer.deliver(&subject, &html));
+        });
+        Box::new(receiver.then(|result| {
+            result.unwrap_or_else(|_| Err(IoError::new(ErrorKind::Other, "the mailer stopped")))
+        }))
+    }
+
+    fn deliver(&self, subject: &str, html: &str) -> Result<(), IoError> {
+        let mut socket = TcpStream::connect(&self.server[..])?;
+        let mut replies = BufReader::new(socket.try_clone()?);
+        expect(&mut replies, 220)?;
+        let mut say = |line: String, code: u32| -> Result<(), IoError> {
+            socket.write_all(line.as_bytes())?;
+            socket.write_all(b"\r\n")?;
+            expect(&mut replies, code)
+        };
+        say("EHLO localhost".to_owned(), 250)?;
+        say(format!("MAIL FROM:<{}>", self.from), 250)?;
+        for to in &self.to {
+            say(format!("RCPT TO:<{}>", to), 250)?;
+        }
+        say("DATA".to_owned(), 354)?;
+        // The server adds the `Date` header.
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/html; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            subject
+        );
+        for line in html.lines() {
+            // A line that starts with a dot would end the message early, so it gets another one.
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push('.');
+        say(message, 250)?;
+        say("QUIT".to_owned(), 221)
+    }
+}
+
+/// Reads a reply of the server, which may span several lines, and checks its code.
+fn expect<R: BufRead>(replies: &mut R, code: u32) -> Result<(), IoError> {
+    loop {
+        let mut line = String::new();
+        if replies.read_line(&mut line)? == 0 {
+            return Err(IoError::new(ErrorKind::UnexpectedEof, "the server closed the connection"));
+        }
+        let line = line.trim_right();
+        if line.get(..3).and_then(|start| start.parse().ok()) != Some(code) {
+            return Err(IoError::new(ErrorKind::Other, line.to_owned()));
+        }
+        // `250-` continues the reply, `250 ` ends it.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
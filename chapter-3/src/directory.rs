@@ -0,0 +1,81 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Discovery of the rooms of the network.
+//!
+//! Every node periodically advertises the rooms it is in on a well-known topic, to which everyone
+//! is subscribed. From these advertisements we build a directory of the rooms, with the number
+//! of members that advertised them recently.
+
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Name of the topic on which the rooms are advertised.
+pub const TOPIC: &str = "rustfest-chat/directory";
+
+pub struct Directory {
+    /// For each room, the peers that advertised it and when.
+    rooms: HashMap<String, HashMap<PeerId, Instant>>,
+    /// Time after which an advertisement is forgotten.
+    timeout: Duration,
+}
+
+impl Directory {
+    pub fn new(timeout: Duration) -> Directory {
+        Directory {
+            rooms: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Records that `peer` is in exactly the given `rooms`.
+    pub fn advertised(&mut self, peer: &PeerId, rooms: &[String]) {
+        for (room, members) in self.rooms.iter_mut() {
+            if !rooms.contains(room) {
+                members.remove(peer);
+            }
+        }
+        for room in rooms {
+            self.rooms
+                .entry(room.clone())
+                .or_insert_with(HashMap::new)
+                .insert(peer.clone(), Instant::now());
+        }
+    }
+
+    /// Returns the known rooms with their approximate number of members, the most crowded first.
+    pub fn rooms(&self) -> Vec<(&str, usize)> {
+        let mut rooms: Vec<(&str, usize)> = self
+            .rooms
+            .iter()
+            .map(|(room, members)| {
+                let alive = members
+                    .values()
+                    .filter(|seen| seen.elapsed() < self.timeout)
+                    .count();
+                (room.as_str(), alive)
+            })
+            .filter(|&(_, members)| members > 0)
+            .collect();
+        rooms.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        rooms
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Helpers for formatting what we print.
+//!
+//! Terminals understand ANSI escape codes, but the `<textarea>` of `browser.html` doesn't, so
+//! decorations are only applied outside of the browser.
+//!
+//! Besides the messages, we print what happens around them. With `-q`, the chatter about peers
+//! coming and going and such is hidden; it goes through `chatter`. With `-v` and `-vv`, the
+//! protocol-level events passed to `event` are shown as well.
+//!
+//! With `--plain`, the terminal gets the same treatment as the browser, and no prompt or status
+//! line either: every line is printed once and never rewritten, for screen readers. The messages
+//! are then prefixed with the time at which they were displayed.
+//!
+//! Every line goes to all the `OutputSink`s at once: the terminal, or the page in the browser,
+//! and those added with `add_sink`, such as the ones of the `sinks` module.
+
+use envelope;
+use platform;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+
+thread_local! {
+    /// Prompt printed in front of what the user types, if any.
+    static PROMPT: RefCell<Option<String>> = RefCell::new(None);
+    /// Status line printed above the prompt, if any.
+    static STATUS: RefCell<Option<String>> = RefCell::new(None);
+    /// True if the status line and the prompt are currently on screen.
+    static SHOWN: Cell<bool> = Cell::new(false);
+    static VERBOSITY: Cell<Verbosity> = Cell::new(Verbosity::Normal);
+    static PLAIN: Cell<bool> = Cell::new(false);
+    /// Where the lines go.
+    static SINKS: RefCell<Vec<Box<OutputSink>>> = RefCell::new(vec![default_sink()]);
+}
+
+/// What a line that we display is, for the sinks that tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// A message of a room.
+    Message,
+    /// The answer to a command, or an error.
+    Reply,
+    /// See `chatter`.
+    Chatter,
+    /// See `event`.
+    Event,
+}
+
+impl Output {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Output::Message => "message",
+            Output::Reply => "reply",
+            Output::Chatter => "chatter",
+            Output::Event => "event",
+        }
+    }
+}
+
+/// Somewhere the lines that we display are written.
+pub trait OutputSink {
+    fn write(&mut self, output: Output, line: &str);
+}
+
+/// Writes on the terminal, around the prompt and the status line.
+#[cfg(not(target_os = "emscripten"))]
+pub struct Terminal;
+
+#[cfg(not(target_os = "emscripten"))]
+impl OutputSink for Terminal {
+    fn write(&mut self, output: Output, line: &str) {
+        match output {
+            Output::Message | Output::Event => {
+                clear_prompt();
+                println!("{}", line);
+                restore_prompt();
+            }
+            Output::Reply | Output::Chatter => println!("{}", line),
+        }
+    }
+}
+
+#[cfg(not(target_os = "emscripten"))]
+fn default_sink() -> Box<OutputSink> {
+    Box::new(Terminal)
+}
+#[cfg(target_os = "emscripten")]
+fn default_sink() -> Box<OutputSink> {
+    use sinks::Page;
+
+    Box::new(Page)
+}
+
+/// Writes the lines to `sink` too from now on.
+pub fn add_sink(sink: Box<OutputSink>) {
+    SINKS.with(|sinks| sinks.borrow_mut().push(sink));
+}
+
+/// Writes `line` to all the sinks.
+pub fn show(output: Output, line: &str) {
+    SINKS.with(|sinks| {
+        for sink in sinks.borrow_mut().iter_mut() {
+            sink.write(output, line);
+        }
+    });
+}
+
+/// Displays a message of a room.
+pub fn message(line: &str) {
+    show(Output::Message, line);
+}
+
+/// Displays the answer to a command. `say!` translates it first.
+pub fn reply(line: &str) {
+    show(Output::Reply, line);
+}
+
+pub fn set_plain(plain: bool) {
+    PLAIN.with(|p| p.set(plain));
+}
+
+/// Returns true if we may print escape codes, such as colors.
+pub fn decorated() -> bool {
+    cfg!(not(target_os = "emscripten")) && !PLAIN.with(|p| p.get())
+}
+
+/// Returns `fancy`, or its ASCII replacement `plain` with `--plain`.
+pub fn symbol(fancy: &'static str, plain: &'static str) -> &'static str {
+    if PLAIN.with(|p| p.get()) {
+        plain
+    } else {
+        fancy
+    }
+}
+
+/// Returns what goes in front of a message: the current time, in UTC, with `--plain`.
+pub fn timestamp() -> String {
+    if !PLAIN.with(|p| p.get()) {
+        return String::new();
+    }
+    format!("[{} UTC] ", clock())
+}
+
+/// Returns the current time of day, in UTC.
+pub fn clock() -> String {
+    let secs = envelope::now() % 86_400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// `-q`: only the messages and the answers to commands.
+    Quiet,
+    Normal,
+    /// `-v`: also the connections and the dials.
+    Verbose,
+    /// `-vv`: also every message sent and received.
+    Debug,
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.with(|v| v.set(verbosity));
+}
+
+/// Prints a line about what happens in the network, such as a peer leaving, unless `-q` was
+/// passed.
+pub fn chatter(line: &str) {
+    if VERBOSITY.with(|v| v.get()) > Verbosity::Quiet {
+        show(Output::Chatter, line);
+    }
+}
+
+/// Prints a protocol-level event if we are at least as verbose as `level`. Events happen
+/// outside of the handling of messages and commands, so they take care of the prompt.
+pub fn event(level: Verbosity, line: &str) {
+    if VERBOSITY.with(|v| v.get()) >= level {
+        show(Output::Event, &format!("~ {}", line));
+    }
+}
+
+/// Makes `line` stand out, for example because it mentions us.
+pub fn highlight(line: &str) -> String {
+    if !decorated() {
+        format!(">>> {}", line)
+    } else {
+        format!("\x1b[1;33m{}\x1b[0m", line)
+    }
+}
+
+/// Sets the prompt, which shows the current room, and prints it. Prompts are only used on
+/// terminals.
+pub fn set_prompt(room: Option<String>) {
+    clear_prompt();
+    let prompt = room.filter(|_| platform::is_terminal() && decorated());
+    PROMPT.with(|p| *p.borrow_mut() = prompt);
+    restore_prompt();
+}
+
+/// Sets the status line, which stays just above the prompt, and prints it. Like prompts, status
+/// lines are only used on terminals.
+pub fn set_status(status: String) {
+    if !platform::is_terminal() || !decorated() {
+        return;
+    }
+    clear_prompt();
+    STATUS.with(|s| *s.borrow_mut() = Some(status));
+    restore_prompt();
+}
+
+/// Called once the user has typed `line` and pressed enter. The status line is then above the
+/// line that was typed, so we erase them both and print the line again without the status.
+pub fn line_typed(line: &str) {
+    if !SHOWN.with(|shown| shown.replace(false)) {
+        return;
+    }
+    if STATUS.with(|s| s.borrow().is_some()) {
+        println!("\x1b[2A\r\x1b[J{}{}", prompt(), line);
+    }
+}
+
+/// Erases the prompt and the status line, before printing a message.
+pub fn clear_prompt() {
+    if !SHOWN.with(|shown| shown.replace(false)) {
+        return;
+    }
+    if PROMPT.with(|p| p.borrow().is_some()) {
+        print!("\r\x1b[K");
+    }
+    if STATUS.with(|s| s.borrow().is_some()) {
+        print!("\x1b[1A\r\x1b[K");
+    }
+}
+
+/// Prints the status line and the prompt again, after a message or once the user has typed a
+/// line.
+pub fn restore_prompt() {
+    if SHOWN.with(|shown| shown.replace(true)) {
+        return;
+    }
+    STATUS.with(|s| {
+        if let Some(ref status) = *s.borrow() {
+            println!("\x1b[7m{}\x1b[0m", status);
+        }
+    });
+    print!("{}", prompt());
+    let _ = io::stdout().flush();
+}
+
+fn prompt() -> String {
+    PROMPT.with(|p| match *p.borrow() {
+        Some(ref room) => format!("{}> ", room),
+        None => String::new(),
+    })
+}
@@ -0,0 +1,350 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Explaining raw bytes field by field, for the `decode` subcommand.
+//!
+//! The input is a capture file written with `--capture` (see the `capture` module), another file,
+//! or bytes typed in hexadecimal. Each chunk of bytes is tried in turn as one of our envelopes, a
+//! multistream-select negotiation, floodsub frames, a multiaddress and a multihash. What isn't
+//! recognised is shown as a hex dump.
+
+use capture;
+use envelope::{self, Body};
+use export;
+use identity;
+use libp2p::{Multiaddr, PeerId};
+use serde_json;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+
+/// Known multihash functions, by code.
+const HASHES: &[(usize, &str)] = &[
+    (0x00, "identity"),
+    (0x11, "sha1"),
+    (0x12, "sha2-256"),
+    (0x13, "sha2-512"),
+    (0x14, "sha3-512"),
+    (0x16, "sha3-256"),
+];
+
+/// Decodes `input`, a file or hexadecimal bytes, and prints the explanation.
+pub fn run(input: &str) -> Result<(), IoError> {
+    let data = match fs::read(input) {
+        // A file can also hold bytes in hexadecimal, as copied from a debugger.
+        Ok(data) => {
+            let hex = ::std::str::from_utf8(&data).ok().and_then(parse_hex);
+            hex.unwrap_or(data)
+        }
+        Err(ref err) if err.kind() == ErrorKind::NotFound => match parse_hex(input) {
+            Some(data) => data,
+            None => {
+                let message = "neither a file nor hexadecimal bytes";
+                return Err(IoError::new(ErrorKind::InvalidInput, message));
+            }
+        },
+        Err(err) => return Err(err),
+    };
+
+    if !data.starts_with(capture::MAGIC) {
+        for line in explain(&data) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    let records = capture::parse(&data)?;
+    for record in &records {
+        let arrow = if record.sent { "->" } else { "<-" };
+        println!(
+            "{}.{:03} #{} {} {} ({} bytes)",
+            export::format_time(record.millis / 1000),
+            record.millis % 1000,
+            record.connection,
+            arrow,
+            record.address,
+            record.data.len()
+        );
+        for line in explain(&record.data) {
+            println!("    {}", line);
+        }
+    }
+    say!("{} records", records.len());
+    Ok(())
+}
+
+/// Parses bytes written in hexadecimal, possibly prefixed with `0x` and separated by spaces or
+/// colons.
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    let text = text.trim_left_matches("0x");
+    let digits: Vec<u8> = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':')
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<_>>()?;
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
+
+/// Returns the lines explaining `data`.
+pub fn explain(data: &[u8]) -> Vec<String> {
+    envelope(data)
+        .or_else(|| negotiation(data))
+        .or_else(|| floodsub(data))
+        .or_else(|| multiaddr(data))
+        .or_else(|| multihash(data))
+        .unwrap_or_else(|| {
+            let mut lines = vec![format!("{} bytes, not recognised:", data.len())];
+            lines.extend(indent(hexdump(data)));
+            lines
+        })
+}
+
+fn indent(lines: Vec<String>) -> Vec<String> {
+    lines.into_iter().map(|line| format!("  {}", line)).collect()
+}
+
+fn envelope(data: &[u8]) -> Option<Vec<String>> {
+    let envelope = envelope::decode(data)?;
+    let mut lines = vec![format!("envelope, {} bytes of JSON", data.len())];
+    lines.push(match envelope.version {
+        Some(ref version) => format!("  version: {}", version),
+        None => "  version: missing, so 1".to_owned(),
+    });
+    lines.push(format!(
+        "  public key: {}, so the peer ID {}",
+        identity::encode_key(&envelope.public_key),
+        PeerId::from_public_key(&envelope.public_key).to_base58()
+    ));
+    let valid = identity::verify(
+        &envelope.public_key,
+        &envelope.signed_bytes(),
+        &envelope.signature,
+    );
+    lines.push(format!(
+        "  signature: {} bytes, {}",
+        envelope.signature.len(),
+        if valid { "valid" } else { "INVALID" }
+    ));
+    let known = serde_json::from_slice::<Body>(&envelope.body).is_ok();
+    lines.push(format!(
+        "  body: {} bytes of JSON{}",
+        envelope.body.len(),
+        if known { "" } else { ", which this version doesn't understand" }
+    ));
+    match serde_json::from_slice::<serde_json::Value>(&envelope.body) {
+        Ok(body) => {
+            let pretty = serde_json::to_string_pretty(&body).unwrap_or_default();
+            lines.extend(indent(pretty.lines().map(|line| line.to_owned()).collect()));
+        }
+        Err(_) => lines.extend(indent(hexdump(&envelope.body))),
+    }
+    Some(lines)
+}
+
+/// Explains a multistream-select chunk: lines prefixed by their length as a varint and ending
+/// with a newline.
+fn negotiation(data: &[u8]) -> Option<Vec<String>> {
+    let mut lines = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (len, prefix) = varint(rest)?;
+        if len == 0 || rest.len() < prefix + len || rest[prefix + len - 1] != b'\n' {
+            return None;
+        }
+        let line = ::std::str::from_utf8(&rest[prefix..prefix + len - 1]).ok()?;
+        if line.chars().any(|c| c.is_control()) {
+            return None;
+        }
+        lines.push(format!("multistream-select: {:?} ({}-byte length prefix)", line, prefix));
+        rest = &rest[prefix + len..];
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines)
+    }
+}
+
+/// Explains floodsub frames: protobuf `RPC` messages, each prefixed by its length as a varint.
+fn floodsub(data: &[u8]) -> Option<Vec<String>> {
+    let mut lines = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (len, prefix) = varint(rest)?;
+        if rest.len() < prefix + len {
+            return None;
+        }
+        lines.push(format!("floodsub frame, {} bytes", len));
+        for (field, value) in fields(&rest[prefix..prefix + len])? {
+            match (field, value) {
+                (1, Value::Bytes(bytes)) => lines.extend(indent(subscription(bytes)?)),
+                (2, Value::Bytes(bytes)) => lines.extend(indent(message(bytes)?)),
+                _ => return None,
+            }
+        }
+        rest = &rest[prefix + len..];
+    }
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines)
+    }
+}
+
+/// Explains a protobuf `SubOpts`.
+fn subscription(data: &[u8]) -> Option<Vec<String>> {
+    let mut subscribe = None;
+    let mut topic = None;
+    for (field, value) in fields(data)? {
+        match (field, value) {
+            (1, Value::Varint(value)) => subscribe = Some(value != 0),
+            (2, Value::Bytes(bytes)) => topic = Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => return None,
+        }
+    }
+    let action = match subscribe {
+        Some(true) => "subscribe to",
+        Some(false) => "unsubscribe from",
+        None => "(un)subscribe to",
+    };
+    Some(vec![format!("{} the topic {:?}", action, topic.unwrap_or_default())])
+}
+
+/// Explains a protobuf `Message`, whose data is explained in turn.
+fn message(data: &[u8]) -> Option<Vec<String>> {
+    let mut lines = vec!["published message".to_owned()];
+    let mut payload = None;
+    for (field, value) in fields(data)? {
+        match (field, value) {
+            (1, Value::Bytes(bytes)) => {
+                let source = match PeerId::from_bytes(bytes.to_vec()) {
+                    Ok(peer_id) => peer_id.to_base58(),
+                    Err(_) => hex(bytes),
+                };
+                lines.push(format!("  from: {}", source));
+            }
+            (2, Value::Bytes(bytes)) => payload = Some(bytes),
+            (3, Value::Bytes(bytes)) => lines.push(format!("  sequence number: {}", hex(bytes))),
+            (4, Value::Bytes(bytes)) => {
+                lines.push(format!("  topic: {:?}", String::from_utf8_lossy(bytes)))
+            }
+            _ => return None,
+        }
+    }
+    if let Some(payload) = payload {
+        lines.push(format!("  data, {} bytes:", payload.len()));
+        lines.extend(indent(explain(payload)));
+    }
+    Some(lines)
+}
+
+fn multiaddr(data: &[u8]) -> Option<Vec<String>> {
+    let address = Multiaddr::from_bytes(data.to_vec()).ok()?;
+    let mut lines = vec![format!("multiaddress {}", address)];
+    for component in address.iter() {
+        lines.push(format!("  {:?}", component));
+    }
+    Some(lines)
+}
+
+fn multihash(data: &[u8]) -> Option<Vec<String>> {
+    let (code, prefix) = varint(data)?;
+    let name = HASHES.iter().find(|&&(known, _)| known == code)?.1;
+    let (len, len_prefix) = varint(&data[prefix..])?;
+    let digest = &data[prefix + len_prefix..];
+    if digest.len() != len {
+        return None;
+    }
+    let mut lines = vec![format!("multihash, {} bytes", data.len())];
+    lines.push(format!("  function: {} (code 0x{:02x})", name, code));
+    lines.push(format!("  digest, {} bytes: {}", len, hex(digest)));
+    if let Ok(peer_id) = PeerId::from_bytes(data.to_vec()) {
+        lines.push(format!("  which is the peer ID {}", peer_id.to_base58()));
+    }
+    Some(lines)
+}
+
+/// Value of a protobuf field. We only need the wire types that floodsub uses.
+enum Value<'a> {
+    Varint(usize),
+    Bytes(&'a [u8]),
+}
+
+/// Splits a protobuf message into its fields, or returns `None` if it isn't one.
+fn fields(data: &[u8]) -> Option<Vec<(usize, Value)>> {
+    let mut fields = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (key, len) = varint(rest)?;
+        rest = &rest[len..];
+        let value = match key & 7 {
+            0 => {
+                let (value, len) = varint(rest)?;
+                rest = &rest[len..];
+                Value::Varint(value)
+            }
+            2 => {
+                let (size, len) = varint(rest)?;
+                if rest.len() < len + size {
+                    return None;
+                }
+                let bytes = &rest[len..len + size];
+                rest = &rest[len + size..];
+                Value::Bytes(bytes)
+            }
+            _ => return None,
+        };
+        fields.push((key >> 3, value));
+    }
+    Some(fields)
+}
+
+/// Decodes an unsigned varint, returning its value and the number of bytes it took.
+pub fn varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, &byte) in data.iter().enumerate().take(9) {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Formats `data` as lines of 16 bytes, in hexadecimal then in ASCII.
+fn hexdump(data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|&byte| if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' })
+                .collect();
+            format!("{:04x}  {:<47}  |{}|", i * 16, hex.join(" "), ascii)
+        })
+        .collect()
+}
@@ -0,0 +1,176 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! `doctor`, which checks that the environment lets the chat work.
+//!
+//! Before a workshop, the network of the venue is the usual culprit: a firewall that drops
+//! multicast, a DNS that doesn't answer, a proxy that breaks websockets. Each check prints what
+//! it found, and what to do about it when it failed.
+
+use beacon;
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use options::Options;
+use ports;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// Host name resolved to check the DNS, when no reference server is given.
+const DNS_PROBE: &str = "example.com";
+const TIMEOUT_SECS: u64 = 3;
+
+/// Runs the checks and prints their results. Returns the number of checks that failed.
+pub fn run(options: &Options) -> usize {
+    let mut failed = 0;
+    {
+        let mut report = |label: String, result: Result<String, (String, &'static str)>| {
+            match result {
+                Ok(found) => say!("[ok]   {}: {}", label, found),
+                Err((found, advice)) => {
+                    failed += 1;
+                    say!("[FAIL] {}: {}", label, found);
+                    say!("       {}", tr!(advice));
+                }
+            }
+        };
+
+        for address in &options.listen {
+            report(tr!("Listening on {}", address), check_port(address));
+        }
+        let host = options
+            .reference_server
+            .as_ref()
+            .and_then(host_name)
+            .unwrap_or_else(|| DNS_PROBE.to_owned());
+        report(tr!("Resolving {}", host), check_dns(&host));
+        report(tr!("Multicast beacons"), check_multicast());
+        match options.reference_server {
+            Some(ref server) => report(tr!("Websockets to {}", server), check_websockets(server)),
+            None => say!("[skip] {}", tr!("Pass --server to check websockets against a node")),
+        }
+    }
+    failed
+}
+
+fn check_port(address: &Multiaddr) -> Result<String, (String, &'static str)> {
+    let (ip, port) = match ports::socket_address(address) {
+        Some(socket_address) => socket_address,
+        None => return Ok(tr!("not a TCP address, nothing to check")),
+    };
+    match TcpListener::bind(SocketAddr::new(ip, port)) {
+        Ok(_) => Ok(tr!("port {} is free", port)),
+        Err(err) => Err((
+            err.to_string(),
+            "Another program uses this port; the chat will pick another one, or pass --listen",
+        )),
+    }
+}
+
+fn check_dns(host: &str) -> Result<String, (String, &'static str)> {
+    match (host, 80).to_socket_addrs() {
+        Ok(mut addresses) => match addresses.next() {
+            Some(address) => Ok(address.ip().to_string()),
+            None => Err((tr!("no address"), "Dial the nodes by their IP address instead")),
+        },
+        Err(err) => Err((err.to_string(), "Dial the nodes by their IP address instead")),
+    }
+}
+
+/// Sends a beacon-like datagram to the multicast group of the beacons, and waits for it to come
+/// back to us.
+fn check_multicast() -> Result<String, (String, &'static str)> {
+    const ADVICE: &str = "The nodes won't find each other by themselves; use --dial";
+    let probe = || -> Result<(), IoError> {
+        let group = Ipv4Addr::from(beacon::GROUP);
+        let socket = UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0), 0))?;
+        socket.join_multicast_v4(&group, &Ipv4Addr::new(0, 0, 0, 0))?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+        let local_port = socket.local_addr()?.port();
+        // We send to our own port, so that the chats running on this machine don't see it.
+        socket.send_to(b"rustfest-chat-doctor", (group, local_port))?;
+        let mut buffer = [0; 64];
+        socket.recv_from(&mut buffer).map(|_| ())
+    };
+    match probe() {
+        Ok(()) => Ok(tr!("multicast works")),
+        Err(err) => Err((err.to_string(), ADVICE)),
+    }
+}
+
+/// Opens a TCP connection to `server` and asks for a websockets upgrade.
+fn check_websockets(server: &Multiaddr) -> Result<String, (String, &'static str)> {
+    const ADVICE: &str = "Check the address, or whether a firewall or proxy blocks the connection";
+    let probe = || -> Result<String, IoError> {
+        let host = host_name(server).ok_or_else(|| error("not an address with a host"))?;
+        let port = server
+            .iter()
+            .filter_map(|component| match component {
+                AddrComponent::TCP(port) => Some(port),
+                _ => None,
+            })
+            .next()
+            .ok_or_else(|| error("not a TCP address"))?;
+        let address = (&host[..], port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| error("no address"))?;
+        let timeout = Duration::from_secs(TIMEOUT_SECS);
+        let mut stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\nHost: {}:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            host, port
+        )?;
+        let mut response = [0; 256];
+        let len = stream.read(&mut response)?;
+        let status = String::from_utf8_lossy(&response[..len])
+            .lines()
+            .next()
+            .unwrap_or("")
+            .to_owned();
+        if status.split_whitespace().nth(1) == Some("101") {
+            Ok(status)
+        } else {
+            Err(error(&format!("unexpected answer: {}", status)))
+        }
+    };
+    probe().map_err(|err| (err.to_string(), ADVICE))
+}
+
+/// Returns the IP address or the domain name of `address`.
+fn host_name(address: &Multiaddr) -> Option<String> {
+    address
+        .iter()
+        .filter_map(|component| match component {
+            AddrComponent::IP4(ip) => Some(ip.to_string()),
+            AddrComponent::IP6(ip) => Some(ip.to_string()),
+            AddrComponent::DNS4(name) | AddrComponent::DNS6(name) => Some(name),
+            _ => None,
+        })
+        .next()
+}
+
+fn error(message: &str) -> IoError {
+    IoError::new(ErrorKind::Other, message.to_owned())
+}
@@ -0,0 +1,85 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Stopping a relay without stranding the nodes that joined through it.
+//!
+//! `/drain`, or `kill -TERM`, puts the node in the draining state. From then on, `admission`
+//! turns away every node that dials us, and the chat tells the others on the directory topic
+//! that we are restarting, with the relay to reconnect to: the one given to `/drain`, or else
+//! `--alternate`. Once the outbox is empty and `GRACE` has passed, to let the last messages
+//! leave the sockets, the node exits. The nodes that joined through us dial the alternate relay
+//! by themselves; see the `handoff` module.
+
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// How long we keep running after draining started, at least.
+const GRACE: Duration = Duration::from_secs(5);
+
+struct State {
+    /// When draining started.
+    since: Instant,
+    alternate: Option<Multiaddr>,
+}
+
+/// Whether the node is draining, shared between the admission of connections and the chat.
+#[derive(Clone, Default)]
+pub struct Drain {
+    state: Rc<RefCell<Option<State>>>,
+}
+
+impl Drain {
+    /// Starts draining. Returns false if we already were.
+    pub fn start(&self, alternate: Option<Multiaddr>) -> bool {
+        let mut state = self.state.borrow_mut();
+        if state.is_some() {
+            return false;
+        }
+        *state = Some(State {
+            since: Instant::now(),
+            alternate,
+        });
+        true
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.state.borrow().is_some()
+    }
+
+    /// Returns true once draining started long enough ago for us to exit.
+    pub fn grace_elapsed(&self) -> bool {
+        match *self.state.borrow() {
+            Some(ref state) => state.since.elapsed() >= GRACE,
+            None => false,
+        }
+    }
+
+    /// Explains to a node that dials us why it is turned away, if we are draining.
+    pub fn refusal(&self) -> Option<String> {
+        let state = self.state.borrow();
+        let state = state.as_ref()?;
+        Some(match state.alternate {
+            Some(ref alternate) => format!("this node is restarting; reconnect to {}", alternate),
+            None => "this node is restarting; try another relay".to_owned(),
+        })
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The echo mode of `--echo`, to test a client without a second machine.
+//!
+//! An echo node publishes back every text it receives, prefixed with `echo:`, in the room where
+//! it was received. Echoes are limited to one per second, and the texts that are already echoes
+//! aren't echoed, so that two echo nodes can't keep each other busy.
+
+use std::time::{Duration, Instant};
+
+const PREFIX: &str = "echo: ";
+/// Minimum number of milliseconds between two echoes.
+const INTERVAL_MS: u64 = 1000;
+
+#[derive(Debug, Default)]
+pub struct Echo {
+    last: Option<Instant>,
+}
+
+impl Echo {
+    /// Returns the echo of `text`, or `None` if we echoed another text too recently.
+    pub fn reply(&mut self, text: &str) -> Option<String> {
+        if text.starts_with(PREFIX.trim_right()) {
+            return None;
+        }
+        let now = Instant::now();
+        if let Some(last) = self.last {
+            if now.duration_since(last) < Duration::from_millis(INTERVAL_MS) {
+                return None;
+            }
+        }
+        self.last = Some(now);
+        Some(format!("{}{}", PREFIX, text))
+    }
+}
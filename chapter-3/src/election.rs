@@ -0,0 +1,98 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Leader election among the members of the room, using the *bully* algorithm.
+//!
+//! Peers are ordered by their `PeerId`. A peer that doesn't know of any live peer higher than
+//! itself declares itself coordinator, and keeps announcing it at each heartbeat. A peer that
+//! receives an announcement from a lower peer "bullies" it by announcing itself instead. When the
+//! coordinator stops sending heartbeats, the next highest peer takes over.
+//!
+//! There is no voting and no term numbers as in Raft: if the network is split, each half elects
+//! its own coordinator, and they agree again once the halves can talk to each other.
+
+use display;
+use libp2p::PeerId;
+use presence::Presence;
+use std::iter;
+
+pub struct Election {
+    local: PeerId,
+    coordinator: Option<PeerId>,
+}
+
+impl Election {
+    pub fn new(local: PeerId) -> Election {
+        Election {
+            local,
+            coordinator: None,
+        }
+    }
+
+    /// Called at each heartbeat. Returns true if we should announce that we are the coordinator.
+    pub fn tick(&mut self, presence: &Presence) -> bool {
+        let gone = match self.coordinator {
+            Some(ref coordinator) => *coordinator != self.local && !presence.is_alive(coordinator),
+            None => false,
+        };
+        if gone {
+            let coordinator = self.coordinator.take().expect("checked above");
+            display::chatter(&tr!("* Coordinator {} is gone", coordinator.to_base58()));
+        }
+
+        let highest = presence
+            .alive()
+            .chain(iter::once(&self.local))
+            .max_by(|a, b| a.as_bytes().cmp(b.as_bytes()))
+            .expect("the iterator contains at least the local peer")
+            .clone();
+        if highest == self.local {
+            self.set_coordinator(highest);
+        }
+        self.is_coordinator()
+    }
+
+    /// Called when `sender` announces that it is the coordinator. Returns true if we have a
+    /// higher ID and should announce ourselves instead.
+    pub fn handle_announcement(&mut self, sender: PeerId) -> bool {
+        if sender.as_bytes() < self.local.as_bytes() {
+            let local = self.local.clone();
+            self.set_coordinator(local);
+            return true;
+        }
+        self.set_coordinator(sender);
+        false
+    }
+
+    fn is_coordinator(&self) -> bool {
+        self.coordinator.as_ref() == Some(&self.local)
+    }
+
+    fn set_coordinator(&mut self, peer: PeerId) {
+        if self.coordinator.as_ref() != Some(&peer) {
+            if peer == self.local {
+                display::chatter(&tr!("* We are now coordinator"));
+            } else {
+                display::chatter(&tr!("* {} is now coordinator", peer.to_base58()));
+            }
+            self.coordinator = Some(peer);
+        }
+    }
+}
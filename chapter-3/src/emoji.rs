@@ -0,0 +1,85 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Expansion of `:shortcode:` emoji, such as `:tada:`.
+
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "\u{1f44d}"),
+    ("-1", "\u{1f44e}"),
+    ("clap", "\u{1f44f}"),
+    ("crab", "\u{1f980}"),
+    ("eyes", "\u{1f440}"),
+    ("fire", "\u{1f525}"),
+    ("heart", "\u{2764}\u{fe0f}"),
+    ("joy", "\u{1f602}"),
+    ("ok_hand", "\u{1f44c}"),
+    ("pray", "\u{1f64f}"),
+    ("rocket", "\u{1f680}"),
+    ("see_no_evil", "\u{1f648}"),
+    ("slightly_smiling_face", "\u{1f642}"),
+    ("smile", "\u{1f604}"),
+    ("sob", "\u{1f62d}"),
+    ("sparkles", "\u{2728}"),
+    ("tada", "\u{1f389}"),
+    ("thinking", "\u{1f914}"),
+    ("thumbsup", "\u{1f44d}"),
+    ("thumbsdown", "\u{1f44e}"),
+    ("warning", "\u{26a0}\u{fe0f}"),
+    ("wave", "\u{1f44b}"),
+    ("wink", "\u{1f609}"),
+    ("x", "\u{274c}"),
+    ("white_check_mark", "\u{2705}"),
+];
+
+/// Returns the emoji corresponding to `shortcode` (without the colons).
+pub fn lookup(shortcode: &str) -> Option<&'static str> {
+    SHORTCODES
+        .iter()
+        .find(|&&(code, _)| code == shortcode)
+        .map(|&(_, emoji)| emoji)
+}
+
+/// Replaces all the known `:shortcode:`s of `text` with the corresponding emoji. Unknown
+/// shortcodes are left untouched.
+pub fn expand(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(':') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let emoji = after
+            .find(':')
+            .and_then(|end| lookup(&after[..end]).map(|emoji| (end, emoji)));
+        match emoji {
+            Some((end, emoji)) => {
+                out.push_str(emoji);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push(':');
+                rest = after;
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,412 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The format of the messages that we publish over floodsub.
+//!
+//! Floodsub only transports opaque bytes and doesn't tell us who originally published a message
+//! (only which node relayed it to us). Every message is therefore wrapped in an `Envelope` that
+//! carries the public key of its author and a signature of its version and body. The bytes are
+//! written in base64; the first version of the protocol wrote them as arrays of numbers, about
+//! four times larger, and only signed the body.
+//!
+//! A message can be given a time to live, after which receivers drop it. We can't bound the
+//! number of hops instead: floodsub forwards the bytes it receives to the other peers before
+//! handing them to us, so there is no way for us to decrement a counter when relaying.
+
+use backfill::{Archived, Cursor};
+use identity::{self, Identity};
+use libp2p::PeerId;
+use metadata::Description;
+use moderation::SignedEntry;
+use pad::PadOp;
+use reports::SignedReport;
+use transcript::{self, Range};
+use serde_json;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use version;
+
+/// Maximum number of options of a poll.
+pub const MAX_POLL_OPTIONS: usize = 10;
+
+/// Maximum number of characters of the question of a poll, and of each of its options.
+pub const MAX_POLL_TEXT_LEN: usize = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Version of the protocol, see the `version` module. Missing in the envelopes of the clients
+    /// that predate versioning, which all spoke version 1.
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(with = "base64_bytes")]
+    pub public_key: Vec<u8>,
+    /// Signature of `Envelope::signed_bytes`.
+    #[serde(with = "base64_bytes")]
+    pub signature: Vec<u8>,
+    /// Serialized `Body`. We sign the serialized bytes rather than the structure itself so that
+    /// we don't depend on the serialization being canonical.
+    #[serde(with = "base64_bytes")]
+    pub body: Vec<u8>,
+}
+
+impl Envelope {
+    /// The bytes that `signature` covers.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        signed_bytes(self.version.as_ref().map(|version| &version[..]), &self.body)
+    }
+}
+
+/// The envelope of the clients of the first major version. We still decode it to tell that they
+/// are incompatible, and write it for the beacons of the `migration` module.
+#[derive(Serialize, Deserialize)]
+struct LegacyEnvelope {
+    #[serde(default)]
+    version: Option<String>,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl From<Envelope> for LegacyEnvelope {
+    fn from(envelope: Envelope) -> LegacyEnvelope {
+        LegacyEnvelope {
+            version: envelope.version,
+            public_key: envelope.public_key,
+            signature: envelope.signature,
+            body: envelope.body,
+        }
+    }
+}
+
+impl From<LegacyEnvelope> for Envelope {
+    fn from(legacy: LegacyEnvelope) -> Envelope {
+        Envelope {
+            version: legacy.version,
+            public_key: legacy.public_key,
+            signature: legacy.signature,
+            body: legacy.body,
+        }
+    }
+}
+
+/// Writes bytes as a base64 string.
+mod base64_bytes {
+    use base64;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        base64::decode(&text).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Body {
+    /// Random identifier of the message, chosen by its author.
+    pub id: u64,
+    /// Number of seconds since the UNIX epoch, according to the author's clock.
+    pub timestamp: u64,
+    /// Nickname chosen by the author, if any.
+    #[serde(default)]
+    pub nick: Option<String>,
+    /// Number of seconds after `timestamp` during which the message is relevant, if limited.
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    /// ID of the message this one answers, if any. See the `history` module.
+    #[serde(default)]
+    pub reply_to: Option<u64>,
+    pub kind: Kind,
+}
+
+/// The `room` of a message is written by `TopicNaming::label`, so that it doesn't reveal the name
+/// of a room with `--hash-topics`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Kind {
+    /// A regular chat message.
+    Text(String),
+    /// Several regular chat messages, sent together. See the `batch` module.
+    Batch(Vec<String>),
+    /// An action performed by the author, typed as `/me waves` and displayed as `* alice waves`.
+    Action(String),
+    /// Replaces the text of an earlier message of the author, identified as in the `history`
+    /// module.
+    Edit { message: u64, text: String },
+    /// Asks everyone to stop displaying an earlier message of the author.
+    Delete { message: u64 },
+    /// A reaction, usually an emoji, to an earlier message of anyone.
+    React { message: u64, reaction: String },
+    /// Pins a message in a room. `text` is the message as displayed.
+    Pin {
+        room: String,
+        message: u64,
+        text: String,
+    },
+    /// Removes a pin of the author.
+    Unpin { room: String, message: u64 },
+    /// What the author knows about a room: its description, and the base58 `PeerId`s of its
+    /// members.
+    RoomState {
+        room: String,
+        description: Option<Description>,
+        members: Vec<String>,
+    },
+    /// Moderation: the author asks everyone to ignore the given peer in the given room.
+    Ban { room: String, peer: String },
+    /// Moderation: revokes an earlier `Ban`.
+    Unban { room: String, peer: String },
+    /// Moderation: bans and unbans signed by the moderators of `room`, published by them or
+    /// passed on to a peer who joins. See the `moderation` module.
+    Bans {
+        room: String,
+        entries: Vec<SignedEntry>,
+    },
+    /// A new poll, identified by the ID of the envelope. See `poll_fits`.
+    Poll { question: String, options: Vec<String> },
+    /// A vote for the poll with the given ID. `choice` starts at 0.
+    Vote { poll: u64, choice: usize },
+    /// An operation on a shared notepad, published on the topic of that pad.
+    Pad { name: String, op: PadOp },
+    /// Asks the nodes responsible for `key` to store `value`.
+    KvPut { key: String, value: String },
+    /// Asks the nodes responsible for `key` for its value.
+    KvGet { key: String, request: u64 },
+    /// Answer to a `KvGet`.
+    KvValue { request: u64, value: String },
+    /// Published periodically to tell the others that we are still here.
+    Heartbeat,
+    /// Multiaddresses at which the author can be dialed, published along with the heartbeats
+    /// when it knows its public address.
+    Addresses(Vec<String>),
+    /// Leader election: the author claims to be the coordinator of the room.
+    Coordinator,
+    /// The rooms the author is in, published on the directory topic.
+    Topics(Vec<String>),
+    /// The author replaces their key with `new_key`, which signed `tofu::rotation_proof` of the
+    /// key of the author as `proof`. Published on the directory topic.
+    Rotate { new_key: Vec<u8>, proof: Vec<u8> },
+    /// A reminder of `/remind` for the peer whose base58 `PeerId` is `to`, numbered `reminder` by
+    /// the author. Published on the directory topic. See the `schedule` module.
+    Reminder {
+        to: String,
+        reminder: u32,
+        text: String,
+    },
+    /// Tells the peer whose base58 `PeerId` is `to` that we got its reminder numbered `reminder`.
+    /// Published on the directory topic.
+    ReminderAck { to: String, reminder: u32 },
+    /// A report of an abusive message, for the moderators of the room. See the `reports` module.
+    Report(SignedReport),
+    /// The author is about to restart, and the nodes connected to it should reconnect to the
+    /// multiaddress `alternate`, if given. Published on the directory topic. See the `drain`
+    /// module.
+    Draining { alternate: Option<String> },
+    /// Asks the room for the messages older than `before`, or for the most recent ones, at most
+    /// `limit` of them. See the `backfill` module.
+    HistoryQuery {
+        request: u64,
+        before: Option<Cursor>,
+        limit: usize,
+    },
+    /// Answer to a `HistoryQuery` of the peer whose base58 `PeerId` is `to`, oldest first. `more`
+    /// tells whether the author remembers even older messages.
+    HistoryPage {
+        to: String,
+        request: u64,
+        messages: Vec<Archived>,
+        more: bool,
+    },
+    /// Asks for the digests of the messages of the room in `ranges`. Only the peer whose base58
+    /// `PeerId` is `peer` answers, or everyone if `None`. See the `transcript` module.
+    VerifyQuery {
+        request: u64,
+        peer: Option<String>,
+        ranges: Vec<Range>,
+    },
+    /// Answer to a `VerifyQuery` of the peer whose base58 `PeerId` is `to`.
+    VerifyAnswer {
+        to: String,
+        request: u64,
+        digests: Vec<transcript::Digest>,
+    },
+}
+
+/// A message whose signature has been verified.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub sender: PeerId,
+    pub public_key: Vec<u8>,
+    /// Protocol version of the envelope, if it told us.
+    pub version: Option<String>,
+    pub body: Body,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenError {
+    Malformed,
+    BadSignature,
+    /// The envelope is larger than `--max-message-size`.
+    TooLarge { size: usize, limit: usize },
+    /// The envelope was written with a protocol version we don't understand.
+    Incompatible { sender: PeerId, version: String },
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpenError::Malformed => write!(f, "malformed envelope"),
+            OpenError::BadSignature => write!(f, "invalid signature"),
+            OpenError::TooLarge { size, limit } => {
+                write!(f, "{} bytes long, but the limit is {} bytes", size, limit)
+            }
+            OpenError::Incompatible { ref version, .. } => write!(
+                f,
+                "protocol version {} is incompatible with ours ({})",
+                version,
+                version::PROTOCOL
+            ),
+        }
+    }
+}
+
+impl Body {
+    pub fn new(nick: Option<String>, kind: Kind) -> Body {
+        Body {
+            id: ::rand::random(),
+            timestamp: now(),
+            nick,
+            ttl: None,
+            reply_to: None,
+            kind,
+        }
+    }
+
+    /// Returns true if the time to live of the message has elapsed.
+    pub fn is_expired(&self) -> bool {
+        match self.ttl {
+            Some(ttl) => self.timestamp.saturating_add(ttl) < now(),
+            None => false,
+        }
+    }
+}
+
+/// Returns true if a poll is within `MAX_POLL_OPTIONS` and `MAX_POLL_TEXT_LEN`. Envelopes with a
+/// larger poll are malformed.
+pub fn poll_fits(question: &str, options: &[String]) -> bool {
+    let fits = |text: &str| text.chars().count() <= MAX_POLL_TEXT_LEN;
+    !options.is_empty()
+        && options.len() <= MAX_POLL_OPTIONS
+        && fits(question)
+        && options.iter().all(|option| fits(option))
+}
+
+/// Serializes and signs `body`, producing the bytes to publish over floodsub.
+pub fn seal(identity: &Identity, body: &Body) -> Vec<u8> {
+    seal_as(identity, body, version::PROTOCOL)
+}
+
+/// Like `seal`, but claims that the envelope was written with the protocol `version`, in the
+/// format of that version.
+pub fn seal_as(identity: &Identity, body: &Body, version: &str) -> Vec<u8> {
+    let body = serde_json::to_vec(body).expect("serializing a body never fails");
+    let envelope = Envelope {
+        version: Some(version.to_owned()),
+        public_key: identity.public_key().to_vec(),
+        signature: identity.sign(&signed_bytes(Some(version), &body)),
+        body,
+    };
+    let bytes = if version::major(version) == "1" {
+        serde_json::to_vec(&LegacyEnvelope::from(envelope))
+    } else {
+        serde_json::to_vec(&envelope)
+    };
+    bytes.expect("serializing an envelope never fails")
+}
+
+/// The bytes that the signature of an envelope covers: its version, prefixed by its length so
+/// that no part of the body can pass for the end of the version, then its body. Otherwise,
+/// anyone relaying the envelope could change its version. The first major version only signed
+/// the body.
+fn signed_bytes(version: Option<&str>, body: &[u8]) -> Vec<u8> {
+    match version {
+        Some(version) if version::major(version) != "1" => {
+            let mut bytes = format!("{}:{}", version.len(), version).into_bytes();
+            bytes.extend_from_slice(body);
+            bytes
+        }
+        _ => body.to_vec(),
+    }
+}
+
+/// Parses an envelope, in the format of the current version or of the first one, without
+/// verifying it.
+pub fn decode(data: &[u8]) -> Option<Envelope> {
+    match serde_json::from_slice::<Envelope>(data) {
+        Ok(envelope) => Some(envelope),
+        Err(_) => serde_json::from_slice::<LegacyEnvelope>(data)
+            .ok()
+            .map(Envelope::from),
+    }
+}
+
+/// Parses bytes received from floodsub and verifies their signature.
+pub fn open(data: &[u8]) -> Result<Received, OpenError> {
+    let envelope = decode(data).ok_or(OpenError::Malformed)?;
+    if !identity::verify(&envelope.public_key, &envelope.signed_bytes(), &envelope.signature) {
+        return Err(OpenError::BadSignature);
+    }
+    // Check the version before the body, whose format may have changed. Removing the version
+    // from an envelope makes it pass for one of the first version, which we don't accept either.
+    let version = envelope.version.clone().unwrap_or_else(|| "1".to_owned());
+    if !version::is_compatible(&version) {
+        return Err(OpenError::Incompatible {
+            sender: PeerId::from_public_key(&envelope.public_key),
+            version,
+        });
+    }
+    let body: Body = serde_json::from_slice(&envelope.body).map_err(|_| OpenError::Malformed)?;
+    if let Kind::Poll {
+        ref question,
+        ref options,
+    } = body.kind
+    {
+        if !poll_fits(question, options) {
+            return Err(OpenError::Malformed);
+        }
+    }
+    Ok(Received {
+        sender: PeerId::from_public_key(&envelope.public_key),
+        public_key: envelope.public_key,
+        version: envelope.version,
+        body,
+    })
+}
+
+/// Returns the current time as a number of seconds since the UNIX epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
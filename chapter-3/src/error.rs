@@ -0,0 +1,93 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The errors that stop the chat, and how they are shown.
+//!
+//! `main` only reports them and exits, so each one reads as a sentence meant for the user. They
+//! come in three families, with an exit code of their own:
+//!
+//! - `Config`: an option, the configuration file or a file that it names is wrong. The user can
+//!   correct it.
+//! - `Network`: we couldn't listen or connect. Often temporary, or due to another program.
+//! - `Internal`: a bug, which should be reported.
+//!
+//! `expect` remains for what can't fail, such as serializing a string, and for the events loop,
+//! where an error must be shown without stopping the chat.
+
+use std::error;
+use std::fmt;
+use std::io::Error as IoError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The message says what was wrong, and what was expected instead.
+    Config(String),
+    /// What we were doing, such as `can't listen on /ip4/0.0.0.0/tcp/63204/ws`, and why it
+    /// failed.
+    Network { what: String, err: IoError },
+    Internal(String),
+}
+
+impl Error {
+    /// An error of the configuration, caused by `err`.
+    pub fn config<E: fmt::Display>(what: &str, err: E) -> Error {
+        Error::Config(format!("{}: {}", what, err))
+    }
+
+    pub fn network(what: &str, err: IoError) -> Error {
+        Error::Network {
+            what: what.to_owned(),
+            err,
+        }
+    }
+
+    /// The code with which the process exits, from `sysexits.h`.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Config(_) => 78,
+            Error::Network { .. } => 69,
+            Error::Internal(_) => 70,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Config(ref message) => write!(f, "{}", message),
+            Error::Network { ref what, ref err } => write!(f, "{}: {}", what, err),
+            Error::Internal(ref message) => write!(
+                f,
+                "internal error, please report it along with what you were doing: {}",
+                message
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Config(_) => "configuration error",
+            Error::Network { .. } => "network error",
+            Error::Internal(_) => "internal error",
+        }
+    }
+}
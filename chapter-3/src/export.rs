@@ -0,0 +1,238 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Rendering of the history as a document, for `/export`.
+//!
+//! The room's recent messages become a Markdown or HTML transcript, the format being picked from
+//! the extension of the file. A message shows when the author says they sent it, in UTC. Replies
+//! quote what they answer, so the threads can be followed. Only what is still in the `history`
+//! can be exported.
+//!
+//! The same messages can also become an Atom feed, which the `/feed` of `--http` serves.
+
+use history::{Entry, History};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+    /// Not picked by `from_path`, since `/export` writes transcripts rather than feeds.
+    Atom,
+}
+
+impl Format {
+    /// Picks the format of the file at `path` from its extension.
+    pub fn from_path(path: &str) -> Option<Format> {
+        let extension = path.rsplit('.').next()?.to_lowercase();
+        match &extension[..] {
+            "md" | "markdown" => Some(Format::Markdown),
+            "html" | "htm" => Some(Format::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Renders `entries`, a part of `history`, as a transcript of `room`.
+pub fn render(room: &str, entries: &[&Entry], history: &History, format: Format) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Markdown => out.push_str(&format!("# {}\n\n", room)),
+        Format::Html => out.push_str(&format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+             </head>\n<body>\n<h1>{0}</h1>\n",
+            escape_html(room)
+        )),
+        Format::Atom => out.push_str(&format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+             <feed xmlns=\"http://www.w3.org/2005/Atom\">\n<title>{}</title>\n<id>{}</id>\n\
+             <updated>{}</updated>\n",
+            escape_html(room),
+            feed_id(room),
+            format_rfc3339(entries.iter().map(|entry| entry.timestamp).max().unwrap_or(0))
+        )),
+    }
+    for entry in entries {
+        let parent = entry.reply_to.and_then(|id| history.find(id));
+        match format {
+            Format::Markdown => render_markdown(&mut out, entry, parent),
+            Format::Html => render_html(&mut out, entry, parent),
+            Format::Atom => render_atom(&mut out, room, entry, parent),
+        }
+    }
+    match format {
+        Format::Markdown => {}
+        Format::Html => out.push_str("</body>\n</html>\n"),
+        Format::Atom => out.push_str("</feed>\n"),
+    }
+    out
+}
+
+fn render_markdown(out: &mut String, entry: &Entry, parent: Option<&Entry>) {
+    out.push_str(&format!("**{}** _{}_", entry.name, format_time(entry.timestamp)));
+    if entry.edited {
+        out.push_str(" (edited)");
+    }
+    out.push_str("\n\n");
+    if let Some(parent) = parent {
+        out.push_str(&format!("> {}\n\n", parent.snippet()));
+    }
+    if entry.deleted {
+        out.push_str("_deleted_\n\n");
+    } else {
+        // Two trailing spaces keep the line breaks of the message.
+        out.push_str(&entry.text.replace('\n', "  \n"));
+        out.push_str("\n\n");
+    }
+    if !entry.reactions.is_empty() {
+        out.push_str(&entry.render_reactions());
+        out.push_str("\n\n");
+    }
+}
+
+fn render_html(out: &mut String, entry: &Entry, parent: Option<&Entry>) {
+    out.push_str("<div class=\"message\">\n");
+    out.push_str(&format!(
+        "<p><b>{}</b> <time>{}</time>{}</p>\n",
+        escape_html(&entry.name),
+        format_time(entry.timestamp),
+        if entry.edited { " (edited)" } else { "" }
+    ));
+    if let Some(parent) = parent {
+        out.push_str(&format!("<blockquote>{}</blockquote>\n", escape_html(&parent.snippet())));
+    }
+    if entry.deleted {
+        out.push_str("<p><i>deleted</i></p>\n");
+    } else {
+        let text = escape_html(&entry.text).replace('\n', "<br>\n");
+        out.push_str(&format!("<p>{}</p>\n", text));
+    }
+    if !entry.reactions.is_empty() {
+        out.push_str(&format!("<p>{}</p>\n", escape_html(&entry.render_reactions())));
+    }
+    out.push_str("</div>\n");
+}
+
+/// An entry of the feed, whose content is the message as `render_html` shows it.
+fn render_atom(out: &mut String, room: &str, entry: &Entry, parent: Option<&Entry>) {
+    let mut content = String::new();
+    render_html(&mut content, entry, parent);
+    out.push_str(&format!(
+        "<entry>\n<id>{}:{}</id>\n<title>{}</title>\n<author><name>{}</name></author>\n\
+         <updated>{}</updated>\n<content type=\"html\">{}</content>\n</entry>\n",
+        feed_id(room),
+        entry.id,
+        escape_html(&entry.snippet()),
+        escape_html(&entry.name),
+        format_rfc3339(entry.timestamp),
+        escape_html(&content)
+    ));
+}
+
+/// The ID of the feed of `room`, which must stay the same across restarts.
+fn feed_id(room: &str) -> String {
+    let mut id = String::from("urn:chat:room:");
+    for byte in room.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._".contains(&byte) {
+            id.push(byte as char);
+        } else {
+            id.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    id
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats a number of seconds since the UNIX epoch as `2018-05-20 14:03:07 UTC`.
+pub fn format_time(timestamp: u64) -> String {
+    // Converts the number of days since 1970-01-01 to a civil date, as described in
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let secs = timestamp % 86_400;
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Formats a number of seconds since the UNIX epoch as `2018-05-20T14:03:07Z`, for Atom.
+fn format_rfc3339(timestamp: u64) -> String {
+    format_time(timestamp).replace(" UTC", "Z").replace(' ', "T")
+}
+
+/// Parses a time formatted by `format_time`.
+pub fn parse_time(time: &str) -> Option<u64> {
+    let mut numbers = time
+        .trim_right_matches(" UTC")
+        .split(|c| c == '-' || c == ' ' || c == ':')
+        .map(|n| n.parse::<i64>().ok());
+    let mut next = || numbers.next().and_then(|n| n);
+    let (year, month, day) = (next()?, next()?, next()?);
+    let (hours, minutes, seconds) = (next()?, next()?, next()?);
+
+    // The inverse of the conversion of `format_time`, from the same page.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let timestamp = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    if timestamp < 0 {
+        return None;
+    }
+    Some(timestamp as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atom_times_are_rfc3339() {
+        assert_eq!(format_rfc3339(1_526_825_000), "2018-05-20T14:03:20Z");
+    }
+
+    #[test]
+    fn feed_ids_escape_the_room() {
+        assert_eq!(feed_id("release notes/v1.0"), "urn:chat:room:release%20notes%2Fv1.0");
+    }
+}
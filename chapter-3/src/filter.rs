@@ -0,0 +1,104 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Spam filter.
+//!
+//! Messages are dropped if they match one of the patterns given with `--filter` or `/filter add`.
+//! Additionally, when a sender repeats the same message more than `--max-repeats` times in a
+//! row, the repetitions are hidden and replaced with a single summary once they stop.
+
+use libp2p::PeerId;
+use regex::{Error as RegexError, Regex};
+use std::collections::HashMap;
+
+pub enum Verdict {
+    /// The message must be displayed. If the sender had been repeating their previous message,
+    /// contains the total number of repetitions, in order to display a summary first.
+    Show(Option<usize>),
+    /// The message matches one of the patterns.
+    Drop,
+    /// The message is one repetition too many.
+    Collapse,
+}
+
+pub struct Filter {
+    patterns: Vec<Regex>,
+    max_repeats: usize,
+    /// Last message of each sender, and how many times in a row they sent it.
+    last: HashMap<PeerId, (String, usize)>,
+}
+
+impl Filter {
+    pub fn new(patterns: &[String], max_repeats: usize) -> Result<Filter, RegexError> {
+        let mut filter = Filter {
+            patterns: Vec::new(),
+            max_repeats,
+            last: HashMap::new(),
+        };
+        for pattern in patterns {
+            filter.add(pattern)?;
+        }
+        Ok(filter)
+    }
+
+    pub fn add(&mut self, pattern: &str) -> Result<(), RegexError> {
+        self.patterns.push(Regex::new(pattern)?);
+        Ok(())
+    }
+
+    /// Removes a pattern. Returns false if there was no such pattern.
+    pub fn remove(&mut self, pattern: &str) -> bool {
+        let before = self.patterns.len();
+        self.patterns.retain(|regex| regex.as_str() != pattern);
+        self.patterns.len() != before
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.patterns.iter().map(|regex| regex.as_str())
+    }
+
+    /// Decides what to do with `text`, sent by `sender`.
+    pub fn check(&mut self, sender: &PeerId, text: &str) -> Verdict {
+        if self.patterns.iter().any(|regex| regex.is_match(text)) {
+            return Verdict::Drop;
+        }
+
+        let max_repeats = self.max_repeats;
+        let entry = self
+            .last
+            .entry(sender.clone())
+            .or_insert_with(|| (String::new(), 0));
+        if entry.0 == text {
+            entry.1 += 1;
+            if entry.1 > max_repeats {
+                return Verdict::Collapse;
+            }
+            return Verdict::Show(None);
+        }
+
+        let repeated = entry.1;
+        *entry = (text.to_owned(), 1);
+        if repeated > max_repeats {
+            Verdict::Show(Some(repeated))
+        } else {
+            Verdict::Show(None)
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Export of the topology of the mesh, as far as we know it, in the DOT language of Graphviz.
+//!
+//! We know the addresses of our own connections, the addresses that the peers advertise, and the
+//! members of the rooms. Since connections are only known by their address, a connection is
+//! linked to a peer when the peer advertises the address we dialed. Inbound connections come from
+//! ephemeral ports and stay unnamed. Render with `dot -Tsvg mesh.dot > mesh.svg`.
+
+use libp2p::Multiaddr;
+use std::collections::BTreeSet;
+
+pub struct Graph {
+    local: String,
+    nodes: BTreeSet<String>,
+    edges: BTreeSet<String>,
+}
+
+impl Graph {
+    /// Starts a graph centered on our node, whose label is `local`.
+    pub fn new(local: &str) -> Graph {
+        let mut graph = Graph {
+            local: node("peer", "local"),
+            nodes: BTreeSet::new(),
+            edges: BTreeSet::new(),
+        };
+        let line = format!("{} [label={}, shape=doublecircle];", graph.local, quote(local));
+        graph.nodes.insert(line);
+        graph
+    }
+
+    /// One of our connections. Edges point from the dialer to the listener.
+    pub fn connection(&mut self, address: &Multiaddr, outbound: bool) {
+        let address = self.address(address);
+        let (from, to) = if outbound {
+            (self.local.clone(), address)
+        } else {
+            (address, self.local.clone())
+        };
+        self.edges.insert(format!("{} -> {};", from, to));
+    }
+
+    /// A peer other than us, identified by its base58 `PeerId`, with the addresses it advertises.
+    pub fn peer(&mut self, peer: &str, label: &str, addresses: &[Multiaddr]) {
+        let id = node("peer", peer);
+        self.nodes.insert(format!("{} [label={}];", id, quote(label)));
+        for address in addresses {
+            let address = self.address(address);
+            self.edges.insert(format!("{} -> {} [style=dashed, arrowhead=none];", id, address));
+        }
+    }
+
+    /// `peer`, identified by its base58 `PeerId` or `None` for us, is a member of `room`.
+    pub fn member(&mut self, room: &str, peer: Option<&str>) {
+        let id = node("room", room);
+        let line = format!("{} [label={}, shape=box];", id, quote(&format!("#{}", room)));
+        self.nodes.insert(line);
+        let peer = peer.map(|peer| node("peer", peer)).unwrap_or_else(|| self.local.clone());
+        self.edges.insert(format!("{} -> {} [style=dotted];", peer, id));
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph mesh {\n");
+        for line in self.nodes.iter().chain(&self.edges) {
+            dot.push_str("    ");
+            dot.push_str(line);
+            dot.push('\n');
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn address(&mut self, address: &Multiaddr) -> String {
+        let address = address.to_string();
+        let id = node("addr", &address);
+        let line = format!("{} [label={}, shape=plaintext];", id, quote(&address));
+        self.nodes.insert(line);
+        id
+    }
+}
+
+fn node(kind: &str, name: &str) -> String {
+    quote(&format!("{}:{}", kind, name))
+}
+
+/// Turns `text` into a DOT string literal.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
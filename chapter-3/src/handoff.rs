@@ -0,0 +1,102 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Following the relay we joined through when it drains.
+//!
+//! A relay that drains, as described in the `drain` module, tells everyone the relay to
+//! reconnect to. If we dialed nodes for floodsub, we remember those connections, and once all of
+//! them have closed, the drainer was likely among them, so we dial the alternate relay. Floodsub
+//! sends our subscriptions on every new connection: we are back in our rooms as soon as it opens.
+//!
+//! A message published while we have no floodsub connection at all reaches nobody. During a
+//! handoff, we keep a copy of those, up to `MAX_RESEND`, and publish them again once connected to
+//! the alternate relay. The ones published before went out through the drainer, which empties
+//! its outbox before exiting; resending them would only get us penalized for replays.
+
+use bytes::Bytes;
+use libp2p::floodsub::Topic;
+use libp2p::Multiaddr;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// We give up on a handoff that didn't complete this long after the notice.
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// Number of unsent messages kept for after the handoff, at most.
+const MAX_RESEND: usize = 64;
+
+/// What the chat has to do next.
+pub enum Step {
+    /// Our connections closed: dial the alternate relay.
+    Dial(Multiaddr),
+    /// We are connected to the alternate relay. Publish these messages again.
+    Done(Multiaddr, Vec<(Vec<Topic>, Bytes)>),
+    /// The alternate relay didn't answer in time.
+    Expired(Multiaddr),
+}
+
+pub struct Handoff {
+    alternate: Multiaddr,
+    started: Instant,
+    /// The addresses we had dialed for floodsub when the notice arrived.
+    watched: Vec<Multiaddr>,
+    dialed: bool,
+    unsent: VecDeque<(Vec<Topic>, Bytes)>,
+}
+
+impl Handoff {
+    /// Starts following the drain notice that sent us to `alternate`. `outbound` are the
+    /// addresses we dialed for floodsub.
+    pub fn new(alternate: Multiaddr, outbound: Vec<Multiaddr>) -> Handoff {
+        Handoff {
+            alternate,
+            started: Instant::now(),
+            watched: outbound,
+            dialed: false,
+            unsent: VecDeque::new(),
+        }
+    }
+
+    /// Keeps a copy of a message published while we had no floodsub connection.
+    pub fn unsent(&mut self, topics: Vec<Topic>, data: Bytes) {
+        if self.unsent.len() >= MAX_RESEND {
+            self.unsent.pop_front();
+        }
+        self.unsent.push_back((topics, data));
+    }
+
+    /// Advances the handoff, given the addresses we dialed for floodsub that are still open.
+    pub fn poll(&mut self, outbound: &[Multiaddr]) -> Option<Step> {
+        if self.started.elapsed() >= TIMEOUT {
+            return Some(Step::Expired(self.alternate.clone()));
+        }
+        if !self.dialed {
+            if self.watched.iter().any(|address| outbound.contains(address)) {
+                return None;
+            }
+            self.dialed = true;
+            return Some(Step::Dial(self.alternate.clone()));
+        }
+        if outbound.contains(&self.alternate) {
+            let unsent = self.unsent.drain(..).collect();
+            return Some(Step::Done(self.alternate.clone(), unsent));
+        }
+        None
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The recent messages of all the rooms, which later messages can refer to.
+//!
+//! Floodsub is an append-only broadcast medium: once published, a message can't be changed.
+//! Edits and deletions are therefore new messages that refer to the ID of the original one, and
+//! only the nodes that still remember the original can apply them.
+//!
+//! In commands, the user refers to messages by their position instead, `1` being the most recent
+//! one, as listed by `/history`.
+
+use display;
+use libp2p::PeerId;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+
+/// Number of messages that we remember.
+const CAPACITY: usize = 200;
+
+/// Number of characters of a message quoted in front of the replies to it.
+const SNIPPET_LEN: usize = 40;
+
+/// Maximum number of characters of a reaction. Reactions are meant to be an emoji or a word.
+pub const MAX_REACTION_LEN: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// ID of the message, see `line_id`.
+    pub id: u64,
+    pub room: String,
+    pub author: PeerId,
+    /// Name of the author, as displayed.
+    pub name: String,
+    /// When the author sent the message, in seconds since the UNIX epoch, on our clock.
+    pub timestamp: u64,
+    /// The timestamp as the author wrote it, before correcting it with `clocks`.
+    pub sent: u64,
+    pub text: String,
+    /// ID of the message this one answers, if any.
+    pub reply_to: Option<u64>,
+    /// The peers who reacted to the message, by reaction.
+    pub reactions: BTreeMap<String, HashSet<PeerId>>,
+    pub edited: bool,
+    /// Deleted messages are kept as tombstones, so that `/history` positions don't shift.
+    pub deleted: bool,
+}
+
+impl Entry {
+    /// The beginning of the message, to remind what a reply answers.
+    pub fn snippet(&self) -> String {
+        if self.deleted {
+            return format!("{}: [deleted]", self.name);
+        }
+        let mut snippet: String = self.text.chars().take(SNIPPET_LEN).collect();
+        if snippet.len() < self.text.len() {
+            snippet.push_str(display::symbol("…", "..."));
+        }
+        format!("{}: {}", self.name, snippet)
+    }
+
+    /// The text of the message, as displayed by `/history`.
+    pub fn render(&self) -> String {
+        let mut line = if self.deleted {
+            format!("{}: [deleted]", self.name)
+        } else if self.edited {
+            format!("{}: {} (edited)", self.name, self.text)
+        } else {
+            format!("{}: {}", self.name, self.text)
+        };
+        if !self.reactions.is_empty() {
+            line.push_str("  ");
+            line.push_str(&self.render_reactions());
+        }
+        line
+    }
+
+    /// The reactions and their number, for example `👍 2  🎉 1`.
+    pub fn render_reactions(&self) -> String {
+        let counts: Vec<String> = self
+            .reactions
+            .iter()
+            .map(|(reaction, peers)| format!("{} {}", reaction, peers.len()))
+            .collect();
+        counts.join("  ")
+    }
+}
+
+/// Returns the ID of the `index`th line of the message `id`. Batches carry several lines in one
+/// envelope, and every line needs its own ID to be referred to.
+pub fn line_id(id: u64, index: usize) -> u64 {
+    id.wrapping_add(index as u64)
+}
+
+pub struct History {
+    /// The most recent message last.
+    entries: VecDeque<Entry>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: VecDeque::with_capacity(CAPACITY),
+        }
+    }
+
+    pub fn push(&mut self, entry: Entry) {
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the `n`th most recent message, starting at 1.
+    pub fn get(&self, n: usize) -> Option<&Entry> {
+        self.entries.iter().rev().nth(n.checked_sub(1)?)
+    }
+
+    /// Iterates over the messages with their position, from the oldest to the most recent.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Entry)> {
+        let len = self.entries.len();
+        self.entries.iter().enumerate().map(move |(i, e)| (len - i, e))
+    }
+
+    /// Returns the message with the given ID, if we remember it.
+    pub fn find(&self, id: u64) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    /// Returns the thread that the `n`th most recent message is part of: the message that started
+    /// it, then all the replies we remember, each with its depth in the conversation.
+    pub fn thread(&self, n: usize) -> Vec<(usize, &Entry)> {
+        let mut root = match self.get(n) {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+        while let Some(parent) = root.reply_to.and_then(|id| self.find(id)) {
+            root = parent;
+        }
+
+        // Replies always come after what they answer, so a single pass finds them all.
+        let mut thread = vec![(0, root)];
+        for entry in self.entries.iter() {
+            let depth = entry
+                .reply_to
+                .and_then(|id| thread.iter().find(|&&(_, e)| e.id == id))
+                .map(|&(depth, _)| depth + 1);
+            if let Some(depth) = depth {
+                thread.push((depth, entry));
+            }
+        }
+        thread
+    }
+
+    /// Replaces the text of the message `id`, if `author` wrote it. Returns the message.
+    pub fn edit(&mut self, id: u64, author: &PeerId, text: String) -> Option<&Entry> {
+        let entry = self.find_mut(id, author)?;
+        entry.text = text;
+        entry.edited = true;
+        Some(entry)
+    }
+
+    /// Records that `peer` reacted to the message `id`. Returns the message, unless we don't
+    /// remember it or `peer` had already reacted that way.
+    pub fn react(&mut self, id: u64, peer: &PeerId, reaction: String) -> Option<&Entry> {
+        let entry = self.entries.iter_mut().find(|e| e.id == id && !e.deleted)?;
+        let added = entry
+            .reactions
+            .entry(reaction)
+            .or_insert_with(HashSet::new)
+            .insert(peer.clone());
+        if added {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Turns the message `id` into a tombstone, if `author` wrote it. Returns the message.
+    pub fn delete(&mut self, id: u64, author: &PeerId) -> Option<&Entry> {
+        let entry = self.find_mut(id, author)?;
+        entry.text.clear();
+        entry.deleted = true;
+        Some(entry)
+    }
+
+    fn find_mut(&mut self, id: u64, author: &PeerId) -> Option<&mut Entry> {
+        self.entries
+            .iter_mut()
+            .find(|e| e.id == id && e.author == *author && !e.deleted)
+    }
+
+    /// Forgets the messages of `room`, or of every room if `None`.
+    pub fn purge(&mut self, room: Option<&str>) {
+        self.entries
+            .retain(|e| room.map(|room| room != e.room).unwrap_or(false));
+    }
+}
@@ -0,0 +1,601 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The control HTTP server, which shows the state of the node to the programs and people around
+//! it.
+//!
+//! With `--http <address:port>`, we answer HTTP requests there. There is no authentication, so
+//! the address should only be reachable by those allowed to look, such as `127.0.0.1` behind a
+//! reverse proxy.
+//!
+//! A few pages are for the supervisors of a daemonized node:
+//!
+//! - `/healthz` answers as long as the event loop runs, since it is the one answering;
+//! - `/readyz` answers `200 OK` once we listen for connections and have at least
+//!   `--ready-peers` of them, and `503 Service Unavailable` otherwise, or while draining.
+//!
+//! With `--dashboard`, `/dashboard` is a page to project during the workshop, with the peers, the
+//! rate of the messages and the topology of the mesh. It follows `/events`, a stream of
+//! server-sent events that carries a `Status` as JSON every `EVENTS_INTERVAL`.
+//!
+//! Each `--feed <room>` is served at `/feed/<room>` as an Atom feed of its last `FEED_ENTRIES`
+//! messages, for those who follow an announcements room from a feed reader. Only the rooms given
+//! to `--feed` are, since anyone who reaches the address can read them.
+//!
+//! With `--http-publish`, bots can publish in our rooms, such as the `announcer` binary which
+//! announces the pushes and the CI results of a project. A `POST` of `/publish` whose body is
+//! `{"room": "...", "text": "..."}` publishes the text in that room, as if we had written it. It
+//! answers `422 Unprocessable Entity` if we aren't in the room or the text is too large. Whoever
+//! reaches the address can then speak for us.
+//!
+//! A client that takes longer than `REQUEST_TIMEOUT` to send its request and read the response
+//! is disconnected, so that slow clients can't hold all the `CONCURRENT_REQUESTS`.
+//!
+//! Only compiled with the `http-server` feature, which is on by default.
+
+use futures::{future, stream, Async, Future, Poll, Stream};
+use serde_json;
+use std::cell::{Cell, RefCell};
+use std::io::{Error as IoError, ErrorKind, Read};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::{Handle, Interval, Timeout};
+use tokio_io::io;
+
+/// Largest request we accept.
+const MAX_REQUEST: usize = 16 * 1024;
+/// Requests handled at the same time.
+const CONCURRENT_REQUESTS: usize = 16;
+/// Time a client has to send its request and read the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Clients of `/events` beyond which the others are turned away.
+const MAX_WATCHERS: usize = 8;
+/// Time between two events of `/events`.
+const EVENTS_INTERVAL: Duration = Duration::from_secs(1);
+/// The page of `--dashboard`.
+const DASHBOARD: &str = include_str!("dashboard.html");
+/// Messages in a feed. The history doesn't remember many more anyway.
+const FEED_ENTRIES: usize = 50;
+
+/// What the pages show of the node. Implemented by the chat.
+pub trait Node {
+    /// Number of connections currently open.
+    fn connections(&self) -> usize;
+    /// Whether the node is draining, see `/drain`.
+    fn is_draining(&self) -> bool;
+    /// What the dashboard shows.
+    fn status(&self) -> Status;
+    /// The Atom feed of the last `count` messages of `room`. See `export`.
+    fn feed(&self, room: &str, count: usize) -> String;
+    /// Publishes the `text` of a bot in `room`. Returns false if we aren't in the room, or if the
+    /// text is too large to be sent.
+    fn announce(&mut self, room: &str, text: String) -> bool;
+}
+
+/// The state of the node, as sent to the dashboard.
+#[derive(Serialize, Default)]
+pub struct Status {
+    /// Our nickname, or else our `PeerId`.
+    pub local: String,
+    /// The peers that are alive.
+    pub peers: Vec<PeerStatus>,
+    pub connections: Vec<ConnectionStatus>,
+    pub rooms: Vec<RoomStatus>,
+}
+
+#[derive(Serialize)]
+pub struct PeerStatus {
+    /// The `PeerId`, in base58.
+    pub id: String,
+    pub label: String,
+    /// The addresses it advertises, to tell which of our connections lead to it.
+    pub addresses: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ConnectionStatus {
+    pub address: String,
+    /// True if we dialed it.
+    pub outbound: bool,
+    /// Seconds since it opened.
+    pub age: u64,
+}
+
+#[derive(Serialize)]
+pub struct RoomStatus {
+    pub name: String,
+    /// The `PeerId`s of the members we know of, in base58.
+    pub members: Vec<String>,
+    /// Envelopes received and sent in the room since the start, from which the dashboard computes
+    /// the rates.
+    pub received: u64,
+    pub sent: u64,
+}
+
+/// What the server serves.
+pub struct Pages {
+    /// Whether we listen for connections, which `/readyz` requires.
+    pub listening: bool,
+    /// Connections below which `/readyz` fails.
+    pub ready_peers: usize,
+    /// Whether we serve `/dashboard` and `/events`.
+    pub dashboard: bool,
+    /// The rooms whose feed we serve.
+    pub feeds: Vec<String>,
+    /// Whether we accept the `POST`s of `/publish`.
+    pub publish: bool,
+}
+
+/// What to answer to a request.
+enum Reply {
+    Page(Response),
+    /// `/events`, which goes on until the client leaves.
+    Events,
+    /// `/publish`, which the node must do before we answer.
+    Publish(Publish),
+}
+
+/// The body of a `POST` of `/publish`.
+#[derive(Deserialize)]
+struct Publish {
+    room: String,
+    text: String,
+}
+
+/// Listens on `address`, and answers the requests until an error occurs on the listener.
+pub fn listen<N: Node + 'static>(
+    address: &SocketAddr,
+    handle: &Handle,
+    pages: Pages,
+    node: Rc<RefCell<N>>,
+) -> Result<impl Future<Item = (), Error = IoError>, IoError> {
+    let listener = TcpListener::bind(address, handle)?;
+    let pages = Rc::new(pages);
+    let watchers = Rc::new(Cell::new(0));
+    let handle = handle.clone();
+    let server = listener
+        .incoming()
+        .map(move |(socket, _)| {
+            let request = ReadRequest {
+                socket: Some(socket),
+                buffer: Vec::new(),
+            };
+            let (pages, node) = (pages.clone(), node.clone());
+            let (watchers, handle) = (watchers.clone(), handle.clone());
+            let timeout = future::result(Timeout::new(REQUEST_TIMEOUT, &handle)).flatten();
+            let exchange = request.and_then(move |(socket, request)| {
+                let reply = handle_request(&request, &pages, &*node.borrow());
+                let response = match reply {
+                    Reply::Page(response) => response,
+                    Reply::Events if watchers.get() < MAX_WATCHERS => {
+                        // The events don't hold one of the `CONCURRENT_REQUESTS`.
+                        let watcher = Watcher::new(watchers);
+                        handle.spawn(events(socket, node, watcher, &handle));
+                        return future::Either::A(future::ok(()));
+                    }
+                    Reply::Events => Response::new("503 Service Unavailable", ""),
+                    Reply::Publish(publish) => {
+                        if node.borrow_mut().announce(&publish.room, publish.text) {
+                            Response::new("204 No Content", "")
+                        } else {
+                            let reason = "not in this room, or too large\n";
+                            Response::new("422 Unprocessable Entity", reason)
+                        }
+                    }
+                };
+                let write = io::write_all(socket, response.into_bytes()).map(|_| ());
+                future::Either::B(write)
+            });
+            // A failed or timed out request only concerns its sender, whose socket is dropped.
+            exchange.select2(timeout).then(|_| Ok(()))
+        })
+        .buffer_unordered(CONCURRENT_REQUESTS)
+        .for_each(|()| Ok(()));
+    Ok(server)
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn new(status: &'static str, body: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.to_owned(),
+        }
+    }
+
+    fn html(body: &str) -> Response {
+        Response {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            body: body.to_owned(),
+        }
+    }
+
+    fn atom(body: String) -> Response {
+        Response {
+            status: "200 OK",
+            content_type: "application/atom+xml; charset=utf-8",
+            body,
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        ).into_bytes();
+        bytes.extend_from_slice(self.body.as_bytes());
+        bytes
+    }
+}
+
+fn handle_request<N: Node>(request: &Request, pages: &Pages, node: &N) -> Reply {
+    match (&request.method[..], &request.path[..]) {
+        ("GET", "/events") if pages.dashboard => Reply::Events,
+        ("GET", path) => Reply::Page(page(path, pages, node)),
+        ("POST", "/publish") if pages.publish => match serde_json::from_slice(&request.body) {
+            Ok(publish) => Reply::Publish(publish),
+            Err(_) => Reply::Page(Response::new("400 Bad Request", "")),
+        },
+        _ => Reply::Page(Response::new("405 Method Not Allowed", "")),
+    }
+}
+
+/// Answers a `GET` of `path`.
+fn page<N: Node>(path: &str, pages: &Pages, node: &N) -> Response {
+    match path {
+        "/healthz" => Response::new("200 OK", "ok\n"),
+        "/readyz" => match unready(pages, node) {
+            Some(reason) => Response::new("503 Service Unavailable", &format!("{}\n", reason)),
+            None => Response::new("200 OK", "ready\n"),
+        },
+        "/dashboard" if pages.dashboard => Response::html(DASHBOARD),
+        _ if path.starts_with("/feed/") => {
+            let room = decode(&path["/feed/".len()..]);
+            if pages.feeds.contains(&room) {
+                Response::atom(node.feed(&room, FEED_ENTRIES))
+            } else {
+                Response::new("404 Not Found", "")
+            }
+        }
+        _ => Response::new("404 Not Found", ""),
+    }
+}
+
+/// Decodes the `%XX` escapes of a path. Those that aren't valid are kept as they are.
+fn decode(path: &str) -> String {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = if byte == b'%' && tail.len() >= 2 {
+            ::std::str::from_utf8(&tail[..2])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(escaped) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Returns why the node isn't ready, if it isn't.
+fn unready<N: Node>(pages: &Pages, node: &N) -> Option<String> {
+    if !pages.listening {
+        return Some("not listening".to_owned());
+    }
+    if node.is_draining() {
+        return Some("draining".to_owned());
+    }
+    let connections = node.connections();
+    if connections < pages.ready_peers {
+        return Some(format!("{} of {} connections", connections, pages.ready_peers));
+    }
+    None
+}
+
+/// A client of `/events`, counted until it leaves.
+struct Watcher(Rc<Cell<usize>>);
+
+impl Watcher {
+    fn new(watchers: Rc<Cell<usize>>) -> Watcher {
+        watchers.set(watchers.get() + 1);
+        Watcher(watchers)
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+/// Sends the status of `node` to `socket` at once, then every `EVENTS_INTERVAL`, until the client
+/// leaves.
+fn events<N: Node + 'static>(
+    socket: TcpStream,
+    node: Rc<RefCell<N>>,
+    watcher: Watcher,
+    handle: &Handle,
+) -> Box<Future<Item = (), Error = ()>> {
+    let ticks = match Interval::new(EVENTS_INTERVAL, handle) {
+        Ok(ticks) => stream::once(Ok(())).chain(ticks),
+        Err(_) => return Box::new(future::ok(())),
+    };
+    let head = concat!(
+        "HTTP/1.1 200 OK\r\n",
+        "Content-Type: text/event-stream\r\n",
+        "Cache-Control: no-cache\r\n\r\n"
+    );
+    let events = io::write_all(socket, head)
+        .and_then(move |(socket, _)| {
+            ticks.fold(socket, move |socket, ()| {
+                let status = serde_json::to_string(&node.borrow().status())
+                    .expect("statuses always serialize");
+                let event = format!("data: {}\n\n", status);
+                io::write_all(socket, event.into_bytes()).map(|(socket, _)| socket)
+            })
+        })
+        .then(move |_| {
+            drop(watcher);
+            Ok(())
+        });
+    Box::new(events)
+}
+
+/// Reads an HTTP request, and its body if it has a `Content-Length`.
+struct ReadRequest {
+    socket: Option<TcpStream>,
+    buffer: Vec<u8>,
+}
+
+impl Future for ReadRequest {
+    type Item = (TcpStream, Request);
+    type Error = IoError;
+
+    fn poll(&mut self) -> Poll<(TcpStream, Request), IoError> {
+        loop {
+            if let Some(request) = parse_request(&self.buffer)? {
+                let socket = self.socket.take().expect("polled after completion");
+                return Ok(Async::Ready((socket, request)));
+            }
+            if self.buffer.len() > MAX_REQUEST {
+                return Err(IoError::new(ErrorKind::InvalidData, "the request is too large"));
+            }
+            let mut chunk = [0; 4096];
+            let read = self
+                .socket
+                .as_mut()
+                .expect("polled after completion")
+                .read(&mut chunk);
+            let len = match read {
+                Ok(len) => len,
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(err) => return Err(err),
+            };
+            if len == 0 {
+                return Err(IoError::new(ErrorKind::UnexpectedEof, "the request is incomplete"));
+            }
+            self.buffer.extend_from_slice(&chunk[..len]);
+        }
+    }
+}
+
+/// Parses `buffer` if it holds a whole request.
+fn parse_request(buffer: &[u8]) -> Result<Option<Request>, IoError> {
+    let end = match buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let head = String::from_utf8_lossy(&buffer[..end]);
+    let mut lines = head.split("\r\n");
+    let mut words = lines.next().unwrap_or("").split(' ');
+    let method = words.next().unwrap_or("").to_owned();
+    // The query string doesn't select anything here.
+    let path = words.next().unwrap_or("").split('?').next().unwrap_or("").to_owned();
+    let mut length = 0;
+    for line in lines {
+        let colon = match line.find(':') {
+            Some(colon) => colon,
+            None => continue,
+        };
+        if line[..colon].eq_ignore_ascii_case("content-length") {
+            length = line[colon + 1..]
+                .trim()
+                .parse()
+                .map_err(|_| IoError::new(ErrorKind::InvalidData, "invalid Content-Length"))?;
+        }
+    }
+    let body = &buffer[end + 4..];
+    if body.len() < length {
+        return Ok(None);
+    }
+    Ok(Some(Request {
+        method,
+        path,
+        body: body[..length].to_vec(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Mock {
+        connections: usize,
+        draining: bool,
+    }
+
+    impl Node for Mock {
+        fn connections(&self) -> usize {
+            self.connections
+        }
+
+        fn is_draining(&self) -> bool {
+            self.draining
+        }
+
+        fn status(&self) -> Status {
+            Status::default()
+        }
+
+        fn feed(&self, room: &str, _: usize) -> String {
+            format!("feed of {}", room)
+        }
+
+        fn announce(&mut self, _: &str, _: String) -> bool {
+            true
+        }
+    }
+
+    fn get(path: &str, pages: &Pages, node: &Mock) -> Response {
+        let request = format!("GET {}?probe=1 HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        let request = parse_request(request.as_bytes()).unwrap().unwrap();
+        match handle_request(&request, pages, node) {
+            Reply::Page(response) => response,
+            Reply::Events => Response::new("events", ""),
+            Reply::Publish(_) => Response::new("publish", ""),
+        }
+    }
+
+    fn pages(listening: bool, dashboard: bool) -> Pages {
+        Pages {
+            listening,
+            ready_peers: 2,
+            dashboard,
+            feeds: vec!["release notes".to_owned()],
+            publish: false,
+        }
+    }
+
+    #[test]
+    fn healthy_as_long_as_it_answers() {
+        let node = Mock {
+            connections: 0,
+            draining: false,
+        };
+        assert_eq!(get("/healthz", &pages(false, false), &node).status, "200 OK");
+    }
+
+    #[test]
+    fn ready_once_listening_with_enough_connections() {
+        let mut node = Mock {
+            connections: 1,
+            draining: false,
+        };
+        let response = get("/readyz", &pages(true, false), &node);
+        assert_eq!(response.status, "503 Service Unavailable");
+        assert_eq!(response.body, "1 of 2 connections\n");
+        node.connections = 2;
+        assert_eq!(get("/readyz", &pages(true, false), &node).status, "200 OK");
+        assert_eq!(get("/readyz", &pages(false, false), &node).body, "not listening\n");
+        node.draining = true;
+        assert_eq!(get("/readyz", &pages(true, false), &node).body, "draining\n");
+    }
+
+    #[test]
+    fn the_dashboard_is_only_served_with_dashboard() {
+        let node = Mock {
+            connections: 0,
+            draining: false,
+        };
+        assert_eq!(get("/dashboard", &pages(true, true), &node).status, "200 OK");
+        assert_eq!(get("/events", &pages(true, true), &node).status, "events");
+        assert_eq!(get("/dashboard", &pages(true, false), &node).status, "404 Not Found");
+        assert_eq!(get("/events", &pages(true, false), &node).status, "404 Not Found");
+        assert_eq!(get("/", &pages(true, true), &node).status, "404 Not Found");
+    }
+
+    #[test]
+    fn only_the_feeds_of_feed_are_served() {
+        let node = Mock {
+            connections: 0,
+            draining: false,
+        };
+        let response = get("/feed/release%20notes", &pages(true, false), &node);
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.body, "feed of release notes");
+        assert_eq!(get("/feed/general", &pages(true, false), &node).status, "404 Not Found");
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        assert_eq!(decode("a%2Fb%zz%4"), "a/b%zz%4");
+    }
+
+    #[test]
+    fn a_request_is_parsed_once_its_body_is_complete() {
+        assert!(parse_request(b"GET / HTTP/1.1\r\nHost: x\r\n").unwrap().is_none());
+        let post = b"POST /publish HTTP/1.1\r\nContent-Length: 4\r\n\r\n{}";
+        assert!(parse_request(post).unwrap().is_none());
+        let post = b"POST /publish HTTP/1.1\r\ncontent-length: 2\r\n\r\n{}";
+        assert_eq!(parse_request(post).unwrap().unwrap().body, b"{}");
+    }
+
+    #[test]
+    fn bots_only_publish_with_http_publish() {
+        let node = Mock {
+            connections: 0,
+            draining: false,
+        };
+        let body = r#"{"room": "general", "text": "Build 12 passed"}"#;
+        let request = format!(
+            "POST /publish HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let request = parse_request(request.as_bytes()).unwrap().unwrap();
+        let mut pages = pages(true, false);
+        match handle_request(&request, &pages, &node) {
+            Reply::Page(response) => assert_eq!(response.status, "405 Method Not Allowed"),
+            _ => panic!("/publish needs --http-publish"),
+        }
+        pages.publish = true;
+        match handle_request(&request, &pages, &node) {
+            Reply::Publish(publish) => assert_eq!(publish.text, "Build 12 passed"),
+            _ => panic!("the text should be published"),
+        }
+    }
+}
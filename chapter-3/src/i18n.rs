@@ -0,0 +1,1061 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Translation of the texts we print.
+//!
+//! The English text is its own key, as with gettext: `say!("* Joined {}", room)` prints the
+//! translation of `"* Joined {}"` in the current language, or the English text if there is none.
+//! Translations can use `{0}`, `{1}`... to put the arguments in another order.
+//!
+//! The language is picked with `--lang`, or else from the usual `LC_ALL`, `LC_MESSAGES` and
+//! `LANG` environment variables. The help of the command-line options, which clap wants before
+//! we know the language, and the protocol-level events of `-v` stay in English.
+//!
+//! To add a language, add a variant to `Lang` and a table like `FRENCH`.
+
+use std::cell::Cell;
+use std::env;
+use std::fmt::Display;
+
+/// `format!` with the translation of the format string. Only `{}` and `{0}`... are supported.
+macro_rules! tr {
+    ($text:expr) => {
+        ::i18n::tr($text).to_owned()
+    };
+    ($text:expr, $($arg:expr),+ $(,)*) => {
+        ::i18n::fill(::i18n::tr($text), &[$(&$arg as &::std::fmt::Display),+])
+    };
+}
+
+/// `println!` with the translation of the format string, as the answer to a command. See
+/// `display::reply`.
+macro_rules! say {
+    ($($args:tt)*) => {
+        ::display::reply(&tr!($($args)*))
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    English,
+    French,
+}
+
+impl Lang {
+    /// Parses a locale such as `fr`, `fr_FR.UTF-8` or `en-GB`.
+    pub fn from_locale(locale: &str) -> Option<Lang> {
+        let language = locale.split(|c| c == '_' || c == '-' || c == '.').next()?;
+        match &language.to_lowercase()[..] {
+            "en" | "c" | "posix" => Some(Lang::English),
+            "fr" => Some(Lang::French),
+            _ => None,
+        }
+    }
+
+    /// The language of the environment, English if it isn't one we know.
+    pub fn from_env() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|locale| !locale.is_empty())
+            .and_then(|locale| Lang::from_locale(&locale))
+            .unwrap_or(Lang::English)
+    }
+}
+
+thread_local! {
+    static LANG: Cell<Lang> = Cell::new(Lang::English);
+}
+
+pub fn set_lang(lang: Lang) {
+    LANG.with(|l| l.set(lang));
+}
+
+/// Returns the translation of `text`.
+pub fn tr(text: &'static str) -> &'static str {
+    let table = match LANG.with(|l| l.get()) {
+        Lang::English => return text,
+        Lang::French => FRENCH,
+    };
+    table
+        .iter()
+        .find(|&&(english, _)| english == text)
+        .map(|&(_, translated)| translated)
+        .unwrap_or(text)
+}
+
+/// Replaces the placeholders of `template` with `args`.
+pub fn fill(template: &str, args: &[&Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    let mut next = 0;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        let index = match &rest[start + 1..end] {
+            "" => {
+                next += 1;
+                Some(next - 1)
+            }
+            n => n.parse().ok(),
+        };
+        match index.and_then(|index| args.get(index)) {
+            Some(arg) => out.push_str(&arg.to_string()),
+            None => out.push_str(&rest[start..end + 1]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+const FRENCH: &[(&str, &str)] = &[
+    (
+        "* There is no configuration file to reload",
+        "* Il n'y a pas de fichier de configuration à recharger",
+    ),
+    ("* Configuration reloaded", "* Configuration rechargée"),
+    ("* Can't reload the configuration: {}", "* Impossible de recharger la configuration : {}"),
+    (
+        "* Invalid pattern {} in the configuration: {}",
+        "* Motif {} invalide dans la configuration : {}",
+    ),
+    (
+        "* Invalid address in the configuration: {}",
+        "* Adresse invalide dans la configuration : {}",
+    ),
+    (
+        "* {} uses protocol version {}, which is incompatible with ours ({}); ignoring their \
+         messages",
+        "* {} utilise la version {} du protocole, incompatible avec la nôtre ({}) ; ses messages \
+         sont ignorés",
+    ),
+    ("Dropped message: {}", "Message abandonné : {}"),
+    ("* {} = {} (from {})", "* {} = {} (de {})"),
+    ("* Ignoring {}, whose score dropped below {}", "* {} est ignoré, son score est passé sous {}"),
+    ("* The others can dial us at {}", "* Les autres peuvent nous joindre à {}"),
+    ("* No longer ignoring {}", "* {} n'est plus ignoré"),
+    ("* Couldn't write the graph to {}: {}", "* Impossible d'écrire le graphe dans {} : {}"),
+    ("* {} left (no news for {}s)", "* {} est parti (pas de nouvelles depuis {} s)"),
+    ("* {} sent the same message {}{}", "* {} a envoyé le même message {}{}"),
+    (" times", " fois"),
+    ("* {} asks: {}", "* {} demande : {}"),
+    ("* Vote with `/vote {} <n>`", "* Votez avec `/vote {} <n>`"),
+    ("* {} updated pad {}:", "* {} a modifié le bloc-notes {} :"),
+    ("* Closed pad {}", "* Bloc-notes {} fermé"),
+    ("* You haven't joined pad {}", "* Vous n'avez pas rejoint le bloc-notes {}"),
+    ("* Pad {}:", "* Bloc-notes {} :"),
+    (
+        "* You haven't joined pad {}; use `/pad {}` first",
+        "* Vous n'avez pas rejoint le bloc-notes {} ; utilisez d'abord `/pad {}`",
+    ),
+    ("* Pad {} has no such line", "* Le bloc-notes {} n'a pas cette ligne"),
+    (
+        "* You aren't in any room; use `/join <room>`",
+        "* Vous n'êtes dans aucun salon ; utilisez `/join <salon>`",
+    ),
+    ("* {} was {} by a moderator", "* {} a été {} par un modérateur"),
+    ("banned", "banni"),
+    ("unbanned", "réintégré"),
+    ("* banned: {}", "* banni : {}"),
+    (
+        "* Composing a multi-line message; type `{}` on its own line to send it",
+        "* Message sur plusieurs lignes ; tapez `{}` seul sur une ligne pour l'envoyer",
+    ),
+    (
+        "* Emoji shortcodes are now {} in your messages",
+        "* Les codes d'emoji sont maintenant {} dans vos messages",
+    ),
+    ("expanded", "remplacés"),
+    ("left as-is", "laissés tels quels"),
+    ("No such poll: {}", "Sondage inconnu : {}"),
+    ("This poll doesn't have an option {}", "Ce sondage n'a pas de choix {}"),
+    ("Not a valid multiaddress: {}", "Multiadresse invalide : {}"),
+    ("* Values are limited to {} bytes", "* Les valeurs sont limitées à {} octets"),
+    (
+        "* Stored {} locally, as we are one of the closest nodes",
+        "* {} est stocké ici, car nous sommes l'un des nœuds les plus proches",
+    ),
+    ("* {} = {} (stored locally)", "* {} = {} (stocké ici)"),
+    ("* {} (1 member)", "* {} (1 membre)"),
+    ("* {} ({} member{})", "* {} ({} membre{})"),
+    ("* filter: {}", "* filtre : {}"),
+    ("* Messages matching {} will be dropped", "* Les messages correspondant à {} seront ignorés"),
+    ("* Invalid pattern: {}", "* Motif invalide : {}"),
+    ("* There is no filter {}", "* Il n'y a pas de filtre {}"),
+    ("* You aren't in {}; use `/join {}`", "* Vous n'êtes pas dans {} ; utilisez `/join {}`"),
+    ("outbound", "sortante"),
+    ("inbound", "entrante"),
+    ("* {} ({}, {}, open for {}s)", "* {} ({}, {}, ouverte depuis {} s)"),
+    (
+        "* This forgets the messages and data of {}",
+        "* Cela oublie les messages et les données de {}",
+    ),
+    (
+        "* This forgets everything and deletes the files we keep, the identity included",
+        "* Cela oublie tout et supprime les fichiers que nous gardons, identité comprise",
+    ),
+    (
+        "* Type the same command again to confirm",
+        "* Tapez à nouveau la même commande pour confirmer",
+    ),
+    ("* Notifications in {}: {}", "* Notifications dans {} : {}"),
+    ("* The clipboard is empty", "* Le presse-papiers est vide"),
+    ("* Can't read the clipboard: {}", "* Impossible de lire le presse-papiers : {}"),
+    ("* That message was deleted", "* Ce message a été supprimé"),
+    ("* Only {} messages to copy from", "* Seulement {} messages à copier"),
+    ("* Copied to the clipboard", "* Copié dans le presse-papiers"),
+    ("* Can't write to the clipboard: {}", "* Impossible d'écrire dans le presse-papiers : {}"),
+    ("* Reactions are at most {} characters long", "* Les réactions font au plus {} caractères"),
+    (
+        "* That message is in {0}; use `/switch {0}` first",
+        "* Ce message est dans {0} ; utilisez d'abord `/switch {0}`",
+    ),
+    ("* That message is already pinned", "* Ce message est déjà épinglé"),
+    ("* There is no pin {} in {}", "* Il n'y a pas d'épingle {} dans {}"),
+    (
+        "* Only the peer who pinned a message can unpin it",
+        "* Seul le pair qui a épinglé un message peut le désépingler",
+    ),
+    ("* {}. {} (pinned by {})", "* {}. {} (épinglé par {})"),
+    ("* {}: {} (set by {})", "* {} : {} (par {})"),
+    (
+        "* {} has no description; set one with `/room describe`",
+        "* {} n'a pas de description ; définissez-en une avec `/room describe`",
+    ),
+    ("* Recently seen members: {}", "* Membres vus récemment : {}"),
+    (
+        "* Polls have at most {} options, and {} characters per text",
+        "* Les sondages ont au plus {} options, et {} caractères par texte",
+    ),
+    (
+        "* Descriptions are at most {} characters long",
+        "* Les descriptions font au plus {} caractères",
+    ),
+    ("* You: {}", "* Vous : {}"),
+    ("* {}: protocol {}", "* {} : protocole {}"),
+    (
+        "* Outbox: {}/{} messages queued, {} dropped",
+        "* File d'envoi : {}/{} messages en attente, {} abandonnés",
+    ),
+    ("* Open connections: {}", "* Connexions ouvertes : {}"),
+    ("* Peers seen recently: {}", "* Pairs vus récemment : {}"),
+    ("* Key-value records stored here: {}", "* Enregistrements clé-valeur stockés ici : {}"),
+    (
+        "* There is no line {} in the input history",
+        "* Il n'y a pas de ligne {} dans l'historique de saisie",
+    ),
+    (
+        "* {}: {} ({} duplicates, {} invalid signatures, {} over the rate limit)",
+        "* {} : {} ({} doublons, {} signatures invalides, {} au-delà de la limite de débit)",
+    ),
+    ("* Export to a .md or a .html file", "* Exportez vers un fichier .md ou .html"),
+    ("* Exported {} messages of {} to {}", "* {} messages de {} exportés dans {}"),
+    ("* Can't write {}: {}", "* Impossible d'écrire {} : {}"),
+    ("* Usage: {}", "* Utilisation : {}"),
+    ("* Aliases: /{}", "* Alias : /{}"),
+    (
+        "* Unknown command /{}, did you mean /{}?",
+        "* Commande /{} inconnue, vouliez-vous dire /{} ?",
+    ),
+    (
+        "* Unknown command /{}, type /help for the list",
+        "* Commande /{} inconnue, tapez /help pour la liste",
+    ),
+    ("* Joined {}", "* Vous avez rejoint {}"),
+    ("* You aren't in any room", "* Vous n'êtes dans aucun salon"),
+    ("* Left {}", "* Vous avez quitté {}"),
+    (
+        "* Not saving the configuration, {} is invalid: {}",
+        "* La configuration n'est pas enregistrée, {} est invalide : {}",
+    ),
+    (
+        "* Can't save the configuration to {}: {}",
+        "* Impossible d'enregistrer la configuration dans {} : {}",
+    ),
+    ("* Purged the local data of {}", "* Données locales de {} effacées"),
+    ("* Can't delete {}", "* Impossible de supprimer {}"),
+    (
+        "* The bot {} doesn't keep up, so it still has its values",
+        "* Le bot {} ne suit pas, il garde donc ses valeurs",
+    ),
+    (
+        "* Can't remove the sessions from {}: {}",
+        "* Impossible de retirer les sessions de {} : {}",
+    ),
+    (
+        "* Deleted {}; your identity only lives until you quit",
+        "* {} supprimé ; votre identité disparaîtra quand vous quitterez",
+    ),
+    ("* Can't delete {}: {}", "* Impossible de supprimer {} : {}"),
+    ("* Purged all the local data", "* Toutes les données locales ont été effacées"),
+    (
+        "* You aren't in any room anymore; use `/join <room>`",
+        "* Vous n'êtes plus dans aucun salon ; utilisez `/join <salon>`",
+    ),
+    ("* Now talking in {}", "* Vous parlez maintenant dans {}"),
+    (
+        "Only the moderators of the room can do that",
+        "Seuls les modérateurs du salon peuvent faire cela",
+    ),
+    ("Not a valid peer ID: {}", "Identifiant de pair invalide : {}"),
+    ("* There are only {} messages in the history", "* Il n'y a que {} messages dans l'historique"),
+    (
+        "* You can only change your own messages",
+        "* Vous ne pouvez modifier que vos propres messages",
+    ),
+    (
+        "* That message is in {}; use `/switch {}` first",
+        "* Ce message est dans {} ; utilisez d'abord `/switch {}`",
+    ),
+    (
+        "* Not sent: the message is {} bytes long, but the limit is {} bytes",
+        "* Non envoyé : le message fait {} octets, mais la limite est de {} octets",
+    ),
+    ("Our public key is {}", "Notre clé publique est {}"),
+    ("Now listening on {}", "En écoute sur {}"),
+    (
+        "* --serial requires the serial-transport feature",
+        "* --serial nécessite la fonctionnalité serial-transport",
+    ),
+    (
+        "* --capture requires the diagnostics feature",
+        "* --capture nécessite la fonctionnalité diagnostics",
+    ),
+    (
+        "* --bridge requires the broker-bridge feature",
+        "* --bridge nécessite la fonctionnalité broker-bridge",
+    ),
+    (
+        "* --http requires the http-server feature",
+        "* --http nécessite la fonctionnalité http-server",
+    ),
+    (
+        "* --accept-calls requires the calls feature",
+        "* --accept-calls nécessite la fonctionnalité calls",
+    ),
+    (
+        "* Tic-tac-toe requires the games feature",
+        "* Le morpion nécessite la fonctionnalité games",
+    ),
+    (
+        "* Voice calls require the calls feature",
+        "* Les appels vocaux nécessitent la fonctionnalité calls",
+    ),
+    (
+        "* Notepads require the pads feature",
+        "* Les blocs-notes nécessitent la fonctionnalité pads",
+    ),
+    (
+        "* The key-value store requires the dht feature",
+        "* Le stockage clé-valeur nécessite la fonctionnalité dht",
+    ),
+    ("* Beacons stopped: {}", "* Balises arrêtées : {}"),
+    ("* Couldn't start the beacons: {}", "* Impossible de démarrer les balises : {}"),
+    ("* Stopped listening for SIGHUP: {}", "* SIGHUP n'est plus écouté : {}"),
+    ("* Stopped recording: {}", "* Enregistrement arrêté : {}"),
+    ("* Stopped capturing: {}", "* Capture arrêtée : {}"),
+    ("{} records", "{} enregistrements"),
+    (
+        "Another node is already running with the identity file {}. Stop it, or pass another file \
+         with --identity.",
+        "Un autre nœud utilise déjà le fichier d'identité {}. Arrêtez-le, ou passez un autre \
+         fichier avec --identity.",
+    ),
+    (
+        "* Port {} is taken; now listening on {} instead",
+        "* Le port {} est pris ; écoute sur {} à la place",
+    ),
+    ("* Couldn't listen on {}", "* Impossible d'écouter sur {}"),
+    ("* Chaos dropped connection {}", "* Le chaos a coupé la connexion {}"),
+    (
+        "* Chaos is on: connections drop, messages are delayed and duplicated",
+        "* Le chaos est activé : les connexions tombent, les messages sont retardés et dupliqués",
+    ),
+    ("* You are {}", "* Vous êtes {}"),
+    ("* {}: {}", "* {} : {}"),
+    (
+        "* Pass --persona <name> to play other identities",
+        "* Passez --persona <nom> pour jouer d'autres identités",
+    ),
+    ("* You are already {}", "* Vous êtes déjà {}"),
+    (
+        "* There is no persona named {}; see /identity",
+        "* Aucun personnage ne s'appelle {} ; voir /identity",
+    ),
+    (
+        "* You are now {}, whose public key is {}",
+        "* Vous êtes maintenant {}, dont la clé publique est {}",
+    ),
+    (
+        "List the personas of --persona, or publish as another one",
+        "Lister les personnages de --persona, ou publier en tant qu'un autre",
+    ),
+    ("* Ignoring the known keys in {}: {}", "* Les clés connues de {} sont ignorées : {}"),
+    (
+        "* Can't save the known keys to {}: {}",
+        "* Impossible d'enregistrer les clés connues dans {} : {}",
+    ),
+    (
+        "* Careful: {} now signs with an unknown key instead of {}; it may be an impostor",
+        "* Attention : {} signe maintenant avec une clé inconnue au lieu de {} ; c'est peut-être \
+         un imposteur",
+    ),
+    (
+        "* Careful: {} signed with a key that was replaced by {}; it may have leaked",
+        "* Attention : {} a signé avec une clé remplacée par {} ; elle a peut-être fuité",
+    ),
+    (
+        "* {} replaced their key; their new public key is {}",
+        "* {} a remplacé sa clé ; sa nouvelle clé publique est {}",
+    ),
+    ("* Can't replace the key in {}: {}", "* Impossible de remplacer la clé de {} : {}"),
+    (
+        "* Replaced our key; our public key is now {}",
+        "* Notre clé a été remplacée ; notre clé publique est maintenant {}",
+    ),
+    (
+        "Replace our key with a new one, and revoke the old one",
+        "Remplacer notre clé par une nouvelle, et révoquer l'ancienne",
+    ),
+    ("* You hung up on {}", "* Vous avez raccroché l'appel avec {}"),
+    ("* You aren't in a call", "* Vous n'êtes pas en communication"),
+    ("* The call with {} ended", "* L'appel avec {} est terminé"),
+    (
+        "* Declined a call from {}: you are already in a call",
+        "* Appel de {} refusé : vous êtes déjà en communication",
+    ),
+    (
+        "* Declined a call from {}; pass --accept-calls to accept them",
+        "* Appel de {} refusé ; passez --accept-calls pour accepter les appels",
+    ),
+    ("* Can't call {}: {}", "* Impossible d'appeler {} : {}"),
+    (
+        "* In a call with {}; `/hangup` to end it",
+        "* En communication avec {} ; `/hangup` pour raccrocher",
+    ),
+    (
+        "Talk with the node at the address, through the microphone",
+        "Parler avec le nœud à cette adresse, avec le micro",
+    ),
+    ("End the call in progress", "Terminer l'appel en cours"),
+    (
+        "* Can't type in the shared terminal: {}",
+        "* Impossible d'écrire dans le terminal partagé : {}",
+    ),
+    ("* You aren't sharing your terminal", "* Vous ne partagez pas votre terminal"),
+    (
+        "* Stopped sharing your terminal with {}",
+        "* Vous ne partagez plus votre terminal avec {}",
+    ),
+    (
+        "* {} stopped sharing their terminal",
+        "* {} ne partage plus son terminal",
+    ),
+    (
+        "* You are already sharing your terminal; `/unshare` first",
+        "* Vous partagez déjà votre terminal ; faites d'abord `/unshare`",
+    ),
+    (
+        "* Can't share your terminal: {}",
+        "* Impossible de partager votre terminal : {}",
+    ),
+    (
+        "* Sharing your terminal with {}; `/unshare` to stop",
+        "* Terminal partagé avec {} ; `/unshare` pour arrêter",
+    ),
+    (
+        "* Declined the terminal of {}; pass --accept-screens to watch them",
+        "* Terminal de {} refusé ; passez --accept-screens pour regarder les terminaux partagés",
+    ),
+    (
+        "* Declined the terminal of {}: you are already watching one",
+        "* Terminal de {} refusé : vous en regardez déjà un",
+    ),
+    (
+        "* Watching the terminal of {} ({}x{}), read-only",
+        "* Vous regardez le terminal de {} ({}x{}), en lecture seule",
+    ),
+    (
+        "Show a shell to the node at the address, which can't type in it",
+        "Montrer un shell au nœud à cette adresse, sans qu'il puisse y écrire",
+    ),
+    ("Stop sharing the terminal", "Arrêter de partager le terminal"),
+    ("* The bridge stopped: {}", "* Le pont s'est arrêté : {}"),
+    (
+        "* Dropped an envelope from the bridge: {}",
+        "* Enveloppe du pont ignorée : {}",
+    ),
+    ("* Mailed the digest of {}", "* Résumé de {} envoyé par courriel"),
+    ("* Can't mail the digest: {}", "* Impossible d'envoyer le résumé par courriel : {}"),
+    (
+        "* Not announcing in {}, which we left",
+        "* Pas d'annonce dans {}, que nous avons quitté",
+    ),
+    ("* Loaded the bot {}", "* Bot {} chargé"),
+    ("* Can't load the bot {}: {}", "* Impossible de charger le bot {} : {}"),
+    (
+        "* The bot {} doesn't keep up; dropping messages for it",
+        "* Le bot {} ne suit pas ; des messages ne lui sont pas transmis",
+    ),
+    ("* The bot {} stopped: {}", "* Le bot {} s'est arrêté : {}"),
+    (
+        "* Ignoring the scheduled messages in {}: {}",
+        "* Messages programmés de {} ignorés : {}",
+    ),
+    (
+        "* Can't save the scheduled messages to {}: {}",
+        "* Impossible d'enregistrer les messages programmés dans {} : {}",
+    ),
+    (
+        "* Scheduled message {} for {} from now",
+        "* Message {} programmé dans {}",
+    ),
+    ("* No message is scheduled", "* Aucun message n'est programmé"),
+    ("* {}. in {}, in {}: {}", "* {}. dans {}, dans {} : {}"),
+    ("* Cancelled scheduled message {}", "* Message programmé {} annulé"),
+    ("* There is no scheduled message {}", "* Il n'y a pas de message programmé {}"),
+    (
+        "* Publishing scheduled message {} in {}",
+        "* Publication du message programmé {} dans {}",
+    ),
+    (
+        "Publish a message later, after a delay such as 30s, 10m or 2h",
+        "Publier un message plus tard, après un délai tel que 30s, 10m ou 2h",
+    ),
+    (
+        "List the scheduled messages, or cancel one",
+        "Lister les messages programmés, ou en annuler un",
+    ),
+    (
+        "* Reminder {} for {} is due in {}, and will be delivered once they are around",
+        "* Le rappel {} pour {} est dû dans {}, et sera remis dès que cette personne sera là",
+    ),
+    (
+        "* {}. for {}, waiting for them to acknowledge it: {}",
+        "* {}. pour {}, en attente de son accusé de réception : {}",
+    ),
+    (
+        "* {}. for {}, in {} at the earliest: {}",
+        "* {}. pour {}, dans {} au plus tôt : {}",
+    ),
+    ("* {} got reminder {}", "* {} a reçu le rappel {}"),
+    ("* Reminder from {}: {}", "* Rappel de {} : {}"),
+    (
+        "Send a reminder to someone once it is due and they are around",
+        "Envoyer un rappel à quelqu'un une fois qu'il est dû et que cette personne est là",
+    ),
+    (
+        "* The clock of {} is {} ahead of ours; correcting its timestamps",
+        "* L'horloge de {} avance de {} sur la nôtre ; ses horodatages sont corrigés",
+    ),
+    (
+        "* The clock of {} is {} behind ours; correcting its timestamps",
+        "* L'horloge de {} retarde de {} sur la nôtre ; ses horodatages sont corrigés",
+    ),
+    (
+        "* Turning away the nodes that dial us, because of --max-clients or --max-joins",
+        "* Les nœuds qui nous appellent sont refusés, à cause de --max-clients ou --max-joins",
+    ),
+    ("* Ignoring a report in {}: {}", "* Signalement de {} ignoré : {}"),
+    ("* Can't save the report to {}: {}", "* Impossible d'enregistrer le signalement dans {} : {}"),
+    (
+        "* {} reported a message of {} in {}: {} ({})",
+        "* {} a signalé un message de {} dans {} : {} ({})",
+    ),
+    ("* No report yet", "* Aucun signalement pour l'instant"),
+    ("you", "vous"),
+    ("* By {}, about {} in {}: {} ({})", "* Par {}, à propos de {} dans {} : {} ({})"),
+    (
+        "* The reason can't be longer than {} characters",
+        "* La raison ne peut pas dépasser {} caractères",
+    ),
+    ("* Sent the report to the moderators of {}", "* Signalement envoyé aux modérateurs de {}"),
+    (
+        "* Not sending the report to {}, which we left",
+        "* Signalement non envoyé à {}, que nous avons quitté",
+    ),
+    ("* Recorded the report about message {}", "* Signalement du message {} enregistré"),
+    (
+        "Report an abusive message to the moderators of the room",
+        "Signaler un message abusif aux modérateurs du salon",
+    ),
+    (
+        "List the reports you made, and those you received as a moderator",
+        "Lister vos signalements, et ceux reçus en tant que modérateur",
+    ),
+    (
+        "Highlight the messages that match a pattern in every room, or list them",
+        "Surligner les messages qui correspondent à un motif dans tous les salons, ou les lister",
+    ),
+    ("Stop watching a pattern", "Ne plus surveiller un motif"),
+    ("* watch: {}", "* surveillé : {}"),
+    ("* You aren't watching anything", "* Vous ne surveillez rien"),
+    (
+        "* Messages matching {} will be highlighted in every room",
+        "* Les messages correspondant à {} seront surlignés dans tous les salons",
+    ),
+    ("* Already watching {}", "* {} est déjà surveillé"),
+    ("* You aren't watching {}", "* Vous ne surveillez pas {}"),
+    ("* Stopped watching {}", "* {} n'est plus surveillé"),
+    ("* Send {} lines ({}) to {}? [y/N]", "* Envoyer {} lignes ({}) à {} ? [o/N]"),
+    ("* Not sent", "* Non envoyé"),
+    ("an AWS access key", "une clé d'accès AWS"),
+    ("a GitHub token", "un jeton GitHub"),
+    ("a Slack token", "un jeton Slack"),
+    ("a private key", "une clé privée"),
+    ("a bearer token", "un jeton d'authentification"),
+    ("an email address", "une adresse email"),
+    ("a match of {}", "une correspondance de {}"),
+    ("* Masked in your message: {}", "* Masqué dans votre message : {}"),
+    (
+        "Retract the message you just sent, if the others' clients allow it",
+        "Retirer le message que vous venez d'envoyer, si les clients des autres le permettent",
+    ),
+    ("* You haven't sent anything to undo", "* Vous n'avez rien envoyé à annuler"),
+    (
+        "* Your last message is older than {} seconds; use `/delete` instead",
+        "* Votre dernier message date de plus de {} secondes ; utilisez plutôt `/delete`",
+    ),
+    (
+        "* Retracted; whoever already read your message or ignores retractions still has it",
+        "* Retiré ; qui a déjà lu votre message ou ignore les retraits l'a toujours",
+    ),
+    ("* {} doesn't support {}", "* {} ne gère pas {}"),
+    ("the chat", "la discussion"),
+    ("tic-tac-toe", "le morpion"),
+    ("voice calls", "les appels vocaux"),
+    ("screen sharing", "le partage d'écran"),
+    (
+        "Show how the connections to a peer were built, layer by layer",
+        "Montrer comment les connexions à un pair ont été construites, couche par couche",
+    ),
+    ("* No connection to {}", "* Aucune connexion à {}"),
+    ("* Connection to {}, open for {}s", "* Connexion vers {}, ouverte depuis {}s"),
+    ("* Connection from {}, open for {}s", "* Connexion depuis {}, ouverte depuis {}s"),
+    ("unknown", "inconnu"),
+    ("*   transport: {}, {}", "*   transport : {}, {}"),
+    (
+        "*   security: none, the messages are signed instead",
+        "*   sécurité : aucune, les messages sont signés à la place",
+    ),
+    (
+        "*   muxer: none, one connection per protocol",
+        "*   multiplexeur : aucun, une connexion par protocole",
+    ),
+    ("*   negotiation: multistream-select, {}", "*   négociation : multistream-select, {}"),
+    ("*   protocol: {}, handshake {}", "*   protocole : {}, poignée de main {}"),
+    ("* Ignoring the scores in {}: {}", "* Scores de {} ignorés : {}"),
+    ("* Ignoring the bans in {}: {}", "* Bannissements de {} ignorés : {}"),
+    (
+        "* Can't save the bans to {}: {}",
+        "* Impossible d'enregistrer les bannissements dans {} : {}",
+    ),
+    ("* Can't save the scores to {}: {}", "* Impossible d'enregistrer les scores dans {} : {}"),
+    (
+        "* Still ignoring {} peers from the last run; see /scores and /forgive",
+        "* {} pairs de la session précédente sont toujours ignorés ; voir /scores et /forgive",
+    ),
+    ("* Forgave {}", "* {} est pardonné"),
+    ("* {} has no score to forgive", "* {} n'a pas de score à pardonner"),
+    ("* Not dialing {}, who is ignored; see /forgive", "* Pas d'appel vers {}, qui est ignoré ; voir /forgive"),
+    (
+        "Reset the score of a peer, to stop ignoring it",
+        "Remettre à zéro le score d'un pair, pour ne plus l'ignorer",
+    ),
+    ("{} (rooms: {})", "{} (salons : {})"),
+    (
+        "* LAN party: looking for the nodes of the local network, in {}",
+        "* LAN party : recherche des nœuds du réseau local, dans {}",
+    ),
+    ("{}; did you mean {}?", "{} ; vouliez-vous dire {} ?"),
+    ("{} isn't a port number", "{} n'est pas un numéro de port"),
+    ("couldn't resolve the host name {}", "impossible de résoudre le nom d'hôte {}"),
+    (
+        "{} must be an IP address; browsers can't resolve host names here",
+        "{} doit être une adresse IP ; un navigateur ne résout pas les noms d'hôte ici",
+    ),
+    ("an address is expected, such as {}", "une adresse est attendue, comme {}"),
+    ("{} should start with a /", "{} devrait commencer par un /"),
+    ("{} has an empty component", "{} a un composant vide"),
+    ("/{} isn't a protocol", "/{} n'est pas un protocole"),
+    ("/{} must be followed by a value", "/{} doit être suivi d'une valeur"),
+    ("{} isn't an IPv4 address", "{} n'est pas une adresse IPv4"),
+    ("{} isn't an IPv6 address", "{} n'est pas une adresse IPv6"),
+    ("{} isn't a valid multiaddress", "{} n'est pas une multiadresse valide"),
+    (
+        "{} lacks the /tcp component, with the port",
+        "il manque à {} le composant /tcp, avec le port",
+    ),
+    (
+        "{} lacks the /ws suffix, which browser peers require",
+        "il manque à {} le suffixe /ws, que les pairs dans un navigateur exigent",
+    ),
+    (
+        "* Browsers can't join us, since none of our addresses ends with /ws",
+        "* Les navigateurs ne peuvent pas nous rejoindre : aucune de nos adresses ne finit par /ws",
+    ),
+    ("[FAIL] {}", "[ÉCHEC] {}"),
+    (
+        "{} has no host and TCP port to connect to",
+        "{} n'a pas d'hôte ni de port TCP où se connecter",
+    ),
+    ("TCP connection to {}:{}", "Connexion TCP à {}:{}"),
+    ("Websockets: not a /ws address", "Websockets : pas une adresse /ws"),
+    (
+        "Websockets: /wss needs TLS, which the probe doesn't speak",
+        "Websockets : /wss demande TLS, que la sonde ne parle pas",
+    ),
+    ("Websockets handshake", "Poignée de main websockets"),
+    (
+        "Encryption and multiplexing: the chat uses neither",
+        "Chiffrement et multiplexage : le chat n'utilise ni l'un ni l'autre",
+    ),
+    ("Negotiation of {}", "Négociation de {}"),
+    ("Floodsub", "Floodsub"),
+    (
+        "Check the address and that the node runs; a firewall may block the port",
+        "Vérifiez l'adresse et que le nœud tourne ; un pare-feu bloque peut-être le port",
+    ),
+    ("connected to {}", "connecté à {}"),
+    (
+        "The node may not listen with /ws, or a proxy may be in the way",
+        "Le nœud n'écoute peut-être pas en /ws, ou un proxy est sur le chemin",
+    ),
+    ("unexpected answer: {}", "réponse inattendue : {}"),
+    (
+        "This isn't a node of the chat, or something rewrites the traffic",
+        "Ce n'est pas un nœud du chat, ou quelque chose réécrit le trafic",
+    ),
+    (
+        "The node is full; try another relay, or again later",
+        "Le nœud est plein ; essayez un autre relais, ou plus tard",
+    ),
+    ("the node doesn't speak {}", "le nœud ne parle pas {}"),
+    (
+        "The node may run another version of the chat",
+        "Le nœud utilise peut-être une autre version du chat",
+    ),
+    ("the node agreed", "le nœud a accepté"),
+    (
+        "The node hung up on us; its output may say why",
+        "Le nœud a raccroché ; sa sortie dit peut-être pourquoi",
+    ),
+    ("the node announced its rooms", "le nœud a annoncé ses salons"),
+    (
+        "connected, but the node announced no room in {} s",
+        "connecté, mais le nœud n'a annoncé aucun salon en {} s",
+    ),
+    ("the node closed the connection", "le nœud a fermé la connexion"),
+    ("* Broadcast in {}", "* Diffusé dans {}"),
+    ("Send a message to several rooms at once", "Envoyer un message dans plusieurs salons à la fois"),
+    (
+        "* Direct messages: {}/{} queued, {} sent, {} dropped",
+        "* Messages directs : {}/{} en attente, {} envoyés, {} abandonnés",
+    ),
+    ("* Already draining", "* Déjà en cours de vidange"),
+    (
+        "* Draining: turning away new nodes, sending the others to {}, and exiting",
+        "* Vidange : les nouveaux nœuds sont refusés, les autres envoyés vers {}, puis arrêt",
+    ),
+    (
+        "* Draining: turning away new nodes, and exiting",
+        "* Vidange : les nouveaux nœuds sont refusés, puis arrêt",
+    ),
+    ("* {} is restarting; reconnect to {}", "* {} redémarre ; reconnectez-vous à {}"),
+    ("* {} is restarting", "* {} redémarre"),
+    ("* Can't drain to {}: {}", "* Impossible de vider vers {} : {}"),
+    ("* Stopped listening for SIGTERM: {}", "* SIGTERM n'est plus écouté : {}"),
+    (
+        "Turn away new nodes, send the others to another relay, and exit",
+        "Refuser les nouveaux nœuds, envoyer les autres vers un autre relais, puis s'arrêter",
+    ),
+    ("* Our relay left; dialing {}", "* Notre relais est parti ; connexion à {}"),
+    (
+        "* Handed off to {}; resending {} messages",
+        "* Passage à {} effectué ; {} messages renvoyés",
+    ),
+    ("* Couldn't hand off to {}", "* Impossible de passer à {}"),
+    ("* Asking {} for older messages", "* Demande des messages plus anciens à {}"),
+    (
+        "* Older messages of {}, from the history of {}:",
+        "* Messages plus anciens de {}, tirés de l'historique de {} :",
+    ),
+    (
+        "* Type `/history more` again for older messages",
+        "* Tapez à nouveau `/history more` pour des messages plus anciens",
+    ),
+    (
+        "* That's the beginning of {} as far as {} remembers",
+        "* C'est le début de {} pour autant que {} s'en souvienne",
+    ),
+    (
+        "Compare the messages of the room with the history of another peer",
+        "Comparer les messages du salon avec l'historique d'un autre pair",
+    ),
+    ("* There are no messages of {} to verify", "* Il n'y a aucun message de {} à vérifier"),
+    (
+        "* Comparing our history of {} with the room",
+        "* Comparaison de notre historique de {} avec le salon",
+    ),
+    ("* {} doesn't have: {}", "* {} n'a pas : {}"),
+    ("* {} has another version of: {}", "* {} a une autre version de : {}"),
+    (
+        "* {} has a message sent at {} that we don't",
+        "* {} a un message envoyé le {} que nous n'avons pas",
+    ),
+    (
+        "* {} has messages between {} and {} that differ from ours",
+        "* {} a des messages entre {} et {} qui diffèrent des nôtres",
+    ),
+    (
+        "* Our history of {} matches the one of {}",
+        "* Notre historique de {} correspond à celui de {}",
+    ),
+    (
+        "* Found {} diverging messages between our history of {} and the one of {}",
+        "* {} messages divergent entre notre historique de {} et celui de {}",
+    ),
+    (
+        "* The message is too large for a single envelope; sending it in {} parts",
+        "* Le message est trop grand pour une seule enveloppe ; envoi en {} parties",
+    ),
+    (
+        "* Not sent: the outbox is full, with {} messages waiting",
+        "* Non envoyé : la file d'envoi est pleine, avec {} messages en attente",
+    ),
+    (
+        "* The outbox is full; {} of them weren't resent",
+        "* La file d'envoi est pleine ; {} d'entre eux n'ont pas été renvoyés",
+    ),
+    ("* Chaos is off", "* Le chaos est désactivé"),
+    (
+        "* {} is flooded; showing only a sample of its messages",
+        "* {} est inondé ; seul un échantillon de ses messages est affiché",
+    ),
+    ("* The previous message was repeated {}{}", "* Le message précédent a été répété {}{}"),
+    ("* Sockets: {} sent, {} received", "* Sockets : {} envoyés, {} reçus"),
+    (
+        "* Room {}: {} messages ({}) received, {} messages ({}) sent",
+        "* Salon {} : {} messages ({}) reçus, {} messages ({}) envoyés",
+    ),
+    ("* From {}: {} messages ({})", "* De {} : {} messages ({})"),
+    ("* Hour {}: {} messages ({})", "* Heure {} : {} messages ({})"),
+    (
+        "Show the bandwidth used, by room, peer and hour",
+        "Afficher la bande passante utilisée, par salon, pair et heure",
+    ),
+    ("unreachable", "injoignable"),
+    ("Relay {}: {} (current)", "Relais {} : {} (actuel)"),
+    ("Relay {}: {}", "Relais {} : {}"),
+    ("* Using the relay {}", "* Utilisation du relais {}"),
+    ("* Stopped probing the relays: {}", "* Les relais ne sont plus sondés : {}"),
+    ("* Couldn't probe the relays: {}", "* Impossible de sonder les relais : {}"),
+    (
+        "Drop connections, delay and duplicate messages, to test the chat",
+        "Couper des connexions, retarder et dupliquer des messages, pour tester le chat",
+    ),
+    ("[ok]   {}: {}", "[ok]   {} : {}"),
+    ("[FAIL] {}: {}", "[ÉCHEC] {} : {}"),
+    ("[skip] {}", "[passé] {}"),
+    ("Listening on {}", "Écoute sur {}"),
+    ("Resolving {}", "Résolution de {}"),
+    ("Multicast beacons", "Balises multicast"),
+    ("Websockets to {}", "Websockets vers {}"),
+    (
+        "Pass --server to check websockets against a node",
+        "Passez --server pour tester les websockets avec un nœud",
+    ),
+    ("not a TCP address, nothing to check", "pas une adresse TCP, rien à vérifier"),
+    ("port {} is free", "le port {} est libre"),
+    (
+        "Another program uses this port; the chat will pick another one, or pass --listen",
+        "Un autre programme utilise ce port ; le chat en choisira un autre, ou passez --listen",
+    ),
+    ("no address", "aucune adresse"),
+    ("Dial the nodes by their IP address instead", "Appelez plutôt les nœuds par leur adresse IP"),
+    (
+        "The nodes won't find each other by themselves; use --dial",
+        "Les nœuds ne se trouveront pas tout seuls ; utilisez --dial",
+    ),
+    ("multicast works", "le multicast fonctionne"),
+    (
+        "Check the address, or whether a firewall or proxy blocks the connection",
+        "Vérifiez l'adresse, ou si un pare-feu ou un proxy bloque la connexion",
+    ),
+    ("* Not dialing {}, which is ourselves", "* {} n'est pas appelé : c'est nous-mêmes"),
+    (
+        "Couldn't lock the identity file {}: {}",
+        "Impossible de verrouiller le fichier d'identité {} : {}",
+    ),
+    (
+        "* Another node is using our identity. If it isn't you on another device, someone has a \
+         copy of the identity file: stop that node, or restart with another --identity",
+        "* Un autre nœud utilise notre identité. Si ce n'est pas vous sur un autre appareil, \
+         quelqu'un a une copie du fichier d'identité : arrêtez ce nœud, ou relancez avec une autre \
+         --identity",
+    ),
+    (
+        "* Couldn't write the diagnostics to {}: {}",
+        "* Impossible d'écrire le diagnostic dans {} : {}",
+    ),
+    ("* Stopped listening for SIGUSR1: {}", "* SIGUSR1 n'est plus écouté : {}"),
+    (
+        "* Couldn't learn our public address: {}",
+        "* Impossible de connaître notre adresse publique : {}",
+    ),
+    ("* The HTTP server stopped: {}", "* Le serveur HTTP s'est arrêté : {}"),
+    ("* Couldn't serve HTTP on {}: {}", "* Impossible de servir HTTP sur {} : {}"),
+    ("Failed to dial {}", "Échec de la connexion à {}"),
+    ("* It's not your turn", "* Ce n'est pas votre tour"),
+    ("* You can't play there", "* Vous ne pouvez pas jouer là"),
+    (
+        "* You aren't playing; start a game with `/ttt <multiaddr>`",
+        "* Vous ne jouez pas ; lancez une partie avec `/ttt <multiadresse>`",
+    ),
+    ("* You resigned the game against {}", "* Vous avez abandonné la partie contre {}"),
+    ("* {} made an invalid move", "* {} a joué un coup invalide"),
+    ("* {} resigned, you win!", "* {} a abandonné, vous gagnez !"),
+    ("* {} left the game", "* {} a quitté la partie"),
+    ("* You win!", "* Vous gagnez !"),
+    ("* {} wins!", "* {} gagne !"),
+    ("* It's a draw", "* Match nul"),
+    ("* Your turn: `/move <1-9>`", "* À vous : `/move <1-9>`"),
+    (
+        "* Declined a game from {}: you are already playing",
+        "* Partie de {} refusée : vous jouez déjà",
+    ),
+    (
+        "* Started a game of tic-tac-toe against {}; you are {}",
+        "* Partie de morpion lancée contre {} ; vous êtes {}",
+    ),
+    ("* Coordinator {} is gone", "* Le coordinateur {} est parti"),
+    ("* We are now coordinator", "* Nous sommes maintenant coordinateur"),
+    ("* {} is now coordinator", "* {} est maintenant coordinateur"),
+    ("* Found {} at {} by its beacon", "* {} trouvé à {} grâce à sa balise"),
+    ("* Dialing {} timed out after {}s", "* La connexion à {} a expiré après {} s"),
+    (
+        "Welcome! Let's create {} with a few questions.",
+        "Bienvenue ! Créons {} en quelques questions.",
+    ),
+    (
+        "Press enter to keep the value in brackets.",
+        "Appuyez sur Entrée pour garder la valeur entre crochets.",
+    ),
+    ("Nickname", "Pseudonyme"),
+    ("File to store your key pair in", "Fichier où stocker votre paire de clés"),
+    ("Port to listen on", "Port d'écoute"),
+    (
+        "Address of a node to join, if you were given one",
+        "Adresse d'un nœud à rejoindre, si on vous en a donné une",
+    ),
+    ("That isn't a port number.", "Ce n'est pas un numéro de port."),
+    ("That isn't a multiaddress: {}", "Ce n'est pas une multiadresse : {}"),
+    ("Your public key is {}", "Votre clé publique est {}"),
+    (
+        "Saved. You can edit {} to change these settings later.",
+        "Enregistré. Vous pourrez modifier {} pour changer ces réglages.",
+    ),
+    ("* Can't read the input history: {}", "* Impossible de lire l'historique de saisie : {}"),
+    ("* Can't write the input history: {}", "* Impossible d'écrire l'historique de saisie : {}"),
+    (
+        "* Can't notify systemd through the abstract socket {}",
+        "* Impossible de prévenir systemd par la socket abstraite {}",
+    ),
+    ("* Couldn't notify systemd: {}", "* Impossible de prévenir systemd : {}"),
+    (
+        "List the commands, or show how to use one",
+        "Lister les commandes, ou montrer comment en utiliser une",
+    ),
+    ("Tell the room what you are doing", "Dire au salon ce que vous faites"),
+    ("Join a room and make it the current one", "Rejoindre un salon et en faire le salon courant"),
+    ("Leave the current room", "Quitter le salon courant"),
+    ("Make a room you joined the current one", "Faire d'un salon rejoint le salon courant"),
+    ("List the rooms you joined", "Lister les salons rejoints"),
+    ("List the peers seen recently", "Lister les pairs vus récemment"),
+    ("List the open connections", "Lister les connexions ouvertes"),
+    (
+        "Show or set the description of the current room",
+        "Afficher ou définir la description du salon courant",
+    ),
+    ("Compose a message over several lines", "Écrire un message sur plusieurs lignes"),
+    ("Send the content of the clipboard", "Envoyer le contenu du presse-papiers"),
+    (
+        "Copy the nth last message to the clipboard",
+        "Copier le n-ième dernier message dans le presse-papiers",
+    ),
+    (
+        "Show the last messages, numbered, or ask the room for older ones",
+        "Afficher les derniers messages, numérotés, ou demander les plus anciens au salon",
+    ),
+    ("Replace the text of one of your messages", "Remplacer le texte d'un de vos messages"),
+    ("Delete one of your messages", "Supprimer un de vos messages"),
+    ("Reply to a message of the history", "Répondre à un message de l'historique"),
+    ("Show a message and its replies", "Afficher un message et ses réponses"),
+    ("React to a message of the history", "Réagir à un message de l'historique"),
+    ("Pin a message of the history", "Épingler un message de l'historique"),
+    ("Unpin a message of the list shown by /pins", "Désépingler un message de la liste de /pins"),
+    ("List the pinned messages", "Lister les messages épinglés"),
+    (
+        "Write the history of a room to a Markdown or HTML file",
+        "Écrire l'historique d'un salon dans un fichier Markdown ou HTML",
+    ),
+    ("Show the last lines you typed, numbered", "Afficher les dernières lignes tapées, numérotées"),
+    ("Run a line shown by /recent again", "Exécuter à nouveau une ligne de /recent"),
+    ("List the messages that mention you", "Lister les messages qui vous mentionnent"),
+    ("Show or set when to be notified", "Afficher ou choisir quand être notifié"),
+    (
+        "Turn the :shortcode: replacement on or off",
+        "Activer ou désactiver le remplacement des :codes:",
+    ),
+    (
+        "List, add or remove the patterns of hidden messages",
+        "Lister, ajouter ou retirer les motifs des messages masqués",
+    ),
+    (
+        "Stop displaying and relaying the messages of a peer",
+        "Ne plus afficher ni relayer les messages d'un pair",
+    ),
+    ("Show the messages of a peer again", "Afficher à nouveau les messages d'un pair"),
+    ("List the banned peers", "Lister les pairs bannis"),
+    (
+        "Delete the local data of a room, or of everything",
+        "Effacer les données locales d'un salon, ou de tout",
+    ),
+    ("Start a poll, or show its results", "Lancer un sondage, ou afficher ses résultats"),
+    ("Vote in a poll", "Voter dans un sondage"),
+    ("Open or edit a shared pad", "Ouvrir ou modifier un bloc-notes partagé"),
+    ("Play tic-tac-toe with a peer", "Jouer au morpion avec un pair"),
+    ("Play a cell of the tic-tac-toe grid", "Jouer une case de la grille de morpion"),
+    ("Give up the tic-tac-toe game", "Abandonner la partie de morpion"),
+    ("Store a value in the DHT", "Stocker une valeur dans la DHT"),
+    ("Look up a value in the DHT", "Chercher une valeur dans la DHT"),
+    ("Show the outbox and dial counters", "Afficher les compteurs d'envoi et de connexion"),
+    ("Show the scores of the peers", "Afficher les scores des pairs"),
+    (
+        "Print the known network as a Graphviz graph",
+        "Afficher le réseau connu sous forme de graphe Graphviz",
+    ),
+    ("Reload the configuration file", "Recharger le fichier de configuration"),
+    ("Show the versions of the known peers", "Afficher les versions des pairs connus"),
+];
@@ -0,0 +1,241 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The cryptographic identity of the local node.
+//!
+//! In chapters 1 and 2 the `PeerId` was derived from random bytes. Here we instead generate an
+//! ed25519 key pair and derive the `PeerId` from its public key, which lets us sign the messages
+//! we publish and lets the other nodes verify who sent them.
+
+use bs58;
+use ed25519_dalek::{Keypair, PublicKey, Signature};
+#[cfg(not(target_os = "emscripten"))]
+use fs2::{self, FileExt};
+use libp2p::PeerId;
+use rand::OsRng;
+use sha2::Sha512;
+use std::fs::{self, File};
+#[cfg(not(target_os = "emscripten"))]
+use std::fs::OpenOptions;
+#[cfg(all(unix, not(target_os = "emscripten")))]
+use std::fs::Permissions;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+#[cfg(all(unix, not(target_os = "emscripten")))]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use vault;
+
+/// Keeps other instances of the program from using the same identity file while it is alive.
+/// The operating system releases the lock when the process exits, even if it crashes.
+#[cfg(not(target_os = "emscripten"))]
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Locks the identity file at `path`, through a `.lock` file next to it. Fails with
+/// `ErrorKind::WouldBlock` if another instance holds the lock.
+#[cfg(not(target_os = "emscripten"))]
+pub fn lock<P: AsRef<Path>>(path: P) -> Result<InstanceLock, IoError> {
+    let mut lock_path = path.as_ref().as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = OpenOptions::new().write(true).create(true).open(&lock_path)?;
+    file.try_lock_exclusive().map_err(|err| {
+        if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+            IoError::new(ErrorKind::WouldBlock, "the identity file is in use")
+        } else {
+            err
+        }
+    })?;
+    Ok(InstanceLock { _file: file })
+}
+
+pub struct Identity {
+    keypair: Keypair,
+    peer_id: PeerId,
+}
+
+impl Identity {
+    /// Generates a brand new identity.
+    pub fn generate() -> Identity {
+        let mut rng = OsRng::new().expect("failed to access the OS random number generator");
+        Identity::from_keypair(Keypair::generate::<Sha512>(&mut rng))
+    }
+
+    /// Loads the identity stored at `path`, or generates a new one and stores it there if the file
+    /// doesn't exist yet.
+    ///
+    /// Reusing the same identity is what allows a moderator to keep their powers between two
+    /// runs of the program.
+    ///
+    /// If the file is encrypted, the user is asked for its passphrase. If `encrypt` is true, a
+    /// plain file is encrypted with a new passphrase, so that it can't be used to impersonate us.
+    pub fn load_or_generate<P: AsRef<Path>>(path: P, encrypt: bool) -> Result<Identity, IoError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            let identity = Identity::generate();
+            identity.store(path, encrypt)?;
+            return Ok(identity);
+        }
+
+        // Older versions created the file with the default permissions.
+        restrict(path)?;
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let sealed = vault::is_sealed(&bytes);
+        if sealed {
+            bytes = vault::open(&vault::ask_passphrase()?, &bytes)?;
+        }
+        let keypair = Keypair::from_bytes(&bytes)
+            .map_err(|_| IoError::new(ErrorKind::InvalidData, "corrupted identity file"))?;
+        let identity = Identity::from_keypair(keypair);
+        if encrypt && !sealed {
+            identity.store(path, true)?;
+        }
+        Ok(identity)
+    }
+
+    /// Generates a new identity and stores it at `path`, in place of the current one. See the
+    /// `tofu` module.
+    ///
+    /// The passphrase of an encrypted file can't be typed while chatting, so encrypted files
+    /// aren't replaced.
+    pub fn regenerate<P: AsRef<Path>>(path: P, encrypt: bool) -> Result<Identity, IoError> {
+        let path = path.as_ref();
+        let mut bytes = Vec::new();
+        if path.exists() {
+            File::open(path)?.read_to_end(&mut bytes)?;
+        }
+        if encrypt || vault::is_sealed(&bytes) {
+            let message = "the identity file is encrypted, so its passphrase would be needed";
+            return Err(IoError::new(ErrorKind::PermissionDenied, message));
+        }
+        let identity = Identity::generate();
+        identity.store(path, false)?;
+        Ok(identity)
+    }
+
+    /// Writes the key pair to `path`. It is written to a temporary file first, then renamed, so
+    /// that a crash or a full disk can't leave a truncated file in place of the key, which would
+    /// lose our identity. Encrypting an existing file replaces it this way too.
+    fn store(&self, path: &Path, encrypt: bool) -> Result<(), IoError> {
+        let mut bytes = self.keypair.to_bytes().to_vec();
+        if encrypt {
+            bytes = vault::seal(&vault::new_passphrase()?, &bytes)?;
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let written = create(&temporary).and_then(|mut file| {
+            // The mode given to `create` only applies if the file is new.
+            restrict(&temporary)?;
+            file.write_all(&bytes)?;
+            file.sync_all()
+        });
+        match written.and_then(|()| fs::rename(&temporary, path)) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = fs::remove_file(&temporary);
+                Err(err)
+            }
+        }
+    }
+
+    fn from_keypair(keypair: Keypair) -> Identity {
+        let peer_id = PeerId::from_public_key(keypair.public.as_bytes());
+        Identity { keypair, peer_id }
+    }
+
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.keypair.public.as_bytes()
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.keypair.sign::<Sha512>(data).to_bytes().to_vec()
+    }
+}
+
+/// Deletes the identity file at `path`, and its lock file.
+pub fn remove(path: &str) -> Result<(), IoError> {
+    fs::remove_file(path)?;
+    // The lock file is empty, and Windows doesn't let us delete it while we hold the lock.
+    let _ = fs::remove_file(format!("{}.lock", path));
+    Ok(())
+}
+
+/// Creates the file at `path`, or truncates it, without letting the other users read it: whoever
+/// reads the key can impersonate us.
+#[cfg(all(unix, not(target_os = "emscripten")))]
+fn create(path: &Path) -> Result<File, IoError> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(all(unix, not(target_os = "emscripten"))))]
+fn create(path: &Path) -> Result<File, IoError> {
+    File::create(path)
+}
+
+/// Makes the file at `path` readable and writable by its owner only.
+#[cfg(all(unix, not(target_os = "emscripten")))]
+fn restrict(path: &Path) -> Result<(), IoError> {
+    fs::set_permissions(path, Permissions::from_mode(0o600))
+}
+
+#[cfg(not(all(unix, not(target_os = "emscripten"))))]
+fn restrict(_path: &Path) -> Result<(), IoError> {
+    Ok(())
+}
+
+/// Returns true if `signature` is a valid signature of `data` by `public_key`.
+pub fn verify(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    let public_key = match PublicKey::from_bytes(public_key) {
+        Ok(key) => key,
+        Err(_) => return false,
+    };
+    let signature = match Signature::from_bytes(signature) {
+        Ok(sig) => sig,
+        Err(_) => return false,
+    };
+    public_key.verify::<Sha512>(data, &signature)
+}
+
+/// Formats a public key the same way `PeerId`s are formatted, so that it can be copy-pasted on
+/// the command line.
+pub fn encode_key(public_key: &[u8]) -> String {
+    bs58::encode(public_key).into_string()
+}
+
+pub fn decode_key(key: &str) -> Option<Vec<u8>> {
+    bs58::decode(key).into_vec().ok()
+}
+
+/// Parses a base58-encoded `PeerId`, as displayed by `PeerId::to_base58()`.
+pub fn parse_peer_id(peer_id: &str) -> Option<PeerId> {
+    let bytes = bs58::decode(peer_id).into_vec().ok()?;
+    PeerId::from_bytes(bytes).ok()
+}
@@ -0,0 +1,119 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Sparing the battery while nothing happens.
+//!
+//! On a laptop running on battery, once nothing was typed or said for `IDLE_AFTER`, only every
+//! other tick is performed: the heartbeats, the announcements and the chores that come with them
+//! happen half as often. Peers with the default settings consider us gone after three missed
+//! heartbeats, so halving is as far as we can go. The first line typed or message received, or
+//! plugging the laptop in, brings back the usual pace.
+//!
+//! When the heartbeats are already far apart, as with `--low-bandwidth`, halving them would make
+//! the peers consider us gone, so we never slow down.
+
+use display::{self, Verbosity};
+use platform;
+use presence;
+use std::time::{Duration, Instant};
+
+/// Time without activity after which we slow down, on battery.
+const IDLE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Time without news after which the peers with the default settings consider us gone.
+const PEER_TIMEOUT: Duration = Duration::from_secs(
+    presence::DEFAULT_HEARTBEAT_INTERVAL_SECS * presence::DEFAULT_MISSED_HEARTBEATS as u64,
+);
+
+pub struct Idle {
+    last_activity: Instant,
+    /// False if skipping every other tick would leave more than `PEER_TIMEOUT` between two.
+    can_slow_down: bool,
+    idle: bool,
+    /// True if the last tick was skipped.
+    skipped: bool,
+}
+
+impl Idle {
+    /// `tick` is the time between two calls to `skip_tick`.
+    pub fn new(tick: Duration) -> Idle {
+        Idle {
+            last_activity: Instant::now(),
+            can_slow_down: tick * 2 < PEER_TIMEOUT,
+            idle: false,
+            skipped: false,
+        }
+    }
+
+    /// Called when something is typed or said.
+    pub fn activity(&mut self) {
+        self.last_activity = Instant::now();
+        if self.idle {
+            self.idle = false;
+            display::event(Verbosity::Verbose, "Activity: back to the usual heartbeats");
+        }
+    }
+
+    /// Called at every tick. Returns true if this one must be skipped.
+    pub fn skip_tick(&mut self) -> bool {
+        let idle = self.last_activity.elapsed() >= IDLE_AFTER && platform::on_battery();
+        self.skip(idle)
+    }
+
+    /// Like `skip_tick`, `idle` telling whether we are idle on battery.
+    fn skip(&mut self, idle: bool) -> bool {
+        let idle = idle && self.can_slow_down;
+        if idle != self.idle {
+            self.idle = idle;
+            let event = if idle {
+                "Idle on battery: halving the heartbeats"
+            } else {
+                "Plugged in: back to the usual heartbeats"
+            };
+            display::event(Verbosity::Verbose, event);
+        }
+        self.skipped = self.idle && !self.skipped;
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_every_other_tick_when_idle() {
+        let mut idle = Idle::new(Duration::from_secs(presence::DEFAULT_HEARTBEAT_INTERVAL_SECS));
+        assert!(!idle.skip(false));
+        let skipped: Vec<bool> = (0..4).map(|_| idle.skip(true)).collect();
+        assert_eq!(skipped, vec![true, false, true, false]);
+        assert!(!idle.skip(false));
+        assert!(!idle.skip(false));
+    }
+
+    #[test]
+    fn never_skips_when_the_peers_would_time_out() {
+        // The heartbeats of `--low-bandwidth`.
+        let mut idle = Idle::new(Duration::from_secs(10));
+        assert!((0..4).all(|_| !idle.skip(true)));
+        let mut idle = Idle::new(PEER_TIMEOUT / 2);
+        assert!(!idle.skip(true));
+    }
+}
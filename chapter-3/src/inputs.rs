@@ -0,0 +1,154 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The lines typed by the user, kept across runs.
+//!
+//! The terminal does the line editing, so there is no arrow-key recall. Instead, `/recent` lists
+//! the last lines and `/again <n>` runs one of them again.
+//!
+//! The lines are appended to the file given with `--input-history`, one JSON string per line so
+//! that pasted multi-line messages stay in one piece. The file is trimmed to the size limit when
+//! loaded. Lines matching one of the `--input-history-exclude` patterns, for example those
+//! containing a password, are neither remembered nor written.
+//!
+//! Unlike the identity file, the file is never sealed with the `vault`. A sealed file can't be
+//! appended to, so every line typed would rewrite the whole file and derive the key again with
+//! Argon2, which is meant to be slow. `--input-history-exclude` is how secrets are kept out of it.
+
+use purge;
+use regex::{Error as RegexError, Regex};
+use serde_json;
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{Error as IoError, Write};
+
+pub struct InputHistory {
+    path: Option<String>,
+    lines: VecDeque<String>,
+    max: usize,
+    exclude: Vec<Regex>,
+}
+
+impl InputHistory {
+    /// Loads the last `max` lines of the file at `path`, if there is one.
+    pub fn load(
+        path: Option<String>,
+        max: usize,
+        exclude: &[String],
+    ) -> Result<InputHistory, RegexError> {
+        let mut history = InputHistory {
+            path,
+            lines: VecDeque::new(),
+            max,
+            exclude: Vec::new(),
+        };
+        for pattern in exclude {
+            history.exclude.push(Regex::new(pattern)?);
+        }
+        if let Err(err) = history.read() {
+            say!("* Can't read the input history: {}", err);
+        }
+        Ok(history)
+    }
+
+    fn read(&mut self) -> Result<(), IoError> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return Ok(()),
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) => return Ok(()),
+        };
+        let total = content.lines().count();
+        for line in content.lines() {
+            // Lines we can't decode are skipped rather than losing the whole history.
+            if let Ok(line) = serde_json::from_str::<String>(line) {
+                self.remember(line);
+            }
+        }
+        if total > self.max {
+            self.rewrite()?;
+        }
+        Ok(())
+    }
+
+    /// Records a line typed by the user.
+    pub fn push(&mut self, line: &str) {
+        if line.trim().is_empty() || self.exclude.iter().any(|regex| regex.is_match(line)) {
+            return;
+        }
+        self.remember(line.to_owned());
+        if let Err(err) = self.append(line) {
+            say!("* Can't write the input history: {}", err);
+        }
+    }
+
+    fn remember(&mut self, line: String) {
+        self.lines.push_back(line);
+        while self.lines.len() > self.max {
+            self.lines.pop_front();
+        }
+    }
+
+    fn append(&self, line: &str) -> Result<(), IoError> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{}", serde_json::to_string(line).expect("strings always serialize"))
+    }
+
+    fn rewrite(&self) -> Result<(), IoError> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut content = String::new();
+        for line in &self.lines {
+            content.push_str(&serde_json::to_string(line).expect("strings always serialize"));
+            content.push('\n');
+        }
+        fs::write(path, content)
+    }
+
+    /// Returns the `n`th last line, starting at 1.
+    pub fn get(&self, n: usize) -> Option<&str> {
+        let index = self.lines.len().checked_sub(n)?;
+        self.lines.get(index).map(|line| line.as_str())
+    }
+
+    /// The last `count` lines with their number for `get`, the oldest first.
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = (usize, &str)> {
+        let len = self.lines.len();
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(len.saturating_sub(count))
+            .map(move |(index, line)| (len - index, line.as_str()))
+    }
+
+    /// Forgets everything and deletes the file.
+    pub fn clear(&mut self) -> Result<(), IoError> {
+        self.lines.clear();
+        purge::remove_file(&self.path)
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A small distributed key-value store.
+//!
+//! The version of `libp2p-kad` we depend on only implements the `FIND_NODE` part of Kademlia and
+//! can't store records. We therefore reproduce the idea on top of floodsub: keys and peers live
+//! in the same 256-bits space (the SHA-256 of the key, or of the `PeerId`), and each record is
+//! stored by the `REPLICATION` peers whose position is the closest to the key according to the
+//! XOR metric. Every node decides on its own whether it is responsible for a key, based on the
+//! peers it knows about.
+
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// Number of peers that store each record.
+pub const REPLICATION: usize = 3;
+
+/// Maximum size of a value, in bytes.
+pub const MAX_VALUE_LEN: usize = 1024;
+
+/// Number of seconds after which a `/get` request or its answer is no longer relevant.
+pub const REQUEST_TTL_SECS: u64 = 30;
+
+pub struct KvStore {
+    /// Position of the local node in the key space.
+    local: Vec<u8>,
+    /// Positions of the other peers we know about.
+    peers: HashSet<Vec<u8>>,
+    records: HashMap<String, String>,
+    /// Keys of the `/get`s that are waiting for an answer, by request ID.
+    pending: HashMap<u64, String>,
+}
+
+impl KvStore {
+    pub fn new(local: &PeerId) -> KvStore {
+        KvStore {
+            local: position(local.as_bytes()),
+            peers: HashSet::new(),
+            records: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn add_peer(&mut self, peer: &PeerId) {
+        let position = position(peer.as_bytes());
+        if position != self.local {
+            self.peers.insert(position);
+        }
+    }
+
+    /// Returns true if the local node is one of the `REPLICATION` closest nodes to `key`.
+    pub fn is_responsible(&self, key: &str) -> bool {
+        let target = position(key.as_bytes());
+        let ours = distance(&self.local, &target);
+        let closer = self
+            .peers
+            .iter()
+            .filter(|peer| distance(peer, &target) < ours)
+            .count();
+        closer < REPLICATION
+    }
+
+    /// Stores a record if we are responsible for it. Returns true if stored.
+    pub fn put(&mut self, key: String, value: String) -> bool {
+        if !self.is_responsible(&key) {
+            return false;
+        }
+        self.records.insert(key, value);
+        true
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.records.get(key)
+    }
+
+    /// Remembers that we are waiting for the value of `key`.
+    pub fn add_pending(&mut self, request: u64, key: String) {
+        self.pending.insert(request, key);
+    }
+
+    /// Called when someone answers one of our requests. Returns the key that was requested if we
+    /// were still waiting for it.
+    pub fn complete(&mut self, request: u64) -> Option<String> {
+        self.pending.remove(&request)
+    }
+
+    /// Number of records stored locally.
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+}
+
+fn position(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+fn distance(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
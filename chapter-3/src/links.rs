@@ -0,0 +1,207 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Detection of the URLs contained in messages.
+//!
+//! URLs are turned into clickable links with the OSC 8 escape sequence, which terminals that
+//! don't support it simply ignore. When the crate is compiled with the `link-preview` feature,
+//! the title of the linked page can also be fetched and printed under the message. Only the
+//! first `MAX_BODY` bytes of the page are read, and the fetch is abandoned after
+//! `FETCH_TIMEOUT`, so a large or slow page costs us little.
+
+use display;
+use platform::PlatformSpecific;
+
+/// Number of bytes of a page that we read to find its title, which is in the head.
+#[cfg(all(feature = "link-preview", not(target_os = "emscripten")))]
+const MAX_BODY: usize = 16 * 1024;
+
+/// Time after which we give up on fetching the title of a page.
+#[cfg(all(feature = "link-preview", not(target_os = "emscripten")))]
+const FETCH_TIMEOUT_SECS: u64 = 5;
+
+/// Returns the start and the end of each URL contained in `text`.
+fn spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut word = None;
+    // The extra space ends the last word.
+    for (i, c) in text.char_indices().chain(Some((text.len(), ' '))) {
+        match (word, c.is_whitespace()) {
+            (None, false) => word = Some(i),
+            (Some(start), true) => {
+                word = None;
+                let candidate = &text[start..i];
+                if !candidate.starts_with("http://") && !candidate.starts_with("https://") {
+                    continue;
+                }
+                // Punctuation at the end of a sentence is very unlikely to be part of the URL.
+                let url = candidate.trim_right_matches(|c| ",.;:!?)'\"".contains(c));
+                spans.push((start, start + url.len()));
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+/// Returns the URLs contained in `text`.
+pub fn find_urls(text: &str) -> Vec<&str> {
+    spans(text).into_iter().map(|(start, end)| &text[start..end]).collect()
+}
+
+/// Wraps all the URLs of `text` in OSC 8 escape sequences.
+pub fn render(text: &str) -> String {
+    if !display::decorated() {
+        return text.to_owned();
+    }
+    wrap_urls(text)
+}
+
+/// Wraps the URLs of `text` in a single pass, so that a URL which starts another one isn't
+/// wrapped inside it.
+fn wrap_urls(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, end) in spans(text) {
+        let url = &text[start..end];
+        out.push_str(&text[copied..start]);
+        out.push_str(&format!("\x1b]8;;{0}\x1b\\{0}\x1b]8;;\x1b\\", url));
+        copied = end;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Extracts the content of the `<title>` tag of an HTML page.
+pub fn extract_title(html: &str) -> Option<String> {
+    let lowercase = html.to_ascii_lowercase();
+    let start = lowercase.find("<title")?;
+    let start = start + lowercase[start..].find('>')? + 1;
+    let end = start + lowercase[start..].find("</title>")?;
+    let title = html[start..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+/// Fetches the titles of the pages we are linked to.
+pub struct Previewer {
+    #[cfg(all(feature = "link-preview", not(target_os = "emscripten")))]
+    inner: Option<(::tokio_core::reactor::Handle, ::hyper::Client<::hyper::client::HttpConnector>)>,
+}
+
+#[cfg(all(feature = "link-preview", not(target_os = "emscripten")))]
+impl Previewer {
+    /// Creates a `Previewer` that fetches titles in the background on the events loop of
+    /// `platform`, or that does nothing if `enabled` is false.
+    pub fn new(platform: &PlatformSpecific, enabled: bool) -> Previewer {
+        if !enabled {
+            return Previewer { inner: None };
+        }
+        let handle = platform.handle();
+        let client = ::hyper::Client::new(&handle);
+        Previewer {
+            inner: Some((handle, client)),
+        }
+    }
+
+    /// Fetches the title of the page at `url`, and prints it once it's available.
+    ///
+    /// Only plain HTTP is supported, as we don't want to pull a TLS implementation for this.
+    pub fn preview(&self, url: &str) {
+        use futures::{Future, Stream};
+        use std::time::Duration;
+        use tokio_core::reactor::Timeout;
+
+        let (handle, client) = match self.inner {
+            Some(ref inner) => (&inner.0, &inner.1),
+            None => return,
+        };
+        let uri: ::hyper::Uri = match url.parse() {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+
+        let timeout = match Timeout::new(Duration::from_secs(FETCH_TIMEOUT_SECS), handle) {
+            Ok(timeout) => timeout,
+            Err(_) => return,
+        };
+
+        let url = url.to_owned();
+        let fetch = client
+            .get(uri)
+            .and_then(|response| {
+                // Dropping the rest of the body closes the connection.
+                let mut read = 0;
+                let head = response.body().take_while(move |chunk| {
+                    let more = read < MAX_BODY;
+                    read += chunk.len();
+                    Ok(more)
+                });
+                head.concat2()
+            })
+            .map(Some)
+            .map_err(|_| ())
+            .select(timeout.map(|()| None).map_err(|_| ()))
+            .then(move |first| {
+                if let Ok((Some(body), _)) = first {
+                    let head = &body[..body.len().min(MAX_BODY)];
+                    if let Some(title) = extract_title(&String::from_utf8_lossy(head)) {
+                        let arrow = display::symbol("\u{21b3}", "->");
+                        display::message(&format!("  {} {} ({})", arrow, title, url));
+                    }
+                }
+                Ok(())
+            });
+        handle.spawn(fetch);
+    }
+}
+
+#[cfg(not(all(feature = "link-preview", not(target_os = "emscripten"))))]
+impl Previewer {
+    pub fn new(_platform: &PlatformSpecific, _enabled: bool) -> Previewer {
+        Previewer {}
+    }
+
+    pub fn preview(&self, _url: &str) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_urls, wrap_urls};
+
+    #[test]
+    fn trailing_punctuation_is_not_part_of_the_url() {
+        let text = "See http://example.com/a, or https://example.com/b).";
+        assert_eq!(find_urls(text), vec!["http://example.com/a", "https://example.com/b"]);
+    }
+
+    #[test]
+    fn a_url_starting_another_is_wrapped_once() {
+        let wrapped = wrap_urls("http://a.test http://a.test/b");
+        assert_eq!(
+            wrapped,
+            "\x1b]8;;http://a.test\x1b\\http://a.test\x1b]8;;\x1b\\ \
+             \x1b]8;;http://a.test/b\x1b\\http://a.test/b\x1b]8;;\x1b\\"
+        );
+    }
+}
@@ -0,0 +1,60 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Recognizes the dials that would only connect us to ourselves.
+//!
+//! Our own addresses come back to us in many ways: the configuration, a beacon, or the address
+//! that someone pasted in the room. Dialing them would succeed and open a useless connection,
+//! so we recognize them before dialing.
+
+use libp2p::multiaddr::AddrComponent;
+use libp2p::{Multiaddr, PeerId};
+use std::net::IpAddr;
+
+/// Returns true if dialing `address` would reach us, given the addresses in `own` on which we
+/// listen or are reachable.
+pub fn is_own(address: &Multiaddr, own: &[Multiaddr], peer_id: &PeerId) -> bool {
+    let text = address.to_string();
+    let id = peer_id.to_base58();
+    if text.ends_with(&format!("/ipfs/{}", id)) || text.ends_with(&format!("/p2p/{}", id)) {
+        return true;
+    }
+    own.iter().any(|own| same_endpoint(address, own))
+}
+
+/// Returns true if `dialed` is `own`, or if `own` listens on all the interfaces and `dialed` is a
+/// loopback or unspecified address with the same port.
+fn same_endpoint(dialed: &Multiaddr, own: &Multiaddr) -> bool {
+    let dialed: Vec<_> = dialed.iter().collect();
+    let own: Vec<_> = own.iter().collect();
+    dialed.len() == own.len() && dialed.iter().zip(&own).all(|pair| match pair {
+        (&AddrComponent::IP4(dialed), &AddrComponent::IP4(own)) => {
+            reaches(IpAddr::V4(dialed), IpAddr::V4(own))
+        }
+        (&AddrComponent::IP6(dialed), &AddrComponent::IP6(own)) => {
+            reaches(IpAddr::V6(dialed), IpAddr::V6(own))
+        }
+        (dialed, own) => dialed == own,
+    })
+}
+
+fn reaches(dialed: IpAddr, own: IpAddr) -> bool {
+    dialed == own || (own.is_unspecified() && (dialed.is_loopback() || dialed.is_unspecified()))
+}
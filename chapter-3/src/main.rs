@@ -35,8 +35,9 @@
 //!   find the generated JavaScript code.
 //!
 //! In addition to `browser.html`, you are also given a file `platform.rs`. This file contains
-//! platform-independant code that allows you to run an events loop and receive messages from stdin
-//! in a cross-plaform way. See the usage in the `main()` function below.
+//! platform-independant code that allows you to run an events loop in a cross-plaform way, and
+//! `sources.rs` reads the messages from stdin, or from a file or a named pipe. See the usage in the
+//! `main()` function below.
 //!
 //! The browser doesn't support dialing to a TCP port. The only protocol that is allowed is
 //! websockets. Good news, however! The `build_transport()` method in the `platform` module
@@ -49,35 +50,349 @@
 //!
 //! Good luck!
 
+extern crate base64;
+extern crate bs58;
+extern crate bytes;
+extern crate clap;
+extern crate ed25519_dalek;
 extern crate futures;
 extern crate libp2p;
+extern crate multistream_select;
 extern crate rand;
+extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate sha2;
 extern crate tokio_io;
 extern crate tokio_stdin;
 
+use bytes::Bytes;
+use error::Error;
+use futures::future::{self, Either};
+use futures::stream;
+use futures::sync::mpsc;
 use futures::{Future, Stream};
+use std::cell::RefCell;
+use std::io::Error as IoError;
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
 
 use libp2p::core::Transport;
-use libp2p::floodsub::{FloodSubController, FloodSubUpgrade, TopicBuilder};
-use libp2p::{Multiaddr, PeerId};
+use libp2p::floodsub::{FloodSubController, FloodSubUpgrade};
+use libp2p::Multiaddr;
 
+#[cfg(all(feature = "link-preview", not(target_os = "emscripten")))]
+extern crate hyper;
+#[cfg(all(feature = "desktop-notifications", not(target_os = "emscripten")))]
+extern crate notify_rust;
+#[cfg(all(feature = "system-clipboard", not(target_os = "emscripten")))]
+extern crate clipboard as system_clipboard;
+#[cfg(all(feature = "serial-transport", not(target_os = "emscripten")))]
+extern crate tokio_file_unix;
+#[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+extern crate cpal;
+#[cfg(all(feature = "audio-call", not(target_os = "emscripten")))]
+extern crate opus;
+#[cfg(all(feature = "wasm-plugins", not(target_os = "emscripten")))]
+extern crate wasmi;
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+extern crate argon2;
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+extern crate ring;
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+extern crate rpassword;
+#[cfg(not(target_os = "emscripten"))]
+extern crate fs2;
+#[cfg(not(target_os = "emscripten"))]
+extern crate futures_cpupool;
+#[cfg(not(target_os = "emscripten"))]
+extern crate tokio_core;
+#[cfg(all(unix, not(target_os = "emscripten")))]
+extern crate libc;
+#[cfg(all(unix, not(target_os = "emscripten")))]
+extern crate tokio_signal;
+#[cfg(windows)]
+extern crate winapi;
 #[cfg(target_os = "emscripten")]
 #[macro_use]
 extern crate stdweb;
 
+// Defines `tr!` and `say!`, so it comes before the modules that use them.
+#[macro_use]
+mod i18n;
+
+mod addresses;
+#[cfg(not(target_os = "emscripten"))]
+mod admission;
+#[cfg(feature = "calls")]
+mod audio;
+mod backfill;
+mod batch;
+#[cfg(all(feature = "diagnostics", not(target_os = "emscripten")))]
+mod capture;
+#[cfg(not(target_os = "emscripten"))]
+mod beacon;
+#[cfg(all(feature = "broker-bridge", not(target_os = "emscripten")))]
+mod bridge;
+mod capabilities;
+mod chaos;
+mod chat;
+mod clipboard;
+mod clocks;
+mod command;
+#[cfg(windows)]
+mod console;
+mod config;
+mod compose;
+mod damping;
+mod demo;
+#[cfg(not(target_os = "emscripten"))]
+mod dials;
+mod digest;
+mod directory;
+mod display;
+#[cfg(all(feature = "diagnostics", not(target_os = "emscripten")))]
+mod dissect;
+#[cfg(all(feature = "diagnostics", not(target_os = "emscripten")))]
+mod doctor;
+mod drain;
+mod echo;
+mod election;
+mod emoji;
+mod envelope;
+mod error;
+mod export;
+mod filter;
+mod graph;
+mod handoff;
+mod history;
+#[cfg(all(feature = "http-server", not(target_os = "emscripten")))]
+mod http;
+mod identity;
+mod idle;
+mod inputs;
+#[cfg(feature = "dht")]
+mod kv;
+mod links;
+mod loopback;
+mod markdown;
+mod mentions;
+mod metadata;
+mod migration;
+mod moderation;
+mod names;
+mod notifier;
+mod options;
+mod outbox;
+mod pad;
+mod peers;
+mod personas;
+mod pins;
 mod platform;
+mod plugins;
+mod poll;
+mod ports;
+mod presence;
+#[cfg(all(feature = "diagnostics", not(target_os = "emscripten")))]
+mod probe;
+mod purge;
+#[cfg(not(target_os = "emscripten"))]
+mod race;
+mod recording;
+mod redact;
+mod relays;
+mod reorder;
+#[cfg(not(target_os = "emscripten"))]
+mod replay;
+mod reports;
+mod schedule;
+mod scores;
+mod screen;
+mod screening;
+#[cfg(all(feature = "serial-transport", not(target_os = "emscripten")))]
+mod serial;
+mod sinks;
+#[cfg(not(target_os = "emscripten"))]
+mod socks;
+mod sources;
+mod stack;
+#[cfg(not(target_os = "emscripten"))]
+mod stun;
+#[cfg(not(target_os = "emscripten"))]
+mod systemd;
+mod template;
+#[cfg(not(target_os = "emscripten"))]
+mod throttle;
+mod tofu;
+mod topics;
+mod transcript;
+#[cfg(feature = "games")]
+mod ttt;
+mod upgrade;
+mod usage;
+mod vault;
+mod version;
+mod watches;
+mod wizard;
+mod workers;
 
 fn main() {
-    // The `PlatformSpecific` object allows you to handle the transport and stdin in a
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        ::std::process::exit(err.exit_code());
+    }
+}
+
+fn run() -> Result<(), Error> {
+    let options = options::Options::from_args()?;
+    display::set_verbosity(options.verbosity);
+    display::set_plain(options.plain);
+    #[cfg(not(target_os = "emscripten"))]
+    {
+        if let Some(ref path) = options.replay {
+            return replay::run(path, &options)
+                .map_err(|err| Error::config(&format!("can't replay {}", path), err));
+        }
+    }
+    #[cfg(all(feature = "diagnostics", not(target_os = "emscripten")))]
+    {
+        if options.doctor {
+            // The exit code tells scripts whether a check failed.
+            if doctor::run(&options) > 0 {
+                ::std::process::exit(1);
+            }
+            return Ok(());
+        }
+        if let Some(ref address) = options.probe {
+            if !probe::run(address) {
+                ::std::process::exit(1);
+            }
+            return Ok(());
+        }
+        if let Some(ref input) = options.decode {
+            return dissect::run(input).map_err(|err| Error::config("can't decode the input", err));
+        }
+    }
+    #[cfg(not(all(feature = "diagnostics", not(target_os = "emscripten"))))]
+    {
+        if options.doctor || options.probe.is_some() || options.decode.is_some() {
+            let reason = "doctor, probe and decode require the diagnostics feature";
+            return Err(Error::Config(reason.to_owned()));
+        }
+    }
+
+    // Besides the terminal, what we display can go to a file and to the programs that listen.
+    #[cfg(not(target_os = "emscripten"))]
+    {
+        if let Some(ref path) = options.output_jsonl {
+            let file = ::std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| Error::config(&format!("can't open {}", path), err))?;
+            display::add_sink(Box::new(sinks::JsonLines::new(file)));
+        }
+    }
+    #[cfg(all(unix, not(target_os = "emscripten")))]
+    {
+        if let Some(ref path) = options.control_socket {
+            let socket = sinks::ControlSocket::bind(path)
+                .map_err(|err| Error::config(&format!("can't listen on {}", path), err))?;
+            display::add_sink(Box::new(socket));
+        }
+    }
+    #[cfg(windows)]
+    {
+        if options.control_socket.is_some() {
+            return Err(Error::Config("--control-socket is only available on Unix".to_owned()));
+        }
+    }
+
+    // The `PlatformSpecific` object allows you to handle the transport and the timers in a
     // cross-platform manner.
     let platform = platform::PlatformSpecific::default();
 
     // This builds an implementation of the `Transport` trait (similar to the `TcpConfig` object in
     // earlier chapters).
-    let transport = platform.build_transport();
+    let transport = platform.build_transport(options.proxy.clone());
+
+    // Experimental: the chat also works over a serial link, without IP.
+    #[cfg(all(feature = "serial-transport", not(target_os = "emscripten")))]
+    let transport = transport.or_transport(serial::SerialConfig::new(platform.handle()));
+
+    // Dials that never complete would otherwise hang silently, and many of them at once would
+    // exhaust the sockets.
+    #[cfg(not(target_os = "emscripten"))]
+    let dial_limit = dials::DialLimit::new(
+        transport,
+        options.max_dials,
+        options.dial_timeout,
+        platform.handle(),
+    );
+    #[cfg(not(target_os = "emscripten"))]
+    let transport = dial_limit.clone();
+
+    // A peer known under several addresses is dialed on all of them, with staggered starts, and
+    // the first connection wins.
+    #[cfg(not(target_os = "emscripten"))]
+    let addresses = race::AddressBook::default();
+    #[cfg(not(target_os = "emscripten"))]
+    let transport = race::Race::new(transport, addresses.clone(), platform.handle());
+
+    // On constrained networks, all the connections can share a maximum upload and download rate.
+    //
+    // The bytes of all the connections are also counted, for `/usage`.
+    let counters = usage::Counters::default();
+    #[cfg(not(target_os = "emscripten"))]
+    let transport = {
+        let limits = throttle::Limits::new(options.max_upload, options.max_download);
+        let handle = platform.handle();
+        let counters = counters.clone();
+        transport.map(move |socket, _| {
+            usage::Counted::new(throttle::Throttled::new(socket, limits, handle), counters)
+        })
+    };
+
+    // With `--capture`, the bytes of every connection are written to a file, for `decode`.
+    #[cfg(all(feature = "diagnostics", not(target_os = "emscripten")))]
+    let transport = {
+        let writer = match options.capture {
+            Some(ref path) => Some(
+                capture::Writer::create(path)
+                    .map_err(|err| Error::config(&format!("can't create {}", path), err))?,
+            ),
+            None => None,
+        };
+        capture::Capture::new(transport, writer)
+    };
+    #[cfg(not(all(feature = "diagnostics", not(target_os = "emscripten"))))]
+    {
+        if options.capture.is_some() {
+            say!("* --capture requires the diagnostics feature");
+        }
+    }
+
+    // A relay can limit the nodes that dial it, for when a whole workshop joins at once, and
+    // turns them all away once it drains.
+    let drain = drain::Drain::default();
+    #[cfg(not(target_os = "emscripten"))]
+    let transport = admission::Admission::new(
+        transport,
+        options.max_clients,
+        options.max_joins,
+        drain.clone(),
+    );
 
-    // This builds a stream of messages coming from stdin.
-    let stdin = platform.stdin();
+    // For `/conn`, we time how long each socket takes to open.
+    let timings = stack::Timings::default();
+    let transport = timings.wrap(transport);
+
+    // This builds a stream of messages coming from stdin, or from `--input-file` or
+    // `--input-fifo`.
+    let stdin = sources::open(&options)?.lines();
 
     // Insert your code here!
 
@@ -87,13 +402,47 @@ fn main() {
     // Tweaking the transport is done by first creating a `FloodSubUpgrade`, then calling
     // `with_upgrade`.
     //
-    // As part of the protocol, which need to pass a *PeerId* to `FloodSubUpgrade::news()`. In this
-    // workshop we just generate it randomly.
-    let (floodsub_upgrade, floodsub_rx) = {
-        let key = (0..2048).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
-        FloodSubUpgrade::new(PeerId::from_public_key(&key))
+    // As part of the protocol, which need to pass a *PeerId* to `FloodSubUpgrade::news()`.
+    // Contrary to chapter 2, we derive it from a real key pair, which we also use to sign the
+    // messages we publish.
+    //
+    // Two nodes with the same identity would confuse the others, so only one instance at a time
+    // can use an identity file.
+    #[cfg(not(target_os = "emscripten"))]
+    let _lock = match options.identity {
+        Some(ref path) => match identity::lock(path) {
+            Ok(lock) => Some(lock),
+            Err(ref err) if err.kind() == ::std::io::ErrorKind::WouldBlock => {
+                let text = "Another node is already running with the identity file {}. Stop it, \
+                            or pass another file with --identity.";
+                return Err(Error::Config(tr!(text, path)));
+            }
+            Err(err) => {
+                return Err(Error::Config(tr!("Couldn't lock the identity file {}: {}", path, err)));
+            }
+        },
+        None => None,
     };
-    let upgraded_transport = transport.with_upgrade(floodsub_upgrade.clone());
+    let identity = match options.identity {
+        Some(ref path) => {
+            identity::Identity::load_or_generate(path, options.encrypt_identity)
+                .map_err(|err| Error::config(&format!("can't load the identity {}", path), err))?
+        }
+        None => identity::Identity::generate(),
+    };
+    say!(
+        "Our public key is {}",
+        identity::encode_key(identity.public_key())
+    );
+    let (floodsub_upgrade, floodsub_rx) = FloodSubUpgrade::new(identity.peer_id().clone());
+
+    // Floodsub isn't the only protocol that we support. `ChatUpgrade` negotiates either floodsub
+    // or one of our own direct protocols, such as tic-tac-toe, on each connection.
+    // The floodsub connections drop the messages of banned peers before floodsub relays them,
+    // see the `screening` module.
+    let bans = screening::Bans::new();
+    let chat_upgrade = upgrade::ChatUpgrade::new(floodsub_upgrade.clone(), bans.clone());
+    let upgraded_transport = transport.clone().with_upgrade(chat_upgrade.clone());
 
     // We now create a *swarm*. A swarm is a convenient object that is responsible for handling all
     // the incoming and outgoing connections in a single point.
@@ -104,30 +453,178 @@ fn main() {
     // a new connection every time. In order to add support for muxing with any transport, we can
     // just call the `with_dummy_muxing()` method of the `Transport` trait.
     let upgr_trans_with_muxing = upgraded_transport.with_dummy_muxing();
-    let (swarm_controller, swarm_future) =
-        libp2p::swarm(upgr_trans_with_muxing.clone(), |future, _remote_addr| {
-            // The first parameter of this closure (`future`) is the output of the floodsub
-            // upgrade. If we didn't apply any upgrade on the transport, it would be the raw socket
-            // instead.
+    #[cfg(feature = "games")]
+    let games = ttt::Games::new();
+    #[cfg(feature = "calls")]
+    let calls = audio::Calls::new(options.accept_calls);
+    #[cfg(not(feature = "calls"))]
+    {
+        if options.accept_calls {
+            say!("* --accept-calls requires the calls feature");
+        }
+    }
+    let screens = screen::Screens::new(options.accept_screens);
+    let peers = peers::PeerTable::new();
+    let chaos = chaos::Chaos::default();
+    let relays = relays::Relays::new(options.relays.clone());
+    let capabilities = capabilities::Capabilities::default();
+    // With `--record`, what the network sends us is also written to a file.
+    let recorder = match options.record {
+        Some(ref path) => Some(Rc::new(
+            recording::Recorder::create(path)
+                .map_err(|err| Error::config(&format!("can't create {}", path), err))?,
+        )),
+        None => None,
+    };
+    #[cfg(not(target_os = "emscripten"))]
+    let playback_peers = peers.clone();
+    let (swarm_controller, swarm_future) = {
+        #[cfg(feature = "games")]
+        let games = games.clone();
+        #[cfg(feature = "calls")]
+        let calls = calls.clone();
+        let screens = screens.clone();
+        let peers = peers.clone();
+        let recorder = recorder.clone();
+        let chaos = chaos.clone();
+        let timings = timings.clone();
+        libp2p::swarm(upgr_trans_with_muxing, move |negotiated, remote_addr| {
+            // The first parameter of this closure (`output`) is the output of the upgrade. If we
+            // didn't apply any upgrade on the transport, it would be the raw socket instead.
             //
             // In the case of floodsub, the output is a future that must be driven to completion
             // for the protocol to work.
             // Coincidentially, the return value of this closure must be a future that is going to
             // be integrated inside of `swarm_future`. By driving `swarm_future` to completion, we
             // will also drive to completion the future coming from floodsub.
+            //
+            // This is also where we learn about every connection, in both directions. We keep
+            // track of them in `peers` until their future finishes.
+            let upgrade::Negotiated {
+                endpoint,
+                output,
+                chosen,
+            } = negotiated;
+            let protocol = output.protocol();
+            let id = peers.borrow_mut().opened(remote_addr.clone(), endpoint, protocol);
+            peers.borrow_mut().timed(id, timings.finish(&remote_addr, chosen));
+            if let Some(ref recorder) = recorder {
+                recorder.opened(id, &remote_addr, endpoint, protocol);
+            }
+            let future = match output {
+                upgrade::ChatOutput::FloodSub(future) => Either::A(future),
+                #[cfg(feature = "games")]
+                upgrade::ChatOutput::Ttt(connection) => {
+                    Either::B(ttt::handle_connection(games.clone(), connection, remote_addr))
+                }
+                #[cfg(feature = "calls")]
+                upgrade::ChatOutput::Audio(connection) => {
+                    Either::B(audio::handle_connection(calls.clone(), connection, remote_addr))
+                }
+                upgrade::ChatOutput::Screen(connection) => {
+                    Either::B(screen::handle_connection(screens.clone(), connection, remote_addr))
+                }
+            };
+            // With `/chaos on`, the connection may be dropped at any time.
+            let dropped = chaos.register(id);
+            let peers = peers.clone();
+            let recorder = recorder.clone();
+            let chaos = chaos.clone();
             future
-        });
+                .select(dropped)
+                .map(|_| ())
+                .map_err(|(err, _)| err)
+                .then(move |result| {
+                    peers.borrow_mut().closed(id);
+                    if let Some(ref recorder) = recorder {
+                        recorder.closed(id);
+                    }
+                    chaos.unregister(id);
+                    result
+                })
+        })
+    };
 
-    if cfg!(not(target_os = "emscripten")) {
-        let listen_multiaddr: Multiaddr = "/ip4/0.0.0.0/tcp/63204/ws"
-            .parse()
-            .expect("failed to parse multiaddress");
+    // When playing back a recording, we stay off the network.
+    let listen_addrs = if cfg!(not(target_os = "emscripten")) && options.playback.is_none() {
         // Let's use the swarm to listen, instead of the raw transport.
-        let actual_multiaddr = swarm_controller
-            .listen_on(listen_multiaddr)
-            .expect("failed to listen");
-        println!("Now listening on {}", actual_multiaddr);
-    }
+        //
+        // If a port is taken, we listen on another one. The first listener uses the socket
+        // passed by systemd, if any, whose port we must keep.
+        #[cfg(not(target_os = "emscripten"))]
+        let mut inherited = systemd::activated();
+        #[cfg(target_os = "emscripten")]
+        let mut inherited = false;
+        let actual_multiaddrs: Vec<Multiaddr> = options
+            .listen
+            .iter()
+            .filter_map(|listen_multiaddr| {
+                let candidate = if mem::replace(&mut inherited, false) {
+                    Some(listen_multiaddr.clone())
+                } else {
+                    ports::available(listen_multiaddr)
+                };
+                let actual_multiaddr = match candidate {
+                    Some(ref candidate) => swarm_controller.listen_on(candidate.clone()).ok(),
+                    None => None,
+                };
+                match actual_multiaddr {
+                    Some(actual_multiaddr) => {
+                        let requested = ports::port(listen_multiaddr);
+                        if candidate.as_ref().and_then(ports::port) != requested {
+                            say!(
+                                "* Port {} is taken; now listening on {} instead",
+                                requested.unwrap_or(0),
+                                actual_multiaddr
+                            );
+                        } else {
+                            say!("Now listening on {}", actual_multiaddr);
+                        }
+                        Some(actual_multiaddr)
+                    }
+                    None => {
+                        say!("* Couldn't listen on {}", listen_multiaddr);
+                        None
+                    }
+                }
+            })
+            .collect();
+        if !actual_multiaddrs.is_empty()
+            && !actual_multiaddrs.iter().any(addresses::reachable_by_browsers)
+        {
+            say!("* Browsers can't join us, since none of our addresses ends with /ws");
+        }
+        #[cfg(all(feature = "serial-transport", not(target_os = "emscripten")))]
+        {
+            if let Some(ref device) = options.serial {
+                let addr = serial::address(device)
+                    .map_err(|err| Error::config("--serial expects the name of a device", err))?;
+                let addr = swarm_controller.listen_on(addr).map_err(|addr| {
+                    let err = IoError::new(::std::io::ErrorKind::Other, "the transport refused it");
+                    Error::network(&format!("can't listen on {}", addr), err)
+                })?;
+                say!("Now listening on {}", addr);
+            }
+        }
+        #[cfg(not(all(feature = "serial-transport", not(target_os = "emscripten"))))]
+        {
+            if options.serial.is_some() {
+                say!("* --serial requires the serial-transport feature");
+            }
+        }
+        #[cfg(not(target_os = "emscripten"))]
+        systemd::notify_ready();
+        actual_multiaddrs
+    } else {
+        Vec::new()
+    };
+    let listen_addr = listen_addrs.first().cloned();
+    // Dialing one of these would only connect us to ourselves.
+    let own_addresses: Vec<Multiaddr> = listen_addrs
+        .into_iter()
+        .chain(options.external_addresses.iter().cloned())
+        .collect();
+    let own_peer_id = identity.peer_id().clone();
 
     // Now let's handle the floodsub protocol.
     // We already have `floodsub_rx`, which was created earlier. It is a `Stream` of all the
@@ -136,44 +633,473 @@ fn main() {
     let floodsub_controller = FloodSubController::new(&floodsub_upgrade);
 
     // All the messages dispatched through the floodsub protocol belong to what is called a
-    // *topic*. This is what we create here.
-    let topic = TopicBuilder::new("workshop-chapter3-topic").build();
+    // *topic*. This is what we create here, one per room passed with `--topic`.
+    // Their names can be namespaced or hashed; see the `topics` module.
+    let naming = topics::TopicNaming::new(options.topic_namespace.clone(), options.hash_topics);
+    let rooms: Vec<_> = options
+        .topics
+        .iter()
+        .map(|room| (room.clone(), naming.topic(room)))
+        .collect();
 
     // We need to subscribe to a topic in order to receive the messages that belong to it.
     // Subscribing to a topic broadcasts a message over the network to signal all the connected
     // nodes that we are interested in this topic.
-    floodsub_controller.subscribe(&topic);
+    for &(ref room, ref topic) in &rooms {
+        floodsub_controller.subscribe(topic);
+        if let Some(legacy) = migration::legacy_topic(&naming, room) {
+            floodsub_controller.subscribe(&legacy);
+        }
+    }
 
-    // Let's tweak `floodsub_rx` so that we print on stdout the messages we receive.
-    let floodsub_rx = floodsub_rx.for_each(|msg| {
-        if let Ok(msg) = String::from_utf8(msg.data) {
-            println!("> {}", msg);
-        } else {
-            println!("Received non-utf8 message");
+    // With `--bridge`, the envelopes of the first room are mirrored to a message broker.
+    let bridged_topic = rooms[0].1.clone();
+    #[cfg(all(feature = "broker-bridge", not(target_os = "emscripten")))]
+    let (bridge, bridge_rx) = match options.bridge {
+        Some(ref url) => {
+            let (bridge, incoming) = bridge::Bridge::connect(url, &own_peer_id.to_base58())
+                .map_err(|err| Error::network(&format!("can't connect to {}", url), err))?;
+            (Some(bridge), Either::A(incoming))
+        }
+        None => (None, Either::B(stream::empty())),
+    };
+    #[cfg(not(all(feature = "broker-bridge", not(target_os = "emscripten"))))]
+    let bridge_rx = {
+        if options.bridge.is_some() {
+            say!("* --bridge requires the broker-bridge feature");
         }
+        stream::empty::<Result<Vec<u8>, IoError>, ()>()
+    };
 
-        Ok(())
-    });
+    // The state of the chat is shared between the stream of messages received from the network
+    // and the stream of lines typed by the user.
+    //
+    // Some commands, such as `/ttt`, need to open new connections. They are passed to us through
+    // `dial_rx`.
+    let previewer = links::Previewer::new(&platform, options.link_preview);
+    let (dial_tx, dial_rx) = mpsc::unbounded();
+    // The nodes passed on the command line are dialed through the same path.
+    for peer in options.dial.iter().filter(|_| options.playback.is_none()) {
+        let alternatives = peer
+            .split(',')
+            .map(|addr| options::parse_address("dial", addr))
+            .collect::<Result<Vec<_>, _>>()?;
+        #[cfg(not(target_os = "emscripten"))]
+        addresses.add(alternatives.clone());
+        let _ = dial_tx.unbounded_send(upgrade::DialRequest {
+            address: alternatives[0].clone(),
+            protocol: upgrade::Protocol::FloodSub,
+        });
+    }
+    // On the local network, the nodes can also find each other with multicast beacons.
+    #[cfg(not(target_os = "emscripten"))]
+    {
+        if let (true, Some(listen_addr)) = (options.beacon, listen_addr.as_ref()) {
+            if options.lan_party {
+                say!(
+                    "* LAN party: looking for the nodes of the local network, in {}",
+                    options.topics.join(", ")
+                );
+            }
+            let handle = platform.handle();
+            // Hashed topics are meant to hide the names of the rooms.
+            let rooms = if options.hash_topics {
+                Vec::new()
+            } else {
+                options.topics.clone()
+            };
+            let nick = options.nick.clone();
+            let peer_id = identity.peer_id();
+            match beacon::run(peer_id, nick, rooms, listen_addr, &handle, dial_tx.clone()) {
+                Ok(future) => {
+                    handle.spawn(future.map_err(|err| say!("* Beacons stopped: {}", err)))
+                }
+                Err(err) => say!("* Couldn't start the beacons: {}", err),
+            }
+        }
+    }
+    // Of the relays passed with `--relay`, we only dial the fastest.
+    #[cfg(not(target_os = "emscripten"))]
+    {
+        if !relays.is_empty() && options.playback.is_none() {
+            let handle = platform.handle();
+            match relays::run(relays.clone(), &handle, dial_tx.clone()) {
+                Ok(future) => handle.spawn(
+                    future.map_err(|err| say!("* Stopped probing the relays: {}", err)),
+                ),
+                Err(err) => say!("* Couldn't probe the relays: {}", err),
+            }
+        }
+    }
+    let config = match options.config {
+        Some(ref path) => config::Config::load(path)
+            .map_err(|err| Error::config(&format!("can't load {}", path), err))?,
+        None => config::Config::default(),
+    };
+    // With `--plugins`, the bots of the directory react to the texts we receive.
+    let (plugins, plugin_actions) = plugins::Plugins::load(options.plugins.as_ref().map(|d| &d[..]))
+        .map_err(|err| Error::config("can't read the plugins directory", err))?;
+    let parts = chat::Parts {
+        identity,
+        floodsub: floodsub_controller,
+        naming,
+        rooms,
+        config,
+        previewer,
+        #[cfg(feature = "games")]
+        games,
+        #[cfg(feature = "calls")]
+        calls,
+        screens,
+        plugins,
+        peers,
+        chaos: chaos.clone(),
+        relays: relays.clone(),
+        capabilities: capabilities.clone(),
+        drain: drain.clone(),
+        counters,
+        dial: dial_tx,
+        bans,
+    };
+    let chat = Rc::new(RefCell::new(chat::Chat::new(parts, &options)?));
 
-    let args: Vec<String> = if cfg!(not(target_os = "emscripten")) {
-        std::env::args().skip(1).collect()
-    } else {
-        vec!["/ip4/127.0.0.1/tcp/63204/ws".to_owned()]
+    // `kill -HUP` reloads the configuration file.
+    #[cfg(all(unix, not(target_os = "emscripten")))]
+    {
+        use tokio_signal::unix::{Signal, SIGHUP};
+
+        let chat = chat.clone();
+        let reloads = Signal::new(SIGHUP, &platform.handle())
+            .flatten_stream()
+            .for_each(move |_| {
+                chat.borrow_mut().reload();
+                Ok(())
+            })
+            .map_err(|err| say!("* Stopped listening for SIGHUP: {}", err));
+        platform.handle().spawn(reloads);
+    }
+
+    // `kill -USR1` dumps the state of the node without stopping it, to debug the one that seems
+    // stuck.
+    #[cfg(all(unix, not(target_os = "emscripten")))]
+    {
+        use tokio_signal::unix::{Signal, SIGUSR1};
+
+        let chat = chat.clone();
+        let dump_file = options.dump_file.clone();
+        let dumps = Signal::new(SIGUSR1, &platform.handle())
+            .flatten_stream()
+            .for_each(move |_| {
+                let (dialing, queued) = dial_limit.pending();
+                let dump = format!(
+                    "{}Dials: {} in progress, {} waiting\n",
+                    chat.borrow().diagnostics(),
+                    dialing,
+                    queued
+                );
+                match dump_file {
+                    Some(ref path) => {
+                        if let Err(err) = ::std::fs::write(path, dump) {
+                            let text = "* Couldn't write the diagnostics to {}: {}";
+                            eprintln!("{}", tr!(text, path, err));
+                        }
+                    }
+                    None => eprint!("{}", dump),
+                }
+                Ok(())
+            })
+            .map_err(|err| say!("* Stopped listening for SIGUSR1: {}", err));
+        platform.handle().spawn(dumps);
+    }
+
+    // `kill -TERM` drains the node instead of dropping the connections of everyone at once.
+    #[cfg(all(unix, not(target_os = "emscripten")))]
+    {
+        use tokio_signal::unix::{Signal, SIGTERM};
+
+        let chat = chat.clone();
+        let terms = Signal::new(SIGTERM, &platform.handle())
+            .flatten_stream()
+            .for_each(move |_| {
+                chat.borrow_mut().drain(None);
+                Ok(())
+            })
+            .map_err(|err| say!("* Stopped listening for SIGTERM: {}", err));
+        platform.handle().spawn(terms);
+    }
+
+    // Behind a NAT, a STUN server tells us the public IP address on which to advertise our
+    // listening port.
+    #[cfg(not(target_os = "emscripten"))]
+    {
+        if let (Some(server), Some(listen_addr)) = (options.stun.as_ref(), listen_addr) {
+            let chat = chat.clone();
+            let probe = stun::probe(server, &platform.handle()).then(move |result| {
+                match result {
+                    Ok(ip) => chat
+                        .borrow_mut()
+                        .add_external_address(stun::with_ip(&listen_addr, ip)),
+                    Err(err) => say!("* Couldn't learn our public address: {}", err),
+                }
+                Ok::<(), ()>(())
+            });
+            platform.handle().spawn(probe);
+        }
+    }
+
+    // With `--http`, the state of the node can be looked at over HTTP, its supervisor can ask
+    // whether it is healthy, `--feed` serves rooms to feed readers and `--http-publish` lets bots
+    // publish.
+    #[cfg(all(feature = "http-server", not(target_os = "emscripten")))]
+    {
+        if let Some(ref address) = options.http {
+            let pages = http::Pages {
+                listening: listen_addr.is_some(),
+                ready_peers: options.ready_peers,
+                dashboard: options.dashboard,
+                feeds: options.feeds.clone(),
+                publish: options.http_publish,
+            };
+            match http::listen(address, &platform.handle(), pages, chat.clone()) {
+                Ok(server) => platform.handle().spawn(
+                    server.map_err(|err| say!("* The HTTP server stopped: {}", err)),
+                ),
+                Err(err) => say!("* Couldn't serve HTTP on {}: {}", address, err),
+            }
+        }
+    }
+    #[cfg(not(all(feature = "http-server", not(target_os = "emscripten"))))]
+    {
+        if options.http.is_some() {
+            say!("* --http requires the http-server feature");
+        }
+    }
+
+    // Let's tweak `floodsub_rx` so that we handle the messages we receive.
+    // Their signatures are verified by worker threads; `buffered` hands the results back to us
+    // in the order in which the messages arrived, along with the topics they were published on.
+    // The envelopes are shared from then on, by the recorder, the bridge and the workers.
+    // With `--playback`, the messages come from the recording instead.
+    let workers = workers::Workers::new(options.threads, options.max_message_size);
+    let floodsub_rx = floodsub_rx.map(|msg| (msg.topics, msg.source, Bytes::from(msg.data)));
+    #[cfg(not(target_os = "emscripten"))]
+    let floodsub_rx = match options.playback {
+        Some(ref path) => Either::A(
+            recording::playback(path, &platform.handle(), playback_peers)
+                .map_err(|err| Error::config(&format!("can't load {}", path), err))?,
+        ),
+        None => Either::B(floodsub_rx),
+    };
+    let floodsub_rx = {
+        let chat = chat.clone();
+        let usage = chat.clone();
+        #[cfg(all(feature = "broker-bridge", not(target_os = "emscripten")))]
+        let bridged_topic = bridged_topic.clone();
+        floodsub_rx
+            .map(move |message| {
+                {
+                    let (ref topics, ref source, ref data) = message;
+                    if let Some(ref recorder) = recorder {
+                        recorder.message(topics, source, data);
+                    }
+                    #[cfg(all(feature = "broker-bridge", not(target_os = "emscripten")))]
+                    {
+                        if let Some(ref bridge) = bridge {
+                            if topics.contains(bridged_topic.hash()) {
+                                bridge.forward(data);
+                            }
+                        }
+                    }
+                    usage.borrow_mut().count_received(topics, source, data.len());
+                }
+                // With `/chaos on`, some of the envelopes are handled twice.
+                stream::iter_ok(vec![message; chaos.copies()])
+            })
+            .flatten()
+            .map(move |(topics, source, data)| {
+                workers
+                    .open(data)
+                    .map(move |opened| (topics, source, opened))
+            })
+            .buffered(64)
+            .for_each(move |(topics, source, opened)| {
+                chat.borrow_mut().handle_message(&topics, &source, opened);
+                Ok(())
+            })
+    };
+
+    // Every few seconds, we tell the others that we are still here.
+    let heartbeat_future = {
+        let chat = chat.clone();
+        platform
+            .interval(options.heartbeat_interval)
+            .for_each(move |()| {
+                chat.borrow_mut().tick();
+                Ok(())
+            })
+    };
+
+    // Messages are published from the outbox at a fixed rate.
+    let outbox_future = {
+        let chat = chat.clone();
+        let period = Duration::new(0, 1_000_000_000 / options.publish_rate.max(1));
+        platform.interval(period).for_each(move |()| {
+            chat.borrow_mut().flush_one();
+            Ok(())
+        })
     };
 
-    for peer in args {
-        swarm_controller
-            .dial(
-                peer.parse().expect("Argument is not a valid multiaddress"),
-                upgr_trans_with_muxing.clone(),
+    // With batching enabled, the lines typed since the last tick are published together.
+    let batch_future = match options.batch_delay {
+        Some(delay) => {
+            let chat = chat.clone();
+            Either::A(
+                platform
+                    .interval(Duration::from_millis(delay.max(1)))
+                    .for_each(move |()| {
+                        chat.borrow_mut().flush_batch();
+                        Ok(())
+                    }),
             )
-            .expect("Failed to connect to Peer");
-    }
+        }
+        None => Either::B(future::empty()),
+    };
+
+    // With `--reorder-delay`, the texts we received are displayed once their turn came, which is
+    // checked four times per delay.
+    let reorder_future = match options.reorder_delay {
+        Some(delay) => {
+            let chat = chat.clone();
+            Either::A(
+                platform
+                    .interval(Duration::from_millis((delay / 4).max(1)))
+                    .for_each(move |()| {
+                        chat.borrow_mut().flush_reorder();
+                        Ok(())
+                    }),
+            )
+        }
+        None => Either::B(future::empty()),
+    };
+
+    // With `--demo-traffic`, the personas may say something every second.
+    let demo_future = if options.demo_traffic {
+        let chat = chat.clone();
+        Either::A(platform.interval(Duration::from_secs(1)).for_each(move |()| {
+            chat.borrow_mut().demo_tick();
+            Ok(())
+        }))
+    } else {
+        Either::B(future::empty())
+    };
+
+    // The envelopes that the broker sends us are published in the bridged room.
+    let bridge_future = {
+        let chat = chat.clone();
+        bridge_rx
+            .map_err(|()| -> IoError { unreachable!() })
+            .for_each(move |incoming| {
+                match incoming {
+                    Ok(data) => chat.borrow_mut().inject(&bridged_topic, Bytes::from(data)),
+                    Err(err) => display::chatter(&tr!("* The bridge stopped: {}", err)),
+                }
+                Ok(())
+            })
+    };
+
+    // What the bots ask for is done on the event loop.
+    let plugins_future = {
+        let chat = chat.clone();
+        plugin_actions
+            .map_err(|()| -> IoError { unreachable!() })
+            .for_each(move |action| {
+                chat.borrow_mut().handle_plugin_action(action);
+                Ok(())
+            })
+    };
+
+    // The scheduled messages are published within a second of their time.
+    let schedule_future = {
+        let chat = chat.clone();
+        platform.interval(Duration::from_secs(1)).for_each(move |()| {
+            chat.borrow_mut().publish_scheduled();
+            Ok(())
+        })
+    };
 
-    let stdin_future = stdin.for_each(move |message| {
-        floodsub_controller.publish(&topic, message.into_bytes());
-        Ok(())
+    // With `--digest-to`, the messages of the room are mailed at a fixed interval.
+    let digest_future = match options.digest_to {
+        Some(ref to) => {
+            let chat = chat.clone();
+            let mailer = digest::Mailer {
+                server: options.smtp.clone(),
+                from: options.digest_from.clone(),
+                to: to.clone(),
+            };
+            let period = options.digest_period;
+            Either::A(platform.interval(period).for_each(move |()| {
+                let digest = chat.borrow().digest(period);
+                match digest {
+                    Some((room, html)) => {
+                        let sent = mailer.send(&room, html);
+                        Either::A(sent.then(move |result| {
+                            match result {
+                                Ok(()) => display::chatter(&tr!("* Mailed the digest of {}", room)),
+                                Err(err) => {
+                                    display::chatter(&tr!("* Can't mail the digest: {}", err))
+                                }
+                            }
+                            Ok(())
+                        }))
+                    }
+                    None => Either::B(future::ok(())),
+                }
+            }))
+        }
+        None => Either::B(future::empty()),
+    };
+
+    // Once drained, we stop, whatever the other futures are doing.
+    let drain_future = {
+        let chat = chat.clone();
+        platform
+            .interval(Duration::from_millis(250))
+            .take_while(move |()| Ok(!chat.borrow().drained()))
+            .for_each(|()| Ok(()))
+    };
+
+    // After each line, we wait for the outbox to have room before reading the next one.
+    let stdin_future = stdin.for_each(move |line| {
+        chat.borrow_mut().handle_input(&line);
+        let chat = chat.clone();
+        future::poll_fn(move || Ok::<_, IoError>(chat.borrow_mut().poll_input_ready()))
+    });
+
+    // Each dial request only proposes the protocol it was made for, and we remember whether the
+    // remote accepted it.
+    let dialers = upgrade::Dialers::new(|protocol| {
+        let upgraded = transport.clone().with_upgrade(chat_upgrade.only(protocol));
+        capabilities.watch(upgraded, protocol).with_dummy_muxing()
     });
+    let dial_future = dial_rx
+        .for_each(move |request: upgrade::DialRequest| {
+            if loopback::is_own(&request.address, &own_addresses, &own_peer_id) {
+                display::chatter(&tr!("* Not dialing {}, which is ourselves", request.address));
+                return Ok(());
+            }
+            display::event(
+                display::Verbosity::Verbose,
+                &format!("Dialing {} for {:?}", request.address, request.protocol),
+            );
+            if swarm_controller
+                .dial(request.address.clone(), dialers.get(request.protocol))
+                .is_err()
+            {
+                say!("Failed to dial {}", request.address);
+            }
+            Ok(())
+        })
+        .map_err(|()| -> IoError { unreachable!() });
 
     // `final_future` is a future that contains all the behaviour that we want, but nothing has
     // actually started yet. Because we created the `TcpConfig` with tokio, we need to run the
@@ -184,9 +1110,43 @@ fn main() {
         .and_then(|(_, n)| n)
         .select(stdin_future)
         .map_err(|(err, _)| err)
-        .and_then(|(_, n)| n);
+        .and_then(|(_, n)| n)
+        .select(dial_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(heartbeat_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(outbox_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(batch_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(demo_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(bridge_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(digest_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(plugins_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(schedule_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(reorder_future)
+        .map_err(|(err, _)| err)
+        .and_then(|(_, n)| n)
+        .select(drain_future)
+        .map(|_| ())
+        .map_err(|(err, _)| err);
     // core.run(final_future).unwrap();
 
     // Instead of `core.run()`, use `platform.run()`.
     platform.run(final_future);
+    Ok(())
 }
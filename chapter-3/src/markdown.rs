@@ -0,0 +1,135 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! A very small subset of Markdown, rendered with ANSI escape codes.
+//!
+//! We support `*bold*`, `` `code` `` and fenced code blocks. Code blocks are printed on their own
+//! lines with their indentation preserved, and Rust code blocks get their keywords highlighted.
+//! In the browser and with `--plain` the text is left untouched, but for its control characters.
+
+use display;
+
+const BOLD: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+const CODE: (&str, &str) = ("\x1b[36m", "\x1b[39m");
+const KEYWORD: (&str, &str) = ("\x1b[35m", "\x1b[39m");
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while",
+];
+
+/// Removes the C0 and C1 control characters of `text`, but for its line feeds and tabs. Printed
+/// as they are, the texts of others could move the cursor, rename the terminal or hide what
+/// follows them.
+pub fn strip_controls(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c == '\n' || c == '\t' || !c.is_control())
+        .collect()
+}
+
+pub fn render(text: &str) -> String {
+    let text = &strip_controls(text);
+    if !display::decorated() {
+        return text.to_owned();
+    }
+
+    let mut out = Vec::new();
+    // `Some(language)` while we are inside of a fenced code block.
+    let mut fence: Option<String> = None;
+
+    for line in text.lines() {
+        if line.trim_left().starts_with("```") {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(line.trim_left()[3..].trim().to_lowercase()),
+            };
+            continue;
+        }
+
+        match fence {
+            Some(ref language) if language == "rust" || language == "rs" => {
+                out.push(format!("    {}", highlight_rust(line)))
+            }
+            Some(_) => out.push(format!("    {}{}{}", CODE.0, line, CODE.1)),
+            None => out.push(render_inline(line)),
+        }
+    }
+
+    // A code block on the first line would otherwise be glued to the name of the sender.
+    let mut rendered = out.join("\n");
+    if text.trim_left().starts_with("```") {
+        rendered.insert(0, '\n');
+    }
+    rendered
+}
+
+/// Renders `*bold*` and `` `code` `` spans. Unterminated markers are printed as-is.
+fn render_inline(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(pos) = rest.find(|c| c == '*' || c == '`') {
+        let marker = rest[pos..].chars().next().unwrap();
+        let (open, close) = if marker == '*' { BOLD } else { CODE };
+        match rest[pos + 1..].find(marker) {
+            Some(len) if len > 0 => {
+                out.push_str(&rest[..pos]);
+                out.push_str(open);
+                out.push_str(&rest[pos + 1..pos + 1 + len]);
+                out.push_str(close);
+                rest = &rest[pos + 2 + len..];
+            }
+            _ => {
+                out.push_str(&rest[..pos + 1]);
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+fn highlight_rust(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut word = String::new();
+
+    for c in line.chars().chain(Some('\0')) {
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        if RUST_KEYWORDS.contains(&word.as_str()) {
+            out.push_str(KEYWORD.0);
+            out.push_str(&word);
+            out.push_str(KEYWORD.1);
+        } else {
+            out.push_str(&word);
+        }
+        word.clear();
+        if c != '\0' {
+            out.push(c);
+        }
+    }
+
+    out
+}
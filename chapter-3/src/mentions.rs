@@ -0,0 +1,81 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Detection of the `@nick` mentions in incoming messages.
+
+use std::collections::VecDeque;
+
+/// Maximum number of mentions remembered for `/mentions`.
+const BACKLOG_SIZE: usize = 50;
+
+/// Returns true if `text` contains `@nick`. The comparison is case-insensitive, and `@nick` must
+/// not be immediately followed by another character that could be part of a nickname.
+pub fn is_mentioned(text: &str, nick: &str) -> bool {
+    mentioned_nicks(text).any(|mention| mention.eq_ignore_ascii_case(nick))
+}
+
+/// Returns all the nicknames mentioned with `@` in `text`.
+pub fn mentioned_nicks<'a>(text: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    text.split('@').skip(1).filter_map(|after| {
+        let end = after
+            .find(|c: char| !is_nick_char(c))
+            .unwrap_or(after.len());
+        if end == 0 {
+            None
+        } else {
+            Some(&after[..end])
+        }
+    })
+}
+
+fn is_nick_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+/// The recent messages that mentioned us.
+pub struct Mentions {
+    /// The room of each mention, and the line as displayed.
+    backlog: VecDeque<(String, String)>,
+}
+
+impl Mentions {
+    pub fn new() -> Mentions {
+        Mentions {
+            backlog: VecDeque::with_capacity(BACKLOG_SIZE),
+        }
+    }
+
+    pub fn push(&mut self, room: String, line: String) {
+        if self.backlog.len() == BACKLOG_SIZE {
+            self.backlog.pop_front();
+        }
+        self.backlog.push_back((room, line));
+    }
+
+    /// Iterates over the mentions, from the oldest to the most recent.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.backlog.iter().map(|&(_, ref line)| line)
+    }
+
+    /// Forgets the mentions made in `room`, or everywhere if `None`.
+    pub fn purge(&mut self, room: Option<&str>) {
+        self.backlog.retain(|&(ref r, _)| room.map(|room| room != r).unwrap_or(false));
+    }
+}
@@ -0,0 +1,160 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! What each room is about, and roughly who is in it.
+//!
+//! Every node periodically gossips a snapshot of the state of its current room: the description
+//! and the members it has heard from. Descriptions are resolved with "last writer wins", using
+//! the timestamp chosen by the writer. Members are remembered for a while after the last snapshot
+//! that mentioned them, so the list is approximate but doesn't need anyone to be authoritative.
+
+use identity;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Number of heartbeats between two snapshots of the current room.
+pub const SNAPSHOT_TICKS: u32 = 6;
+
+/// Maximum number of members in a snapshot, to keep them small.
+pub const MAX_SNAPSHOT_MEMBERS: usize = 100;
+
+/// Maximum length of a description, in characters.
+pub const MAX_DESCRIPTION_LEN: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Description {
+    pub text: String,
+    /// Base58 `PeerId` of the peer who wrote the description. It is only as trustworthy as the
+    /// peer who gossiped it, since gossip is signed by the relaying peer, not by the writer.
+    pub author: String,
+    /// Number of seconds since the UNIX epoch, according to the writer's clock.
+    pub timestamp: u64,
+}
+
+#[derive(Default)]
+struct RoomState {
+    description: Option<Description>,
+    /// The members and when we last heard of them.
+    members: HashMap<PeerId, Instant>,
+}
+
+pub struct Metadata {
+    rooms: HashMap<String, RoomState>,
+    /// Time after which a member we no longer hear of is forgotten.
+    timeout: Duration,
+}
+
+impl Metadata {
+    pub fn new(timeout: Duration) -> Metadata {
+        Metadata {
+            rooms: HashMap::new(),
+            timeout,
+        }
+    }
+
+    /// Records that `peer` is in `room`, because we heard from them or of them. Returns true if
+    /// we didn't know that they were.
+    pub fn seen(&mut self, room: &str, peer: PeerId) -> bool {
+        self.state(room).members.insert(peer, Instant::now()).is_none()
+    }
+
+    /// Sets the description of `room`, unless we know of a more recent one. Returns true if the
+    /// description changed.
+    pub fn describe(&mut self, room: &str, description: Description) -> bool {
+        let state = self.state(room);
+        let newer = match state.description {
+            Some(ref current) => {
+                (description.timestamp, &description.author) > (current.timestamp, &current.author)
+            }
+            None => true,
+        };
+        if newer {
+            state.description = Some(description);
+        }
+        newer
+    }
+
+    /// Applies a snapshot of `room` that another node gossiped. A description that is too long
+    /// is ignored, and only the first `MAX_SNAPSHOT_MEMBERS` members are kept. Returns the new
+    /// text of the description if it changed, and the members.
+    pub fn apply_snapshot(
+        &mut self,
+        room: &str,
+        description: Option<Description>,
+        members: &[String],
+    ) -> (Option<String>, Vec<PeerId>) {
+        let described = match description {
+            Some(ref description) if description.text.chars().count() > MAX_DESCRIPTION_LEN => {
+                None
+            }
+            Some(description) => {
+                let text = description.text.clone();
+                if self.describe(room, description) {
+                    Some(text)
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        let members: Vec<PeerId> = members
+            .iter()
+            .take(MAX_SNAPSHOT_MEMBERS)
+            .filter_map(|peer| identity::parse_peer_id(peer))
+            .collect();
+        for peer in &members {
+            self.seen(room, peer.clone());
+        }
+        (described, members)
+    }
+
+    pub fn description(&self, room: &str) -> Option<&Description> {
+        self.rooms.get(room)?.description.as_ref()
+    }
+
+    /// The members of `room` we heard of recently, the most recently heard of first.
+    pub fn members(&self, room: &str) -> Vec<&PeerId> {
+        let mut members: Vec<(&PeerId, &Instant)> = match self.rooms.get(room) {
+            Some(state) => state
+                .members
+                .iter()
+                .filter(|&(_, seen)| seen.elapsed() < self.timeout)
+                .collect(),
+            None => Vec::new(),
+        };
+        members.sort_by(|a, b| b.1.cmp(a.1));
+        members.into_iter().map(|(peer, _)| peer).collect()
+    }
+
+    /// Forgets `room`, or every room if `None`.
+    pub fn purge(&mut self, room: Option<&str>) {
+        match room {
+            Some(room) => {
+                self.rooms.remove(room);
+            }
+            None => self.rooms.clear(),
+        }
+    }
+
+    fn state(&mut self, room: &str) -> &mut RoomState {
+        self.rooms.entry(room.to_owned()).or_insert_with(RoomState::default)
+    }
+}
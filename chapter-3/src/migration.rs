@@ -0,0 +1,75 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Moving the rooms to new topics when the major version of the protocol changes.
+//!
+//! Clients of different major versions can't read each other's envelopes, so every major
+//! version after the first has topics of its own (`general@v2`, see `TopicNaming::versioned`).
+//! To avoid splitting a room silently, the clients stay subscribed to the topics of the
+//! previous version for `GRACE_DAYS` after the release of the new one, recorded in
+//! `version::PREVIOUS`. What the old clients publish there is reported as incompatible by
+//! `Chat::handle_message`, and every `BEACON_TICKS` heartbeats we publish a beacon there, in
+//! the format of the previous version, telling them to upgrade.
+//!
+//! The beacon is a `Kind::Text`, which every version must keep encoding the same way.
+
+use envelope::{self, Body, Kind};
+use identity::Identity;
+use libp2p::floodsub::Topic;
+use topics::TopicNaming;
+use version;
+
+/// Number of days after the release of a major version during which we still listen to the
+/// clients of the previous one.
+const GRACE_DAYS: u64 = 30;
+
+/// Number of heartbeats between two beacons.
+pub const BEACON_TICKS: u64 = 10;
+
+/// Where the clients of the previous version can get a newer one.
+const UPGRADE_URL: &str = "https://github.com/tomaka/rustfest-2018-workshop";
+
+/// Returns the previous version of the protocol, if we are still in its grace period.
+pub fn previous() -> Option<&'static str> {
+    let (previous, released) = version::PREVIOUS?;
+    if envelope::now() < released + GRACE_DAYS * 24 * 3600 {
+        Some(previous)
+    } else {
+        None
+    }
+}
+
+/// Returns the topic of `room` for the clients of the previous version, during its grace period.
+pub fn legacy_topic(naming: &TopicNaming, room: &str) -> Option<Topic> {
+    previous().map(|previous| naming.versioned(room, previous))
+}
+
+/// Builds the beacon for the topics of the previous version, during its grace period.
+pub fn beacon(identity: &Identity, nick: Option<String>) -> Option<Vec<u8>> {
+    let previous = previous()?;
+    let text = format!(
+        "This room moved to version {} of the protocol, which your client doesn't speak. \
+         Please upgrade it from {}",
+        version::PROTOCOL,
+        UPGRADE_URL
+    );
+    let body = Body::new(nick, Kind::Text(text));
+    Some(envelope::seal_as(identity, &body, previous))
+}
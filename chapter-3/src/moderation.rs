@@ -0,0 +1,264 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Decentralized moderation of a room.
+//!
+//! There is no server that could kick someone out of a room. Instead, the moderators of a room are
+//! identified by their public key, which every node is told about on the command line. Moderators
+//! publish ban entries that they sign, and the nodes that trust them stop displaying and relaying
+//! the banned peers: floodsub relays every message before handing it to us, so the messages of
+//! banned peers are taken out of the floodsub connections, see the `screening` module.
+//!
+//! Anyone can pass an entry on, since its signature is its own, not that of the envelope. When we
+//! first hear from a member of a room, we send the entries we know about the room in
+//! `Kind::Bans`, so that a newcomer doesn't wait for the next ban to learn the earlier ones. The
+//! latest entry about a peer wins, so an unban is an entry too. With `--bans-file <file>`, the
+//! entries are kept across runs, as JSON.
+//!
+//! The rooms are written as by `TopicNaming::label`, so that the entries don't reveal the names
+//! of the rooms with `--hash-topics`.
+
+use identity::{self, Identity};
+use libp2p::PeerId;
+use purge;
+use serde_json;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error as IoError;
+
+/// Prefix of the bytes signed by a moderator, so that an entry can't pass for anything else that
+/// the moderator signs.
+const ENTRY_CONTEXT: &[u8] = b"rustfest-chat ban:";
+
+/// Number of entries sent to a peer who joins a room.
+pub const MAX_SYNCED_ENTRIES: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The room, as written by `TopicNaming::label`.
+    pub room: String,
+    /// Base58 `PeerId` of the peer.
+    pub peer: String,
+    /// False if the entry lifts a ban.
+    pub banned: bool,
+    /// When the moderator wrote the entry, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedEntry {
+    pub entry: Entry,
+    /// Public key of the moderator, in base58.
+    pub moderator: String,
+    /// Signature by the moderator of `ENTRY_CONTEXT` followed by `entry` as JSON.
+    pub signature: Vec<u8>,
+}
+
+impl SignedEntry {
+    pub fn sign(entry: Entry, identity: &Identity) -> SignedEntry {
+        let signature = identity.sign(&signed_bytes(&entry));
+        SignedEntry {
+            entry,
+            moderator: identity::encode_key(identity.public_key()),
+            signature,
+        }
+    }
+
+    /// Returns the public key of the moderator if the signature is valid.
+    fn verify(&self) -> Option<Vec<u8>> {
+        let key = identity::decode_key(&self.moderator)?;
+        if identity::verify(&key, &signed_bytes(&self.entry), &self.signature) {
+            Some(key)
+        } else {
+            None
+        }
+    }
+}
+
+fn signed_bytes(entry: &Entry) -> Vec<u8> {
+    let mut bytes = ENTRY_CONTEXT.to_vec();
+    bytes.extend(serde_json::to_vec(entry).expect("entries always serialize"));
+    bytes
+}
+
+pub struct Moderation {
+    moderators: HashSet<Vec<u8>>,
+    /// For each room, by label, the banned peers.
+    banned: HashMap<String, HashSet<PeerId>>,
+    /// For each room, by label, the latest signed entry about each peer. The bans of the
+    /// envelopes of `Kind::Ban` have none, and can't be passed on.
+    entries: HashMap<String, HashMap<PeerId, SignedEntry>>,
+    /// File in which the entries are kept, if any.
+    path: Option<String>,
+}
+
+impl Moderation {
+    /// Loads the entries stored at `path`, if there is a file there. The entries of keys that
+    /// aren't `moderators` are ignored.
+    pub fn load<I>(moderators: I, path: Option<String>) -> Moderation
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let mut moderation = Moderation {
+            moderators: moderators.into_iter().collect(),
+            banned: HashMap::new(),
+            entries: HashMap::new(),
+            path: None,
+        };
+        if let Some(ref path) = path {
+            if let Ok(content) = fs::read(path) {
+                match serde_json::from_slice::<Vec<SignedEntry>>(&content) {
+                    Ok(entries) => {
+                        for entry in entries {
+                            moderation.apply(entry);
+                        }
+                    }
+                    Err(err) => say!("* Ignoring the bans in {}: {}", path, err),
+                }
+            }
+        }
+        moderation.path = path;
+        moderation
+    }
+
+    /// Returns true if the room has moderators, as far as we were told.
+    pub fn has_moderators(&self) -> bool {
+        !self.moderators.is_empty()
+    }
+
+    pub fn is_moderator(&self, public_key: &[u8]) -> bool {
+        self.moderators.contains(public_key)
+    }
+
+    pub fn is_banned(&self, room: &str, peer: &PeerId) -> bool {
+        self.banned
+            .get(room)
+            .map(|banned| banned.contains(peer))
+            .unwrap_or(false)
+    }
+
+    /// Applies a ban signed by `signer`. Returns false if `signer` isn't a moderator or if the peer
+    /// was already banned.
+    pub fn ban(&mut self, signer: &[u8], room: &str, peer: PeerId) -> bool {
+        if !self.is_moderator(signer) {
+            return false;
+        }
+        self.banned
+            .entry(room.to_owned())
+            .or_insert_with(HashSet::new)
+            .insert(peer)
+    }
+
+    /// Revokes a ban signed by `signer`. Returns false if `signer` isn't a moderator or if the
+    /// peer wasn't banned.
+    pub fn unban(&mut self, signer: &[u8], room: &str, peer: &PeerId) -> bool {
+        if !self.is_moderator(signer) {
+            return false;
+        }
+        self.banned
+            .get_mut(room)
+            .map(|banned| banned.remove(peer))
+            .unwrap_or(false)
+    }
+
+    /// Applies `signed`, signed by a moderator, unless we know of a later entry about its peer.
+    /// Returns the peer if the entry changed whether it is banned.
+    pub fn apply(&mut self, signed: SignedEntry) -> Option<PeerId> {
+        let signer = signed.verify()?;
+        let peer = identity::parse_peer_id(&signed.entry.peer)?;
+        if !self.is_moderator(&signer) {
+            return None;
+        }
+        let room = signed.entry.room.clone();
+        let later = match self.entries.get(&room).and_then(|entries| entries.get(&peer)) {
+            Some(known) => signed.entry.timestamp > known.entry.timestamp,
+            None => true,
+        };
+        if !later {
+            return None;
+        }
+        let changed = if signed.entry.banned {
+            self.ban(&signer, &room, peer.clone())
+        } else {
+            self.unban(&signer, &room, &peer)
+        };
+        self.entries
+            .entry(room)
+            .or_insert_with(HashMap::new)
+            .insert(peer.clone(), signed);
+        self.save();
+        if changed {
+            Some(peer)
+        } else {
+            None
+        }
+    }
+
+    pub fn banned<'a>(&'a self, room: &str) -> Box<Iterator<Item = &'a PeerId> + 'a> {
+        match self.banned.get(room) {
+            Some(banned) => Box::new(banned.iter()),
+            None => Box::new(::std::iter::empty()),
+        }
+    }
+
+    /// The banned peers of every room, with the label of the room.
+    pub fn all_banned<'a>(&'a self) -> impl Iterator<Item = (&'a str, &'a PeerId)> + 'a {
+        self.banned
+            .iter()
+            .flat_map(|(room, banned)| banned.iter().map(move |peer| (room.as_str(), peer)))
+    }
+
+    /// The signed entries about `room`, to pass on to a peer who joins it.
+    pub fn entries(&self, room: &str) -> Vec<SignedEntry> {
+        match self.entries.get(room) {
+            Some(entries) => entries.values().take(MAX_SYNCED_ENTRIES).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Forgets the bans of `room`, or every ban, file included, if `None`.
+    pub fn purge(&mut self, room: Option<&str>) -> Result<(), IoError> {
+        match room {
+            Some(room) => {
+                self.banned.remove(room);
+                self.entries.remove(room);
+                self.save();
+                Ok(())
+            }
+            None => {
+                self.banned.clear();
+                self.entries.clear();
+                purge::remove_file(&self.path)
+            }
+        }
+    }
+
+    fn save(&self) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        let entries: Vec<&SignedEntry> = self.entries.values().flat_map(|e| e.values()).collect();
+        let content = serde_json::to_vec(&entries).expect("entries always serialize");
+        if let Err(err) = fs::write(path, content) {
+            say!("* Can't save the bans to {}: {}", path, err);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Short names for the peers.
+//!
+//! A `PeerId` is too long to be displayed next to every message, and nicknames are neither
+//! mandatory nor unique. We therefore display the shortest prefix of the base58 `PeerId` that
+//! doesn't collide with the prefix of another peer we know about, with a minimum length.
+
+use libp2p::PeerId;
+use std::collections::BTreeSet;
+
+/// Minimum number of characters of a short ID. All the `PeerId`s start with `Qm`, so this leaves
+/// six meaningful characters.
+const MIN_LEN: usize = 8;
+
+pub struct ShortIds {
+    /// The base58 representation of all the peers we know about, sorted.
+    known: BTreeSet<String>,
+}
+
+impl ShortIds {
+    pub fn new() -> ShortIds {
+        ShortIds {
+            known: BTreeSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, peer: &PeerId) {
+        self.known.insert(peer.to_base58());
+    }
+
+    /// Returns the short ID of `peer`. Since the IDs are sorted, the longest common prefix with
+    /// another ID is found with one of the two neighbours of `peer`.
+    pub fn get(&self, peer: &PeerId) -> String {
+        let id = peer.to_base58();
+        let before = self.known.range::<String, _>(..id.clone()).next_back();
+        let after = self
+            .known
+            .range::<String, _>(id.clone()..)
+            .find(|other| **other != id);
+        let shared = before
+            .into_iter()
+            .chain(after)
+            .map(|other| common_prefix(&id, other))
+            .max()
+            .unwrap_or(0);
+        let len = (shared + 1).max(MIN_LEN).min(id.len());
+        id[..len].to_owned()
+    }
+}
+
+/// Number of bytes at the start of `a` and `b` that are equal. Base58 is ASCII, so this is also a
+/// number of characters.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|&(a, b)| a == b).count()
+}
@@ -0,0 +1,124 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Notifications: a terminal bell, and a desktop notification.
+//!
+//! Desktop notifications are only available outside of the browser, and only if the crate is
+//! compiled with the `desktop-notifications` feature. Otherwise `show` does nothing.
+//!
+//! Since stdin is read line by line, we can't tell whether the terminal currently has the focus.
+//! Instead, the user chooses the level of each room: every message produces a notification,
+//! only mentions do (the default), or nothing does. The messages that match a `/watch` notify
+//! whatever the level.
+
+use platform;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    All,
+    Mentions,
+    Silent,
+}
+
+impl Level {
+    /// Parses the name of a level, as typed with `/notify`.
+    pub fn parse(name: &str) -> Option<Level> {
+        match name {
+            "all" => Some(Level::All),
+            "mentions" => Some(Level::Mentions),
+            "silent" => Some(Level::Silent),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::All => "all",
+            Level::Mentions => "mentions",
+            Level::Silent => "silent",
+        }
+    }
+}
+
+pub struct Notifier {
+    /// Level of the rooms that don't use `Level::Mentions`.
+    levels: HashMap<String, Level>,
+}
+
+impl Notifier {
+    pub fn new(levels: HashMap<String, Level>) -> Notifier {
+        Notifier { levels }
+    }
+
+    pub fn level(&self, room: &str) -> Level {
+        self.levels.get(room).cloned().unwrap_or(Level::Mentions)
+    }
+
+    pub fn set_level(&mut self, room: String, level: Level) {
+        if level == Level::Mentions {
+            self.levels.remove(&room);
+        } else {
+            self.levels.insert(room, level);
+        }
+    }
+
+    /// The levels that differ from the default, by room.
+    pub fn levels(&self) -> &HashMap<String, Level> {
+        &self.levels
+    }
+
+    /// Called for every message displayed in `room`.
+    pub fn message(&self, room: &str, sender: &str, text: &str, mentioned: bool) {
+        let summary = match (self.level(room), mentioned) {
+            (Level::Silent, _) | (Level::Mentions, false) => return,
+            (_, true) => format!("{} mentioned you in {}", sender, room),
+            (Level::All, false) => format!("{} in {}", sender, room),
+        };
+        alert(&summary, text);
+    }
+
+    /// Called for every message displayed in `room` that matches the watched `pattern`.
+    pub fn watched(&self, room: &str, sender: &str, text: &str, pattern: &str) {
+        alert(&format!("{} in {} matched {}", sender, room, pattern), text);
+    }
+}
+
+fn alert(summary: &str, body: &str) {
+    if platform::is_terminal() {
+        print!("\x07");
+        let _ = io::stdout().flush();
+    }
+    show(summary, body);
+}
+
+#[cfg(all(feature = "desktop-notifications", not(target_os = "emscripten")))]
+fn show(summary: &str, body: &str) {
+    // Failing to show a notification isn't worth interrupting the chat for.
+    let _ = ::notify_rust::Notification::new()
+        .summary(summary)
+        .body(body)
+        .show();
+}
+
+#[cfg(not(all(feature = "desktop-notifications", not(target_os = "emscripten"))))]
+fn show(_summary: &str, _body: &str) {}
@@ -0,0 +1,1412 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Command-line options.
+//!
+//! In the browser there is no command line, so every option has a default value that makes
+//! sense there.
+//!
+//! In containers, the main options can also be set with environment variables named after them,
+//! such as `CHAT_NICK` for `--nick` or `CHAT_DIAL` for the addresses to dial. The options that
+//! take several values are separated with whitespace. The command line wins over the
+//! environment, which wins over the profile.
+
+use addresses;
+use clap::{App, Arg, ArgMatches, Shell, SubCommand};
+use config::{Config, Profile, Session, DEFAULT_PROFILE};
+use digest;
+use display::Verbosity;
+use error::Error;
+use i18n::{self, Lang};
+use libp2p::Multiaddr;
+use outbox::Policy;
+use platform;
+use presence;
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+use wizard;
+
+/// Room joined when no `--topic` is passed.
+const DEFAULT_TOPIC: &str = "workshop-chapter3-topic";
+/// Room joined with `--lan-party` when no `--topic` is passed.
+const LAN_PARTY_TOPIC: &str = "lan-party";
+/// With `--low-bandwidth`, the delay during which the lines are batched, unless `--batch-delay`
+/// is passed.
+const LOW_BANDWIDTH_BATCH_DELAY_MS: u64 = 1000;
+/// With `--low-bandwidth`, the time between two heartbeats, unless `--heartbeat-interval` is
+/// passed. The peers with the default settings consider us gone after 15 seconds without news.
+const LOW_BANDWIDTH_HEARTBEAT_SECS: u64 = 10;
+/// Address listened on when no `--listen` is passed.
+const DEFAULT_LISTEN: &str = "/ip4/0.0.0.0/tcp/63204/ws";
+/// Default value of `--max-message-size`.
+const DEFAULT_MAX_MESSAGE_SIZE: &str = "16384";
+/// Default value of `--queue-size`.
+const DEFAULT_QUEUE_SIZE: &str = "64";
+/// Default value of `--publish-rate`.
+const DEFAULT_PUBLISH_RATE: &str = "50";
+/// Default value of `--batch-size`.
+const DEFAULT_BATCH_SIZE: &str = "16";
+/// Default value of `--dial-timeout`.
+const DEFAULT_DIAL_TIMEOUT: &str = "10";
+/// Default value of `--max-dials`.
+const DEFAULT_MAX_DIALS: &str = "8";
+/// Default value of `--max-repeats`.
+const DEFAULT_MAX_REPEATS: &str = "3";
+/// Default value of `--ready-peers`.
+const DEFAULT_READY_PEERS: &str = "1";
+/// Default value of `--confirm-lines`.
+const DEFAULT_CONFIRM_LINES: &str = "20";
+/// Default value of `--confirm-bytes`.
+const DEFAULT_CONFIRM_BYTES: &str = "4096";
+/// Default value of `--undo-window`.
+const DEFAULT_UNDO_WINDOW: &str = "60";
+/// Number of typed lines kept in the input history.
+const DEFAULT_INPUT_HISTORY_SIZE: &str = "1000";
+
+pub struct Options {
+    /// Addresses to dial at startup. The addresses of a peer that can be reached in several ways
+    /// are separated with commas.
+    pub dial: Vec<String>,
+    /// Addresses to listen on, on native platforms. The first one is the one we advertise.
+    pub listen: Vec<Multiaddr>,
+    /// Path to the file containing our key pair. If `None`, a new identity is generated each run.
+    pub identity: Option<String>,
+    /// If true, the identity file is encrypted with a passphrase.
+    pub encrypt_identity: bool,
+    /// If true, a status line is kept above the prompt.
+    pub status_line: bool,
+    /// Public keys (base58) of the moderators of the room.
+    pub moderators: Vec<String>,
+    /// File in which the bans of the moderators are kept. See the `moderation` module.
+    pub bans_file: Option<String>,
+    /// Nickname displayed next to our messages, and which others can mention with `@nick`.
+    pub nick: Option<String>,
+    /// Rooms in which every message produces a notification, whatever the configuration file
+    /// says.
+    pub notify_rooms: Vec<String>,
+    /// Path to the configuration file, if any.
+    pub config: Option<String>,
+    /// Profile of the configuration file under which the session is saved, `default` without
+    /// `--profile`.
+    pub profile: String,
+    /// If true, the titles of the pages linked to in messages are fetched and displayed.
+    pub link_preview: bool,
+    /// If true, `:shortcode:`s are expanded in the messages we send.
+    pub emoji_on_send: bool,
+    /// If true, `:shortcode:`s are expanded in the messages we receive.
+    pub emoji_on_display: bool,
+    /// Number of seconds after which the messages we send to the room are dropped by receivers.
+    pub ttl: Option<u64>,
+    /// Maximum size in bytes of the messages we send or accept, signature included.
+    pub max_message_size: usize,
+    /// Maximum number of bytes per second sent over all the connections, if limited.
+    pub max_upload: Option<u64>,
+    /// Maximum number of bytes per second received over all the connections, if limited.
+    pub max_download: Option<u64>,
+    /// Maximum number of messages waiting to be published.
+    pub queue_size: usize,
+    /// What to do when the outbox is full.
+    pub queue_policy: Policy,
+    /// Number of messages published per second, at most.
+    pub publish_rate: u32,
+    /// If set, the lines we type within this number of milliseconds are sent together.
+    pub batch_delay: Option<u64>,
+    /// Maximum number of lines in a batch.
+    pub batch_size: usize,
+    /// Number of threads verifying the signatures of the messages. `None` means one per CPU,
+    /// and 0 means that they are verified on the events loop thread.
+    pub threads: Option<usize>,
+    /// Time between two heartbeats.
+    pub heartbeat_interval: Duration,
+    /// Number of heartbeats a peer can miss before being considered gone.
+    pub missed_heartbeats: u32,
+    /// Patterns of the messages to drop.
+    pub filters: Vec<String>,
+    /// Patterns masked in the messages we send, besides the default ones.
+    pub redactions: Vec<String>,
+    /// Whether the API keys, tokens and email addresses we send are masked.
+    pub default_redactions: bool,
+    /// Number of times a sender can repeat the same message before the repetitions are hidden.
+    pub max_repeats: usize,
+    /// Rooms to join at startup. The first one is the current room.
+    pub topics: Vec<String>,
+    /// Prefix added to the names of all the topics.
+    pub topic_namespace: String,
+    /// If true, the topics are named after the SHA-256 of their name.
+    pub hash_topics: bool,
+    /// If true, we take part in the election of a coordinator for the room.
+    pub election: bool,
+    /// Address of the SOCKS5 proxy through which we dial, if any.
+    pub proxy: Option<Multiaddr>,
+    /// Time after which a dial that hasn't completed is abandoned.
+    pub dial_timeout: Duration,
+    /// Maximum number of dials in progress at the same time.
+    pub max_dials: usize,
+    /// Addresses at which the others can dial us, in addition to those we discover.
+    pub external_addresses: Vec<Multiaddr>,
+    /// STUN server, as `host:port`, to ask for our public IP address. See the `stun` module.
+    pub stun: Option<String>,
+    /// Name in `/dev` of a serial device to listen on. See the `serial` module.
+    pub serial: Option<String>,
+    /// If true, we find the nodes of the local network with multicast beacons.
+    pub beacon: bool,
+    /// If true, `--beacon` is on and the room and nickname have defaults for the local network.
+    pub lan_party: bool,
+    /// File in which the topology of the mesh is written periodically, as a Graphviz graph.
+    pub graph_file: Option<String>,
+    /// Address of the control HTTP server, if any. See the `http` module.
+    pub http: Option<SocketAddr>,
+    /// Connections below which the `/readyz` of `--http` fails.
+    pub ready_peers: usize,
+    /// If true, the control HTTP server also serves a live dashboard of the node.
+    pub dashboard: bool,
+    /// Rooms whose Atom feed the control HTTP server serves.
+    pub feeds: Vec<String>,
+    /// If true, bots can publish through the `/publish` of `--http`.
+    pub http_publish: bool,
+    /// File in which the diagnostics are written on `SIGUSR1`, instead of stderr.
+    pub dump_file: Option<String>,
+    /// File from which the lines are read instead of stdin. See the `sources` module.
+    pub input_file: Option<String>,
+    /// If true, only the lines appended to `input_file` are read, as they come.
+    pub follow: bool,
+    /// Named pipe from which the lines are read instead of stdin.
+    pub input_fifo: Option<String>,
+    /// File in which the lines we type are kept. See the `inputs` module.
+    pub input_history: Option<String>,
+    /// Number of lines kept in the input history.
+    pub input_history_size: usize,
+    /// Patterns of the lines that aren't kept in the input history.
+    pub input_history_exclude: Vec<String>,
+    /// How much we print besides the messages.
+    pub verbosity: Verbosity,
+    /// If true, we print no colors, escape codes or non-ASCII symbols. See the `display` module.
+    pub plain: bool,
+    /// Language of what we print.
+    pub lang: Lang,
+    /// File to which what we print is appended as JSON. See the `sinks` module.
+    pub output_jsonl: Option<String>,
+    /// Unix socket on which programs can follow what we print.
+    pub control_socket: Option<String>,
+    /// Transcript to print instead of joining the network. See the `replay` module.
+    pub replay: Option<String>,
+    /// How many times faster than real time the transcript is replayed; 0 for at once.
+    pub replay_speed: f64,
+    /// File to which the network events are recorded. See the `recording` module.
+    pub record: Option<String>,
+    /// Recording to play back instead of joining the network.
+    pub playback: Option<String>,
+    /// If true, we check the environment instead of chatting. See the `doctor` module.
+    pub doctor: bool,
+    /// Node whose websockets listener `doctor` connects to.
+    pub reference_server: Option<Multiaddr>,
+    /// Node that `probe` goes through the layers of a connection with, instead of chatting. See
+    /// the `probe` module.
+    pub probe: Option<Multiaddr>,
+    /// If true, we publish back the texts we receive. See the `echo` module.
+    pub echo: bool,
+    /// If true, fake personas chat in the room. See the `demo` module.
+    pub demo_traffic: bool,
+    /// Relays among which we dial the fastest. See the `relays` module.
+    pub relays: Vec<Multiaddr>,
+    /// Number of messages within 10 seconds above which a room is damped. See `damping`.
+    pub flood_threshold: usize,
+    /// File to which the bytes of every connection are written. See the `capture` module.
+    pub capture: Option<String>,
+    /// Other identities to publish as, with their name and identity file. See `personas`.
+    pub personas: Vec<(String, Option<String>)>,
+    /// File in which the keys of the other nodes are remembered. See the `tofu` module.
+    pub known_keys: Option<String>,
+    /// If true, we accept the calls of the others. See the `audio` module.
+    pub accept_calls: bool,
+    /// If true, we watch the terminals that others share. See the `screen` module.
+    pub accept_screens: bool,
+    /// `nats://` or `redis://` URL of the broker to mirror the first room to. See the `bridge`
+    /// module.
+    pub bridge: Option<String>,
+    /// Addresses to which the digests of the room are mailed, if any. See the `digest` module.
+    pub digest_to: Option<Vec<String>>,
+    pub digest_from: String,
+    /// Period covered by each digest, and time between two of them.
+    pub digest_period: Duration,
+    /// Host and port of the SMTP server that relays the digests.
+    pub smtp: String,
+    /// Directory of the bots to load. See the `plugins` module.
+    pub plugins: Option<String>,
+    /// File in which the scheduled messages are kept. See the `schedule` module.
+    pub schedule_file: Option<String>,
+    /// If set, the texts we receive are displayed after this number of milliseconds, in the order
+    /// they were written. See the `reorder` module.
+    pub reorder_delay: Option<u64>,
+    /// Inbound connections accepted at the same time, at most. See the `admission` module.
+    pub max_clients: Option<usize>,
+    /// New inbound connections accepted per minute, at most.
+    pub max_joins: Option<usize>,
+    /// Relay to which the connected nodes are sent when we drain. See the `drain` module.
+    pub alternate: Option<Multiaddr>,
+    /// File to which the reports of `/report` are appended. See the `reports` module.
+    pub reports: Option<String>,
+    /// A message with more lines than this is only sent once confirmed, in a terminal. 0 never
+    /// asks.
+    pub confirm_lines: usize,
+    /// A message larger than this number of bytes is only sent once confirmed, in a terminal. 0
+    /// never asks.
+    pub confirm_bytes: usize,
+    /// How long after sending a message `/undo` can still retract it.
+    pub undo_window: Duration,
+    /// File in which the negative scores of the peers are kept. See the `scores` module.
+    pub scores_file: Option<String>,
+    /// Capture file, other file or hexadecimal bytes to explain instead of chatting. See the
+    /// `dissect` module.
+    pub decode: Option<String>,
+}
+
+impl Options {
+    pub fn from_args() -> Result<Options, Error> {
+        let matches = app(&[]).get_matches();
+        if let Some(completions) = matches.subcommand_matches("completions") {
+            print_completions(&matches, completions)?;
+            process::exit(0);
+        }
+
+        // The language is needed right away, by the setup wizard.
+        let lang = match value(&matches, "lang") {
+            Some(lang) => Lang::from_locale(&lang)
+                .ok_or_else(|| Error::Config(format!("--lang expects en or fr, not {}", lang)))?,
+            None => Lang::from_env(),
+        };
+        i18n::set_lang(lang);
+
+        let config = value(&matches, "config");
+        if let Some(ref path) = config {
+            let first_run = !Path::new(path).exists() && platform::is_terminal();
+            if first_run && cfg!(not(target_os = "emscripten")) {
+                wizard::run(path)
+                    .map_err(|err| Error::config(&format!("can't write {}", path), err))?;
+            }
+        }
+        let profile_name =
+            value(&matches, "profile").unwrap_or_else(|| DEFAULT_PROFILE.to_owned());
+        let (profile, session) = match (value(&matches, "profile"), config.as_ref()) {
+            (name, Some(path)) => {
+                let mut config = Config::load(path)
+                    .map_err(|err| Error::config(&format!("can't load {}", path), err))?;
+                let profile = match name {
+                    Some(name) => config.profile.remove(&name).ok_or_else(|| {
+                        Error::Config(format!("there is no profile named {} in {}", name, path))
+                    })?,
+                    None => config.profile.remove(DEFAULT_PROFILE).unwrap_or_default(),
+                };
+                let session = config.sessions.remove(&profile_name).unwrap_or_default();
+                (profile, session)
+            }
+            (Some(_), None) => return Err(Error::Config("--profile requires --config".to_owned())),
+            (None, None) => (Profile::default(), Session::default()),
+        };
+        let or_profile = |values: Vec<String>, profile: &[String]| {
+            if values.is_empty() {
+                profile.to_vec()
+            } else {
+                values
+            }
+        };
+
+        let dial = if cfg!(not(target_os = "emscripten")) {
+            or_profile(values_or_env(&matches, "dial"), &profile.dial)
+        } else {
+            vec!["/ip4/127.0.0.1/tcp/63204/ws".to_owned()]
+        };
+
+        // `--lan-party` turns on the beacons and picks a room and a nickname, unless they were
+        // given.
+        let lan_party = matches.is_present("lan-party");
+        // `--low-bandwidth` changes the defaults of the batching and of the heartbeats.
+        let low_bandwidth = matches.is_present("low-bandwidth");
+
+        let mut listen = or_profile(values_or_env(&matches, "listen"), &profile.listen);
+        if listen.is_empty() {
+            listen.push(DEFAULT_LISTEN.to_owned());
+        }
+
+        Ok(Options {
+            dial,
+            listen: parse_addresses("listen", &listen)?,
+            identity: value(&matches, "identity").or_else(|| profile.identity.clone()),
+            encrypt_identity: matches.is_present("encrypt-identity"),
+            status_line: matches.is_present("status-line"),
+            moderators: values(matches.values_of("moderator")),
+            bans_file: value(&matches, "bans-file"),
+            nick: value(&matches, "nick")
+                .or_else(|| session.nick.clone())
+                .or_else(|| profile.nick.clone())
+                .or_else(|| {
+                    if lan_party {
+                        env::var("USER").or_else(|_| env::var("USERNAME")).ok()
+                    } else {
+                        None
+                    }
+                }),
+            notify_rooms: values(matches.values_of("notify")),
+            config,
+            profile: profile_name,
+            link_preview: matches.is_present("link-preview"),
+            emoji_on_send: !matches.is_present("no-emoji"),
+            emoji_on_display: matches.is_present("emoji-display"),
+            ttl: parse_option(&matches, "ttl", "a number of seconds")?,
+            max_message_size: parse_or(
+                &matches,
+                "max-message-size",
+                DEFAULT_MAX_MESSAGE_SIZE,
+                "a number of bytes",
+            )?,
+            max_upload: parse_option(&matches, "max-upload", "a number of bytes per second")?,
+            max_download: parse_option(&matches, "max-download", "a number of bytes per second")?,
+            queue_size: parse_or(
+                &matches,
+                "queue-size",
+                DEFAULT_QUEUE_SIZE,
+                "a number of messages",
+            )?,
+            queue_policy: Policy::from_name(matches.value_of("queue-policy").unwrap_or("block"))
+                .ok_or_else(|| Error::Internal("clap accepted an unknown policy".to_owned()))?,
+            publish_rate: parse_or(
+                &matches,
+                "publish-rate",
+                DEFAULT_PUBLISH_RATE,
+                "a number of messages per second",
+            )?,
+            batch_delay: parse_option(&matches, "batch-delay", "a number of milliseconds")?
+                .or_else(|| Some(LOW_BANDWIDTH_BATCH_DELAY_MS).filter(|_| low_bandwidth)),
+            batch_size: parse_or(&matches, "batch-size", DEFAULT_BATCH_SIZE, "a number of lines")?,
+            threads: parse_option(&matches, "threads", "a number of threads")?,
+            heartbeat_interval: Duration::from_secs(
+                parse_option(&matches, "heartbeat-interval", "a number of seconds")?
+                    .unwrap_or(if low_bandwidth {
+                        LOW_BANDWIDTH_HEARTBEAT_SECS
+                    } else {
+                        presence::DEFAULT_HEARTBEAT_INTERVAL_SECS
+                    })
+                    .max(1),
+            ),
+            missed_heartbeats: parse_option(&matches, "missed-heartbeats", "a number")?
+                .unwrap_or(presence::DEFAULT_MISSED_HEARTBEATS)
+                .max(1),
+            filters: values(matches.values_of("filter")),
+            redactions: values(matches.values_of("redact")),
+            default_redactions: !matches.is_present("no-default-redactions"),
+            max_repeats: parse_or(&matches, "max-repeats", DEFAULT_MAX_REPEATS, "a number")?,
+            topics: {
+                let topics = values_or_env(&matches, "topic");
+                let topics = if topics.is_empty() && !session.rooms.is_empty() {
+                    session.rooms.clone()
+                } else {
+                    or_profile(topics, &profile.topics)
+                };
+                if topics.is_empty() && lan_party {
+                    vec![LAN_PARTY_TOPIC.to_owned()]
+                } else if topics.is_empty() {
+                    vec![DEFAULT_TOPIC.to_owned()]
+                } else {
+                    topics
+                }
+            },
+            topic_namespace: matches
+                .value_of("topic-namespace")
+                .unwrap_or("")
+                .to_owned(),
+            hash_topics: matches.is_present("hash-topics"),
+            election: matches.is_present("election"),
+            proxy: match value(&matches, "proxy") {
+                Some(url) => Some(parse_proxy(&url).ok_or_else(|| {
+                    let expected = "a URL like socks5://127.0.0.1:9050";
+                    Error::Config(format!("--proxy expects {}, not {}", expected, url))
+                })?),
+                None => None,
+            },
+            dial_timeout: Duration::from_secs(parse_or(
+                &matches,
+                "dial-timeout",
+                DEFAULT_DIAL_TIMEOUT,
+                "a number of seconds",
+            )?),
+            max_dials: parse_or(&matches, "max-dials", DEFAULT_MAX_DIALS, "a number of dials")?,
+            external_addresses: parse_addresses(
+                "external-address",
+                &values_or_env(&matches, "external-address"),
+            )?,
+            stun: value(&matches, "stun"),
+            serial: matches.value_of("serial").map(|s| s.to_owned()),
+            beacon: matches.is_present("beacon") || lan_party,
+            lan_party,
+            graph_file: matches.value_of("graph-file").map(|s| s.to_owned()),
+            http: match value(&matches, "http") {
+                Some(address) => {
+                    let expected = "an IP address and a port, such as 127.0.0.1:8080";
+                    Some(parse("http", &address, expected)?)
+                }
+                None => None,
+            },
+            ready_peers: parse_or(
+                &matches,
+                "ready-peers",
+                DEFAULT_READY_PEERS,
+                "a number of connections",
+            )?,
+            dashboard: matches.is_present("dashboard"),
+            feeds: values(matches.values_of("feed")),
+            http_publish: matches.is_present("http-publish"),
+            dump_file: matches.value_of("dump-file").map(|s| s.to_owned()),
+            input_file: value(&matches, "input-file"),
+            follow: matches.is_present("follow"),
+            input_fifo: value(&matches, "input-fifo"),
+            input_history: matches
+                .value_of("input-history")
+                .map(|s| s.to_owned())
+                .or_else(|| profile.input_history.clone()),
+            input_history_size: parse_or(
+                &matches,
+                "input-history-size",
+                DEFAULT_INPUT_HISTORY_SIZE,
+                "a number of lines",
+            )?,
+            input_history_exclude: values(matches.values_of("input-history-exclude")),
+            verbosity: match (matches.is_present("quiet"), matches.occurrences_of("verbose")) {
+                (true, _) => Verbosity::Quiet,
+                (false, 0) => Verbosity::Normal,
+                (false, 1) => Verbosity::Verbose,
+                (false, _) => Verbosity::Debug,
+            },
+            plain: matches.is_present("plain"),
+            output_jsonl: value(&matches, "output-jsonl"),
+            control_socket: value(&matches, "control-socket"),
+            lang,
+            replay: matches.value_of("replay").map(|s| s.to_owned()),
+            replay_speed: parse_or(&matches, "replay-speed", "1", "a number")?,
+            record: matches.value_of("record").map(|s| s.to_owned()),
+            playback: matches.value_of("playback").map(|s| s.to_owned()),
+            doctor: matches.subcommand_matches("doctor").is_some(),
+            reference_server: match matches.subcommand_matches("doctor") {
+                Some(doctor) => match doctor.value_of("server") {
+                    Some(address) => Some(parse_address("server", address)?),
+                    None => None,
+                },
+                None => None,
+            },
+            probe: match matches.subcommand_matches("probe").and_then(|p| p.value_of("MULTIADDR")) {
+                Some(address) => Some(addresses::parse(address).map_err(|invalid| {
+                    Error::Config(format!("probe expects a multiaddress: {}", invalid))
+                })?),
+                None => None,
+            },
+            echo: matches.is_present("echo"),
+            demo_traffic: matches.is_present("demo-traffic"),
+            flood_threshold: parse_or(&matches, "flood-threshold", "50", "a number of messages")?,
+            relays: parse_addresses("relay", &values_or_env(&matches, "relay"))?,
+            personas: values_or_env(&matches, "persona")
+                .iter()
+                .map(|persona| match persona.find('=') {
+                    Some(i) => (persona[..i].to_owned(), Some(persona[i + 1..].to_owned())),
+                    None => (persona.clone(), None),
+                })
+                .collect(),
+            known_keys: value(&matches, "known-keys"),
+            accept_calls: matches.is_present("accept-calls"),
+            accept_screens: matches.is_present("accept-screens"),
+            bridge: value(&matches, "bridge"),
+            digest_to: matches.values_of("digest-to").map(|to| to.map(|s| s.to_owned()).collect()),
+            digest_from: matches
+                .value_of("digest-from")
+                .unwrap_or(digest::DEFAULT_FROM)
+                .to_owned(),
+            digest_period: Duration::from_secs(
+                parse_or::<u64>(
+                    &matches,
+                    "digest-hours",
+                    digest::DEFAULT_HOURS,
+                    "a number of hours",
+                )?
+                    .max(1) * 3600,
+            ),
+            smtp: {
+                let server = matches.value_of("smtp").unwrap_or(digest::DEFAULT_SERVER);
+                if server.contains(':') {
+                    server.to_owned()
+                } else {
+                    format!("{}:25", server)
+                }
+            },
+            plugins: value(&matches, "plugins"),
+            schedule_file: value(&matches, "schedule-file"),
+            reorder_delay: parse_option(&matches, "reorder-delay", "a number of milliseconds")?,
+            max_clients: parse_option(&matches, "max-clients", "a number of nodes")?,
+            max_joins: parse_option(&matches, "max-joins", "a number of nodes")?,
+            alternate: match matches.value_of("alternate") {
+                Some(address) => Some(parse_address("alternate", address)?),
+                None => None,
+            },
+            reports: value(&matches, "reports"),
+            confirm_lines: parse_or(
+                &matches,
+                "confirm-lines",
+                DEFAULT_CONFIRM_LINES,
+                "a number of lines",
+            )?,
+            confirm_bytes: parse_or(
+                &matches,
+                "confirm-bytes",
+                DEFAULT_CONFIRM_BYTES,
+                "a number of bytes",
+            )?,
+            undo_window: Duration::from_secs(parse_or(
+                &matches,
+                "undo-window",
+                DEFAULT_UNDO_WINDOW,
+                "a number of seconds",
+            )?),
+            scores_file: value(&matches, "scores-file"),
+            capture: matches.value_of("capture").map(|s| s.to_owned()),
+            decode: matches
+                .subcommand_matches("decode")
+                .and_then(|decode| decode.value_of("INPUT"))
+                .map(|s| s.to_owned()),
+        })
+    }
+}
+
+/// The command line interface. `profiles`, the names of the profiles of the config file, are
+/// only known for the completions.
+fn app<'a>(profiles: &[&'a str]) -> App<'a, 'a> {
+    App::new("chapter-3")
+        .about("Peer-to-peer chat built with libp2p")
+        .arg(
+            Arg::with_name("dial")
+                .value_name("MULTIADDR")
+                .multiple(true)
+                .help(
+                    "Addresses of the nodes to connect to; separate the addresses of the \
+                     same node with commas in order to race them",
+                ),
+        )
+        .arg(
+            Arg::with_name("identity")
+                .long("identity")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("File in which the key pair of this node is stored (created if missing)"),
+        )
+        .arg(
+            Arg::with_name("encrypt-identity")
+                .long("encrypt-identity")
+                .requires("identity")
+                .help("Protect the identity file with a passphrase, asked for at startup"),
+        )
+        .arg(
+            Arg::with_name("moderator")
+                .long("moderator")
+                .value_name("PUBLIC_KEY")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Public key of a moderator of the room; can be passed multiple times"),
+        )
+        .arg(
+            Arg::with_name("bans-file")
+                .long("bans-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Remember the bans of the moderators in this file, across restarts"),
+        )
+        .arg(
+            Arg::with_name("nick")
+                .long("nick")
+                .value_name("NICKNAME")
+                .takes_value(true)
+                .help("Nickname displayed next to your messages"),
+        )
+        .arg(
+            Arg::with_name("notify")
+                .long("notify")
+                .value_name("ROOM")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Show a desktop notification for every message in this room"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .conflicts_with("verbose")
+                .help("Only show the messages, not the peers coming and going and such"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("Also show the connections and the dials; twice, every message as well"),
+        )
+        .arg(
+            Arg::with_name("lang")
+                .long("lang")
+                .value_name("LANG")
+                .takes_value(true)
+                .help("Language of the chat, such as en or fr (default: from the locale)"),
+        )
+        .arg(
+            Arg::with_name("plain")
+                .long("plain")
+                .conflicts_with("status-line")
+                .help("Print plain lines, without colors or a prompt, for screen readers"),
+        )
+        .arg(
+            Arg::with_name("output-jsonl")
+                .long("output-jsonl")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Also append what we print to this file, as one JSON object per line"),
+        )
+        .arg(
+            Arg::with_name("control-socket")
+                .long("control-socket")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Also write what we print, as JSON, to the programs connected to this Unix \
+                       socket"),
+        )
+        .arg(
+            Arg::with_name("status-line")
+                .long("status-line")
+                .help("Show the number of peers and of unread messages above the prompt"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("File in which the settings changed in the chat are saved"),
+        )
+        .arg(profile_arg(profiles))
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("MULTIADDR")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Address to listen on; can be passed multiple times"),
+        )
+        .arg(
+            Arg::with_name("link-preview")
+                .long("link-preview")
+                .help("Print the title of the web pages linked to in messages"),
+        )
+        .arg(
+            Arg::with_name("no-emoji")
+                .long("no-emoji")
+                .help("Don't expand :shortcode: emoji in the messages you send"),
+        )
+        .arg(
+            Arg::with_name("emoji-display")
+                .long("emoji-display")
+                .help("Expand :shortcode: emoji in the messages you receive"),
+        )
+        .arg(
+            Arg::with_name("ttl")
+                .long("ttl")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Receivers drop your messages once they are older than this"),
+        )
+        .arg(
+            Arg::with_name("max-message-size")
+                .long("max-message-size")
+                .value_name("BYTES")
+                .takes_value(true)
+                .default_value(DEFAULT_MAX_MESSAGE_SIZE)
+                .help("Refuse to send, and drop on receipt, messages larger than this"),
+        )
+        .arg(
+            Arg::with_name("max-upload")
+                .long("max-upload")
+                .value_name("BYTES_PER_SEC")
+                .takes_value(true)
+                .help("Limit the total upload rate of this node (native only)"),
+        )
+        .arg(
+            Arg::with_name("max-download")
+                .long("max-download")
+                .value_name("BYTES_PER_SEC")
+                .takes_value(true)
+                .help("Limit the total download rate of this node (native only)"),
+        )
+        .arg(
+            Arg::with_name("queue-size")
+                .long("queue-size")
+                .value_name("MESSAGES")
+                .takes_value(true)
+                .default_value(DEFAULT_QUEUE_SIZE)
+                .help("Maximum number of messages waiting to be published"),
+        )
+        .arg(
+            Arg::with_name("queue-policy")
+                .long("queue-policy")
+                .takes_value(true)
+                .possible_values(&["drop-oldest", "block"])
+                .default_value("block")
+                .help("When the queue is full, drop the oldest message or stop reading input"),
+        )
+        .arg(
+            Arg::with_name("publish-rate")
+                .long("publish-rate")
+                .value_name("MESSAGES_PER_SEC")
+                .takes_value(true)
+                .default_value(DEFAULT_PUBLISH_RATE)
+                .help("Maximum number of messages published per second"),
+        )
+        .arg(
+            Arg::with_name("batch-delay")
+                .long("batch-delay")
+                .value_name("MILLISECONDS")
+                .takes_value(true)
+                .help("Pack the lines sent within this delay into a single message"),
+        )
+        .arg(
+            Arg::with_name("batch-size")
+                .long("batch-size")
+                .value_name("LINES")
+                .takes_value(true)
+                .default_value(DEFAULT_BATCH_SIZE)
+                .help("Maximum number of lines packed into a single message"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
+                .takes_value(true)
+                .help("Threads verifying signatures, 0 for none (default: one per CPU)"),
+        )
+        .arg(
+            Arg::with_name("heartbeat-interval")
+                .long("heartbeat-interval")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Time between two heartbeats telling the others that we are here"),
+        )
+        .arg(
+            Arg::with_name("missed-heartbeats")
+                .long("missed-heartbeats")
+                .value_name("N")
+                .takes_value(true)
+                .help("Number of heartbeats a peer can miss before being considered gone"),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .value_name("REGEX")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Drop the messages matching this pattern; can be passed multiple times"),
+        )
+        .arg(
+            Arg::with_name("redact")
+                .long("redact")
+                .value_name("REGEX")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Mask this pattern in what we send; can be passed multiple times"),
+        )
+        .arg(
+            Arg::with_name("no-default-redactions")
+                .long("no-default-redactions")
+                .help("Don't mask the API keys, tokens and email addresses we send"),
+        )
+        .arg(
+            Arg::with_name("max-repeats")
+                .long("max-repeats")
+                .value_name("N")
+                .takes_value(true)
+                .default_value(DEFAULT_MAX_REPEATS)
+                .help("Hide the repetitions of a message after it was sent N times in a row"),
+        )
+        .arg(
+            Arg::with_name("topic")
+                .long("topic")
+                .value_name("ROOM")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Room to join at startup; can be passed multiple times"),
+        )
+        .arg(
+            Arg::with_name("topic-namespace")
+                .long("topic-namespace")
+                .value_name("PREFIX")
+                .takes_value(true)
+                .help("Prefix of all the topics, e.g. `workshop/2018/`, to separate sessions"),
+        )
+        .arg(
+            Arg::with_name("hash-topics")
+                .long("hash-topics")
+                .help("Name the topics after the hash of the room names"),
+        )
+        .arg(
+            Arg::with_name("election")
+                .long("election")
+                .help("Take part in the election of a coordinator among the peers of the room"),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .takes_value(true)
+                .help("Dial through this SOCKS5 proxy, for example socks5://127.0.0.1:9050"),
+        )
+        .arg(
+            Arg::with_name("dial-timeout")
+                .long("dial-timeout")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Time after which a dial that hasn't succeeded is abandoned"),
+        )
+        .arg(
+            Arg::with_name("max-dials")
+                .long("max-dials")
+                .value_name("N")
+                .takes_value(true)
+                .help("Number of dials in progress at the same time, the others are queued"),
+        )
+        .arg(
+            Arg::with_name("external-address")
+                .long("external-address")
+                .value_name("MULTIADDR")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Address at which the others can dial us; can be passed multiple times"),
+        )
+        .arg(
+            Arg::with_name("stun")
+                .long("stun")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .help("STUN server to ask for our public IP address, which we advertise"),
+        )
+        .arg(
+            Arg::with_name("serial")
+                .long("serial")
+                .value_name("DEVICE")
+                .takes_value(true)
+                .help("Experimental: listen on /dev/DEVICE, which must be a raw serial link"),
+        )
+        .arg(
+            Arg::with_name("beacon")
+                .long("beacon")
+                .help("Find the nodes of the local network and dial them, with multicast"),
+        )
+        .arg(
+            Arg::with_name("lan-party")
+                .long("lan-party")
+                .help("Chat with the nodes of the local network, without any other option"),
+        )
+        .arg(
+            Arg::with_name("low-bandwidth")
+                .long("low-bandwidth")
+                .conflicts_with("link-preview")
+                .help("Batch the lines and send fewer heartbeats, for a slow or costly link"),
+        )
+        .arg(
+            Arg::with_name("graph-file")
+                .long("graph-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Write the known topology of the mesh to this file, as a Graphviz graph"),
+        )
+        .arg(
+            Arg::with_name("http")
+                .long("http")
+                .value_name("ADDRESS:PORT")
+                .takes_value(true)
+                .help("Answer the HTTP requests of the control server on this address"),
+        )
+        .arg(
+            Arg::with_name("ready-peers")
+                .long("ready-peers")
+                .value_name("COUNT")
+                .takes_value(true)
+                .default_value(DEFAULT_READY_PEERS)
+                .help("Connections that the /readyz of --http requires"),
+        )
+        .arg(
+            Arg::with_name("dashboard")
+                .long("dashboard")
+                .requires("http")
+                .help("Serve a live dashboard of the node at /dashboard of --http"),
+        )
+        .arg(
+            Arg::with_name("feed")
+                .long("feed")
+                .value_name("ROOM")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .requires("http")
+                .help("Serve the last messages of this room as an Atom feed at /feed/ROOM"),
+        )
+        .arg(
+            Arg::with_name("http-publish")
+                .long("http-publish")
+                .requires("http")
+                .help("Publish the texts that bots POST to /publish of --http"),
+        )
+        .arg(
+            Arg::with_name("dump-file")
+                .long("dump-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Write the diagnostics dumped on SIGUSR1 to this file instead of stderr"),
+        )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Print a transcript written by /export instead of joining the network"),
+        )
+        .arg(
+            Arg::with_name("replay-speed")
+                .long("replay-speed")
+                .value_name("FACTOR")
+                .takes_value(true)
+                .default_value("1")
+                .help("Replay the transcript this many times faster; 0 prints it at once"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Record the envelopes and connections received to this file"),
+        )
+        .arg(
+            Arg::with_name("playback")
+                .long("playback")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with("record")
+                .help("Play back a file written by --record instead of joining the network"),
+        )
+        .arg(
+            Arg::with_name("input-file")
+                .long("input-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .conflicts_with("input-fifo")
+                .help("Read the lines to send from this file instead of stdin"),
+        )
+        .arg(
+            Arg::with_name("follow")
+                .long("follow")
+                .requires("input-file")
+                .help("Wait for the lines appended to --input-file, like tail -f"),
+        )
+        .arg(
+            Arg::with_name("input-fifo")
+                .long("input-fifo")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Read the lines to send from this named pipe, created if needed"),
+        )
+        .arg(
+            Arg::with_name("input-history")
+                .long("input-history")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Keep the lines you type in this file, for /recent and /again"),
+        )
+        .arg(
+            Arg::with_name("input-history-size")
+                .long("input-history-size")
+                .value_name("N")
+                .takes_value(true)
+                .default_value(DEFAULT_INPUT_HISTORY_SIZE)
+                .help("Number of lines kept in the input history"),
+        )
+        .arg(
+            Arg::with_name("input-history-exclude")
+                .long("input-history-exclude")
+                .value_name("REGEX")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Don't keep the lines matching this pattern; can be repeated"),
+        )
+        .arg(
+            Arg::with_name("relay")
+                .long("relay")
+                .value_name("MULTIADDR")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Relay to join the mesh through; of several, the fastest is used"),
+        )
+        .arg(
+            Arg::with_name("flood-threshold")
+                .long("flood-threshold")
+                .value_name("MESSAGES")
+                .takes_value(true)
+                .default_value("50")
+                .help("Only show a sample of a room that gets more messages within 10 seconds"),
+        )
+        .arg(
+            Arg::with_name("persona")
+                .long("persona")
+                .value_name("NAME[=FILE]")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Another identity to publish as after `/identity use NAME`, from FILE"),
+        )
+        .arg(
+            Arg::with_name("known-keys")
+                .long("known-keys")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Remember in this file the key first seen with each nickname"),
+        )
+        .arg(
+            Arg::with_name("accept-calls")
+                .long("accept-calls")
+                .help("Accept the voice calls that others open with /call"),
+        )
+        .arg(
+            Arg::with_name("accept-screens")
+                .long("accept-screens")
+                .help(
+                    "Watch the terminals that others share with /sharescreen; they can draw \
+                     anything in yours, but not set its title or clipboard",
+                ),
+        )
+        .arg(
+            Arg::with_name("bridge")
+                .long("bridge")
+                .value_name("URL")
+                .takes_value(true)
+                .help("Mirror the first room to a NATS subject or a Redis stream, as \
+                       nats://host[:port]/subject or redis://host[:port]/stream"),
+        )
+        .arg(
+            Arg::with_name("digest-to")
+                .long("digest-to")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Mail a digest of the room to this address at every --digest-hours"),
+        )
+        .arg(
+            Arg::with_name("digest-from")
+                .long("digest-from")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .default_value(digest::DEFAULT_FROM)
+                .help("Sender of the digests"),
+        )
+        .arg(
+            Arg::with_name("digest-hours")
+                .long("digest-hours")
+                .value_name("HOURS")
+                .takes_value(true)
+                .default_value(digest::DEFAULT_HOURS)
+                .help("Hours of messages in each digest, and between two digests"),
+        )
+        .arg(
+            Arg::with_name("smtp")
+                .long("smtp")
+                .value_name("HOST[:PORT]")
+                .takes_value(true)
+                .default_value(digest::DEFAULT_SERVER)
+                .help("SMTP server relaying the digests, without authentication"),
+        )
+        .arg(
+            Arg::with_name("plugins")
+                .long("plugins")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Run the bots of this directory, which are sandboxed .wasm modules"),
+        )
+        .arg(
+            Arg::with_name("schedule-file")
+                .long("schedule-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Keep the messages of /schedule in this file, across restarts"),
+        )
+        .arg(
+            Arg::with_name("reorder-delay")
+                .long("reorder-delay")
+                .value_name("MILLISECONDS")
+                .takes_value(true)
+                .help("Hold the texts we receive this long, to display them in order"),
+        )
+        .arg(
+            Arg::with_name("max-clients")
+                .long("max-clients")
+                .value_name("N")
+                .takes_value(true)
+                .help("Turn away the nodes that dial us beyond this many at the same time"),
+        )
+        .arg(
+            Arg::with_name("max-joins")
+                .long("max-joins")
+                .value_name("N")
+                .takes_value(true)
+                .help("Turn away the nodes that dial us beyond this many per minute"),
+        )
+        .arg(
+            Arg::with_name("alternate")
+                .long("alternate")
+                .value_name("MULTIADDR")
+                .takes_value(true)
+                .help("Relay to send the connected nodes to when we drain, with /drain or SIGTERM"),
+        )
+        .arg(
+            Arg::with_name("reports")
+                .long("reports")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Keep the reports we make or receive as moderator in this file"),
+        )
+        .arg(
+            Arg::with_name("confirm-lines")
+                .long("confirm-lines")
+                .value_name("LINES")
+                .takes_value(true)
+                .default_value(DEFAULT_CONFIRM_LINES)
+                .help("Ask before sending a message with more lines than this, 0 never asks"),
+        )
+        .arg(
+            Arg::with_name("confirm-bytes")
+                .long("confirm-bytes")
+                .value_name("BYTES")
+                .takes_value(true)
+                .default_value(DEFAULT_CONFIRM_BYTES)
+                .help("Ask before sending a message larger than this, 0 never asks"),
+        )
+        .arg(
+            Arg::with_name("undo-window")
+                .long("undo-window")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .default_value(DEFAULT_UNDO_WINDOW)
+                .help("How long after sending a message /undo can still retract it"),
+        )
+        .arg(
+            Arg::with_name("scores-file")
+                .long("scores-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Remember the misbehaving peers in this file, across restarts"),
+        )
+        .arg(
+            Arg::with_name("capture")
+                .long("capture")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("Write the raw bytes sent and received on every connection to this file"),
+        )
+        .arg(
+            Arg::with_name("echo")
+                .long("echo")
+                .help("Publish back every text received, to test the clients"),
+        )
+        .arg(
+            Arg::with_name("demo-traffic")
+                .long("demo-traffic")
+                .help("Publish a canned conversation between a few fake personas"),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check that the network lets the chat work, then exit")
+                .arg(
+                    Arg::with_name("server")
+                        .long("server")
+                        .value_name("MULTIADDR")
+                        .takes_value(true)
+                        .help("Node to try a websockets connection with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("probe")
+                .about("Connect to a node layer by layer, report the first that fails, then exit")
+                .arg(
+                    Arg::with_name("MULTIADDR")
+                        .required(true)
+                        .help("Address of the node, as it would be passed to --dial"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("decode")
+                .about("Explain the fields of raw protocol bytes, then exit")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .required(true)
+                        .help("A file written with --capture, another file, or hex bytes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print the completion script of a shell, then exit")
+                .arg(
+                    Arg::with_name("SHELL")
+                        .required(true)
+                        .possible_values(&Shell::variants())
+                        .help("Shell to complete in; pass --config to complete its profile names"),
+                ),
+        )
+}
+
+fn profile_arg<'a>(profiles: &[&'a str]) -> Arg<'a, 'a> {
+    let arg = Arg::with_name("profile")
+        .long("profile")
+        .value_name("NAME")
+        .takes_value(true)
+        .help("Take the options not passed here from this profile of the config file \
+               (default: the profile named default, if any)");
+    if profiles.is_empty() {
+        arg
+    } else {
+        arg.possible_values(profiles)
+    }
+}
+
+/// Prints the completion script for the shell named in `completions`, generated from the same
+/// definitions as the parser.
+fn print_completions(matches: &ArgMatches, completions: &ArgMatches) -> Result<(), Error> {
+    let shell = completions
+        .value_of("SHELL")
+        .and_then(|shell| shell.parse::<Shell>().ok())
+        .ok_or_else(|| Error::Internal("clap accepted an unknown shell".to_owned()))?;
+    let config = match value(matches, "config") {
+        Some(ref path) if Path::new(path).exists() => Config::load(path)
+            .map_err(|err| Error::config(&format!("can't load {}", path), err))?,
+        _ => Config::default(),
+    };
+    let mut profiles: Vec<&str> = config.profile.keys().map(|name| &name[..]).collect();
+    profiles.sort();
+    app(&profiles).gen_completions_to("chapter-3", shell, &mut io::stdout());
+    Ok(())
+}
+
+/// Turns `socks5://host:port` into the corresponding multiaddress.
+fn parse_proxy(url: &str) -> Option<Multiaddr> {
+    // Host names are always resolved by the proxy, so `socks5h` means the same as `socks5`.
+    let address = ["socks5://", "socks5h://"]
+        .iter()
+        .find(|scheme| url.starts_with(*scheme))
+        .map(|scheme| &url[scheme.len()..])?;
+    let colon = address.rfind(':')?;
+    let (host, port) = (&address[..colon], &address[colon + 1..]);
+    let port: u16 = port.parse().ok()?;
+    let host = host.trim_left_matches('[').trim_right_matches(']');
+    let protocol = match host.parse::<::std::net::IpAddr>() {
+        Ok(ref ip) if ip.is_ipv4() => "ip4",
+        Ok(_) => "ip6",
+        Err(_) => "dns4",
+    };
+    format!("/{}/{}/tcp/{}", protocol, host, port).parse().ok()
+}
+
+/// Returns the value of the option `name`, or of the corresponding environment variable.
+fn value(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches
+        .value_of(name)
+        .map(|s| s.to_owned())
+        .or_else(|| env::var(env_name(name)).ok())
+        .filter(|value| !value.is_empty())
+}
+
+/// Parses `text`, a value of the option `name`, or explains that it `expects` something else.
+fn parse<T: FromStr>(name: &str, text: &str, expects: &str) -> Result<T, Error> {
+    text.parse()
+        .map_err(|_| Error::Config(format!("--{} expects {}, not {}", name, expects, text)))
+}
+
+/// Parses the value of the option `name`, if it was passed.
+fn parse_option<T: FromStr>(
+    matches: &ArgMatches,
+    name: &str,
+    expects: &str,
+) -> Result<Option<T>, Error> {
+    match matches.value_of(name) {
+        Some(text) => parse(name, text, expects).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Parses the value of the option `name`, or `default` if it wasn't passed.
+fn parse_or<T: FromStr>(
+    matches: &ArgMatches,
+    name: &str,
+    default: &str,
+    expects: &str,
+) -> Result<T, Error> {
+    parse(name, matches.value_of(name).unwrap_or(default), expects)
+}
+
+/// Parses `text`, a multiaddress or a shorthand passed with the option `name`. See the
+/// `addresses` module.
+pub fn parse_address(name: &str, text: &str) -> Result<Multiaddr, Error> {
+    addresses::parse(text)
+        .map_err(|invalid| Error::Config(format!("--{} expects a multiaddress: {}", name, invalid)))
+}
+
+fn parse_addresses(name: &str, texts: &[String]) -> Result<Vec<Multiaddr>, Error> {
+    texts.iter().map(|text| parse_address(name, text)).collect()
+}
+
+/// Returns the values of the option `name`, or those of the corresponding environment variable.
+fn values_or_env(matches: &ArgMatches, name: &str) -> Vec<String> {
+    let values = values(matches.values_of(name));
+    if !values.is_empty() {
+        return values;
+    }
+    env::var(env_name(name))
+        .map(|var| var.split_whitespace().map(|s| s.to_owned()).collect())
+        .unwrap_or_default()
+}
+
+/// Name of the environment variable that replaces the option `name`.
+fn env_name(name: &str) -> String {
+    format!("CHAT_{}", name.to_uppercase().replace('-', "_"))
+}
+
+fn values(values: Option<::clap::Values>) -> Vec<String> {
+    values
+        .map(|v| v.map(|s| s.to_owned()).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,226 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Bounded queue of the messages waiting to be published.
+//!
+//! `FloodSubController::publish` never blocks: the message is pushed into an unbounded buffer for
+//! each connection, whether the remote reads it or not. In order to bound the memory used when
+//! we produce messages faster than we can send them (for example when a file is piped to stdin),
+//! the chat first puts its messages in an `Outbox`, which is drained at a fixed rate.
+//!
+//! When the outbox is full, the `Policy` decides what happens: either the oldest message is
+//! dropped, or we stop reading stdin until there is room again.
+//!
+//! Control messages, such as heartbeats, go before the bulk of the texts, so that a large paste
+//! doesn't make the others think that we left. They are also the last ones to be dropped.
+//!
+//! Direct messages, meant for one node, have a queue and a capacity of their own: the reminders
+//! and their acknowledgements, and the answers to the key-value, history and verification queries.
+//! A room flooded with texts, or the input being blocked, can't crowd them out, and they go right
+//! after the control messages.
+//!
+//! A message can be published on several topics at once, as `/broadcast` does. It takes a single
+//! place in the outbox, and floodsub sends it once to each node, whatever its topics.
+//!
+//! The envelopes are kept as `Bytes`, so the copy that a handoff keeps, or a message queued
+//! again, shares its buffer with the original.
+
+use bytes::Bytes;
+use futures::task::{self, Task};
+use futures::Async;
+use libp2p::floodsub::Topic;
+use std::collections::VecDeque;
+
+/// Number of direct messages that can wait, besides the capacity of the outbox.
+pub const DIRECT_CAPACITY: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Policy {
+    DropOldest,
+    Block,
+}
+
+impl Policy {
+    pub fn from_name(name: &str) -> Option<Policy> {
+        match name {
+            "drop-oldest" => Some(Policy::DropOldest),
+            "block" => Some(Policy::Block),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Priority {
+    /// Presence, coordination and requests, which are small and time-sensitive.
+    Control,
+    /// Messages addressed to one node, which don't count towards the capacity of the outbox.
+    Direct,
+    /// What the user writes.
+    Bulk,
+}
+
+pub struct Outbox {
+    control: VecDeque<(Vec<Topic>, Bytes)>,
+    bulk: VecDeque<(Vec<Topic>, Bytes)>,
+    direct: VecDeque<(Vec<Topic>, Bytes)>,
+    capacity: usize,
+    policy: Policy,
+    /// Number of messages dropped because the outbox was full.
+    dropped: u64,
+    /// Number of direct messages published, and dropped because their queue was full.
+    direct_sent: u64,
+    direct_dropped: u64,
+    /// Task waiting for the outbox to have room.
+    blocked: Option<Task>,
+}
+
+impl Outbox {
+    pub fn new(capacity: usize, policy: Policy) -> Outbox {
+        Outbox {
+            control: VecDeque::new(),
+            bulk: VecDeque::new(),
+            direct: VecDeque::new(),
+            capacity: capacity.max(1),
+            policy,
+            dropped: 0,
+            direct_sent: 0,
+            direct_dropped: 0,
+            blocked: None,
+        }
+    }
+
+    /// Queues a message. Returns false if it was dropped instead, which the caller must report.
+    ///
+    /// With the `Block` policy, the input is paused before the outbox is full, so a bulk message
+    /// can only be dropped if it was produced in response to the network, by a bot for example.
+    /// A full outbox drops its oldest bulk message to make room for a control message, even with
+    /// the `Block` policy.
+    pub fn push(&mut self, topic: Topic, data: Bytes, priority: Priority) -> bool {
+        self.push_many(vec![topic], data, priority)
+    }
+
+    /// Queues a message to publish on all of `topics`. Returns false as `push` does.
+    pub fn push_many(&mut self, topics: Vec<Topic>, data: Bytes, priority: Priority) -> bool {
+        if priority == Priority::Direct {
+            if self.direct.len() >= DIRECT_CAPACITY {
+                self.direct.pop_front();
+                self.direct_dropped += 1;
+            }
+            self.direct.push_back((topics, data));
+            return true;
+        }
+        if self.len() >= self.capacity {
+            self.dropped += 1;
+            match (self.policy, priority) {
+                (Policy::Block, Priority::Bulk) => return false,
+                (Policy::Block, Priority::Control) | (Policy::DropOldest, Priority::Control) => {
+                    if self.bulk.pop_front().is_none() {
+                        self.control.pop_front();
+                    }
+                }
+                (Policy::DropOldest, Priority::Bulk) => {
+                    if self.bulk.pop_front().is_none() {
+                        return false;
+                    }
+                }
+                (_, Priority::Direct) => unreachable!("queued above"),
+            }
+        }
+        if priority == Priority::Control {
+            self.control.push_back((topics, data));
+        } else {
+            self.bulk.push_back((topics, data));
+        }
+        true
+    }
+
+    /// Returns the next message to publish: the control messages first, then the direct ones.
+    pub fn pop(&mut self) -> Option<(Vec<Topic>, Bytes)> {
+        let message = match self.control.pop_front() {
+            Some(message) => Some(message),
+            None => match self.direct.pop_front() {
+                Some(message) => {
+                    self.direct_sent += 1;
+                    Some(message)
+                }
+                None => self.bulk.pop_front(),
+            },
+        };
+        if message.is_some() {
+            if let Some(task) = self.blocked.take() {
+                task.notify();
+            }
+        }
+        message
+    }
+
+    /// Returns `Ready` if we can keep reading input. Otherwise, the current task is notified once
+    /// there is room in the outbox.
+    pub fn poll_ready(&mut self) -> Async<()> {
+        if self.policy == Policy::DropOldest || self.len() < self.capacity {
+            return Async::Ready(());
+        }
+        self.blocked = Some(task::current());
+        Async::NotReady
+    }
+
+    /// Returns the number of messages queued, not counting the direct ones.
+    pub fn len(&self) -> usize {
+        self.control.len() + self.bulk.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Returns the number of direct messages queued, published and dropped.
+    pub fn direct(&self) -> (usize, u64, u64) {
+        (self.direct.len(), self.direct_sent, self.direct_dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::floodsub::TopicBuilder;
+
+    fn topic() -> Topic {
+        TopicBuilder::new("room").build()
+    }
+
+    #[test]
+    fn a_full_blocking_outbox_refuses_bulk_messages() {
+        let mut outbox = Outbox::new(2, Policy::Block);
+        assert!(outbox.push(topic(), Bytes::from_static(b"1"), Priority::Bulk));
+        assert!(outbox.push(topic(), Bytes::from_static(b"2"), Priority::Bulk));
+        assert!(!outbox.push(topic(), Bytes::from_static(b"3"), Priority::Bulk));
+        assert_eq!(outbox.dropped(), 1);
+        // Control messages still get in, in place of the oldest bulk message.
+        assert!(outbox.push(topic(), Bytes::from_static(b"heartbeat"), Priority::Control));
+        assert_eq!(outbox.pop().unwrap().1, Bytes::from_static(b"heartbeat"));
+        assert_eq!(outbox.pop().unwrap().1, Bytes::from_static(b"2"));
+        assert!(outbox.pop().is_none());
+    }
+}
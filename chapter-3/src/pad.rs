@@ -0,0 +1,367 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Shared notepads, replicated with a CRDT.
+//!
+//! Each pad is a sequence of lines synchronized over its own floodsub topic. Since floodsub
+//! doesn't guarantee that everyone receives the operations in the same order, we use a
+//! *Replicated Growable Array*: every line has a unique ID and remembers the line it was inserted
+//! after. Lines inserted after the same line are ordered by decreasing ID, which gives the same
+//! document on every node no matter the order in which the operations arrived.
+//!
+//! Deleted lines are kept as tombstones so that lines inserted after them can still find their
+//! place. Edits are resolved with "last writer wins", again using IDs to break ties.
+//!
+//! An operation on a line we don't know about yet waits for it. Anyone can publish such
+//! operations, so only the last `MAX_PENDING` of them wait.
+//!
+//! Without the `pads` feature, only the operations are kept, since they are part of the wire
+//! format.
+
+#[cfg(feature = "pads")]
+use std::collections::{HashMap, VecDeque};
+
+/// Number of operations that can wait for their line, at most. Beyond it, the oldest is dropped.
+#[cfg(feature = "pads")]
+const MAX_PENDING: usize = 256;
+
+/// Unique identifier of a line, or version of its content. Compared first by counter, then by
+/// site, so that there is a total order between all the IDs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Id {
+    pub counter: u64,
+    pub site: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PadOp {
+    /// Inserts a new line after the line `after`, or at the start if `None`.
+    Insert {
+        id: Id,
+        after: Option<Id>,
+        text: String,
+    },
+    /// Replaces the content of a line.
+    Edit { id: Id, version: Id, text: String },
+    Delete { id: Id },
+    /// Asks the other participants to send all the operations they know about.
+    SyncRequest,
+    /// Answer to a `SyncRequest`.
+    Snapshot(Vec<PadOp>),
+}
+
+#[cfg(feature = "pads")]
+#[derive(Debug, Clone)]
+struct Line {
+    after: Option<Id>,
+    text: String,
+    version: Id,
+    deleted: bool,
+}
+
+#[cfg(feature = "pads")]
+pub struct Pad {
+    site: u64,
+    /// Lamport clock, always greater than all the counters we've seen.
+    counter: u64,
+    lines: HashMap<Id, Line>,
+    /// Operations whose target line we don't know about yet, the oldest first.
+    pending: VecDeque<PadOp>,
+}
+
+#[cfg(feature = "pads")]
+impl Pad {
+    pub fn new(site: u64) -> Pad {
+        Pad {
+            site,
+            counter: 0,
+            lines: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn next_id(&mut self) -> Id {
+        self.counter += 1;
+        Id {
+            counter: self.counter,
+            site: self.site,
+        }
+    }
+
+    /// Returns the IDs of the visible lines, in document order.
+    fn visible(&self) -> Vec<Id> {
+        let mut children: HashMap<Option<Id>, Vec<Id>> = HashMap::new();
+        for (id, line) in &self.lines {
+            children.entry(line.after).or_insert_with(Vec::new).push(*id);
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| b.cmp(a));
+        }
+
+        let mut order = Vec::new();
+        let mut stack: Vec<Id> = children.get(&None).cloned().unwrap_or_default();
+        stack.reverse();
+        while let Some(id) = stack.pop() {
+            if !self.lines[&id].deleted {
+                order.push(id);
+            }
+            if let Some(next) = children.get(&Some(id)) {
+                stack.extend(next.iter().rev());
+            }
+        }
+        order
+    }
+
+    /// Returns the visible lines of the document.
+    pub fn text(&self) -> Vec<&str> {
+        self.visible()
+            .into_iter()
+            .map(|id| self.lines[&id].text.as_str())
+            .collect()
+    }
+
+    /// Appends a line at the end of the document. Returns the operation to publish.
+    pub fn append(&mut self, text: String) -> PadOp {
+        let after = self.visible().last().cloned();
+        let op = PadOp::Insert {
+            id: self.next_id(),
+            after,
+            text,
+        };
+        self.apply(op.clone());
+        op
+    }
+
+    /// Replaces the content of the `n`th visible line, starting at 1.
+    pub fn edit(&mut self, n: usize, text: String) -> Option<PadOp> {
+        let id = *self.visible().get(n.checked_sub(1)?)?;
+        let op = PadOp::Edit {
+            id,
+            version: self.next_id(),
+            text,
+        };
+        self.apply(op.clone());
+        Some(op)
+    }
+
+    /// Deletes the `n`th visible line, starting at 1.
+    pub fn delete(&mut self, n: usize) -> Option<PadOp> {
+        let id = *self.visible().get(n.checked_sub(1)?)?;
+        let op = PadOp::Delete { id };
+        self.apply(op.clone());
+        Some(op)
+    }
+
+    /// Returns all the operations needed to rebuild the document from scratch.
+    pub fn snapshot(&self) -> PadOp {
+        let mut ops = Vec::new();
+        for (id, line) in &self.lines {
+            ops.push(PadOp::Insert {
+                id: *id,
+                after: line.after,
+                text: line.text.clone(),
+            });
+            if line.version != *id {
+                ops.push(PadOp::Edit {
+                    id: *id,
+                    version: line.version,
+                    text: line.text.clone(),
+                });
+            }
+            if line.deleted {
+                ops.push(PadOp::Delete { id: *id });
+            }
+        }
+        PadOp::Snapshot(ops)
+    }
+
+    /// Applies an operation, local or remote. Applying the same operation twice has no effect.
+    pub fn apply(&mut self, op: PadOp) {
+        if self.try_apply(op) {
+            // Applying an operation can make some pending operations applicable.
+            loop {
+                let pending = ::std::mem::replace(&mut self.pending, VecDeque::new());
+                let before = pending.len();
+                for op in pending {
+                    self.try_apply(op);
+                }
+                if self.pending.len() == before {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Applies `op` if possible, otherwise puts it in `pending`. Returns true if applied.
+    fn try_apply(&mut self, op: PadOp) -> bool {
+        match op {
+            PadOp::Insert { id, after, text } => {
+                if after.map(|a| !self.lines.contains_key(&a)).unwrap_or(false) {
+                    self.wait(PadOp::Insert { id, after, text });
+                    return false;
+                }
+                self.counter = self.counter.max(id.counter);
+                self.lines.entry(id).or_insert(Line {
+                    after,
+                    text,
+                    version: id,
+                    deleted: false,
+                });
+            }
+            PadOp::Edit { id, version, text } => {
+                self.counter = self.counter.max(version.counter);
+                match self.lines.get_mut(&id) {
+                    Some(line) => {
+                        if version > line.version {
+                            line.version = version;
+                            line.text = text;
+                        }
+                    }
+                    None => {
+                        self.wait(PadOp::Edit { id, version, text });
+                        return false;
+                    }
+                }
+            }
+            PadOp::Delete { id } => match self.lines.get_mut(&id) {
+                Some(line) => line.deleted = true,
+                None => {
+                    self.wait(PadOp::Delete { id });
+                    return false;
+                }
+            },
+            PadOp::Snapshot(ops) => {
+                for op in ops {
+                    self.apply(op);
+                }
+            }
+            PadOp::SyncRequest => {}
+        }
+        true
+    }
+
+    /// Keeps `op` until its line arrives, dropping the oldest operation if too many wait.
+    fn wait(&mut self, op: PadOp) {
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(op);
+    }
+}
+
+#[cfg(all(test, feature = "pads"))]
+mod tests {
+    use super::*;
+
+    /// Applies `ops` to `pad`, in that order.
+    fn receive(pad: &mut Pad, ops: &[PadOp]) {
+        for op in ops {
+            pad.apply(op.clone());
+        }
+    }
+
+    #[test]
+    fn concurrent_inserts_converge_whatever_the_order() {
+        let (mut alice, mut bob) = (Pad::new(1), Pad::new(2));
+        let first = alice.append("shared".to_owned());
+        receive(&mut bob, &[first]);
+        let from_alice = vec![
+            alice.append("alice 1".to_owned()),
+            alice.append("alice 2".to_owned()),
+        ];
+        let from_bob = vec![bob.append("bob".to_owned())];
+        receive(&mut alice, &from_bob);
+        // Bob gets Alice's second line before the first one it follows.
+        receive(&mut bob, &[from_alice[1].clone(), from_alice[0].clone()]);
+        assert_eq!(alice.text(), bob.text());
+        // Both were inserted after `shared` with the same counter, and the larger site goes first.
+        assert_eq!(alice.text(), vec!["shared", "bob", "alice 1", "alice 2"]);
+    }
+
+    #[test]
+    fn an_insert_waits_for_the_line_it_follows() {
+        let mut alice = Pad::new(1);
+        let ops = vec![alice.append("a".to_owned()), alice.append("b".to_owned())];
+        let mut bob = Pad::new(2);
+        receive(&mut bob, &ops[1..]);
+        assert!(bob.text().is_empty());
+        receive(&mut bob, &ops[..1]);
+        assert_eq!(bob.text(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn only_the_last_operations_wait_for_their_line() {
+        let mut alice = Pad::new(1);
+        let first = alice.append("first".to_owned());
+        let ops: Vec<_> = (0..MAX_PENDING + 1)
+            .map(|n| alice.append(n.to_string()))
+            .collect();
+        let mut bob = Pad::new(2);
+        receive(&mut bob, &ops);
+        assert_eq!(bob.pending.len(), MAX_PENDING);
+        // The second line was dropped, so the lines after it still wait.
+        receive(&mut bob, &[first]);
+        assert_eq!(bob.text(), vec!["first"]);
+        assert_eq!(bob.pending.len(), MAX_PENDING);
+    }
+
+    #[test]
+    fn concurrent_edits_keep_the_last_writer() {
+        let (mut alice, mut bob) = (Pad::new(1), Pad::new(2));
+        let line = alice.append("draft".to_owned());
+        receive(&mut bob, &[line]);
+        let from_alice = alice.edit(1, "alice's".to_owned()).unwrap();
+        let from_bob = bob.edit(1, "bob's".to_owned()).unwrap();
+        receive(&mut alice, &[from_bob]);
+        receive(&mut bob, &[from_alice]);
+        assert_eq!(alice.text(), vec!["bob's"]);
+        assert_eq!(bob.text(), vec!["bob's"]);
+    }
+
+    #[test]
+    fn lines_inserted_after_a_deleted_one_keep_their_place() {
+        let (mut alice, mut bob) = (Pad::new(1), Pad::new(2));
+        let ops = vec![alice.append("a".to_owned()), alice.append("b".to_owned())];
+        receive(&mut bob, &ops);
+        let deletion = alice.delete(2).unwrap();
+        let insertion = bob.append("c".to_owned());
+        receive(&mut alice, &[insertion]);
+        receive(&mut bob, &[deletion]);
+        assert_eq!(alice.text(), vec!["a", "c"]);
+        assert_eq!(bob.text(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn a_snapshot_rebuilds_the_document_and_applies_twice() {
+        let mut alice = Pad::new(1);
+        alice.append("a".to_owned());
+        alice.append("b".to_owned());
+        alice.edit(1, "A".to_owned());
+        alice.delete(2);
+        alice.append("c".to_owned());
+        let mut bob = Pad::new(2);
+        bob.apply(alice.snapshot());
+        bob.apply(alice.snapshot());
+        assert_eq!(bob.text(), vec!["A", "c"]);
+        // Bob's next line comes after everything he learned of.
+        bob.append("d".to_owned());
+        assert_eq!(bob.text(), vec!["A", "c", "d"]);
+    }
+}
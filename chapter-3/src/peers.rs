@@ -0,0 +1,109 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Table of the connections that are currently open.
+//!
+//! It is filled by the handler passed to `libp2p::swarm`, which is the only place that sees every
+//! connection, whether we opened it or the remote did. Floodsub doesn't tell us the `PeerId` of
+//! the node at the other end of a connection, so connections are identified by their address.
+
+use display::{self, Verbosity};
+use libp2p::core::Endpoint;
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::rc::Rc;
+use stack::Steps;
+use std::time::{Duration, Instant};
+use upgrade::Protocol;
+
+pub struct Connection {
+    pub address: Multiaddr,
+    /// `Dialer` if we opened the connection, `Listener` if the remote did.
+    pub endpoint: Endpoint,
+    /// Protocol negotiated on this connection.
+    pub protocol: Protocol,
+    /// How long building the connection took. See the `stack` module.
+    pub steps: Steps,
+    opened: Instant,
+}
+
+impl Connection {
+    /// How long the connection has been open.
+    pub fn age(&self) -> Duration {
+        self.opened.elapsed()
+    }
+}
+
+pub struct PeerTable {
+    connections: Vec<(u64, Connection)>,
+    next_id: u64,
+}
+
+impl PeerTable {
+    pub fn new() -> Rc<RefCell<PeerTable>> {
+        Rc::new(RefCell::new(PeerTable {
+            connections: Vec::new(),
+            next_id: 0,
+        }))
+    }
+
+    /// Records a new connection. Returns an identifier to pass to `closed`.
+    pub fn opened(&mut self, address: Multiaddr, endpoint: Endpoint, protocol: Protocol) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let direction = match endpoint {
+            Endpoint::Dialer => "to",
+            Endpoint::Listener => "from",
+        };
+        display::event(
+            Verbosity::Verbose,
+            &format!("Connection {} opened {} {} for {:?}", id, direction, address, protocol),
+        );
+        self.connections.push((
+            id,
+            Connection {
+                address,
+                endpoint,
+                protocol,
+                steps: Steps::default(),
+                opened: Instant::now(),
+            },
+        ));
+        id
+    }
+
+    /// Records how long building the connection `id` took.
+    pub fn timed(&mut self, id: u64, steps: Steps) {
+        if let Some(&mut (_, ref mut connection)) =
+            self.connections.iter_mut().find(|&&mut (other, _)| other == id)
+        {
+            connection.steps = steps;
+        }
+    }
+
+    pub fn closed(&mut self, id: u64) {
+        display::event(Verbosity::Verbose, &format!("Connection {} closed", id));
+        self.connections.retain(|&(other, _)| other != id);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Connection> {
+        self.connections.iter().map(|&(_, ref connection)| connection)
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Several identities in one process, to play several parties of a conversation from a single
+//! laptop.
+//!
+//! Besides the main identity, each `--persona <name>[=<file>]` adds an identity that shares the
+//! transport and the event loop with it. A persona has its own key, its own nickname, which is
+//! its name, and its own rooms. `/identity use <name>` makes another persona the current one:
+//! from then on what we publish is signed with its key, and we are only subscribed to its rooms.
+//!
+//! The chat holds the identity, nickname and rooms of the current persona, as if there were no
+//! other; the others are parked in `Personas` until they are used.
+
+use identity::Identity;
+use libp2p::floodsub::Topic;
+use std::io::Error as IoError;
+
+/// Name of the persona of `--identity` and `--nick`.
+pub const MAIN: &str = "main";
+
+pub struct Persona {
+    pub name: String,
+    pub identity: Identity,
+    /// File from which the identity was loaded, if any.
+    pub file: Option<String>,
+    pub nick: Option<String>,
+    /// The rooms of the persona, with their topic.
+    pub rooms: Vec<(String, Topic)>,
+    /// The current room of the persona.
+    pub room: String,
+}
+
+pub struct Personas {
+    /// Name of the persona in use.
+    current: String,
+    /// The personas not in use.
+    parked: Vec<Persona>,
+}
+
+impl Personas {
+    /// Loads the personas of `--persona`, which start in the same rooms as the main one. The
+    /// personas without a file get a new key, which only lives until we quit.
+    pub fn load(
+        specs: &[(String, Option<String>)],
+        encrypt: bool,
+        rooms: &[(String, Topic)],
+    ) -> Result<Personas, IoError> {
+        let mut parked = Vec::with_capacity(specs.len());
+        for &(ref name, ref path) in specs {
+            let identity = match *path {
+                Some(ref path) => Identity::load_or_generate(path, encrypt)?,
+                None => Identity::generate(),
+            };
+            parked.push(Persona {
+                name: name.clone(),
+                identity,
+                file: path.clone(),
+                nick: Some(name.clone()),
+                rooms: rooms.to_vec(),
+                room: rooms[0].0.clone(),
+            });
+        }
+        Ok(Personas {
+            current: MAIN.to_owned(),
+            parked,
+        })
+    }
+
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    pub fn parked(&self) -> &[Persona] {
+        &self.parked
+    }
+
+    /// Removes the persona called `name` so that the chat uses it, and makes it the current one.
+    /// `None` if there is no such persona, or if it is already the current one.
+    pub fn take(&mut self, name: &str) -> Option<Persona> {
+        let position = self.parked.iter().position(|persona| persona.name == name)?;
+        self.current = name.to_owned();
+        Some(self.parked.remove(position))
+    }
+
+    /// Takes the files of the personas not in use, for `/purge all`. Their keys then only live
+    /// until we quit.
+    pub fn take_files(&mut self) -> Vec<String> {
+        self.parked
+            .iter_mut()
+            .filter_map(|persona| persona.file.take())
+            .collect()
+    }
+
+    /// Stores `persona`, which the chat stopped using.
+    pub fn park(&mut self, persona: Persona) {
+        self.parked.push(persona);
+    }
+}
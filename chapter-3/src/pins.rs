@@ -0,0 +1,86 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Messages pinned in each room.
+//!
+//! A pin is a signed message like any other, so everyone knows who pinned what, and only the
+//! peer who pinned a message can unpin it. The pin carries the text of the message, so that it
+//! can be displayed by the nodes that never received the original.
+
+use libp2p::PeerId;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Pin {
+    /// ID of the pinned message.
+    pub message: u64,
+    /// The pinned message as displayed, with its author.
+    pub text: String,
+    pub pinner: PeerId,
+    /// Name of the pinner, as displayed.
+    pub pinner_name: String,
+}
+
+pub struct Pins {
+    /// The pins of each room, the oldest first.
+    rooms: HashMap<String, Vec<Pin>>,
+}
+
+impl Pins {
+    pub fn new() -> Pins {
+        Pins {
+            rooms: HashMap::new(),
+        }
+    }
+
+    /// Adds a pin. Returns false if the message was already pinned in that room.
+    pub fn pin(&mut self, room: &str, pin: Pin) -> bool {
+        let pins = self.rooms.entry(room.to_owned()).or_insert_with(Vec::new);
+        if pins.iter().any(|p| p.message == pin.message) {
+            return false;
+        }
+        pins.push(pin);
+        true
+    }
+
+    /// Removes the pin of `message`, if `peer` pinned it. Returns the removed pin.
+    pub fn unpin(&mut self, room: &str, message: u64, peer: &PeerId) -> Option<Pin> {
+        let pins = self.rooms.get_mut(room)?;
+        let position = pins
+            .iter()
+            .position(|p| p.message == message && p.pinner == *peer)?;
+        Some(pins.remove(position))
+    }
+
+    /// The pins of `room`, the oldest first.
+    pub fn get(&self, room: &str) -> &[Pin] {
+        self.rooms.get(room).map(|pins| pins.as_slice()).unwrap_or(&[])
+    }
+
+    /// Forgets the pins of `room`, or of every room if `None`.
+    pub fn purge(&mut self, room: Option<&str>) {
+        match room {
+            Some(room) => {
+                self.rooms.remove(room);
+            }
+            None => self.rooms.clear(),
+        }
+    }
+}
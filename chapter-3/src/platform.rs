@@ -18,6 +18,8 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+#[cfg(not(target_os = "emscripten"))]
+extern crate atty;
 extern crate futures;
 extern crate libp2p_core;
 extern crate libp2p_floodsub;
@@ -32,17 +34,62 @@ extern crate rand;
 #[cfg(not(target_os = "emscripten"))]
 extern crate tokio_core;
 extern crate tokio_io;
-extern crate tokio_stdin;
 extern crate tokio_timer;
 
 use futures::{Future, Stream};
+use self::libp2p_core::Multiaddr;
 #[cfg(not(target_os = "emscripten"))]
 use self::libp2p_core::Transport;
+#[cfg(not(target_os = "emscripten"))]
+use socks::Socks5;
+#[cfg(not(target_os = "emscripten"))]
+use systemd::{self, Activated};
 use std::fmt::Debug;
 use std::io::Error as IoError;
+use std::time::Duration;
 #[cfg(target_os = "emscripten")]
 use stdweb;
 
+/// Returns true if we are reading from and writing to a terminal.
+#[cfg(not(target_os = "emscripten"))]
+pub fn is_terminal() -> bool {
+    atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stdout)
+}
+#[cfg(target_os = "emscripten")]
+pub fn is_terminal() -> bool {
+    false
+}
+
+/// Returns true if the machine runs on battery, as far as we can tell.
+#[cfg(target_os = "linux")]
+pub fn on_battery() -> bool {
+    use std::fs;
+
+    let supplies = match fs::read_dir("/sys/class/power_supply") {
+        Ok(supplies) => supplies,
+        Err(_) => return false,
+    };
+    let read = |path: &::std::path::Path, name: &str| {
+        fs::read_to_string(path.join(name))
+            .map(|value| value.trim().to_owned())
+            .unwrap_or_default()
+    };
+    let mut discharging = false;
+    for supply in supplies.filter_map(|supply| supply.ok()) {
+        let path = supply.path();
+        match &read(&path, "type")[..] {
+            "Mains" if read(&path, "online") == "1" => return false,
+            "Battery" if read(&path, "status") == "Discharging" => discharging = true,
+            _ => {}
+        }
+    }
+    discharging
+}
+#[cfg(not(target_os = "linux"))]
+pub fn on_battery() -> bool {
+    false
+}
+
 #[cfg(not(target_os = "emscripten"))]
 pub struct PlatformSpecific {
     core: tokio_core::reactor::Core,
@@ -67,32 +114,34 @@ impl Default for PlatformSpecific {
 
 #[cfg(not(target_os = "emscripten"))]
 impl PlatformSpecific {
+    /// Builds a transport for TCP and websockets. If `proxy` is the address of a SOCKS5 proxy,
+    /// all the dials go through it. If systemd passed us a listening socket, the first listener
+    /// uses it.
     pub fn build_transport(
         &self,
+        proxy: Option<Multiaddr>,
     ) -> libp2p_core::transport::OrTransport<
-        libp2p_websocket::WsConfig<libp2p_tcp_transport::TcpConfig>,
-        libp2p_tcp_transport::TcpConfig,
+        libp2p_websocket::WsConfig<Socks5<Activated<libp2p_tcp_transport::TcpConfig>>>,
+        Socks5<Activated<libp2p_tcp_transport::TcpConfig>>,
     > {
-        let tcp = libp2p_tcp_transport::TcpConfig::new(self.core.handle());
+        let tcp = Activated::new(
+            libp2p_tcp_transport::TcpConfig::new(self.core.handle()),
+            systemd::inherited_listener(),
+            self.core.handle(),
+        );
+        let tcp = Socks5::new(tcp, proxy);
         libp2p_websocket::WsConfig::new(tcp.clone()).or_transport(tcp)
     }
 
-    pub fn stdin(&self) -> impl Stream<Item = String, Error = IoError> {
-        use std::mem;
-
-        let mut buffer = Vec::new();
-        tokio_stdin::spawn_stdin_stream_unbounded()
-            .map_err(|_| -> IoError { panic!() })
-            .filter_map(move |msg| {
-                if msg != b'\r' && msg != b'\n' {
-                    buffer.push(msg);
-                    return None;
-                } else if buffer.is_empty() {
-                    return None;
-                }
-
-                Some(String::from_utf8(mem::replace(&mut buffer, Vec::new())).unwrap())
-            })
+    /// Returns a handle to the events loop, in order to spawn background tasks.
+    pub fn handle(&self) -> tokio_core::reactor::Handle {
+        self.core.handle()
+    }
+
+    /// Returns a stream that produces an element every `period`.
+    pub fn interval(&self, period: Duration) -> impl Stream<Item = (), Error = IoError> {
+        tokio_core::reactor::Interval::new(period, &self.core.handle())
+            .expect("failed to create a timer")
     }
 
     pub fn run<F>(mut self, future: F)
@@ -105,31 +154,32 @@ impl PlatformSpecific {
 }
 #[cfg(target_os = "emscripten")]
 impl PlatformSpecific {
-    pub fn build_transport(&self) -> libp2p_websocket::BrowserWsConfig {
+    /// Builds a transport for websockets. The browser decides how to connect, so `proxy` is
+    /// ignored.
+    pub fn build_transport(&self, _proxy: Option<Multiaddr>) -> libp2p_websocket::BrowserWsConfig {
         stdweb::initialize();
         libp2p_websocket::BrowserWsConfig::new()
     }
 
-    pub fn stdin(&self) -> impl Stream<Item = String, Error = IoError> {
+    /// Returns a stream that produces an element every `period`.
+    pub fn interval(&self, period: Duration) -> impl Stream<Item = (), Error = IoError> {
         use futures::sync::mpsc;
-        let (tx, rx) = mpsc::unbounded();
 
-        let cb = move |txt: String| {
-            let _ = tx.unbounded_send(txt);
-        };
-
-        js! {
-            var cb = @{cb};
-            document.getElementById("stdin_form")
-                .addEventListener("submit", function(event) {
-                    var elem = document.getElementById("stdin");
-                    var txt = elem.value;
-                    elem.value = "";
-                    cb(txt);
-                    event.preventDefault();
-                });
-        };
+        fn schedule(tx: mpsc::UnboundedSender<()>, ms: u32) {
+            stdweb::web::set_timeout(
+                move || {
+                    // Stop rescheduling once the stream has been dropped.
+                    if tx.unbounded_send(()).is_ok() {
+                        schedule(tx, ms);
+                    }
+                },
+                ms,
+            );
+        }
 
+        let (tx, rx) = mpsc::unbounded();
+        let ms = period.as_secs() as u32 * 1000 + period.subsec_nanos() / 1_000_000;
+        schedule(tx, ms);
         rx.map_err(|_| -> IoError { unreachable!() })
     }
 
@@ -0,0 +1,408 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Bots loaded at runtime from `--plugins <dir>`, sandboxed so that untrusted ones, such as those
+//! written during the workshop, can't harm the node.
+//!
+//! Each `*.wasm` file of the directory is a WebAssembly module, run with wasmi on a thread of its
+//! own. It can only reach the node through the functions it imports from `env`, which take
+//! pointers into its own memory:
+//!
+//! - `publish(text, text_len)` publishes a text in the room of the message being handled;
+//! - `log(text, text_len)` shows a line to us only;
+//! - `kv_get(key, key_len, out, out_cap) -> i32` copies the value of `key` to `out` and returns
+//!   its length, or -1 if there is none. Nothing is copied if the value is longer than `out_cap`;
+//! - `kv_set(key, key_len, value, value_len)` stores a value, kept in `<name>.json` next to the
+//!   module until `/purge all`.
+//!
+//! The module exports its `memory`, `alloc(len) -> ptr`, through which we copy the message into
+//! that memory, and `on_message(room, room_len, sender, sender_len, text, text_len)`, called for
+//! each text we receive.
+//!
+//! A bot can't reach the files, the network or the rest of the node, and its memory must declare
+//! a maximum of at most 16 MiB. A bot that loops forever only blocks its own thread. Once
+//! `QUEUED_MESSAGES` are waiting for it, the next ones are dropped.
+//!
+//! Running WebAssembly needs the `wasm-plugins` feature.
+//!
+//! What the bots publish can contain placeholders, such as `{{peer_count}}`, which are filled in
+//! when it is sent. See the `template` module.
+
+use display;
+use futures::sync::mpsc as futures_mpsc;
+use std::cell::Cell;
+use std::fs;
+use std::io::Error as IoError;
+use std::sync::mpsc;
+
+/// Messages waiting for a bot beyond which the others are dropped.
+const QUEUED_MESSAGES: usize = 64;
+
+/// A text received, as passed to the bots.
+pub struct Message {
+    pub room: String,
+    pub sender: String,
+    pub text: String,
+}
+
+/// What we pass to the thread of a bot.
+pub enum Event {
+    Message(Message),
+    /// `/purge all`: the bot forgets its values, and deletes their file.
+    Purge,
+}
+
+/// What a bot asks the node to do.
+pub enum Action {
+    Publish { room: String, text: String },
+    Log { bot: String, text: String },
+    /// The bot stopped, because of a trap or of an invalid module.
+    Failed { bot: String, error: String },
+}
+
+pub type Actions = futures_mpsc::UnboundedReceiver<Action>;
+
+struct Bot {
+    name: String,
+    events: mpsc::SyncSender<Event>,
+    /// True once we warned that it doesn't keep up.
+    lagging: Cell<bool>,
+}
+
+#[derive(Default)]
+pub struct Plugins {
+    bots: Vec<Bot>,
+}
+
+impl Plugins {
+    /// Starts the bots of `dir`, if any. A bot that can't be loaded is skipped.
+    pub fn load(dir: Option<&str>) -> Result<(Plugins, Actions), IoError> {
+        let (actions, receiver) = futures_mpsc::unbounded();
+        let mut plugins = Plugins::default();
+        let dir = match dir {
+            Some(dir) => dir,
+            None => return Ok((plugins, receiver)),
+        };
+        let mut paths: Vec<_> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map(|e| e == "wasm").unwrap_or(false))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            match wasm::spawn(&path, name.clone(), actions.clone()) {
+                Ok(events) => {
+                    say!("* Loaded the bot {}", name);
+                    plugins.bots.push(Bot {
+                        name,
+                        events,
+                        lagging: Cell::new(false),
+                    });
+                }
+                Err(err) => say!("* Can't load the bot {}: {}", name, err),
+            }
+        }
+        Ok((plugins, receiver))
+    }
+
+    /// Passes a text that we received to the bots.
+    pub fn message(&mut self, room: &str, sender: &str, text: &str) {
+        self.bots.retain(|bot| {
+            let message = Message {
+                room: room.to_owned(),
+                sender: sender.to_owned(),
+                text: text.to_owned(),
+            };
+            match bot.events.try_send(Event::Message(message)) {
+                Ok(()) => bot.lagging.set(false),
+                Err(mpsc::TrySendError::Full(_)) => {
+                    if !bot.lagging.replace(true) {
+                        display::chatter(&tr!(
+                            "* The bot {} doesn't keep up; dropping messages for it",
+                            bot.name
+                        ));
+                    }
+                }
+                // The bot stopped, which it reported.
+                Err(mpsc::TrySendError::Disconnected(_)) => return false,
+            }
+            true
+        });
+    }
+
+    /// Asks the bots to forget the values they stored. Returns the names of those that can't be
+    /// told, because they don't keep up with their messages.
+    pub fn purge(&mut self) -> Vec<String> {
+        self.bots
+            .iter()
+            .filter(|bot| bot.events.try_send(Event::Purge).is_err())
+            .map(|bot| bot.name.clone())
+            .collect()
+    }
+}
+
+#[cfg(all(feature = "wasm-plugins", not(target_os = "emscripten")))]
+mod wasm {
+    use super::{Action, Event, QUEUED_MESSAGES};
+    use futures::sync::mpsc as futures_mpsc;
+    use serde_json;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc;
+    use std::thread;
+    use wasmi::{Error, Externals, FuncInstance, FuncRef, ImportsBuilder, MemoryRef, Module};
+    use wasmi::{ModuleImportResolver, ModuleInstance, ModuleRef, RuntimeArgs, RuntimeValue};
+    use wasmi::{Signature, Trap, TrapKind, ValueType};
+
+    /// Pages of 64 KiB that the memory of a bot may grow to: 16 MiB.
+    const MAX_PAGES: usize = 256;
+    /// Keys beyond which a bot can't store new ones.
+    const MAX_KEYS: usize = 1024;
+
+    const PUBLISH: usize = 0;
+    const LOG: usize = 1;
+    const KV_GET: usize = 2;
+    const KV_SET: usize = 3;
+
+    /// Starts the bot at `path`. Returns where to send it the events.
+    pub fn spawn(
+        path: &Path,
+        name: String,
+        actions: futures_mpsc::UnboundedSender<Action>,
+    ) -> Result<mpsc::SyncSender<Event>, String> {
+        let bytes = fs::read(path).map_err(|err| err.to_string())?;
+        let module = Module::from_buffer(&bytes).map_err(|err| err.to_string())?;
+        let store = path.with_extension("json");
+        let (sender, events) = mpsc::sync_channel(QUEUED_MESSAGES);
+        thread::spawn(move || {
+            if let Err(error) = run(&module, name.clone(), store, &events, actions.clone()) {
+                let _ = actions.unbounded_send(Action::Failed { bot: name, error });
+            }
+        });
+        Ok(sender)
+    }
+
+    fn run(
+        module: &Module,
+        name: String,
+        store: PathBuf,
+        events: &mpsc::Receiver<Event>,
+        actions: futures_mpsc::UnboundedSender<Action>,
+    ) -> Result<(), String> {
+        let imports = ImportsBuilder::new().with_resolver("env", &Resolver);
+        let instance = ModuleInstance::new(module, &imports).map_err(|err| err.to_string())?;
+        let memory = instance
+            .not_started_instance()
+            .export_by_name("memory")
+            .and_then(|export| export.as_memory().cloned())
+            .ok_or("the module doesn't export its memory")?;
+        if memory.maximum().map(|max| max.0 > MAX_PAGES).unwrap_or(true) {
+            return Err("the memory of the module must have a maximum of at most 16 MiB".to_owned());
+        }
+        let mut host = Host {
+            name,
+            memory,
+            store: Store::load(store),
+            room: String::new(),
+            actions,
+        };
+        let instance = instance
+            .run_start(&mut host)
+            .map_err(|trap| format!("{:?}", trap))?;
+        for event in events {
+            let message = match event {
+                Event::Message(message) => message,
+                Event::Purge => {
+                    host.store.purge();
+                    continue;
+                }
+            };
+            host.room = message.room.clone();
+            let room = pass(&instance, &mut host, &message.room)?;
+            let sender = pass(&instance, &mut host, &message.sender)?;
+            let text = pass(&instance, &mut host, &message.text)?;
+            let args = [room[0], room[1], sender[0], sender[1], text[0], text[1]];
+            instance
+                .invoke_export("on_message", &args, &mut host)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Copies `text` into the memory of the bot, and returns where it is.
+    fn pass(
+        instance: &ModuleRef,
+        host: &mut Host,
+        text: &str,
+    ) -> Result<[RuntimeValue; 2], String> {
+        let len = text.len() as i32;
+        let ptr = match instance
+            .invoke_export("alloc", &[RuntimeValue::I32(len)], host)
+            .map_err(|err| err.to_string())?
+        {
+            Some(RuntimeValue::I32(ptr)) => ptr,
+            _ => return Err("alloc doesn't return a pointer".to_owned()),
+        };
+        host.memory
+            .set(ptr as u32, text.as_bytes())
+            .map_err(|err| err.to_string())?;
+        Ok([RuntimeValue::I32(ptr), RuntimeValue::I32(len)])
+    }
+
+    /// Hands out the functions of `env`, the only imports we allow.
+    struct Resolver;
+
+    impl ModuleImportResolver for Resolver {
+        fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, Error> {
+            let two = &[ValueType::I32, ValueType::I32][..];
+            let four = &[ValueType::I32, ValueType::I32, ValueType::I32, ValueType::I32][..];
+            let (index, params, result) = match field_name {
+                "publish" => (PUBLISH, two, None),
+                "log" => (LOG, two, None),
+                "kv_get" => (KV_GET, four, Some(ValueType::I32)),
+                "kv_set" => (KV_SET, four, None),
+                _ => return Err(Error::Instantiation(format!("unknown import {}", field_name))),
+            };
+            if signature.params() != params || signature.return_type() != result {
+                return Err(Error::Instantiation(format!("wrong signature for {}", field_name)));
+            }
+            Ok(FuncInstance::alloc_host(Signature::new(params, result), index))
+        }
+    }
+
+    /// The values stored by a bot.
+    struct Store {
+        path: PathBuf,
+        values: HashMap<String, String>,
+    }
+
+    impl Store {
+        fn load(path: PathBuf) -> Store {
+            let values = fs::read(&path)
+                .ok()
+                .and_then(|content| serde_json::from_slice(&content).ok())
+                .unwrap_or_default();
+            Store { path, values }
+        }
+
+        fn set(&mut self, key: String, value: String) {
+            if self.values.len() >= MAX_KEYS && !self.values.contains_key(&key) {
+                return;
+            }
+            self.values.insert(key, value);
+            let content = serde_json::to_vec(&self.values).expect("strings always serialize");
+            let _ = fs::write(&self.path, content);
+        }
+
+        fn purge(&mut self) {
+            self.values.clear();
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// What the functions of `env` act upon.
+    struct Host {
+        name: String,
+        memory: MemoryRef,
+        store: Store,
+        /// The room of the message being handled.
+        room: String,
+        actions: futures_mpsc::UnboundedSender<Action>,
+    }
+
+    impl Host {
+        /// Reads the text at the pointer and the length that start at argument `first`.
+        fn text(&self, args: &RuntimeArgs, first: usize) -> Result<String, Trap> {
+            let ptr: u32 = args.nth_checked(first)?;
+            let len: u32 = args.nth_checked(first + 1)?;
+            let bytes = self
+                .memory
+                .get(ptr, len as usize)
+                .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+            Ok(String::from_utf8_lossy(&bytes).into_owned())
+        }
+    }
+
+    impl Externals for Host {
+        fn invoke_index(
+            &mut self,
+            index: usize,
+            args: RuntimeArgs,
+        ) -> Result<Option<RuntimeValue>, Trap> {
+            match index {
+                PUBLISH => {
+                    let text = self.text(&args, 0)?;
+                    let room = self.room.clone();
+                    let _ = self.actions.unbounded_send(Action::Publish { room, text });
+                    Ok(None)
+                }
+                LOG => {
+                    let text = self.text(&args, 0)?;
+                    let bot = self.name.clone();
+                    let _ = self.actions.unbounded_send(Action::Log { bot, text });
+                    Ok(None)
+                }
+                KV_GET => {
+                    let key = self.text(&args, 0)?;
+                    let out: u32 = args.nth_checked(2)?;
+                    let capacity: u32 = args.nth_checked(3)?;
+                    let value = match self.store.values.get(&key) {
+                        Some(value) => value.clone(),
+                        None => return Ok(Some(RuntimeValue::I32(-1))),
+                    };
+                    if value.len() <= capacity as usize {
+                        self.memory
+                            .set(out, value.as_bytes())
+                            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))?;
+                    }
+                    Ok(Some(RuntimeValue::I32(value.len() as i32)))
+                }
+                KV_SET => {
+                    let key = self.text(&args, 0)?;
+                    let value = self.text(&args, 2)?;
+                    self.store.set(key, value);
+                    Ok(None)
+                }
+                _ => unreachable!("the resolver only hands out these functions"),
+            }
+        }
+    }
+}
+
+#[cfg(not(all(feature = "wasm-plugins", not(target_os = "emscripten"))))]
+mod wasm {
+    use super::{Action, Event};
+    use futures::sync::mpsc as futures_mpsc;
+    use std::path::Path;
+    use std::sync::mpsc;
+
+    pub fn spawn(
+        _: &Path,
+        _: String,
+        _: futures_mpsc::UnboundedSender<Action>,
+    ) -> Result<mpsc::SyncSender<Event>, String> {
+        Err("this build doesn't have the wasm-plugins feature".to_owned())
+    }
+}
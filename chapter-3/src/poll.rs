@@ -0,0 +1,145 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Polls and votes.
+//!
+//! A poll is identified by the ID of the envelope that created it. Every node tallies the votes
+//! on its own; since votes are signed, each public key is only counted once per poll, and voting
+//! again replaces the previous vote.
+//!
+//! A poll whose ID is taken, and a vote for a poll that we don't know or for an option it
+//! doesn't have, are dropped. Anyone could otherwise replace a poll, or fill our memory with
+//! votes.
+
+use std::collections::HashMap;
+
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<String>,
+    pub author: String,
+}
+
+pub struct Polls {
+    polls: HashMap<u64, Poll>,
+    /// For each poll, the choice of each voter.
+    votes: HashMap<u64, HashMap<Vec<u8>, usize>>,
+}
+
+impl Polls {
+    pub fn new() -> Polls {
+        Polls {
+            polls: HashMap::new(),
+            votes: HashMap::new(),
+        }
+    }
+
+    /// Adds the poll `id`. Returns false, keeping the poll we have, if there already is one.
+    pub fn add(&mut self, id: u64, poll: Poll) -> bool {
+        if self.polls.contains_key(&id) {
+            return false;
+        }
+        self.polls.insert(id, poll);
+        true
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Poll> {
+        self.polls.get(&id)
+    }
+
+    /// Records the vote of `voter` for the poll `id`. `choice` starts at 0. Returns false if
+    /// there is no such poll, or no such option.
+    pub fn vote(&mut self, id: u64, voter: Vec<u8>, choice: usize) -> bool {
+        match self.polls.get(&id) {
+            Some(poll) if choice < poll.options.len() => {}
+            _ => return false,
+        }
+        self.votes
+            .entry(id)
+            .or_insert_with(HashMap::new)
+            .insert(voter, choice);
+        true
+    }
+
+    /// Finds the poll whose formatted ID starts with `prefix`. Returns `None` if there is no such
+    /// poll, or if the prefix is ambiguous.
+    pub fn find(&self, prefix: &str) -> Option<u64> {
+        let mut matching = self.polls.keys().filter(|id| format_id(**id).starts_with(prefix));
+        match (matching.next(), matching.next()) {
+            (Some(id), None) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of votes for each option of the poll.
+    pub fn results(&self, id: u64) -> Option<Vec<(&str, usize)>> {
+        let poll = self.polls.get(&id)?;
+        let mut counts = vec![0; poll.options.len()];
+        if let Some(votes) = self.votes.get(&id) {
+            for &choice in votes.values() {
+                if let Some(count) = counts.get_mut(choice) {
+                    *count += 1;
+                }
+            }
+        }
+        Some(
+            poll.options
+                .iter()
+                .map(|option| option.as_str())
+                .zip(counts)
+                .collect(),
+        )
+    }
+}
+
+/// Formats the ID of a poll the way users are expected to type it.
+pub fn format_id(id: u64) -> String {
+    format!("{:016x}", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Poll, Polls};
+
+    fn poll(question: &str) -> Poll {
+        Poll {
+            question: question.to_owned(),
+            options: vec!["yes".to_owned(), "no".to_owned()],
+            author: "alice".to_owned(),
+        }
+    }
+
+    #[test]
+    fn a_poll_id_is_not_reused() {
+        let mut polls = Polls::new();
+        assert!(polls.add(1, poll("Lunch?")));
+        assert!(!polls.add(1, poll("Dinner?")));
+        assert_eq!(polls.get(1).unwrap().question, "Lunch?");
+    }
+
+    #[test]
+    fn votes_need_a_known_poll_and_option() {
+        let mut polls = Polls::new();
+        assert!(!polls.vote(1, vec![1], 0));
+        polls.add(1, poll("Lunch?"));
+        assert!(!polls.vote(1, vec![1], 2));
+        assert!(polls.vote(1, vec![1], 1));
+        assert_eq!(polls.results(1).unwrap(), vec![("yes", 0), ("no", 1)]);
+    }
+}
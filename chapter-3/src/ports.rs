@@ -0,0 +1,77 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Listening even when the port we asked for is taken.
+//!
+//! Another program, or another instance of the chat, may already use the port. The TCP transport
+//! only notices once the swarm runs, so we check the port beforehand. If it is taken, we try the
+//! next few ports, and then one picked by the system.
+
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::iter;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+
+/// How many ports after the requested one are tried.
+const NEXT_PORTS: u16 = 10;
+
+/// Returns `address`, or the same address with another port if its port is taken. Addresses
+/// without an IP and a TCP port are returned as they are.
+pub fn available(address: &Multiaddr) -> Option<Multiaddr> {
+    let (ip, port) = match socket_address(address) {
+        Some(socket_address) => socket_address,
+        None => return Some(address.clone()),
+    };
+    let next = if port == 0 { 0 } else { NEXT_PORTS };
+    (0..next + 1)
+        .filter_map(|n| port.checked_add(n))
+        .chain(iter::once(0))
+        .find(|&port| TcpListener::bind(SocketAddr::new(ip, port)).is_ok())
+        .map(|port| with_port(address, port))
+}
+
+/// Returns the TCP port of `address`, if any.
+pub fn port(address: &Multiaddr) -> Option<u16> {
+    socket_address(address).map(|(_, port)| port)
+}
+
+/// Returns the IP address and TCP port of `address`, if it has both.
+pub fn socket_address(address: &Multiaddr) -> Option<(IpAddr, u16)> {
+    let (mut ip, mut port) = (None, None);
+    for component in address.iter() {
+        match component {
+            AddrComponent::IP4(addr) => ip = Some(IpAddr::V4(addr)),
+            AddrComponent::IP6(addr) => ip = Some(IpAddr::V6(addr)),
+            AddrComponent::TCP(number) => port = Some(number),
+            _ => (),
+        }
+    }
+    Some((ip?, port?))
+}
+
+fn with_port(address: &Multiaddr, port: u16) -> Multiaddr {
+    address
+        .iter()
+        .map(|component| match component {
+            AddrComponent::TCP(_) => AddrComponent::TCP(port),
+            component => component,
+        })
+        .collect()
+}
@@ -0,0 +1,126 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Presence of the other members of the room.
+//!
+//! Floodsub doesn't tell us who is subscribed to a topic, so every node periodically publishes a
+//! heartbeat. A peer from which we haven't received anything for a while is considered gone and
+//! is removed from the roster.
+
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Default number of seconds between two heartbeats.
+pub const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 5;
+
+/// Default number of missed heartbeats after which a peer is considered gone.
+pub const DEFAULT_MISSED_HEARTBEATS: u32 = 3;
+
+/// Number of addresses of a peer that we keep.
+const MAX_ADVERTISED_ADDRESSES: usize = 8;
+
+pub struct Presence {
+    peers: HashMap<PeerId, Peer>,
+    /// Time without news after which a peer is considered gone.
+    timeout: Duration,
+}
+
+pub struct Peer {
+    /// Nickname used in the last message of the peer.
+    pub nick: Option<String>,
+    /// Addresses at which the peer says it can be dialed, see `Kind::Addresses`.
+    pub addresses: Vec<Multiaddr>,
+    last_seen: Instant,
+}
+
+impl Presence {
+    pub fn new(timeout: Duration) -> Presence {
+        Presence {
+            peers: HashMap::new(),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Called for every message received from `peer`, heartbeat or not.
+    pub fn seen(&mut self, peer: &PeerId, nick: Option<String>) {
+        let info = self.peers.entry(peer.clone()).or_insert_with(|| Peer {
+            nick: None,
+            addresses: Vec::new(),
+            last_seen: Instant::now(),
+        });
+        info.nick = nick;
+        info.last_seen = Instant::now();
+    }
+
+    /// Called when `peer` advertises the addresses at which it can be dialed. It must have been
+    /// `seen` first. We keep the first `MAX_ADVERTISED_ADDRESSES` of them that are valid.
+    pub fn advertised(&mut self, peer: &PeerId, addresses: &[String]) {
+        if let Some(info) = self.peers.get_mut(peer) {
+            info.addresses = addresses
+                .iter()
+                .take(MAX_ADVERTISED_ADDRESSES)
+                .filter_map(|address| address.parse().ok())
+                .collect();
+        }
+    }
+
+    pub fn is_alive(&self, peer: &PeerId) -> bool {
+        match self.peers.get(peer) {
+            Some(info) => info.last_seen.elapsed() < self.timeout,
+            None => false,
+        }
+    }
+
+    /// Returns the peers that we have heard of recently.
+    pub fn alive<'a>(&'a self) -> impl Iterator<Item = &'a PeerId> + 'a {
+        self.roster().map(|(peer, _)| peer)
+    }
+
+    /// Returns the peers that we have heard of recently, with their information.
+    pub fn roster<'a>(&'a self) -> impl Iterator<Item = (&'a PeerId, &'a Peer)> + 'a {
+        let timeout = self.timeout;
+        self.peers
+            .iter()
+            .filter(move |&(_, info)| info.last_seen.elapsed() < timeout)
+    }
+
+    /// Removes the peers that timed out from the roster, and returns them.
+    pub fn expire(&mut self) -> Vec<(PeerId, Peer)> {
+        let timeout = self.timeout;
+        let expired: Vec<PeerId> = self
+            .peers
+            .iter()
+            .filter(|&(_, info)| info.last_seen.elapsed() >= timeout)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|peer| {
+                let info = self.peers.remove(&peer)?;
+                Some((peer, info))
+            })
+            .collect()
+    }
+}
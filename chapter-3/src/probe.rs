@@ -0,0 +1,382 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! `probe`, which goes through the layers of a connection to one node.
+//!
+//! When two attendees can't connect, `doctor` checks the network in general. `probe <address>`
+//! instead dials that node and tries each layer in the order the chat does, stopping at the first
+//! that fails: the TCP connection, the websockets handshake, the admission of a busy relay, the
+//! multistream-select negotiation of floodsub, and the subscriptions that the node then
+//! announces. There's no encryption or multiplexing to check, since the chat speaks in the clear
+//! and with one protocol per connection.
+//!
+//! The protocols are spoken by hand, on a blocking socket, so that a failure shows the bytes that
+//! the node actually sent instead of an error of the swarm.
+
+use admission;
+use dissect;
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use upgrade::Protocol;
+
+const TIMEOUT_SECS: u64 = 5;
+const MULTISTREAM: &str = "/multistream/1.0.0";
+
+/// What a layer found, or what went wrong and what to do about it.
+type Step<T> = Result<(T, String), (String, &'static str)>;
+
+/// Probes `address` and prints the layers. Returns true if all of them worked.
+pub fn run(address: &Multiaddr) -> bool {
+    probe(address).is_some()
+}
+
+fn probe(address: &Multiaddr) -> Option<()> {
+    let (host, port) = match target(address) {
+        Some(target) => target,
+        None => {
+            say!("[FAIL] {}", tr!("{} has no host and TCP port to connect to", address));
+            return None;
+        }
+    };
+    let stream = report(tr!("TCP connection to {}:{}", host, port), connect(&host, port))?;
+    let mut link = match websockets(address) {
+        Websockets::None => {
+            say!("[skip] {}", tr!("Websockets: not a /ws address"));
+            Link::new(stream, false, Vec::new())
+        }
+        Websockets::Secure => {
+            say!("[skip] {}", tr!("Websockets: /wss needs TLS, which the probe doesn't speak"));
+            return None;
+        }
+        Websockets::Plain => {
+            let received = report(tr!("Websockets handshake"), handshake(&stream, &host, port))?;
+            Link::new(stream, true, received)
+        }
+    };
+    say!("[skip] {}", tr!("Encryption and multiplexing: the chat uses neither"));
+    let protocol = Protocol::FloodSub.name();
+    report(tr!("Negotiation of {}", protocol), negotiate(&mut link, &protocol))?;
+    let lines = report(tr!("Floodsub"), subscriptions(&mut link))?;
+    for line in lines {
+        println!("         {}", line);
+    }
+    Some(())
+}
+
+/// Prints the result of a layer, and returns what it found if it worked.
+fn report<T>(label: String, step: Step<T>) -> Option<T> {
+    match step {
+        Ok((value, found)) => {
+            say!("[ok]   {}: {}", label, found);
+            Some(value)
+        }
+        Err((found, advice)) => {
+            say!("[FAIL] {}: {}", label, found);
+            say!("       {}", tr!(advice));
+            None
+        }
+    }
+}
+
+fn connect(host: &str, port: u16) -> Step<TcpStream> {
+    const ADVICE: &str = "Check the address and that the node runs; a firewall may block the port";
+    let connect = || -> Result<(TcpStream, SocketAddr), IoError> {
+        let address = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| error(&tr!("couldn't resolve the host name {}", host)))?;
+        let timeout = Duration::from_secs(TIMEOUT_SECS);
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok((stream, address))
+    };
+    match connect() {
+        Ok((stream, address)) => Ok((stream, tr!("connected to {}", address))),
+        Err(err) => Err((err.to_string(), ADVICE)),
+    }
+}
+
+/// Asks for a websockets upgrade, and returns the bytes received after the headers.
+fn handshake(stream: &TcpStream, host: &str, port: u16) -> Step<Vec<u8>> {
+    const ADVICE: &str = "The node may not listen with /ws, or a proxy may be in the way";
+    match upgrade(stream, host, port) {
+        Ok((status, received)) => Ok((received, status)),
+        Err(err) => Err((err.to_string(), ADVICE)),
+    }
+}
+
+fn upgrade(mut stream: &TcpStream, host: &str, port: u16) -> Result<(String, Vec<u8>), IoError> {
+    write!(
+        stream,
+        "GET / HTTP/1.1\r\nHost: {}:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        host, port
+    )?;
+    let mut response = Vec::new();
+    let end = loop {
+        if let Some(end) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        let mut buffer = [0; 1024];
+        let len = stream.read(&mut buffer)?;
+        if len == 0 {
+            return Err(closed());
+        }
+        response.extend_from_slice(&buffer[..len]);
+    };
+    let status = String::from_utf8_lossy(&response[..end])
+        .lines()
+        .next()
+        .unwrap_or("")
+        .to_owned();
+    if status.split_whitespace().nth(1) == Some("101") {
+        Ok((status, response[end + 4..].to_vec()))
+    } else {
+        Err(error(&tr!("unexpected answer: {}", status)))
+    }
+}
+
+/// Proposes floodsub with multistream-select, in one go as libp2p does.
+fn negotiate(link: &mut Link, protocol: &str) -> Step<()> {
+    const ADVICE: &str = "This isn't a node of the chat, or something rewrites the traffic";
+    let failed = |err: IoError| (err.to_string(), ADVICE);
+    let mut proposal = line(MULTISTREAM);
+    proposal.extend(line(protocol));
+    link.send(&proposal).map_err(&failed)?;
+    link.fill(1).map_err(&failed)?;
+    if link.received[0] as usize != MULTISTREAM.len() + 1 {
+        // Not a length prefix: a busy relay turns us away with a plain line instead.
+        let text = String::from_utf8_lossy(&link.rest()).trim().to_owned();
+        if text.starts_with(admission::REJECTION) {
+            return Err((text, "The node is full; try another relay, or again later"));
+        }
+        return Err((tr!("unexpected answer: {}", text), ADVICE));
+    }
+    if link.message().map_err(&failed)? != line(MULTISTREAM) {
+        return Err((tr!("the node doesn't speak {}", MULTISTREAM), ADVICE));
+    }
+    let answer = link.message().map_err(&failed)?;
+    if answer == line("na") {
+        let advice = "The node may run another version of the chat";
+        return Err((tr!("the node doesn't speak {}", protocol), advice));
+    }
+    if answer != line(protocol) {
+        let answer = String::from_utf8_lossy(&answer).trim().to_owned();
+        return Err((tr!("unexpected answer: {}", answer), ADVICE));
+    }
+    Ok(((), tr!("the node agreed")))
+}
+
+/// Waits for the first floodsub frame, in which a node announces the topics it subscribed to.
+fn subscriptions(link: &mut Link) -> Step<Vec<String>> {
+    const ADVICE: &str = "The node hung up on us; its output may say why";
+    let err = match link.message() {
+        Ok(frame) => return Ok((dissect::explain(&frame), tr!("the node announced its rooms"))),
+        Err(err) => err,
+    };
+    match err.kind() {
+        // The timeout shows as either kind, depending on the platform.
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+            let found = tr!("connected, but the node announced no room in {} s", TIMEOUT_SECS);
+            Ok((Vec::new(), found))
+        }
+        _ => Err((err.to_string(), ADVICE)),
+    }
+}
+
+enum Websockets {
+    None,
+    Plain,
+    Secure,
+}
+
+fn websockets(address: &Multiaddr) -> Websockets {
+    let mut websockets = Websockets::None;
+    for component in address.iter() {
+        match component {
+            AddrComponent::WS => websockets = Websockets::Plain,
+            AddrComponent::WSS => websockets = Websockets::Secure,
+            _ => (),
+        }
+    }
+    websockets
+}
+
+/// Returns the host and the TCP port of `address`.
+fn target(address: &Multiaddr) -> Option<(String, u16)> {
+    let (mut host, mut port) = (None, None);
+    for component in address.iter() {
+        match component {
+            AddrComponent::IP4(ip) => host = Some(ip.to_string()),
+            AddrComponent::IP6(ip) => host = Some(ip.to_string()),
+            AddrComponent::DNS4(name) | AddrComponent::DNS6(name) => host = Some(name),
+            AddrComponent::TCP(number) => port = Some(number),
+            _ => (),
+        }
+    }
+    Some((host?, port?))
+}
+
+/// The connection, with or without websockets frames around the bytes.
+struct Link {
+    stream: TcpStream,
+    websockets: bool,
+    /// Bytes received and not consumed yet, out of their frames.
+    received: Vec<u8>,
+    /// Websockets frames received and not decoded yet.
+    frames: Vec<u8>,
+}
+
+impl Link {
+    /// `received` are the first bytes, received along with the websockets handshake.
+    fn new(stream: TcpStream, websockets: bool, received: Vec<u8>) -> Link {
+        let mut link = Link {
+            stream,
+            websockets,
+            received: Vec::new(),
+            frames: Vec::new(),
+        };
+        link.push(&received);
+        link
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<(), IoError> {
+        if !self.websockets {
+            return self.stream.write_all(data);
+        }
+        // A final binary frame, masked as the frames of clients must be.
+        let mut frame = vec![0x82];
+        let len = data.len();
+        if len < 126 {
+            frame.push(0x80 | len as u8);
+        } else if len <= 0xffff {
+            frame.extend_from_slice(&[0x80 | 126, (len >> 8) as u8, len as u8]);
+        } else {
+            frame.push(0x80 | 127);
+            frame.extend((0..8).rev().map(|byte| ((len as u64) >> (8 * byte)) as u8));
+        }
+        // The mask only protects proxies from crafted traffic, so a fixed one does.
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        frame.extend_from_slice(&mask);
+        frame.extend(data.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        self.stream.write_all(&frame)
+    }
+
+    /// Reads one message prefixed by its length as a varint: a line of multistream-select or a
+    /// floodsub frame. The prefix is kept.
+    fn message(&mut self) -> Result<Vec<u8>, IoError> {
+        self.fill(1)?;
+        loop {
+            if let Some((len, prefix)) = dissect::varint(&self.received) {
+                self.fill(prefix + len)?;
+                return Ok(self.received.drain(..prefix + len).collect());
+            }
+            let more = self.received.len() + 1;
+            self.fill(more)?;
+        }
+    }
+
+    /// Returns what was received until the node closed the connection or stopped sending.
+    fn rest(&mut self) -> Vec<u8> {
+        loop {
+            let more = self.received.len() + 1;
+            if self.fill(more).is_err() {
+                return self.received.drain(..).collect();
+            }
+        }
+    }
+
+    /// Reads until at least `len` bytes were received.
+    fn fill(&mut self, len: usize) -> Result<(), IoError> {
+        while self.received.len() < len {
+            let mut buffer = [0; 4096];
+            let read = self.stream.read(&mut buffer)?;
+            if read == 0 {
+                return Err(closed());
+            }
+            self.push(&buffer[..read]);
+        }
+        Ok(())
+    }
+
+    /// Takes bytes read from the socket, and decodes the complete websockets frames.
+    fn push(&mut self, data: &[u8]) {
+        if !self.websockets {
+            self.received.extend_from_slice(data);
+            return;
+        }
+        self.frames.extend_from_slice(data);
+        while let Some((opcode, start, end)) = frame(&self.frames) {
+            // Pings and pongs carry nothing for us, and a close frame is followed by the end of
+            // the connection.
+            if opcode <= 2 {
+                self.received.extend_from_slice(&self.frames[start..end]);
+            }
+            self.frames.drain(..end);
+        }
+    }
+}
+
+/// Parses the header of the websockets frame at the start of `data`. Returns its opcode and the
+/// range of its payload, or `None` if it isn't complete yet.
+fn frame(data: &[u8]) -> Option<(u8, usize, usize)> {
+    if data.len() < 2 {
+        return None;
+    }
+    let opcode = data[0] & 0x0f;
+    let masked = data[1] & 0x80 != 0;
+    let (len, mut start) = match data[1] & 0x7f {
+        126 if data.len() >= 4 => (((data[2] as usize) << 8) | data[3] as usize, 4),
+        127 if data.len() >= 10 => (
+            data[2..10].iter().fold(0, |len, &byte| (len << 8) | byte as usize),
+            10,
+        ),
+        126 | 127 => return None,
+        len => (len as usize, 2),
+    };
+    if masked {
+        // Servers don't mask their frames, so we don't bother unmasking.
+        start += 4;
+    }
+    if data.len() < start + len {
+        return None;
+    }
+    Some((opcode, start, start + len))
+}
+
+/// `text` as a line of multistream-select. Our lines are short enough for a one-byte prefix.
+fn line(text: &str) -> Vec<u8> {
+    let mut line = vec![text.len() as u8 + 1];
+    line.extend_from_slice(text.as_bytes());
+    line.push(b'\n');
+    line
+}
+
+fn closed() -> IoError {
+    IoError::new(ErrorKind::UnexpectedEof, tr!("the node closed the connection"))
+}
+
+fn error(message: &str) -> IoError {
+    IoError::new(ErrorKind::Other, message.to_owned())
+}
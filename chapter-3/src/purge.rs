@@ -0,0 +1,183 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! What `/purge` deletes from the disk.
+//!
+//! Most of what we know only lives in memory, and `Chat::purge` forgets it. Besides the identity
+//! files, the sessions of `--config` and the values stored by the bots, we write the stores below
+//! on our own, each to the file given on the command line. `/purge <room>` only takes the entries
+//! of the room out of the schedule, the reports and the bans; `/purge all` deletes all the files.
+//!
+//! The files that we are explicitly told to write, with `--record`, `--capture`, `--output-jsonl`,
+//! `--graph-file`, `--dump-file` or `/export`, are left alone.
+
+use inputs::InputHistory;
+use moderation::Moderation;
+use reports::Reports;
+use schedule::Schedule;
+use scores::Scores;
+use std::fs;
+use std::io::{Error as IoError, ErrorKind};
+use tofu::KnownKeys;
+use topics::TopicNaming;
+
+/// The stores of the chat that are kept on disk.
+pub struct Stores<'a> {
+    pub inputs: &'a mut InputHistory,
+    pub schedule: &'a mut Schedule,
+    pub reports: &'a mut Reports,
+    pub scores: &'a mut Scores,
+    pub known_keys: &'a mut KnownKeys,
+    pub moderation: &'a mut Moderation,
+}
+
+impl<'a> Stores<'a> {
+    /// Forgets what the stores know about `room`, or about everything if `None`. Returns the
+    /// errors, which don't keep the other stores from being purged.
+    pub fn purge(self, room: Option<&str>, naming: &TopicNaming) -> Vec<IoError> {
+        let label = room.map(|room| naming.label(room));
+        let mut results = vec![
+            self.schedule.purge(room),
+            self.reports.purge(label.as_ref().map(|label| label.as_str())),
+            self.moderation.purge(label.as_ref().map(|label| label.as_str())),
+        ];
+        if room.is_none() {
+            results.push(self.inputs.clear());
+            results.push(self.scores.purge());
+            results.push(self.known_keys.purge());
+        }
+        results.into_iter().filter_map(|result| result.err()).collect()
+    }
+}
+
+/// Deletes the file at `path`, if there is one. The error names the file.
+pub fn remove_file(path: &Option<String>) -> Result<(), IoError> {
+    let path = match *path {
+        Some(ref path) => path,
+        None => return Ok(()),
+    };
+    match fs::remove_file(path) {
+        Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(IoError::new(err.kind(), format!("{}: {}", path, err))),
+        Ok(()) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use identity::Identity;
+    use reports::{Report, SignedReport};
+    use std::env;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::time::Duration;
+
+    /// An empty directory of its own for `test`.
+    fn directory(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rustfest-chat-{}-{}", test, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn path(dir: &Path, name: &str) -> Option<String> {
+        Some(dir.join(name).to_string_lossy().into_owned())
+    }
+
+    fn report(room: &str) -> SignedReport {
+        let report = Report {
+            message: 1,
+            room: room.to_owned(),
+            author: "QmAuthor".to_owned(),
+            text: "spam".to_owned(),
+            reason: "it's spam".to_owned(),
+            timestamp: 0,
+        };
+        SignedReport::sign(report, &Identity::generate())
+    }
+
+    #[test]
+    fn purging_everything_leaves_no_file() {
+        let dir = directory("purge-all");
+        let mut inputs = InputHistory::load(path(&dir, "inputs"), 10, &[]).unwrap();
+        inputs.push("hello");
+        let mut schedule = Schedule::load(path(&dir, "schedule"));
+        schedule.add(0, "general".to_owned(), "later".to_owned());
+        schedule.remind(0, "bob".to_owned(), "call".to_owned());
+        let mut reports = Reports::load(path(&dir, "reports"));
+        reports.push(report("general"));
+        let mut scores = Scores::load(path(&dir, "scores"), Duration::from_secs(5));
+        scores.invalid(Identity::generate().peer_id());
+        scores.save();
+        let mut known_keys = KnownKeys::load(path(&dir, "keys"));
+        known_keys.check(Some("alice"), Identity::generate().public_key());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 5);
+
+        let stores = Stores {
+            inputs: &mut inputs,
+            schedule: &mut schedule,
+            reports: &mut reports,
+            scores: &mut scores,
+            known_keys: &mut known_keys,
+            moderation: &mut Moderation::load(Vec::new(), None),
+        };
+        let errors = stores.purge(None, &TopicNaming::new(String::new(), false));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert!(inputs.get(1).is_none());
+        assert!(schedule.queue().is_empty() && schedule.reminders().is_empty());
+        assert_eq!(reports.iter().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn purging_a_room_keeps_the_others() {
+        let dir = directory("purge-room");
+        let mut inputs = InputHistory::load(path(&dir, "inputs"), 10, &[]).unwrap();
+        inputs.push("hello");
+        let mut schedule = Schedule::load(path(&dir, "schedule"));
+        schedule.add(0, "general".to_owned(), "later".to_owned());
+        schedule.add(0, "rust".to_owned(), "later".to_owned());
+        let mut reports = Reports::load(path(&dir, "reports"));
+        let naming = TopicNaming::new(String::new(), true);
+        reports.push(report(&naming.label("general")));
+        reports.push(report(&naming.label("rust")));
+        let mut scores = Scores::load(None, Duration::from_secs(5));
+        let mut known_keys = KnownKeys::load(None);
+
+        let stores = Stores {
+            inputs: &mut inputs,
+            schedule: &mut schedule,
+            reports: &mut reports,
+            scores: &mut scores,
+            known_keys: &mut known_keys,
+            moderation: &mut Moderation::load(Vec::new(), None),
+        };
+        assert!(stores.purge(Some("general"), &naming).is_empty());
+        assert_eq!(inputs.get(1), Some("hello"));
+        let rooms: Vec<_> = schedule.queue().iter().map(|s| s.room.clone()).collect();
+        assert_eq!(rooms, vec!["rust".to_owned()]);
+        assert_eq!(Schedule::load(path(&dir, "schedule")).queue().len(), 1);
+        assert_eq!(reports.iter().count(), 1);
+        assert_eq!(Reports::load(path(&dir, "reports")).iter().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! "Happy eyeballs" dialing of the peers that we know under several addresses.
+//!
+//! A peer can often be reached in several ways, for example on the local network and through
+//! its public address. Rather than trying the addresses one after the other, each waiting for a
+//! timeout, `Race` starts a dial to the first address, then to the next one a little later, and
+//! so on, and keeps the first connection that succeeds. The others are dropped.
+//!
+//! Every success is recorded by class of address, and the classes that worked most often are
+//! tried first next time.
+
+use futures::future::{self, Future, IntoFuture};
+use libp2p::core::Transport;
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Error as IoError;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio_core::reactor::{Handle, Timeout};
+
+/// Delay between the starts of two dials to the same peer.
+const STAGGER_MS: u64 = 250;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Class {
+    /// Loopback and private addresses.
+    Lan,
+    /// Public IP addresses.
+    Wan,
+    /// Host names, which could resolve to anything.
+    Dns,
+    /// Connections relayed by another node.
+    Relayed,
+}
+
+impl Class {
+    pub fn of(addr: &Multiaddr) -> Class {
+        let mut class = Class::Wan;
+        for component in addr.iter() {
+            match component {
+                AddrComponent::P2P_CIRCUIT => return Class::Relayed,
+                AddrComponent::IP4(ip) if ip.is_private() || ip.is_loopback() => class = Class::Lan,
+                AddrComponent::IP6(ip) if ip.is_loopback() => class = Class::Lan,
+                AddrComponent::DNS4(_) | AddrComponent::DNS6(_) => class = Class::Dns,
+                _ => {}
+            }
+        }
+        class
+    }
+}
+
+/// The other addresses of the peers, and what worked so far. Cloning shares the book.
+#[derive(Clone, Default)]
+pub struct AddressBook {
+    inner: Rc<RefCell<Book>>,
+}
+
+#[derive(Default)]
+struct Book {
+    /// All the addresses of a peer, by the address used to dial it.
+    alternatives: HashMap<Multiaddr, Vec<Multiaddr>>,
+    /// Number of winning dials, by class.
+    successes: HashMap<Class, u32>,
+}
+
+impl AddressBook {
+    /// Records that `addresses` all lead to the same peer. Dialing any of them races them all.
+    pub fn add(&self, addresses: Vec<Multiaddr>) {
+        let mut book = self.inner.borrow_mut();
+        for addr in &addresses {
+            book.alternatives.insert(addr.clone(), addresses.clone());
+        }
+    }
+
+    /// Returns the addresses to try for `addr`, the most promising first.
+    fn candidates(&self, addr: &Multiaddr) -> Vec<Multiaddr> {
+        let book = self.inner.borrow();
+        let mut candidates = book
+            .alternatives
+            .get(addr)
+            .cloned()
+            .unwrap_or_else(|| vec![addr.clone()]);
+        // The sort is stable, so the order given by the user breaks ties.
+        candidates.sort_by_key(|addr| {
+            let successes = book.successes.get(&Class::of(addr)).cloned().unwrap_or(0);
+            ::std::cmp::Reverse(successes)
+        });
+        candidates
+    }
+
+    fn succeeded(&self, addr: &Multiaddr) {
+        let mut book = self.inner.borrow_mut();
+        *book.successes.entry(Class::of(addr)).or_insert(0) += 1;
+    }
+}
+
+#[derive(Clone)]
+pub struct Race<T> {
+    inner: T,
+    book: AddressBook,
+    handle: Handle,
+}
+
+impl<T> Race<T> {
+    pub fn new(inner: T, book: AddressBook, handle: Handle) -> Race<T> {
+        Race {
+            inner,
+            book,
+            handle,
+        }
+    }
+}
+
+impl<T> Transport for Race<T>
+where
+    T: Transport + Clone + 'static,
+    T::Output: 'static,
+    T::Dial: 'static,
+{
+    type Output = T::Output;
+    type Listener = T::Listener;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = Box<Future<Item = (T::Output, Multiaddr), Error = IoError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let Race {
+            inner,
+            book,
+            handle,
+        } = self;
+        inner.listen_on(addr).map_err(|(inner, addr)| {
+            let transport = Race {
+                inner,
+                book,
+                handle,
+            };
+            (transport, addr)
+        })
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let candidates = self.book.candidates(&addr);
+        if candidates.len() <= 1 {
+            let Race {
+                inner,
+                book,
+                handle,
+            } = self;
+            return match inner.dial(addr) {
+                Ok(dial) => Ok(Box::new(dial.into_future())),
+                Err((inner, addr)) => Err((Race { inner, book, handle }, addr)),
+            };
+        }
+
+        let mut attempts = Vec::new();
+        for (n, candidate) in candidates.into_iter().enumerate() {
+            // Addresses that the inner transport can't dial are simply not raced.
+            let dial = match self.inner.clone().dial(candidate) {
+                Ok(dial) => dial.into_future(),
+                Err(_) => continue,
+            };
+            let delay = Duration::from_millis(STAGGER_MS * n as u64);
+            let attempt = Timeout::new(delay, &self.handle)
+                .into_future()
+                .flatten()
+                .and_then(move |()| dial);
+            attempts.push(Box::new(attempt) as Self::Dial);
+        }
+        if attempts.is_empty() {
+            return Err((self, addr));
+        }
+
+        let book = self.book.clone();
+        let future = future::select_ok(attempts).map(move |((output, winner), _)| {
+            book.succeeded(&winner);
+            (output, winner)
+        });
+        Ok(Box::new(future))
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
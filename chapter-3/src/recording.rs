@@ -0,0 +1,223 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Recording of what the network sends us, and its playback without a network.
+//!
+//! With `--record`, every envelope received through floodsub and every connection opened or
+//! closed is appended to a file, one JSON object per line, with the number of milliseconds
+//! since the start. With `--playback`, these events are fed back to the chat at the same pace
+//! instead of listening and dialing, which reproduces what a user saw from their recording. The
+//! envelopes are signed, so they go through the same checks as the live ones.
+//!
+//! Playing back relies on the timers of tokio, so it is only available outside of the browser.
+
+use bs58;
+#[cfg(not(target_os = "emscripten"))]
+use bytes::Bytes;
+#[cfg(not(target_os = "emscripten"))]
+use futures::{stream, Future, Stream};
+use libp2p::core::Endpoint;
+use libp2p::floodsub::TopicHash;
+use libp2p::{Multiaddr, PeerId};
+#[cfg(not(target_os = "emscripten"))]
+use peers::PeerTable;
+use serde_json;
+use std::cell::RefCell;
+#[cfg(not(target_os = "emscripten"))]
+use std::collections::HashMap;
+use std::fs::File;
+#[cfg(not(target_os = "emscripten"))]
+use std::fs;
+use std::io::{Error as IoError, Write};
+#[cfg(not(target_os = "emscripten"))]
+use std::io::ErrorKind;
+#[cfg(not(target_os = "emscripten"))]
+use std::rc::Rc;
+use std::time::Instant;
+#[cfg(not(target_os = "emscripten"))]
+use std::time::Duration;
+#[cfg(not(target_os = "emscripten"))]
+use tokio_core::reactor::{Handle, Timeout};
+use upgrade::Protocol;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    /// Milliseconds since the start of the recording.
+    at: u64,
+    event: Event,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Event {
+    /// An envelope received on the floodsub topics `topics`, from the node `source`, both in
+    /// base58. `data` is the envelope, also in base58.
+    Message {
+        topics: Vec<String>,
+        source: String,
+        data: String,
+    },
+    /// The connection `id` of the `peers` module was opened.
+    Opened {
+        id: u64,
+        address: String,
+        dialer: bool,
+        protocol: Protocol,
+    },
+    Closed { id: u64 },
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl Event {
+    /// Returns the topics, source and envelope of a `Message`.
+    fn message(&self) -> Option<(Vec<TopicHash>, PeerId, Bytes)> {
+        match *self {
+            Event::Message {
+                ref topics,
+                ref source,
+                ref data,
+            } => {
+                let topics = topics
+                    .iter()
+                    .map(|topic| TopicHash::from_raw(topic.clone()))
+                    .collect();
+                let source = PeerId::from_base58(source).ok()?;
+                let data = bs58::decode(data).into_vec().ok()?;
+                Some((topics, source, Bytes::from(data)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Appends the events to a file.
+pub struct Recorder {
+    /// `None` once writing has failed.
+    file: RefCell<Option<File>>,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Recorder, IoError> {
+        Ok(Recorder {
+            file: RefCell::new(Some(File::create(path)?)),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn message(&self, topics: &[TopicHash], source: &PeerId, data: &[u8]) {
+        self.write(Event::Message {
+            topics: topics.iter().map(|topic| topic.clone().into_string()).collect(),
+            source: source.to_base58(),
+            data: bs58::encode(data).into_string(),
+        });
+    }
+
+    pub fn opened(&self, id: u64, address: &Multiaddr, endpoint: Endpoint, protocol: Protocol) {
+        let dialer = match endpoint {
+            Endpoint::Dialer => true,
+            Endpoint::Listener => false,
+        };
+        self.write(Event::Opened {
+            id,
+            address: address.to_string(),
+            dialer,
+            protocol,
+        });
+    }
+
+    pub fn closed(&self, id: u64) {
+        self.write(Event::Closed { id });
+    }
+
+    fn write(&self, event: Event) {
+        let elapsed = self.start.elapsed();
+        let record = Record {
+            at: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos() / 1_000_000),
+            event,
+        };
+        let mut file = self.file.borrow_mut();
+        let result = match *file {
+            Some(ref mut file) => {
+                let line = serde_json::to_string(&record).expect("records always serialize");
+                writeln!(file, "{}", line)
+            }
+            None => return,
+        };
+        if let Err(err) = result {
+            say!("* Stopped recording: {}", err);
+            *file = None;
+        }
+    }
+}
+
+/// Plays back the recording at `path`, at the pace at which it was recorded. The connections
+/// are opened and closed in `peers`, and the envelopes are produced by the returned stream with
+/// the topics and node they came from, like floodsub would.
+#[cfg(not(target_os = "emscripten"))]
+pub fn playback(
+    path: &str,
+    handle: &Handle,
+    peers: Rc<RefCell<PeerTable>>,
+) -> Result<impl Stream<Item = (Vec<TopicHash>, PeerId, Bytes), Error = IoError>, IoError> {
+    let mut records = Vec::new();
+    for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let record: Record = serde_json::from_str(line).map_err(|err| {
+            IoError::new(ErrorKind::InvalidData, format!("line {}: {}", n + 1, err))
+        })?;
+        records.push(record);
+    }
+
+    let start = Instant::now();
+    let handle = handle.clone();
+    // The connections get new identifiers in `peers`.
+    let mut ids = HashMap::new();
+    let events = stream::iter_ok(records).and_then(move |record| {
+        let at = start + Duration::from_millis(record.at);
+        let event = record.event;
+        Timeout::new_at(at, &handle)
+            .into_future()
+            .flatten()
+            .map(move |()| event)
+    });
+    Ok(events.filter_map(move |event| match event {
+        Event::Opened {
+            id,
+            ref address,
+            dialer,
+            protocol,
+        } => {
+            let address = address.parse().ok()?;
+            let endpoint = if dialer {
+                Endpoint::Dialer
+            } else {
+                Endpoint::Listener
+            };
+            ids.insert(id, peers.borrow_mut().opened(address, endpoint, protocol));
+            None
+        }
+        Event::Closed { id } => {
+            if let Some(id) = ids.remove(&id) {
+                peers.borrow_mut().closed(id);
+            }
+            None
+        }
+        Event::Message { .. } => event.message(),
+    }))
+}
@@ -0,0 +1,81 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Masking the secrets in what we send, before it leaves the node.
+//!
+//! Terminal output pasted in a hurry often contains an API key or a token. Before a message is
+//! published, the parts matching `BUILT_IN` or a `--redact` pattern are replaced with
+//! `REPLACEMENT`, and we say what was masked. `--no-default-redactions` only keeps the patterns
+//! of `--redact`.
+
+use regex::{Error as RegexError, Regex};
+
+/// What replaces a masked part.
+const REPLACEMENT: &str = "[redacted]";
+
+/// What each of the default patterns masks, and the pattern.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("an AWS access key", r"\b(AKIA|ASIA)[0-9A-Z]{16}\b"),
+    ("a GitHub token", r"\bgh[pousr]_[A-Za-z0-9]{36,}\b"),
+    ("a Slack token", r"\bxox[abeoprs]-[A-Za-z0-9-]{10,}"),
+    (
+        "a private key",
+        r"-----BEGIN [A-Z ]*PRIVATE KEY-----(?s:.*?)(-----END [A-Z ]*PRIVATE KEY-----|\z)",
+    ),
+    ("a bearer token", r"(?i)\bbearer\s+[A-Za-z0-9._~+/-]{20,}=*"),
+    ("an email address", r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b"),
+];
+
+pub struct Redactor {
+    /// What each pattern masks, for the warning, and the pattern.
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String], built_in: bool) -> Result<Redactor, RegexError> {
+        let mut redactor = Redactor {
+            patterns: Vec::new(),
+        };
+        if built_in {
+            for &(name, pattern) in BUILT_IN {
+                let regex = Regex::new(pattern).expect("the built-in patterns are valid");
+                redactor.patterns.push((tr!(name), regex));
+            }
+        }
+        for pattern in patterns {
+            let name = tr!("a match of {}", pattern);
+            redactor.patterns.push((name, Regex::new(pattern)?));
+        }
+        Ok(redactor)
+    }
+
+    /// Masks the secrets of `text`. Returns the masked text, and what was masked, if anything.
+    pub fn redact(&self, text: String) -> (String, Vec<String>) {
+        let mut text = text;
+        let mut masked = Vec::new();
+        for &(ref name, ref regex) in &self.patterns {
+            if regex.is_match(&text) {
+                text = regex.replace_all(&text, REPLACEMENT).into_owned();
+                masked.push(name.clone());
+            }
+        }
+        (text, masked)
+    }
+}
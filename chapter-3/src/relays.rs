@@ -0,0 +1,208 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Choice of the fastest relay among those passed with `--relay`.
+//!
+//! Relays are well-known nodes, such as the one of the instructor, through which the others join
+//! the mesh. One of them is enough, so we only dial the one with the lowest latency. Every
+//! `PROBE_INTERVAL_SECS`, we time a TCP connection to each of them. We keep the current relay
+//! until it stops answering or becomes much slower than another one, and then dial the fastest.
+//!
+//! Relays are probed by IP address; the ones given by host name are never picked. Probing needs
+//! the TCP sockets of tokio, so it is only available outside of the browser.
+
+#[cfg(not(target_os = "emscripten"))]
+use display;
+#[cfg(not(target_os = "emscripten"))]
+use futures::future::{self, Future};
+#[cfg(not(target_os = "emscripten"))]
+use futures::stream::{self, Stream};
+#[cfg(not(target_os = "emscripten"))]
+use futures::sync::mpsc;
+use libp2p::Multiaddr;
+#[cfg(not(target_os = "emscripten"))]
+use ports;
+use std::cell::RefCell;
+#[cfg(not(target_os = "emscripten"))]
+use std::io::Error as IoError;
+#[cfg(not(target_os = "emscripten"))]
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+#[cfg(not(target_os = "emscripten"))]
+use std::time::Instant;
+#[cfg(not(target_os = "emscripten"))]
+use tokio_core::net::TcpStream;
+#[cfg(not(target_os = "emscripten"))]
+use tokio_core::reactor::{Handle, Interval, Timeout};
+#[cfg(not(target_os = "emscripten"))]
+use upgrade::{DialRequest, Protocol};
+
+#[cfg(not(target_os = "emscripten"))]
+const PROBE_INTERVAL_SECS: u64 = 30;
+/// A relay that takes longer than this to accept a connection is considered down.
+#[cfg(not(target_os = "emscripten"))]
+const PROBE_TIMEOUT_SECS: u64 = 5;
+/// We only switch to a faster relay if the current one is this many times slower.
+#[cfg(not(target_os = "emscripten"))]
+const DEGRADED_FACTOR: u32 = 2;
+
+struct Relay {
+    address: Multiaddr,
+    /// Round-trip time of the last probe, `None` if it failed or if there was none yet.
+    rtt: Option<Duration>,
+}
+
+struct Inner {
+    relays: Vec<Relay>,
+    /// Index of the relay we dialed.
+    current: Option<usize>,
+}
+
+/// The relays and the one we use, shared between the probes and the chat.
+#[derive(Clone)]
+pub struct Relays {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Relays {
+    pub fn new(addresses: Vec<Multiaddr>) -> Relays {
+        let relays = addresses
+            .into_iter()
+            .map(|address| Relay { address, rtt: None })
+            .collect();
+        Relays {
+            inner: Rc::new(RefCell::new(Inner {
+                relays,
+                current: None,
+            })),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.borrow().relays.is_empty()
+    }
+
+    /// Describes each relay, for `/stats`.
+    pub fn describe(&self) -> Vec<String> {
+        let inner = self.inner.borrow();
+        inner
+            .relays
+            .iter()
+            .enumerate()
+            .map(|(index, relay)| {
+                let rtt = match relay.rtt {
+                    Some(rtt) => tr!("{} ms", millis(rtt)),
+                    None => tr!("unreachable"),
+                };
+                if inner.current == Some(index) {
+                    tr!("Relay {}: {} (current)", relay.address, rtt)
+                } else {
+                    tr!("Relay {}: {}", relay.address, rtt)
+                }
+            })
+            .collect()
+    }
+
+    /// Records the results of the probes, in the order of the relays, and returns the relay to
+    /// switch to, if any.
+    #[cfg(not(target_os = "emscripten"))]
+    fn update(&self, rtts: Vec<Option<Duration>>) -> Option<Multiaddr> {
+        let mut inner = self.inner.borrow_mut();
+        for (relay, rtt) in inner.relays.iter_mut().zip(rtts) {
+            relay.rtt = rtt;
+        }
+        let (best, best_rtt) = inner
+            .relays
+            .iter()
+            .enumerate()
+            .filter_map(|(index, relay)| relay.rtt.map(|rtt| (index, rtt)))
+            .min_by_key(|&(_, rtt)| rtt)?;
+        let current_rtt = inner.current.and_then(|current| inner.relays[current].rtt);
+        let switch = match current_rtt {
+            Some(rtt) => best_rtt * DEGRADED_FACTOR < rtt,
+            None => true,
+        };
+        if !switch || inner.current == Some(best) {
+            return None;
+        }
+        inner.current = Some(best);
+        Some(inner.relays[best].address.clone())
+    }
+}
+
+/// Probes the relays regularly, starting now, and dials the best one through `dial`.
+#[cfg(not(target_os = "emscripten"))]
+pub fn run(
+    relays: Relays,
+    handle: &Handle,
+    dial: mpsc::UnboundedSender<DialRequest>,
+) -> Result<impl Future<Item = (), Error = IoError>, IoError> {
+    let interval = Interval::new(Duration::from_secs(PROBE_INTERVAL_SECS), handle)?;
+    let handle = handle.clone();
+    Ok(stream::once(Ok(())).chain(interval).for_each(move |()| {
+        let probes: Vec<_> = relays
+            .inner
+            .borrow()
+            .relays
+            .iter()
+            .map(|relay| probe(&relay.address, &handle))
+            .collect();
+        let relays = relays.clone();
+        let dial = dial.clone();
+        future::join_all(probes).map(move |rtts| {
+            if let Some(address) = relays.update(rtts) {
+                display::chatter(&tr!("* Using the relay {}", address));
+                let _ = dial.unbounded_send(DialRequest {
+                    address,
+                    protocol: Protocol::FloodSub,
+                });
+            }
+        })
+    }))
+}
+
+/// Returns the time it takes to open a TCP connection to `address`, or `None` if it fails.
+#[cfg(not(target_os = "emscripten"))]
+fn probe(
+    address: &Multiaddr,
+    handle: &Handle,
+) -> Box<Future<Item = Option<Duration>, Error = IoError>> {
+    let socket = match ports::socket_address(address) {
+        Some((ip, port)) => SocketAddr::new(ip, port),
+        None => return Box::new(future::ok(None)),
+    };
+    let timeout = match Timeout::new(Duration::from_secs(PROBE_TIMEOUT_SECS), handle) {
+        Ok(timeout) => timeout.map(|()| None),
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let start = Instant::now();
+    let connect = TcpStream::connect(&socket, handle).map(move |_| Some(start.elapsed()));
+    Box::new(
+        connect
+            .select(timeout)
+            .map(|(rtt, _)| rtt)
+            .or_else(|_| Ok(None)),
+    )
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000)
+}
@@ -0,0 +1,67 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Displaying the texts of a burst in the order they were written, with `--reorder-delay`.
+//!
+//! Floodsub hands us the messages in the order they reach us, which during a burst isn't always
+//! the order they were written in. With a reorder delay, the texts we receive wait that long
+//! before being displayed, sorted by their timestamp as corrected by the `clocks` module. That
+//! costs as much latency. Timestamps only have a resolution of a second, and texts with the same
+//! one keep the order in which they arrived.
+
+use std::time::{Duration, Instant};
+
+pub struct Reorder<T> {
+    delay: Duration,
+    /// The texts waiting, with their timestamp and when they arrived, by timestamp.
+    pending: Vec<(u64, Instant, T)>,
+}
+
+impl<T> Reorder<T> {
+    pub fn new(delay: Duration) -> Reorder<T> {
+        Reorder {
+            delay,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, timestamp: u64, item: T) {
+        // After the others with the same timestamp.
+        let position = self
+            .pending
+            .iter()
+            .position(|&(other, _, _)| other > timestamp)
+            .unwrap_or(self.pending.len());
+        self.pending.insert(position, (timestamp, Instant::now(), item));
+    }
+
+    /// Takes the texts that waited for the delay, along with those that come before them, in the
+    /// order in which to display them.
+    pub fn take_ready(&mut self, now: Instant) -> Vec<T> {
+        let delay = self.delay;
+        let ready = self
+            .pending
+            .iter()
+            .rposition(|&(_, arrived, _)| now.duration_since(arrived) >= delay)
+            .map(|last| last + 1)
+            .unwrap_or(0);
+        self.pending.drain(..ready).map(|(_, _, item)| item).collect()
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Replay of a transcript written by `/export`, for `--replay`.
+//!
+//! The messages go through the same rendering as the live ones, Markdown, links, emoji and
+//! mentions included, which makes it possible to try the rendering offline. They are printed at
+//! the pace at which they were sent, sped up by `--replay-speed`, or all at once with a speed of
+//! 0. Long silences are shortened to `MAX_GAP`, so that a demo doesn't stall. Only the Markdown
+//! transcripts can be replayed.
+
+use display;
+use emoji;
+use export;
+use links;
+use markdown;
+use mentions;
+use options::Options;
+use std::fs;
+use std::io::Error as IoError;
+use std::thread;
+use std::time::Duration;
+
+/// Longest wait between two messages, in milliseconds, whatever the speed.
+const MAX_GAP: u64 = 5000;
+
+/// A message of the transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub name: String,
+    pub timestamp: Option<u64>,
+    pub edited: bool,
+    /// The snippet of the message answered, if any.
+    pub reply_to: Option<String>,
+    /// `None` if the message was deleted.
+    pub text: Option<String>,
+    pub reactions: Option<String>,
+}
+
+/// Parses a transcript. Each message is made of paragraphs: the author and the time, then maybe
+/// the quote of the message answered, then the text, then maybe the reactions.
+pub fn parse(transcript: &str) -> Vec<Message> {
+    let mut messages = Vec::new();
+    let mut paragraphs = transcript
+        .split("\n\n")
+        .map(|paragraph| paragraph.trim_matches('\n'))
+        .filter(|paragraph| !paragraph.is_empty())
+        .peekable();
+    while let Some(paragraph) = paragraphs.next() {
+        // Skips the title, and whatever we don't understand.
+        let mut message = match parse_header(paragraph) {
+            Some(message) => message,
+            None => continue,
+        };
+        if paragraphs.peek().map_or(false, |p| p.starts_with("> ")) {
+            message.reply_to = paragraphs.next().map(|p| p[2..].to_owned());
+        }
+        if paragraphs.peek().map_or(false, |p| parse_header(p).is_none()) {
+            message.text = paragraphs
+                .next()
+                .filter(|&p| p != "_deleted_")
+                .map(|p| p.replace("  \n", "\n"));
+        }
+        if paragraphs.peek().map_or(false, |p| parse_header(p).is_none()) {
+            message.reactions = paragraphs.next().map(|p| p.to_owned());
+        }
+        messages.push(message);
+    }
+    messages
+}
+
+/// Parses `**name** _time_`, followed by ` (edited)` if the message was edited.
+fn parse_header(paragraph: &str) -> Option<Message> {
+    if !paragraph.starts_with("**") || paragraph.contains('\n') {
+        return None;
+    }
+    let end = paragraph.rfind("** _")?;
+    let name = paragraph[2..end].to_owned();
+    let rest = &paragraph[end + 4..];
+    let edited = rest.ends_with(" (edited)");
+    let time = rest.trim_right_matches(" (edited)").trim_right_matches('_');
+    Some(Message {
+        name,
+        timestamp: export::parse_time(time),
+        edited,
+        reply_to: None,
+        text: None,
+        reactions: None,
+    })
+}
+
+/// Prints the messages of the transcript at `path` as they would have been displayed.
+pub fn run(path: &str, options: &Options) -> Result<(), IoError> {
+    let transcript = fs::read_to_string(path)?;
+    let mut previous = None;
+    for message in parse(&transcript) {
+        if let (Some(previous), Some(timestamp)) = (previous, message.timestamp) {
+            wait(timestamp.saturating_sub(previous), options.replay_speed);
+        }
+        previous = message.timestamp.or(previous);
+        print(&message, options);
+    }
+    Ok(())
+}
+
+/// Waits for `secs` seconds divided by `speed`.
+fn wait(secs: u64, speed: f64) {
+    if speed <= 0.0 {
+        return;
+    }
+    let millis = (secs as f64 * 1000.0 / speed) as u64;
+    thread::sleep(Duration::from_millis(millis.min(MAX_GAP)));
+}
+
+fn print(message: &Message, options: &Options) {
+    let mut line = format!("{}{}: ", display::timestamp(), message.name);
+    if let Some(ref parent) = message.reply_to {
+        line.push_str(&format!("[re {}] ", parent));
+    }
+    let text = match message.text {
+        Some(ref text) if options.emoji_on_display => emoji::expand(text),
+        Some(ref text) => text.clone(),
+        None => "[deleted]".to_owned(),
+    };
+    line.push_str(&markdown::render(&text));
+    if message.edited {
+        line.push_str(" (edited)");
+    }
+    if let Some(ref reactions) = message.reactions {
+        line.push_str("  ");
+        line.push_str(reactions);
+    }
+    let mentioned = match options.nick {
+        Some(ref nick) => mentions::is_mentioned(&text, nick),
+        None => false,
+    };
+    if mentioned {
+        println!("{}", display::highlight(&links::render(&line)));
+    } else {
+        println!("{}", links::render(&line));
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Reports of abusive messages, for the moderators.
+//!
+//! `/report <n> <reason>` records a report about the `n`th message of the history, signed with
+//! our key so that it can be checked later, along with the text as we displayed it. If the room
+//! has moderators, the report is also published in the room as a `Kind::Report`, which only the
+//! nodes of the moderators keep: it is addressed to them, not private.
+//!
+//! `/reports` lists the reports we made and those we received as a moderator. With `--reports
+//! <file>`, they are also appended to that file, one JSON object per line.
+//!
+//! The file isn't sealed with the `vault`, although it quotes the reported messages: it is meant
+//! to be passed on to the other moderators and kept as evidence, and each report is already
+//! signed.
+
+use identity;
+use purge;
+use serde_json;
+use std::fs::{self, OpenOptions};
+use std::io::{Error as IoError, Write};
+
+/// Prefix of what the reporter signs, followed by the report as JSON.
+const REPORT_CONTEXT: &[u8] = b"rustfest-chat report:";
+/// Longest reason we keep, in characters.
+pub const MAX_REASON_LEN: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    /// ID of the message, as in the `history` module.
+    pub message: u64,
+    /// The room, as written by `TopicNaming::label`.
+    pub room: String,
+    /// Base58 `PeerId` of the author of the message.
+    pub author: String,
+    /// The message as the reporter displayed it.
+    pub text: String,
+    pub reason: String,
+    /// When the report was made, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedReport {
+    pub report: Report,
+    /// Public key of the reporter, in base58.
+    pub reporter: String,
+    /// Signature by the reporter of `REPORT_CONTEXT` followed by `report` as JSON.
+    pub signature: Vec<u8>,
+}
+
+impl SignedReport {
+    pub fn sign(report: Report, identity: &identity::Identity) -> SignedReport {
+        let signature = identity.sign(&signed_bytes(&report));
+        SignedReport {
+            report,
+            reporter: identity::encode_key(identity.public_key()),
+            signature,
+        }
+    }
+
+    /// Returns true if the report was signed by the owner of `public_key`, who must also be the
+    /// author of the envelope that carried it.
+    pub fn is_from(&self, public_key: &[u8]) -> bool {
+        self.reporter == identity::encode_key(public_key) && self.verify()
+    }
+
+    /// Returns true if the signature is valid.
+    pub fn verify(&self) -> bool {
+        match identity::decode_key(&self.reporter) {
+            Some(key) => identity::verify(&key, &signed_bytes(&self.report), &self.signature),
+            None => false,
+        }
+    }
+}
+
+fn signed_bytes(report: &Report) -> Vec<u8> {
+    let mut bytes = REPORT_CONTEXT.to_vec();
+    bytes.extend(serde_json::to_vec(report).expect("reports always serialize"));
+    bytes
+}
+
+pub struct Reports {
+    reports: Vec<SignedReport>,
+    path: Option<String>,
+}
+
+impl Reports {
+    /// Loads the reports stored at `path`, if there is a file there.
+    pub fn load(path: Option<String>) -> Reports {
+        let mut reports = Vec::new();
+        if let Some(ref path) = path {
+            if let Ok(content) = fs::read_to_string(path) {
+                for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                    match serde_json::from_str(line) {
+                        Ok(report) => reports.push(report),
+                        Err(err) => say!("* Ignoring a report in {}: {}", path, err),
+                    }
+                }
+            }
+        }
+        Reports { reports, path }
+    }
+
+    pub fn push(&mut self, report: SignedReport) {
+        if let Some(ref path) = self.path {
+            let line = serde_json::to_string(&report).expect("reports always serialize");
+            let written = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+            if let Err(err) = written {
+                say!("* Can't save the report to {}: {}", path, err);
+            }
+        }
+        self.reports.push(report);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SignedReport> {
+        self.reports.iter()
+    }
+
+    /// Forgets the reports about the room that `label` names, as written by
+    /// `TopicNaming::label`, or every report, file included, if `None`.
+    pub fn purge(&mut self, label: Option<&str>) -> Result<(), IoError> {
+        let label = match label {
+            Some(label) => label,
+            None => {
+                self.reports.clear();
+                return purge::remove_file(&self.path);
+            }
+        };
+        self.reports.retain(|signed| signed.report.room != label);
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut content = String::new();
+        for report in &self.reports {
+            content.push_str(&serde_json::to_string(report).expect("reports always serialize"));
+            content.push('\n');
+        }
+        fs::write(path, content)
+    }
+}
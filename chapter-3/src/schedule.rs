@@ -0,0 +1,262 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Messages scheduled for later, with `/schedule <delay> <text>` or its alias `/in`.
+//!
+//! A scheduled message is published in the room where it was scheduled once its time comes. With
+//! `--schedule-file <file>`, the queue is kept across runs, as JSON, and the messages whose time
+//! passed while we weren't running are published as soon as we start again.
+//!
+//! `/remind <nick> <delay> <text>` queues a reminder for another peer instead, which we deliver
+//! as a `Kind::Reminder` addressed to them once it is due and they are around, as far as
+//! `presence` knows. We send it again every `REMINDER_RETRY_SECS` until they acknowledge it, so
+//! a reminder for someone who is away waits, across runs with `--schedule-file`, until they come
+//! back. Reminders go through the directory topic, which every node follows: they are addressed,
+//! not private.
+//!
+//! `/scheduled` lists the messages and reminders, and `/scheduled cancel <n>` takes one out.
+//!
+//! `--schedule-file` holds the texts in plain JSON, not sealed with the `vault`: the queue is
+//! rewritten whenever a message is published or a reminder acknowledged, so we would have to
+//! keep the passphrase in memory for the whole run. Don't schedule what must not be on disk.
+
+use purge;
+use serde_json;
+use std::fs;
+use std::io::Error as IoError;
+use std::mem;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scheduled {
+    /// Number under which `/scheduled` lists it.
+    pub id: u32,
+    /// When to publish it, in seconds since the UNIX epoch.
+    pub at: u64,
+    pub room: String,
+    pub text: String,
+}
+
+/// Seconds after which a reminder that wasn't acknowledged is sent again.
+pub const REMINDER_RETRY_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reminder {
+    /// Number under which `/scheduled` lists it, shared with the messages.
+    pub id: u32,
+    /// When it is due, in seconds since the UNIX epoch.
+    pub at: u64,
+    /// The nickname, short ID or base58 `PeerId` of the recipient, as typed.
+    pub to: String,
+    pub text: String,
+    /// The base58 `PeerId` we last sent it to, and when.
+    #[serde(default)]
+    pub sent: Option<(String, u64)>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    #[serde(default)]
+    queue: Vec<Scheduled>,
+    #[serde(default)]
+    reminders: Vec<Reminder>,
+    #[serde(skip)]
+    path: Option<String>,
+}
+
+impl Schedule {
+    /// Loads the queue stored at `path`, if there is a file there.
+    pub fn load(path: Option<String>) -> Schedule {
+        let mut schedule = match path {
+            Some(ref path) => match fs::read(path) {
+                Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                    say!("* Ignoring the scheduled messages in {}: {}", path, err);
+                    Schedule::default()
+                }),
+                Err(_) => Schedule::default(),
+            },
+            None => Schedule::default(),
+        };
+        schedule.path = path;
+        schedule
+    }
+
+    fn save(&self) {
+        if let (Some(path), Err(err)) = (self.path.as_ref(), self.write()) {
+            say!("* Can't save the scheduled messages to {}: {}", path, err);
+        }
+    }
+
+    fn write(&self) -> Result<(), IoError> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let content = serde_json::to_vec_pretty(self).expect("scheduled messages always serialize");
+        fs::write(path, content)
+    }
+
+    /// Cancels the messages scheduled in `room`, or deletes everything, file included, if `None`.
+    pub fn purge(&mut self, room: Option<&str>) -> Result<(), IoError> {
+        match room {
+            Some(room) => {
+                self.queue.retain(|scheduled| scheduled.room != room);
+                self.write()
+            }
+            None => {
+                self.queue.clear();
+                self.reminders.clear();
+                purge::remove_file(&self.path)
+            }
+        }
+    }
+
+    fn next_id(&self) -> u32 {
+        let messages = self.queue.iter().map(|s| s.id);
+        messages.chain(self.reminders.iter().map(|r| r.id)).max().unwrap_or(0) + 1
+    }
+
+    /// Queues `text` for publication in `room` at `at`. Returns its number.
+    pub fn add(&mut self, at: u64, room: String, text: String) -> u32 {
+        let id = self.next_id();
+        self.queue.push(Scheduled { id, at, room, text });
+        self.save();
+        id
+    }
+
+    /// Queues a reminder for `to`, due at `at`. Returns its number.
+    pub fn remind(&mut self, at: u64, to: String, text: String) -> u32 {
+        let id = self.next_id();
+        self.reminders.push(Reminder {
+            id,
+            at,
+            to,
+            text,
+            sent: None,
+        });
+        self.save();
+        id
+    }
+
+    /// Takes the message or reminder numbered `id` out of the queue. Returns false if there is
+    /// none.
+    pub fn cancel(&mut self, id: u32) -> bool {
+        let before = self.queue.len() + self.reminders.len();
+        self.queue.retain(|s| s.id != id);
+        self.reminders.retain(|r| r.id != id);
+        if self.queue.len() + self.reminders.len() == before {
+            return false;
+        }
+        self.save();
+        true
+    }
+
+    /// The queued messages, the next one first.
+    pub fn queue(&self) -> Vec<&Scheduled> {
+        let mut queue: Vec<_> = self.queue.iter().collect();
+        queue.sort_by_key(|s| (s.at, s.id));
+        queue
+    }
+
+    /// The reminders that wait for their time or for an acknowledgement, the next one first.
+    pub fn reminders(&self) -> Vec<&Reminder> {
+        let mut reminders: Vec<_> = self.reminders.iter().collect();
+        reminders.sort_by_key(|r| (r.at, r.id));
+        reminders
+    }
+
+    /// The reminders to send at `now`: those that are due, and that weren't sent in the last
+    /// `REMINDER_RETRY_SECS`.
+    pub fn reminders_to_send(&self, now: u64) -> Vec<Reminder> {
+        self.reminders
+            .iter()
+            .filter(|r| r.at <= now)
+            .filter(|r| match r.sent {
+                Some((_, at)) => now >= at + REMINDER_RETRY_SECS,
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Records that the reminder numbered `id` was sent to `peer`, in base58, at `now`.
+    pub fn sent(&mut self, id: u32, peer: String, now: u64) {
+        if let Some(reminder) = self.reminders.iter_mut().find(|r| r.id == id) {
+            reminder.sent = Some((peer, now));
+        }
+        self.save();
+    }
+
+    /// Forgets the reminder numbered `id` once `peer`, in base58, acknowledged it. Returns it,
+    /// unless it wasn't sent to that peer.
+    pub fn acknowledged(&mut self, id: u32, peer: &str) -> Option<Reminder> {
+        let position = self.reminders.iter().position(|r| {
+            r.id == id && r.sent.as_ref().map(|&(ref to, _)| to == peer).unwrap_or(false)
+        })?;
+        let reminder = self.reminders.remove(position);
+        self.save();
+        Some(reminder)
+    }
+
+    /// Takes out of the queue the messages due at `now`, the oldest first.
+    pub fn take_due(&mut self, now: u64) -> Vec<Scheduled> {
+        if !self.queue.iter().any(|s| s.at <= now) {
+            return Vec::new();
+        }
+        let queue = mem::replace(&mut self.queue, Vec::new());
+        let (mut due, later): (Vec<_>, Vec<_>) = queue.into_iter().partition(|s| s.at <= now);
+        self.queue = later;
+        self.save();
+        due.sort_by_key(|s| (s.at, s.id));
+        due
+    }
+}
+
+/// Parses a delay such as `30s`, `10m`, `2h` or `1d`.
+pub fn parse_delay(delay: &str) -> Option<Duration> {
+    let unit = delay.chars().last()?;
+    let number: u64 = delay[..delay.len() - unit.len_utf8()].parse().ok()?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.checked_mul(seconds).map(Duration::from_secs)
+}
+
+/// Formats a number of seconds with the units of `parse_delay`, such as `1h 5m`.
+pub fn format_delay(seconds: u64) -> String {
+    let units = [(24 * 60 * 60, 'd'), (60 * 60, 'h'), (60, 'm'), (1, 's')];
+    let mut parts = Vec::new();
+    let mut rest = seconds;
+    for &(length, unit) in &units {
+        if rest >= length {
+            parts.push(format!("{}{}", rest / length, unit));
+            rest %= length;
+        }
+    }
+    if parts.is_empty() {
+        "0s".to_owned()
+    } else {
+        parts.join(" ")
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Scoring of the behavior of the peers.
+//!
+//! Every peer starts at 0. Each valid message it sends raises its score a little, up to
+//! `MAX_SCORE`, while replaying messages, sending messages with an invalid signature and sending
+//! more than `FLOOD_LIMIT` messages in a `FLOOD_WINDOW_SECS` window lower it. Once the score of a
+//! peer drops below `THRESHOLD`, we ignore all its messages. Scores slowly return towards 0 with
+//! every tick, so an ignored peer is given another chance after a while.
+//!
+//! Ideally we would also stop forwarding to a badly behaved peer and close our connections to
+//! it. Floodsub doesn't let us choose to whom messages are forwarded, though, and connections are
+//! only known by their address, not by the `PeerId` of the node at the other end. All we can do
+//! is not to dial the addresses that an ignored peer advertised.
+//!
+//! With `--scores-file <file>`, the peers with a negative score are kept across runs, as JSON,
+//! so that a peer ignored before a restart is still ignored afterwards. The time spent stopped
+//! counts as ticks, so scores return towards 0 all the same. `/forgive` resets a score.
+
+use envelope;
+use identity;
+use libp2p::PeerId;
+use purge;
+use serde_json;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::Error as IoError;
+use std::time::{Duration, Instant};
+
+pub const MAX_SCORE: i32 = 100;
+/// Score under which the messages of a peer are ignored.
+pub const THRESHOLD: i32 = -100;
+/// Number of messages a peer can send in a window before being considered flooding.
+pub const FLOOD_LIMIT: u32 = 100;
+pub const FLOOD_WINDOW_SECS: u64 = 10;
+
+const DUPLICATE_PENALTY: i32 = 10;
+const INVALID_PENALTY: i32 = 50;
+const FLOOD_PENALTY: i32 = 5;
+/// Number of recent message IDs remembered in order to detect replays.
+const MAX_SEEN: usize = 4096;
+/// Number of ticks between two saves of the scores.
+const SAVE_TICKS: u32 = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Score {
+    pub value: i32,
+    pub duplicates: u32,
+    pub invalid: u32,
+    pub flooded: u32,
+    window_start: Instant,
+    in_window: u32,
+}
+
+/// What `--scores-file` contains.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Saved {
+    /// When the scores were saved, in seconds since the UNIX epoch.
+    saved_at: u64,
+    /// The scores, by base58 `PeerId`.
+    peers: HashMap<String, SavedScore>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedScore {
+    value: i32,
+    duplicates: u32,
+    invalid: u32,
+    flooded: u32,
+}
+
+pub struct Scores {
+    peers: HashMap<PeerId, Score>,
+    seen: HashSet<(PeerId, u64)>,
+    /// The entries of `seen`, oldest first.
+    order: VecDeque<(PeerId, u64)>,
+    /// File in which the scores are kept, if any.
+    path: Option<String>,
+    /// Number of ticks since the last save.
+    unsaved_ticks: u32,
+}
+
+impl Scores {
+    /// Loads the scores stored at `path`, if there is a file there. `tick` is the time between
+    /// two calls to `tick`, to make up for the time spent stopped.
+    pub fn load(path: Option<String>, tick: Duration) -> Scores {
+        let saved = match path {
+            Some(ref path) => match fs::read(path) {
+                Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                    say!("* Ignoring the scores in {}: {}", path, err);
+                    Saved::default()
+                }),
+                Err(_) => Saved::default(),
+            },
+            None => Saved::default(),
+        };
+        let ticks = envelope::now().saturating_sub(saved.saved_at) / tick.as_secs().max(1);
+        let mut peers = HashMap::new();
+        for (peer, saved_score) in saved.peers {
+            let peer = match identity::parse_peer_id(&peer) {
+                Some(peer) => peer,
+                None => continue,
+            };
+            let decay = ticks.min(saved_score.value.abs() as u64) as i32;
+            let value = saved_score.value - saved_score.value.signum() * decay;
+            if value == 0 {
+                continue;
+            }
+            let score = Score {
+                value,
+                duplicates: saved_score.duplicates,
+                invalid: saved_score.invalid,
+                flooded: saved_score.flooded,
+                window_start: Instant::now(),
+                in_window: 0,
+            };
+            peers.insert(peer, score);
+        }
+        let scores = Scores {
+            peers,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            path,
+            unsaved_ticks: 0,
+        };
+        if scores.ignored() > 0 {
+            say!(
+                "* Still ignoring {} peers from the last run; see /scores and /forgive",
+                scores.ignored()
+            );
+        }
+        scores
+    }
+
+    /// Writes the negative scores to the file, if there is one.
+    pub fn save(&mut self) {
+        self.unsaved_ticks = 0;
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        let peers = self
+            .peers
+            .iter()
+            .filter(|&(_, score)| score.value < 0)
+            .map(|(peer, score)| {
+                let saved_score = SavedScore {
+                    value: score.value,
+                    duplicates: score.duplicates,
+                    invalid: score.invalid,
+                    flooded: score.flooded,
+                };
+                (peer.to_base58(), saved_score)
+            })
+            .collect();
+        let saved = Saved {
+            saved_at: envelope::now(),
+            peers,
+        };
+        let content = serde_json::to_vec_pretty(&saved).expect("scores always serialize");
+        if let Err(err) = fs::write(path, content) {
+            say!("* Can't save the scores to {}: {}", path, err);
+        }
+    }
+
+    /// Forgets every score, and deletes the file.
+    pub fn purge(&mut self) -> Result<(), IoError> {
+        self.peers.clear();
+        self.seen.clear();
+        self.order.clear();
+        purge::remove_file(&self.path)
+    }
+
+    pub fn is_scored(&self, peer: &PeerId) -> bool {
+        self.peers.contains_key(peer)
+    }
+
+    /// Resets the score of `peer`.
+    pub fn forgive(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        self.save();
+    }
+
+    /// Called for every message with a valid signature. Returns false if the message must be
+    /// dropped, because we already received it or because its sender is ignored.
+    pub fn received(&mut self, peer: &PeerId, id: u64) -> bool {
+        let fresh = self.seen.insert((peer.clone(), id));
+        if fresh {
+            self.order.push_back((peer.clone(), id));
+            if self.order.len() > MAX_SEEN {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.seen.remove(&oldest);
+                }
+            }
+        }
+
+        let score = self.score(peer);
+        if score.window_start.elapsed() >= Duration::from_secs(FLOOD_WINDOW_SECS) {
+            score.window_start = Instant::now();
+            score.in_window = 0;
+        }
+        score.in_window += 1;
+        if !fresh {
+            score.duplicates += 1;
+            score.value -= DUPLICATE_PENALTY;
+        } else if score.in_window > FLOOD_LIMIT {
+            score.flooded += 1;
+            score.value -= FLOOD_PENALTY;
+        } else {
+            score.value = (score.value + 1).min(MAX_SCORE);
+        }
+        fresh && score.value >= THRESHOLD
+    }
+
+    /// Called for every message that `peer` relayed to us with an invalid signature.
+    pub fn invalid(&mut self, peer: &PeerId) {
+        let score = self.score(peer);
+        score.invalid += 1;
+        score.value -= INVALID_PENALTY;
+    }
+
+    /// Number of message IDs remembered in order to detect replays.
+    pub fn remembered(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_ignored(&self, peer: &PeerId) -> bool {
+        self.peers
+            .get(peer)
+            .map(|score| score.value < THRESHOLD)
+            .unwrap_or(false)
+    }
+
+    /// Returns the number of peers that are currently ignored.
+    pub fn ignored(&self) -> usize {
+        self.peers.values().filter(|score| score.value < THRESHOLD).count()
+    }
+
+    /// Brings every score one step closer to 0. Returns the peers that are no longer ignored.
+    pub fn tick(&mut self) -> Vec<PeerId> {
+        let mut forgiven = Vec::new();
+        for (peer, score) in &mut self.peers {
+            if score.value == THRESHOLD - 1 {
+                forgiven.push(peer.clone());
+            }
+            score.value -= score.value.signum();
+        }
+        self.unsaved_ticks += 1;
+        if self.unsaved_ticks >= SAVE_TICKS {
+            self.save();
+        }
+        forgiven
+    }
+
+    /// Returns the scores of the peers, lowest first.
+    pub fn iter(&self) -> Vec<(&PeerId, &Score)> {
+        let mut scores: Vec<_> = self.peers.iter().collect();
+        scores.sort_by_key(|&(_, score)| score.value);
+        scores
+    }
+
+    fn score(&mut self, peer: &PeerId) -> &mut Score {
+        self.peers.entry(peer.clone()).or_insert_with(|| Score {
+            value: 0,
+            duplicates: 0,
+            invalid: 0,
+            flooded: 0,
+            window_start: Instant::now(),
+            in_window: 0,
+        })
+    }
+}
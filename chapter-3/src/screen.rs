@@ -0,0 +1,659 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Sharing a terminal with a single peer, who watches it without being able to type in it.
+//!
+//! `/sharescreen <multiaddr>` starts a shell in a pseudo-terminal and opens a dedicated
+//! connection, negotiated with its own protocol name like tic-tac-toe, since our connections
+//! don't multiplex substreams. Everything the shell prints is shown to us and streamed to the
+//! peer, who writes it to their own terminal. While we share, the lines we type that aren't
+//! commands go to the shell; `/unshare` stops it. Shares are only accepted with
+//! `--accept-screens`.
+//!
+//! The sharer controls what reaches the viewer's terminal, so the viewer filters it first. The
+//! strings of the OSC, DCS, APC, PM and SOS sequences are removed: they can set the title and the
+//! clipboard, and carry the commands specific to a terminal. So are the queries that make the
+//! terminal answer on our input, as if we had typed the answer. The rest goes through, colours
+//! and cursor moves included, so the sharer can still draw anything in the viewer's terminal.
+//!
+//! A busy shell prints faster than a slow link carries, so the stream is flow-controlled: the
+//! viewer acknowledges the bytes once it has written them, and the sharer never has more than
+//! `WINDOW` bytes unacknowledged. Until the viewer catches up, we stop reading from the
+//! pseudo-terminal, which in turn blocks the shell.
+//!
+//! Each frame is prefixed by its length, then starts with a tag byte:
+//!
+//! - 0, `Start`: the width and the height of the terminal as big-endian `u16`s;
+//! - 1, `Data`: bytes printed by the shell;
+//! - 2, `Ack`: the number of bytes the viewer wrote, as a big-endian `u32`.
+
+use bytes::Bytes;
+use futures::future::{self, FutureResult};
+use futures::sync::mpsc;
+use futures::task::{self, Task};
+use futures::{stream, Async, Future, Poll, Sink, Stream};
+use libp2p::core::{ConnectionUpgrade, Endpoint};
+use libp2p::Multiaddr;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Error as IoError, ErrorKind, Write};
+use std::iter;
+use std::process::Child;
+use std::rc::Rc;
+use tokio_io::codec::length_delimited;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Name of the protocol, negotiated with multistream-select.
+pub const PROTOCOL_NAME: &[u8] = b"/rustfest-chat/screen/1.0.0";
+
+/// Bytes sent and not acknowledged yet beyond which the sharer waits.
+const WINDOW: usize = 64 * 1024;
+/// Chunks of output queued between the thread that reads the pseudo-terminal and the event loop.
+const QUEUED_CHUNKS: usize = 4;
+
+/// Length of a CSI sequence, at most, beyond which it is dropped.
+const MAX_CSI: usize = 64;
+
+const START: u8 = 0;
+const DATA: u8 = 1;
+const ACK: u8 = 2;
+
+const BEL: u8 = 0x07;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+const ESC: u8 = 0x1b;
+/// First byte of the UTF-8 encoding of the C1 controls.
+const C1_LEAD: u8 = 0xc2;
+/// Final bytes of the CSI sequences that the terminal answers: the device attributes, the
+/// status reports and the window reports.
+const ANSWERED: &[u8] = b"cnt";
+
+pub enum Message {
+    Start { cols: u16, rows: u16 },
+    Data(Vec<u8>),
+    Ack(u32),
+}
+
+/// An open screen-sharing connection, as produced by `ScreenUpgrade`.
+pub struct ScreenConnection {
+    pub endpoint: Endpoint,
+    pub incoming: Box<Stream<Item = Message, Error = IoError>>,
+    pub outgoing: Box<Sink<SinkItem = Message, SinkError = IoError>>,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ScreenUpgrade;
+
+impl<C> ConnectionUpgrade<C> for ScreenUpgrade
+where
+    C: AsyncRead + AsyncWrite + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    type Output = ScreenConnection;
+    type Future = FutureResult<ScreenConnection, IoError>;
+
+    fn upgrade(self, socket: C, _: (), endpoint: Endpoint, _: &Multiaddr) -> Self::Future {
+        let (sink, stream) = length_delimited::Framed::<_, Vec<u8>>::new(socket).split();
+        let incoming = stream.and_then(|bytes| decode(&bytes));
+        let outgoing = sink.with(|message| -> Result<Vec<u8>, IoError> { Ok(encode(message)) });
+        future::ok(ScreenConnection {
+            endpoint,
+            incoming: Box::new(incoming),
+            outgoing: Box::new(outgoing),
+        })
+    }
+}
+
+fn encode(message: Message) -> Vec<u8> {
+    match message {
+        Message::Start { cols, rows } => vec![
+            START,
+            (cols >> 8) as u8,
+            cols as u8,
+            (rows >> 8) as u8,
+            rows as u8,
+        ],
+        Message::Data(data) => {
+            let mut bytes = Vec::with_capacity(1 + data.len());
+            bytes.push(DATA);
+            bytes.extend_from_slice(&data);
+            bytes
+        }
+        Message::Ack(len) => {
+            let mut bytes = vec![ACK];
+            bytes.extend((0..4).rev().map(|i| (len >> (8 * i)) as u8));
+            bytes
+        }
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<Message, IoError> {
+    let invalid = || IoError::new(ErrorKind::InvalidData, "invalid screen frame");
+    let (&tag, rest) = bytes.split_first().ok_or_else(invalid)?;
+    let number = |rest: &[u8]| rest.iter().fold(0, |n, &byte| (n << 8) | u32::from(byte));
+    match tag {
+        START if rest.len() == 4 => Ok(Message::Start {
+            cols: number(&rest[..2]) as u16,
+            rows: number(&rest[2..]) as u16,
+        }),
+        DATA => Ok(Message::Data(rest.to_vec())),
+        ACK if rest.len() == 4 => Ok(Message::Ack(number(rest))),
+        _ => Err(invalid()),
+    }
+}
+
+/// How many bytes the sharer may still send, and the task that waits for more.
+#[derive(Default)]
+struct Credit {
+    available: Cell<usize>,
+    waiting: RefCell<Option<Task>>,
+}
+
+impl Credit {
+    fn grant(&self, len: usize) {
+        self.available.set(cmp::min(self.available.get() + len, WINDOW));
+        if let Some(task) = self.waiting.borrow_mut().take() {
+            task.notify();
+        }
+    }
+}
+
+/// Stream of the output of the shell, which only yields while the viewer keeps up.
+struct Credited<S> {
+    inner: S,
+    credit: Rc<Credit>,
+}
+
+impl<S: Stream<Item = Vec<u8>>> Stream for Credited<S> {
+    type Item = Message;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Message>, S::Error> {
+        if self.credit.available.get() == 0 {
+            *self.credit.waiting.borrow_mut() = Some(task::current());
+            return Ok(Async::NotReady);
+        }
+        match self.inner.poll()? {
+            Async::Ready(Some(data)) => {
+                let available = self.credit.available.get().saturating_sub(data.len());
+                self.credit.available.set(available);
+                Ok(Async::Ready(Some(Message::Data(data))))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// The terminal we share.
+struct Sharing {
+    peer: Multiaddr,
+    /// The master side of the pseudo-terminal, to type in the shell.
+    input: File,
+    shell: Child,
+}
+
+/// The share in progress, ours or the one we watch.
+pub struct Screens {
+    sharing: Option<Sharing>,
+    /// The peer whose terminal we watch.
+    watching: Option<Multiaddr>,
+    accept: bool,
+}
+
+impl Screens {
+    /// If `accept` is false, the shares that others open are declined.
+    pub fn new(accept: bool) -> Rc<RefCell<Screens>> {
+        Rc::new(RefCell::new(Screens {
+            sharing: None,
+            watching: None,
+            accept,
+        }))
+    }
+
+    /// Returns true if we share our terminal, in which case typed lines go to its shell.
+    pub fn is_sharing(&self) -> bool {
+        self.sharing.is_some()
+    }
+
+    /// Types `line` in the shell of the terminal we share.
+    pub fn type_line(&mut self, line: &str) {
+        if let Some(ref mut sharing) = self.sharing {
+            let mut input = line.as_bytes().to_vec();
+            input.push(b'\n');
+            if let Err(err) = sharing.input.write_all(&input) {
+                say!("* Can't type in the shared terminal: {}", err);
+            }
+        }
+    }
+
+    /// Stops sharing our terminal. Ending the shell closes the pseudo-terminal, which ends the
+    /// stream and thus the connection.
+    pub fn unshare(&mut self) {
+        match self.sharing {
+            Some(ref mut sharing) => {
+                let _ = sharing.shell.kill();
+            }
+            None => say!("* You aren't sharing your terminal"),
+        }
+    }
+
+    /// Called when the connection to `peer` is closed.
+    fn connection_closed(&mut self, peer: &Multiaddr) {
+        if self.sharing.as_ref().map(|sharing| sharing.peer == *peer).unwrap_or(false) {
+            let mut sharing = self.sharing.take().expect("just checked");
+            let _ = sharing.shell.kill();
+            let _ = sharing.shell.wait();
+            restore_terminal();
+            say!("* Stopped sharing your terminal with {}", peer);
+        }
+        if self.watching.as_ref() == Some(peer) {
+            self.watching = None;
+            restore_terminal();
+            say!("* {} stopped sharing their terminal", peer);
+        }
+    }
+}
+
+/// Handles a screen-sharing connection opened by us or by a remote, and returns the future that
+/// drives it. The dialer shares its terminal, and the listener watches it.
+pub fn handle_connection(
+    screens: Rc<RefCell<Screens>>,
+    connection: ScreenConnection,
+    peer: Multiaddr,
+) -> Box<Future<Item = (), Error = IoError>> {
+    match connection.endpoint {
+        Endpoint::Dialer => share(screens, connection, peer),
+        Endpoint::Listener => watch(screens, connection, peer),
+    }
+}
+
+fn share(
+    screens: Rc<RefCell<Screens>>,
+    connection: ScreenConnection,
+    peer: Multiaddr,
+) -> Box<Future<Item = (), Error = IoError>> {
+    if screens.borrow().sharing.is_some() {
+        say!("* You are already sharing your terminal; `/unshare` first");
+        return Box::new(future::ok(()));
+    }
+    let (cols, rows) = pty::size();
+    let (sender, output) = mpsc::channel(QUEUED_CHUNKS);
+    let (input, shell) = match pty::spawn(cols, rows, sender) {
+        Ok(pty) => pty,
+        Err(err) => {
+            say!("* Can't share your terminal: {}", err);
+            return Box::new(future::ok(()));
+        }
+    };
+    screens.borrow_mut().sharing = Some(Sharing {
+        peer: peer.clone(),
+        input,
+        shell,
+    });
+    say!("* Sharing your terminal with {}; `/unshare` to stop", peer);
+
+    let credit = Rc::new(Credit::default());
+    credit.grant(WINDOW);
+    let incoming = {
+        let credit = credit.clone();
+        connection.incoming.for_each(move |message| {
+            if let Message::Ack(len) = message {
+                credit.grant(len as usize);
+            }
+            Ok(())
+        })
+    };
+    // We see what the shell prints too.
+    let output = output.map(|data: Vec<u8>| {
+        print_raw(&data);
+        data
+    });
+    let outgoing = connection
+        .outgoing
+        .send_all(
+            stream::once(Ok(Message::Start { cols, rows })).chain(Credited {
+                inner: output.map_err(|()| -> IoError { unreachable!() }),
+                credit,
+            }),
+        )
+        .map(|_| ());
+    finish(screens, incoming, outgoing, peer)
+}
+
+fn watch(
+    screens: Rc<RefCell<Screens>>,
+    connection: ScreenConnection,
+    peer: Multiaddr,
+) -> Box<Future<Item = (), Error = IoError>> {
+    // Dropping the connection tells the remote that we declined.
+    {
+        let screens = screens.borrow();
+        if !screens.accept {
+            say!("* Declined the terminal of {}; pass --accept-screens to watch them", peer);
+            return Box::new(future::ok(()));
+        }
+        if screens.watching.is_some() {
+            say!("* Declined the terminal of {}: you are already watching one", peer);
+            return Box::new(future::ok(()));
+        }
+    }
+    screens.borrow_mut().watching = Some(peer.clone());
+
+    let (acks, acknowledged) = mpsc::unbounded();
+    let incoming = {
+        let peer = peer.clone();
+        let mut filter = Filter::new();
+        connection.incoming.for_each(move |message| {
+            match message {
+                Message::Start { cols, rows } => {
+                    say!("* Watching the terminal of {} ({}x{}), read-only", peer, cols, rows);
+                }
+                Message::Data(data) => {
+                    print_raw(&filter.filter(&data));
+                    let _ = acks.unbounded_send(Message::Ack(data.len() as u32));
+                }
+                Message::Ack(_) => {}
+            }
+            Ok(())
+        })
+    };
+    let outgoing = connection
+        .outgoing
+        .send_all(acknowledged.map_err(|()| -> IoError { unreachable!() }))
+        .map(|_| ());
+    finish(screens, incoming, outgoing, peer)
+}
+
+/// Drives both directions of the connection until either ends.
+fn finish<I, O>(
+    screens: Rc<RefCell<Screens>>,
+    incoming: I,
+    outgoing: O,
+    peer: Multiaddr,
+) -> Box<Future<Item = (), Error = IoError>>
+where
+    I: Future<Item = (), Error = IoError> + 'static,
+    O: Future<Item = (), Error = IoError> + 'static,
+{
+    Box::new(
+        incoming
+            .select(outgoing)
+            .map(|_| ())
+            .map_err(|(err, _)| err)
+            .then(move |result| {
+                screens.borrow_mut().connection_closed(&peer);
+                result
+            }),
+    )
+}
+
+/// Where the `Filter` is in the bytes of the shared terminal.
+#[derive(Copy, Clone)]
+enum State {
+    Text,
+    /// After an ESC.
+    Escape,
+    /// In a CSI sequence, kept in `Filter::csi` until its final byte.
+    Csi,
+    /// After the first byte of a C1 control.
+    Lead,
+    /// In one of the strings that we remove, until its terminator.
+    String,
+    StringEscape,
+    StringLead,
+}
+
+/// Removes the sequences described in the module documentation from the bytes of a shared
+/// terminal. A sequence can span several frames, so the filter is kept for the whole share.
+struct Filter {
+    state: State,
+    csi: Vec<u8>,
+}
+
+impl Filter {
+    fn new() -> Filter {
+        Filter {
+            state: State::Text,
+            csi: Vec::new(),
+        }
+    }
+
+    fn filter(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut filtered = Vec::with_capacity(data.len());
+        for &byte in data {
+            self.push(byte, &mut filtered);
+        }
+        filtered
+    }
+
+    fn push(&mut self, byte: u8, out: &mut Vec<u8>) {
+        match (self.state, byte) {
+            (State::Text, ESC) => self.state = State::Escape,
+            (State::Text, C1_LEAD) => self.state = State::Lead,
+            (State::Text, _) => out.push(byte),
+            (State::Escape, b'[') => self.start_csi(&[ESC, b'[']),
+            (State::Escape, b']')
+            | (State::Escape, b'P')
+            | (State::Escape, b'_')
+            | (State::Escape, b'^')
+            | (State::Escape, b'X') => self.state = State::String,
+            (State::Escape, _) => {
+                out.push(ESC);
+                self.state = State::Text;
+                self.push(byte, out);
+            }
+            (State::Csi, _) if byte >= 0x20 && byte <= 0x3f && self.csi.len() < MAX_CSI => {
+                self.csi.push(byte)
+            }
+            (State::Csi, _) if byte >= 0x40 && byte <= 0x7e => {
+                if !ANSWERED.contains(&byte) {
+                    out.extend_from_slice(&self.csi);
+                    out.push(byte);
+                }
+                self.state = State::Text;
+            }
+            // A sequence too long or interrupted is dropped.
+            (State::Csi, _) => {
+                self.state = State::Text;
+                self.push(byte, out);
+            }
+            (State::Lead, 0x9b) => self.start_csi(&[C1_LEAD, 0x9b]),
+            (State::Lead, 0x90)
+            | (State::Lead, 0x98)
+            | (State::Lead, 0x9d)
+            | (State::Lead, 0x9e)
+            | (State::Lead, 0x9f) => self.state = State::String,
+            (State::Lead, _) => {
+                out.push(C1_LEAD);
+                self.state = State::Text;
+                self.push(byte, out);
+            }
+            (State::String, BEL) | (State::String, CAN) | (State::String, SUB) => {
+                self.state = State::Text
+            }
+            (State::String, ESC) => self.state = State::StringEscape,
+            (State::String, C1_LEAD) => self.state = State::StringLead,
+            (State::String, _) => {}
+            (State::StringEscape, b'\\') => self.state = State::Text,
+            // Any other escape sequence ends the string too.
+            (State::StringEscape, _) => {
+                self.state = State::Escape;
+                self.push(byte, out);
+            }
+            (State::StringLead, 0x9c) => self.state = State::Text,
+            (State::StringLead, _) => {
+                self.state = State::String;
+                self.push(byte, out);
+            }
+        }
+    }
+
+    fn start_csi(&mut self, introducer: &[u8]) {
+        self.csi.clear();
+        self.csi.extend_from_slice(introducer);
+        self.state = State::Csi;
+    }
+}
+
+/// Writes what a shell printed to our terminal, escape sequences included.
+fn print_raw(data: &[u8]) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = stdout.write_all(data);
+    let _ = stdout.flush();
+}
+
+/// Resets the colours and attributes that a shared shell may have left behind.
+fn restore_terminal() {
+    print_raw(b"\x1b[0m\r\n");
+}
+
+/// Running a shell in a pseudo-terminal.
+#[cfg(all(unix, not(target_os = "emscripten")))]
+mod pty {
+    use futures::sync::mpsc;
+    use futures::{Future, Sink};
+    use libc;
+    use std::env;
+    use std::fs::File;
+    use std::io::{Error as IoError, Read};
+    use std::os::unix::io::FromRawFd;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command, Stdio};
+    use std::ptr;
+    use std::thread;
+
+    /// Returns the width and the height of our terminal.
+    pub fn size() -> (u16, u16) {
+        let mut size: libc::winsize = unsafe { ::std::mem::zeroed() };
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+        if ok && size.ws_col > 0 && size.ws_row > 0 {
+            (size.ws_col, size.ws_row)
+        } else {
+            (80, 24)
+        }
+    }
+
+    /// Starts `$SHELL` in a pseudo-terminal of the given size. A thread sends what it prints to
+    /// `output`, and waits whenever `output` is full. Returns the side of the pseudo-terminal to
+    /// type in, and the shell.
+    pub fn spawn(
+        cols: u16,
+        rows: u16,
+        output: mpsc::Sender<Vec<u8>>,
+    ) -> Result<(File, Child), IoError> {
+        let (mut master, mut slave) = (0, 0);
+        let mut size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let opened = unsafe {
+            libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null_mut(), &mut size)
+        };
+        if opened != 0 {
+            return Err(IoError::last_os_error());
+        }
+        // The shell must not inherit our side of the pseudo-terminal.
+        unsafe { libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC) };
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+        let program = env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_owned());
+        let mut command = Command::new(program);
+        command
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // The pseudo-terminal becomes the controlling terminal of a new session, so that the
+        // shell gets job control and Ctrl-C.
+        command.before_exec(|| unsafe {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(IoError::last_os_error());
+            }
+            Ok(())
+        });
+        let shell = command.spawn()?;
+
+        let mut reader = master.try_clone()?;
+        thread::spawn(move || {
+            let mut output = output;
+            let mut buffer = [0; 4096];
+            // Reading fails once the shell exits.
+            while let Ok(len) = reader.read(&mut buffer) {
+                if len == 0 {
+                    break;
+                }
+                output = match output.send(buffer[..len].to_vec()).wait() {
+                    Ok(output) => output,
+                    Err(_) => break,
+                };
+            }
+        });
+        Ok((master, shell))
+    }
+}
+
+#[cfg(not(all(unix, not(target_os = "emscripten"))))]
+mod pty {
+    use futures::sync::mpsc;
+    use std::fs::File;
+    use std::io::{Error as IoError, ErrorKind};
+    use std::process::Child;
+
+    pub fn size() -> (u16, u16) {
+        (80, 24)
+    }
+
+    pub fn spawn(_: u16, _: u16, _: mpsc::Sender<Vec<u8>>) -> Result<(File, Child), IoError> {
+        Err(IoError::new(
+            ErrorKind::Other,
+            "pseudo-terminals are only available on Unix",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_strings_and_the_queries_are_removed() {
+        let mut filter = Filter::new();
+        let shell = b"\x1b]0;title\x07a\x1b[31mb\x1bP+q544e\x1b\\c\x1b[6nd";
+        assert_eq!(filter.filter(shell), b"a\x1b[31mbcd".to_vec());
+    }
+
+    #[test]
+    fn a_string_can_span_frames() {
+        let mut filter = Filter::new();
+        assert_eq!(filter.filter(b"a\x1b]52;c;"), b"a".to_vec());
+        assert_eq!(filter.filter(b"cm9vdA==\x1b"), b"".to_vec());
+        assert_eq!(filter.filter("\\é".as_bytes()), "é".as_bytes().to_vec());
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Dropping the messages of banned peers before floodsub relays them.
+//!
+//! Floodsub forwards every message it reads to our other peers before handing it to us, so by
+//! the time the chat sees a message from a banned peer, we have already relayed it. The floodsub
+//! connections therefore read through `Screened`, which parses the floodsub frames and takes out
+//! the published messages whose envelope is signed by a peer banned from one of their topics.
+//! Floodsub never sees them, so it neither relays nor delivers them.
+//!
+//! The chat keeps the `Bans` up to date with the moderation entries, see the `moderation`
+//! module. The signatures of the envelopes aren't checked here: forging the envelope of a banned
+//! peer only gets the forged message dropped. Frames that we can't parse are passed on as they
+//! are, and floodsub deals with them.
+
+use envelope;
+use libp2p::floodsub::TopicHash;
+use libp2p::PeerId;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{Error as IoError, ErrorKind, Read, Write};
+use std::mem;
+use std::rc::Rc;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Size of the frames above which we close the connection, as floodsub would.
+const MAX_FRAME: usize = 4 * 1024 * 1024;
+
+/// Number of bytes read from the connection at once.
+const CHUNK: usize = 4096;
+
+/// The peers banned from each topic, shared by the chat and the connections.
+#[derive(Clone, Default)]
+pub struct Bans(Rc<RefCell<HashSet<(TopicHash, PeerId)>>>);
+
+impl Bans {
+    pub fn new() -> Bans {
+        Bans::default()
+    }
+
+    /// Replaces the banned peers.
+    pub fn set(&self, banned: HashSet<(TopicHash, PeerId)>) {
+        *self.0.borrow_mut() = banned;
+    }
+
+    fn contains(&self, topic: TopicHash, peer: PeerId) -> bool {
+        self.0.borrow().contains(&(topic, peer))
+    }
+}
+
+/// A floodsub connection whose incoming frames are screened against the `Bans`.
+pub struct Screened<C> {
+    inner: C,
+    bans: Bans,
+    /// What we read from `inner` that doesn't make a whole frame yet.
+    pending: Vec<u8>,
+    /// The screened frames that weren't read yet.
+    ready: Vec<u8>,
+}
+
+impl<C> Screened<C> {
+    pub fn new(inner: C, bans: Bans) -> Screened<C> {
+        Screened {
+            inner,
+            bans,
+            pending: Vec::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    /// Returns the length of the frame at the start of `pending`, prefix included, once all of
+    /// it is there.
+    fn frame_len(&self) -> Result<Option<usize>, IoError> {
+        let (len, prefix) = match varint(&self.pending) {
+            Some(varint) => varint,
+            None if self.pending.len() < 9 => return Ok(None),
+            None => return Err(IoError::new(ErrorKind::InvalidData, "invalid frame length")),
+        };
+        if len > MAX_FRAME {
+            return Err(IoError::new(ErrorKind::InvalidData, "floodsub frame too large"));
+        }
+        Ok(if self.pending.len() >= prefix + len {
+            Some(prefix + len)
+        } else {
+            None
+        })
+    }
+
+    /// Appends `frame` to `ready`, without the messages of banned peers. A frame left with
+    /// nothing is dropped altogether.
+    fn screen(&mut self, frame: &[u8]) {
+        let rpc = match varint(frame) {
+            Some((_, prefix)) => &frame[prefix..],
+            None => return self.ready.extend_from_slice(frame),
+        };
+        match screen_rpc(rpc, &self.bans) {
+            Some(ref kept) if kept.len() < rpc.len() => {
+                if !kept.is_empty() {
+                    write_varint(&mut self.ready, kept.len());
+                    self.ready.extend_from_slice(kept);
+                }
+            }
+            _ => self.ready.extend_from_slice(frame),
+        }
+    }
+}
+
+impl<C: Read> Read for Screened<C> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        loop {
+            if !self.ready.is_empty() {
+                let len = buf.len().min(self.ready.len());
+                buf[..len].copy_from_slice(&self.ready[..len]);
+                self.ready.drain(..len);
+                return Ok(len);
+            }
+            if let Some(len) = self.frame_len()? {
+                let frame: Vec<u8> = self.pending.drain(..len).collect();
+                self.screen(&frame);
+                continue;
+            }
+            let mut chunk = [0; CHUNK];
+            let read = self.inner.read(&mut chunk)?;
+            if read == 0 {
+                // Floodsub sees the last frame cut short, as it was.
+                self.ready = mem::replace(&mut self.pending, Vec::new());
+                if self.ready.is_empty() {
+                    return Ok(0);
+                }
+                continue;
+            }
+            self.pending.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+impl<C: Write> Write for Screened<C> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.inner.flush()
+    }
+}
+
+impl<C: AsyncRead> AsyncRead for Screened<C> {}
+
+impl<C: AsyncWrite> AsyncWrite for Screened<C> {
+    fn shutdown(&mut self) -> ::futures::Poll<(), IoError> {
+        self.inner.shutdown()
+    }
+}
+
+/// Returns the protobuf `RPC` message `rpc` without its published messages from banned peers,
+/// or `None` if it isn't one.
+fn screen_rpc(rpc: &[u8], bans: &Bans) -> Option<Vec<u8>> {
+    let mut kept = Vec::with_capacity(rpc.len());
+    for field in fields(rpc)? {
+        let banned = match field.value {
+            Some(message) if field.number == 2 => is_banned(message, bans)?,
+            _ => false,
+        };
+        if !banned {
+            kept.extend_from_slice(field.bytes);
+        }
+    }
+    Some(kept)
+}
+
+/// Returns true if the protobuf `Message` `message` is an envelope of a peer banned from one of
+/// its topics, or `None` if it isn't a `Message`.
+fn is_banned(message: &[u8], bans: &Bans) -> Option<bool> {
+    let mut data = None;
+    let mut topics = Vec::new();
+    for field in fields(message)? {
+        match (field.number, field.value) {
+            (2, Some(bytes)) => data = Some(bytes),
+            (4, Some(bytes)) => topics.push(String::from_utf8_lossy(bytes).into_owned()),
+            _ => {}
+        }
+    }
+    let author = match data.and_then(envelope::decode) {
+        Some(envelope) => PeerId::from_public_key(&envelope.public_key),
+        None => return Some(false),
+    };
+    Some(
+        topics
+            .into_iter()
+            .any(|topic| bans.contains(TopicHash::from_raw(topic), author.clone())),
+    )
+}
+
+/// A field of a protobuf message.
+struct Field<'a> {
+    number: usize,
+    /// The whole field, key included.
+    bytes: &'a [u8],
+    /// The content of a length-delimited field.
+    value: Option<&'a [u8]>,
+}
+
+/// Splits a protobuf message into its fields, or returns `None` if it isn't one. Floodsub only
+/// uses varints and length-delimited fields.
+fn fields(data: &[u8]) -> Option<Vec<Field>> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let (key, key_len) = varint(&data[start..])?;
+        let value_start = start + key_len;
+        let (end, value) = match key & 7 {
+            0 => (value_start + varint(&data[value_start..])?.1, None),
+            2 => {
+                let (size, len) = varint(&data[value_start..])?;
+                let content = value_start + len;
+                if data.len() < content + size {
+                    return None;
+                }
+                (content + size, Some(&data[content..content + size]))
+            }
+            _ => return None,
+        };
+        fields.push(Field {
+            number: key >> 3,
+            bytes: &data[start..end],
+            value,
+        });
+        start = end;
+    }
+    Some(fields)
+}
+
+/// Decodes an unsigned varint, returning its value and the number of bytes it took.
+fn varint(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, &byte) in data.iter().enumerate().take(9) {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envelope::{Body, Kind};
+    use identity::Identity;
+    use std::io::Cursor;
+
+    fn field(out: &mut Vec<u8>, number: usize, bytes: &[u8]) {
+        write_varint(out, number << 3 | 2);
+        write_varint(out, bytes.len());
+        out.extend_from_slice(bytes);
+    }
+
+    /// A floodsub frame that publishes a text of `author` on `topic`.
+    fn frame(topic: &str, author: &Identity) -> Vec<u8> {
+        let body = Body::new(None, Kind::Text("hello".to_owned()));
+        let mut message = Vec::new();
+        field(&mut message, 2, &envelope::seal(author, &body));
+        field(&mut message, 4, topic.as_bytes());
+        let mut rpc = Vec::new();
+        field(&mut rpc, 2, &message);
+        let mut frame = Vec::new();
+        write_varint(&mut frame, rpc.len());
+        frame.extend(rpc);
+        frame
+    }
+
+    #[test]
+    fn banned_peers_are_dropped_from_the_topics_they_are_banned_from() {
+        let (banned, other) = (Identity::generate(), Identity::generate());
+        let dropped = frame("general", &banned);
+        let kept = frame("general", &other);
+        let elsewhere = frame("rust", &banned);
+        let input = [&dropped[..], &kept, &elsewhere].concat();
+
+        let bans = Bans::new();
+        let topic = TopicHash::from_raw("general".to_owned());
+        bans.set(Some((topic, banned.peer_id().clone())).into_iter().collect());
+        let mut output = Vec::new();
+        Screened::new(Cursor::new(input), bans)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, [&kept[..], &elsewhere].concat());
+    }
+
+    #[test]
+    fn frames_that_are_not_floodsub_pass_as_they_are() {
+        let input = vec![3, 0xff, 0xff, 0xff];
+        let mut output = Vec::new();
+        Screened::new(Cursor::new(input.clone()), Bans::new())
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Experimental transport over a serial link, such as a USB cable or Bluetooth RFCOMM.
+//!
+//! Nothing in the protocols above the transport needs IP: floodsub and our own protocols only
+//! want a stream of bytes in each direction. `SerialConfig` provides one over a character
+//! device, which lets two laptops chat without any network.
+//!
+//! A device is written as `/unix/<name>`, for `/dev/<name>`. There is no connection to accept
+//! on a serial link, so one side "listens" on its device with `--serial <name>`, which produces
+//! a single incoming connection, and the other side dials `/unix/<name>` with the name of its
+//! own device. The link must be in raw mode beforehand, for example with
+//! `stty -F /dev/ttyUSB0 raw -echo`, otherwise the terminal line discipline mangles the bytes.
+//!
+//! Only available when the crate is compiled with the `serial-transport` feature.
+
+use futures::future::{self, FutureResult};
+use futures::stream::{self, Stream};
+use libp2p::core::Transport;
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::fs::{File, OpenOptions};
+use std::io::{Error as IoError, ErrorKind};
+use tokio_core::reactor::{Handle, PollEvented};
+use tokio_file_unix;
+
+pub type Link = PollEvented<tokio_file_unix::File<File>>;
+
+#[derive(Clone)]
+pub struct SerialConfig {
+    handle: Handle,
+}
+
+impl SerialConfig {
+    pub fn new(handle: Handle) -> SerialConfig {
+        SerialConfig { handle }
+    }
+
+    fn open(&self, addr: &Multiaddr) -> Result<Link, IoError> {
+        let name = device(addr).expect("the address was checked by the caller");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("/dev/{}", name))?;
+        tokio_file_unix::File::new_nb(file)?.into_io(&self.handle)
+    }
+}
+
+impl Transport for SerialConfig {
+    type Output = Link;
+    type Listener = Box<Stream<Item = Self::ListenerUpgrade, Error = IoError>>;
+    type ListenerUpgrade = FutureResult<(Link, Multiaddr), IoError>;
+    type Dial = FutureResult<(Link, Multiaddr), IoError>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        if device(&addr).is_none() {
+            return Err((self, addr));
+        }
+        let link = self.open(&addr).map(|link| (link, addr.clone()));
+        let listener = stream::once(Ok(future::result(link)));
+        Ok((Box::new(listener), addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        if device(&addr).is_none() {
+            return Err((self, addr));
+        }
+        let link = self.open(&addr).map(|link| (link, addr));
+        Ok(future::result(link))
+    }
+
+    fn nat_traversal(&self, _: &Multiaddr, _: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+/// Returns the name of the device in `/dev` designated by `addr`, if it designates one.
+fn device(addr: &Multiaddr) -> Option<String> {
+    let mut components = addr.iter();
+    match (components.next(), components.next()) {
+        (Some(AddrComponent::UNIX(ref name)), None) if !name.contains('/') && name != ".." => {
+            Some(name.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Returns the address of the device named `name`, as expected by `SerialConfig`.
+pub fn address(name: &str) -> Result<Multiaddr, IoError> {
+    format!("/unix/{}", name)
+        .parse()
+        .map_err(|_| IoError::new(ErrorKind::InvalidInput, "invalid device name"))
+}
@@ -0,0 +1,182 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The places besides the terminal where what we display can go. See `display::OutputSink`.
+//!
+//! With `--output-jsonl <file>`, every line is appended to the file as a JSON object with the
+//! time, in seconds since the epoch, the kind of line, as in `display::Output`, and the text
+//! without its colors:
+//!
+//! ```json
+//! {"time":1527250000,"kind":"message","line":"alice: hello"}
+//! ```
+//!
+//! With `--control-socket <path>`, on Unix, the same objects are written to every program
+//! connected to that Unix socket, for example with `socat - UNIX-CONNECT:<path>`. A program that
+//! doesn't keep up is disconnected rather than slowing the chat down.
+//!
+//! In the browser, the lines go to the page instead of the terminal.
+
+#[cfg(not(target_os = "emscripten"))]
+use display::{Output, OutputSink};
+#[cfg(not(target_os = "emscripten"))]
+use envelope;
+#[cfg(not(target_os = "emscripten"))]
+use serde_json;
+#[cfg(not(target_os = "emscripten"))]
+use std::io::Write;
+#[cfg(all(unix, not(target_os = "emscripten")))]
+use std::io::Error as IoError;
+#[cfg(all(unix, not(target_os = "emscripten")))]
+use std::os::unix::net::UnixStream;
+#[cfg(all(unix, not(target_os = "emscripten")))]
+use std::sync::{Arc, Mutex};
+
+/// One line, as the JSON sinks write it.
+#[cfg(not(target_os = "emscripten"))]
+#[derive(Serialize)]
+struct Record<'a> {
+    time: u64,
+    kind: &'static str,
+    line: &'a str,
+}
+
+/// Returns the JSON line for `line`, terminated by a line break.
+#[cfg(not(target_os = "emscripten"))]
+fn encode(output: Output, line: &str) -> Vec<u8> {
+    let line = strip_escapes(line);
+    let record = Record {
+        time: envelope::now(),
+        kind: output.name(),
+        line: &line,
+    };
+    let mut encoded = serde_json::to_vec(&record).expect("a record is always serializable");
+    encoded.push(b'\n');
+    encoded
+}
+
+/// Removes the ANSI escape codes that color `line`.
+#[cfg(not(target_os = "emscripten"))]
+fn strip_escapes(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        // A sequence such as `\x1b[1;33m` ends with its first letter.
+        for c in chars.by_ref() {
+            if c.is_ascii_alphabetic() {
+                break;
+            }
+        }
+    }
+    stripped
+}
+
+/// Appends the lines to a file, one JSON object per line.
+#[cfg(not(target_os = "emscripten"))]
+pub struct JsonLines<W> {
+    writer: W,
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl<W: Write> JsonLines<W> {
+    pub fn new(writer: W) -> JsonLines<W> {
+        JsonLines { writer }
+    }
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl<W: Write> OutputSink for JsonLines<W> {
+    fn write(&mut self, output: Output, line: &str) {
+        // Reporting the error would display a line, and fail again.
+        let _ = self.writer.write_all(&encode(output, line));
+    }
+}
+
+/// Writes the lines to the programs connected to a Unix socket.
+#[cfg(all(unix, not(target_os = "emscripten")))]
+pub struct ControlSocket {
+    subscribers: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+#[cfg(all(unix, not(target_os = "emscripten")))]
+impl ControlSocket {
+    /// Listens on `path`, replacing the socket that an earlier run left there. The connections
+    /// are accepted by a thread of their own.
+    pub fn bind(path: &str) -> Result<ControlSocket, IoError> {
+        use std::fs;
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+        use std::time::Duration;
+
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let accepted = subscribers.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                if stream.set_write_timeout(Some(Duration::from_millis(100))).is_ok() {
+                    accepted.lock().unwrap().push(stream);
+                }
+            }
+        });
+        Ok(ControlSocket { subscribers })
+    }
+}
+
+#[cfg(all(unix, not(target_os = "emscripten")))]
+impl OutputSink for ControlSocket {
+    fn write(&mut self, output: Output, line: &str) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let encoded = encode(output, line);
+        subscribers.retain(|mut subscriber| subscriber.write_all(&encoded).is_ok());
+    }
+}
+
+/// Appends the lines to the `stdout` text area of `browser.html`.
+#[cfg(target_os = "emscripten")]
+pub struct Page;
+
+#[cfg(target_os = "emscripten")]
+impl ::display::OutputSink for Page {
+    fn write(&mut self, _: ::display::Output, line: &str) {
+        let line = line.to_owned();
+        js! {
+            var stdout = document.getElementById("stdout");
+            stdout.value += @{line} + "\n";
+            stdout.scrollTop = stdout.scrollHeight;
+        };
+    }
+}
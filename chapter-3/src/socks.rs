@@ -0,0 +1,291 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Dialing through a SOCKS5 proxy, such as the one that Tor provides.
+//!
+//! `Socks5` wraps the TCP transport: listening is left untouched, but every dial first connects
+//! to the proxy and asks it to connect to the target. Host names are resolved by the proxy, so
+//! that dialing `/dns4/...` doesn't leak DNS requests outside of Tor. Since websockets are built
+//! on top of the TCP transport, they go through the proxy as well.
+
+use futures::{future, Future, IntoFuture};
+use libp2p::core::Transport;
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::io::{Error as IoError, ErrorKind};
+use tokio_io::io::{read_exact, write_all};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+#[derive(Debug, Clone)]
+pub struct Socks5<T> {
+    inner: T,
+    /// Address of the proxy, or `None` to dial directly.
+    proxy: Option<Multiaddr>,
+}
+
+impl<T> Socks5<T> {
+    pub fn new(inner: T, proxy: Option<Multiaddr>) -> Socks5<T> {
+        Socks5 { inner, proxy }
+    }
+}
+
+impl<T> Transport for Socks5<T>
+where
+    T: Transport + 'static,
+    T::Output: AsyncRead + AsyncWrite + 'static,
+    T::Dial: 'static,
+{
+    type Output = T::Output;
+    type Listener = T::Listener;
+    type ListenerUpgrade = T::ListenerUpgrade;
+    type Dial = Box<Future<Item = (T::Output, Multiaddr), Error = IoError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let proxy = self.proxy;
+        self.inner
+            .listen_on(addr)
+            .map_err(|(inner, addr)| (Socks5 { inner, proxy }, addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let (proxy, request) = match (self.proxy.clone(), connect_request(&addr)) {
+            (Some(proxy), Some(request)) => (proxy, request),
+            // Without a proxy, or for addresses we can't give to one, we let `inner` decide.
+            (proxy, _) => {
+                return match self.inner.dial(addr) {
+                    Ok(dial) => Ok(Box::new(dial.into_future())),
+                    Err((inner, addr)) => Err((Socks5 { inner, proxy }, addr)),
+                }
+            }
+        };
+
+        let dial = match self.inner.dial(proxy.clone()) {
+            Ok(dial) => dial,
+            Err(_) => {
+                let err = error(&format!("can't dial the proxy {}", proxy));
+                return Ok(Box::new(future::err(err)));
+            }
+        };
+        let future = dial
+            .into_future()
+            .and_then(move |(socket, _)| handshake(socket, request))
+            .map(move |socket| (socket, addr));
+        Ok(Box::new(future))
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+/// Builds the CONNECT request for `addr`, if it is made of an IP address or a host name followed
+/// by a TCP port.
+fn connect_request(addr: &Multiaddr) -> Option<Vec<u8>> {
+    let mut components = addr.iter();
+    let mut request = vec![VERSION, CONNECT, 0];
+    match components.next()? {
+        AddrComponent::IP4(ip) => {
+            request.push(IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        AddrComponent::IP6(ip) => {
+            request.push(IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        AddrComponent::DNS4(name) | AddrComponent::DNS6(name) => {
+            if name.len() > 255 {
+                return None;
+            }
+            request.push(DOMAIN_NAME);
+            request.push(name.len() as u8);
+            request.extend_from_slice(name.as_bytes());
+        }
+        _ => return None,
+    }
+    match components.next()? {
+        AddrComponent::TCP(port) => request.extend_from_slice(&[(port >> 8) as u8, port as u8]),
+        _ => return None,
+    }
+    if components.next().is_some() {
+        return None;
+    }
+    Some(request)
+}
+
+/// Asks the proxy at the other end of `socket` to connect with `request`, built by
+/// `connect_request`. Yields the socket once it leads to the target.
+fn handshake<S>(socket: S, request: Vec<u8>) -> Box<Future<Item = S, Error = IoError>>
+where
+    S: AsyncRead + AsyncWrite + 'static,
+{
+    let future = write_all(socket, [VERSION, 1, NO_AUTHENTICATION])
+        .and_then(|(socket, _)| read_exact(socket, [0; 2]))
+        .and_then(|(socket, reply)| {
+            if reply != [VERSION, NO_AUTHENTICATION] {
+                return Err(error("the proxy requires an authentication"));
+            }
+            Ok(socket)
+        })
+        .and_then(move |socket| write_all(socket, request))
+        .and_then(|(socket, _)| read_exact(socket, [0; 4]))
+        .and_then(|(socket, reply)| {
+            if reply[1] != 0 {
+                return Err(error(&format!("the proxy refused to connect ({})", reply[1])));
+            }
+            Ok((socket, reply[3]))
+        })
+        .and_then(|(socket, kind)| skip_bound_address(socket, kind));
+    Box::new(future)
+}
+
+/// Reads the address that the proxy bound for the connection, which we don't need.
+fn skip_bound_address<S>(socket: S, kind: u8) -> Box<Future<Item = S, Error = IoError>>
+where
+    S: AsyncRead + 'static,
+{
+    // The address is followed by a port of two bytes.
+    let len = match kind {
+        IPV4 => 4 + 2,
+        IPV6 => 16 + 2,
+        DOMAIN_NAME => {
+            let future = read_exact(socket, [0; 1]).and_then(|(socket, len)| {
+                read_exact(socket, vec![0; len[0] as usize + 2]).map(|(socket, _)| socket)
+            });
+            return Box::new(future);
+        }
+        _ => return Box::new(future::err(error("invalid answer from the proxy"))),
+    };
+    Box::new(read_exact(socket, vec![0; len]).map(|(socket, _)| socket))
+}
+
+fn error(message: &str) -> IoError {
+    IoError::new(ErrorKind::Other, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Poll;
+    use std::io::{Cursor, Read, Write};
+
+    /// A proxy that answers `replies`, and records what we send it.
+    struct Proxy {
+        replies: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Read for Proxy {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Proxy {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), IoError> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for Proxy {}
+
+    impl AsyncWrite for Proxy {
+        fn shutdown(&mut self) -> Poll<(), IoError> {
+            Ok(().into())
+        }
+    }
+
+    fn connect(replies: &[u8]) -> Result<Proxy, IoError> {
+        let proxy = Proxy {
+            replies: Cursor::new(replies.to_vec()),
+            sent: Vec::new(),
+        };
+        let request = connect_request(&"/ip4/10.0.0.1/tcp/80".parse().unwrap()).unwrap();
+        handshake(proxy, request).wait()
+    }
+
+    fn failure(replies: &[u8]) -> IoError {
+        match connect(replies) {
+            Ok(_) => panic!("the handshake should have failed"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn the_socket_is_yielded_past_the_bound_address() {
+        let mut replies = vec![5, 0, 5, 0, 0, DOMAIN_NAME, 5];
+        replies.extend_from_slice(b"proxy\x1f\x90hello");
+        let mut proxy = connect(&replies).unwrap();
+        assert_eq!(proxy.sent, vec![5, 1, 0, 5, 1, 0, IPV4, 10, 0, 0, 1, 0, 80]);
+        let mut rest = String::new();
+        proxy.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "hello");
+    }
+
+    #[test]
+    fn a_proxy_that_wants_a_password_is_refused() {
+        let err = failure(&[5, 2]);
+        assert_eq!(err.to_string(), "the proxy requires an authentication");
+    }
+
+    #[test]
+    fn the_proxy_can_refuse_to_connect() {
+        let err = failure(&[5, 0, 5, 5, 0, IPV4]);
+        assert_eq!(err.to_string(), "the proxy refused to connect (5)");
+    }
+
+    #[test]
+    fn truncated_replies_fail() {
+        for len in 1..12 {
+            let replies = [5, 0, 5, 0, 0, IPV4, 10, 0, 0, 1, 0, 80];
+            let err = failure(&replies[..len]);
+            assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn an_unknown_kind_of_bound_address_fails() {
+        let err = failure(&[5, 0, 5, 0, 0, 9, 1, 2, 3, 4, 0, 80]);
+        assert_eq!(err.to_string(), "invalid answer from the proxy");
+    }
+
+    #[test]
+    fn only_a_host_and_a_tcp_port_are_given_to_the_proxy() {
+        let request = |addr: &str| connect_request(&addr.parse().unwrap());
+        let mut expected = vec![5, 1, 0, DOMAIN_NAME, 11];
+        expected.extend_from_slice(b"example.org");
+        expected.extend_from_slice(&[1, 187]);
+        assert_eq!(request("/dns4/example.org/tcp/443"), Some(expected));
+        assert_eq!(request("/ip4/10.0.0.1/tcp/80/ws"), None);
+        assert_eq!(request("/ip4/10.0.0.1/udp/80"), None);
+        let long = format!("/dns4/{}/tcp/80", "a".repeat(256));
+        assert_eq!(request(&long), None);
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Where the lines that we handle as if they were typed come from.
+//!
+//! Each `Source` turns into a stream of lines, without their line break:
+//!
+//! - `Terminal` reads the keyboard. Natively, on Unix, it turns on bracketed paste so that a
+//!   pasted multi-line snippet stays a single message; on Windows, see the `console` module.
+//! - `Piped` reads stdin when it is redirected, one message per line.
+//! - `Tail`, for `--input-file <file>`, reads a file. With `--follow`, it skips the lines already
+//!   there and waits for new ones, like `tail -f`, starting over if the file is truncated.
+//! - `Fifo`, for `--input-fifo <path>`, reads a named pipe, created if needed, and opens it again
+//!   whenever the writer closes it, so that one bot after the other can feed the node.
+//! - `Form`, in the browser, reads the form of the page.
+//!
+//! Bots and bridges can feed a running node through the last three. The empty lines are skipped
+//! by all of them.
+
+use error::Error;
+use futures::sync::mpsc;
+use futures::Stream;
+use options::Options;
+#[cfg(not(target_os = "emscripten"))]
+use platform;
+#[cfg(not(target_os = "emscripten"))]
+use std::fs::File;
+#[cfg(not(target_os = "emscripten"))]
+use std::io::{BufRead, BufReader};
+use std::io::Error as IoError;
+#[cfg(not(target_os = "emscripten"))]
+use std::thread;
+#[cfg(not(target_os = "emscripten"))]
+use std::time::Duration;
+
+/// How often `Tail` looks for new lines once it reached the end of the file.
+#[cfg(not(target_os = "emscripten"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+pub type Lines = Box<Stream<Item = String, Error = IoError>>;
+
+pub trait Source {
+    /// Starts reading, and returns the lines as they come.
+    fn lines(self: Box<Self>) -> Lines;
+}
+
+/// Returns the source chosen on the command line, or stdin.
+#[cfg(not(target_os = "emscripten"))]
+pub fn open(options: &Options) -> Result<Box<Source>, Error> {
+    if let Some(ref path) = options.input_file {
+        return Ok(Box::new(Tail {
+            path: path.clone(),
+            follow: options.follow,
+        }));
+    }
+    if let Some(ref path) = options.input_fifo {
+        return fifo(path);
+    }
+    if platform::is_terminal() {
+        Ok(Box::new(Terminal))
+    } else {
+        Ok(Box::new(Piped))
+    }
+}
+#[cfg(target_os = "emscripten")]
+pub fn open(_: &Options) -> Result<Box<Source>, Error> {
+    Ok(Box::new(Form))
+}
+
+#[cfg(all(unix, not(target_os = "emscripten")))]
+fn fifo(path: &str) -> Result<Box<Source>, Error> {
+    let fifo = Fifo::create(path)
+        .map_err(|err| Error::config(&format!("can't create the named pipe {}", path), err))?;
+    Ok(Box::new(fifo))
+}
+#[cfg(windows)]
+fn fifo(_: &str) -> Result<Box<Source>, Error> {
+    Err(Error::Config("--input-fifo is only available on Unix".to_owned()))
+}
+
+/// Runs `read` on a thread of its own, and returns what it sends as a stream.
+#[cfg(not(target_os = "emscripten"))]
+fn spawn<F>(read: F) -> Lines
+where
+    F: FnOnce(&mpsc::UnboundedSender<Result<String, IoError>>) -> Result<(), IoError>,
+    F: Send + 'static,
+{
+    let (tx, rx) = mpsc::unbounded();
+    thread::spawn(move || {
+        if let Err(err) = read(&tx) {
+            let _ = tx.unbounded_send(Err(err));
+        }
+    });
+    Box::new(
+        rx.map_err(|()| -> IoError { unreachable!() })
+            .and_then(|line| line)
+            .map(|line| line.trim_right().to_owned())
+            .filter(|line| !line.is_empty()),
+    )
+}
+
+/// Sends the lines of `reader` to `tx` until its end. Returns false if the stream was dropped.
+#[cfg(not(target_os = "emscripten"))]
+fn forward<R: BufRead>(
+    reader: R,
+    tx: &mpsc::UnboundedSender<Result<String, IoError>>,
+) -> Result<bool, IoError> {
+    for line in reader.lines() {
+        if tx.unbounded_send(Ok(line?)).is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(not(target_os = "emscripten"))]
+pub struct Terminal;
+
+#[cfg(all(not(windows), not(target_os = "emscripten")))]
+impl Source for Terminal {
+    fn lines(self: Box<Self>) -> Lines {
+        use display;
+        use std::io::ErrorKind;
+        use std::mem;
+        use tokio_stdin;
+
+        // With bracketed paste enabled, the terminal surrounds pasted text with these sequences,
+        // which lets us keep a pasted multi-line snippet in a single message.
+        const PASTE_START: &[u8] = b"\x1b[200~";
+        const PASTE_END: &[u8] = b"\x1b[201~";
+        if display::decorated() {
+            print!("\x1b[?2004h");
+        }
+
+        let mut buffer = Vec::new();
+        let mut pasting = false;
+        Box::new(
+            tokio_stdin::spawn_stdin_stream_unbounded()
+                // The channel of `tokio_stdin` never fails, but still has an error type.
+                .map_err(|()| IoError::new(ErrorKind::BrokenPipe, "the terminal stopped"))
+                .filter_map(move |msg| {
+                    if msg != b'\r' && msg != b'\n' {
+                        buffer.push(msg);
+                        if buffer.ends_with(PASTE_START) || buffer.ends_with(PASTE_END) {
+                            pasting = buffer.ends_with(PASTE_START);
+                            let len = buffer.len() - PASTE_START.len();
+                            buffer.truncate(len);
+                        }
+                        return None;
+                    } else if pasting {
+                        // Terminals send `\r` for the line breaks of pasted text.
+                        if msg == b'\r' || buffer.last() != Some(&b'\r') {
+                            buffer.push(msg);
+                        }
+                        return None;
+                    } else if buffer.is_empty() {
+                        return None;
+                    }
+
+                    let message = mem::replace(&mut buffer, Vec::new());
+                    // A terminal in another encoding sends bytes that aren't UTF-8.
+                    let message = String::from_utf8_lossy(&message);
+                    Some(message.replace("\r\n", "\n").replace('\r', "\n").trim_right().to_owned())
+                }),
+        )
+    }
+}
+
+/// The Windows console needs its own reader, see the `console` module. It doesn't support
+/// bracketed paste, so each pasted line is a separate message.
+#[cfg(windows)]
+impl Source for Terminal {
+    fn lines(self: Box<Self>) -> Lines {
+        use console;
+
+        let (tx, rx) = mpsc::unbounded();
+        thread::spawn(move || console::read_lines(tx));
+        Box::new(
+            rx.map(|line| line.trim_right().to_owned())
+                .filter(|line| !line.is_empty())
+                .map_err(|()| -> IoError { unreachable!() }),
+        )
+    }
+}
+
+#[cfg(not(target_os = "emscripten"))]
+pub struct Piped;
+
+#[cfg(not(target_os = "emscripten"))]
+impl Source for Piped {
+    fn lines(self: Box<Self>) -> Lines {
+        use std::io;
+
+        spawn(|tx| {
+            let stdin = io::stdin();
+            let _ = forward(stdin.lock(), tx)?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(not(target_os = "emscripten"))]
+pub struct Tail {
+    path: String,
+    follow: bool,
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl Source for Tail {
+    fn lines(self: Box<Self>) -> Lines {
+        use std::io::{Seek, SeekFrom};
+
+        let Tail { path, follow } = *self;
+        spawn(move |tx| {
+            let mut file = BufReader::new(File::open(&path)?);
+            if !follow {
+                let _ = forward(file, tx)?;
+                return Ok(());
+            }
+            file.seek(SeekFrom::End(0))?;
+            // A line that is still being written is only sent once complete.
+            let mut line = String::new();
+            loop {
+                if file.read_line(&mut line)? > 0 && line.ends_with('\n') {
+                    if tx.unbounded_send(Ok(line.clone())).is_err() {
+                        return Ok(());
+                    }
+                    line.clear();
+                    continue;
+                }
+                let position = file.seek(SeekFrom::Current(0))?;
+                if file.get_ref().metadata()?.len() < position {
+                    file.seek(SeekFrom::Start(0))?;
+                    line.clear();
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        })
+    }
+}
+
+#[cfg(all(unix, not(target_os = "emscripten")))]
+pub struct Fifo {
+    path: String,
+}
+
+#[cfg(all(unix, not(target_os = "emscripten")))]
+impl Fifo {
+    /// Creates the named pipe at `path`, unless it exists.
+    pub fn create(path: &str) -> Result<Fifo, IoError> {
+        use libc;
+        use std::ffi::CString;
+        use std::io::ErrorKind;
+        use std::path::Path;
+
+        if !Path::new(path).exists() {
+            let c_path = CString::new(path)
+                .map_err(|_| IoError::new(ErrorKind::InvalidInput, "the path contains a NUL"))?;
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                return Err(IoError::last_os_error());
+            }
+        }
+        Ok(Fifo {
+            path: path.to_owned(),
+        })
+    }
+}
+
+#[cfg(all(unix, not(target_os = "emscripten")))]
+impl Source for Fifo {
+    fn lines(self: Box<Self>) -> Lines {
+        let path = self.path;
+        spawn(move |tx| loop {
+            // Opening blocks until a writer opens the other end.
+            let reader = BufReader::new(File::open(&path)?);
+            if !forward(reader, tx)? {
+                return Ok(());
+            }
+        })
+    }
+}
+
+#[cfg(target_os = "emscripten")]
+pub struct Form;
+
+#[cfg(target_os = "emscripten")]
+impl Source for Form {
+    fn lines(self: Box<Self>) -> Lines {
+        let (tx, rx) = mpsc::unbounded();
+
+        let cb = move |txt: String| {
+            let _ = tx.unbounded_send(txt);
+        };
+
+        js! {
+            var cb = @{cb};
+            document.getElementById("stdin_form")
+                .addEventListener("submit", function(event) {
+                    var elem = document.getElementById("stdin");
+                    var txt = elem.value;
+                    elem.value = "";
+                    cb(txt);
+                    event.preventDefault();
+                });
+        };
+
+        Box::new(rx.map_err(|_| -> IoError { unreachable!() }))
+    }
+}
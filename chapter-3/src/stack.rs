@@ -0,0 +1,176 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! How each connection was built, layer by layer, for `/conn`.
+//!
+//! A connection of the chat is a stack of upgrades. At the bottom is the transport: TCP, maybe
+//! through the SOCKS5 proxy of `--proxy`, with websockets on top for the `/ws` addresses. There
+//! is no encryption layer, since the envelopes are signed rather than encrypted, and no muxer,
+//! since `with_dummy_muxing` opens one connection per protocol. On top, multistream-select
+//! negotiates the protocol, which may then have a handshake of its own.
+//!
+//! `Stopwatch` wraps the transport to time how long the socket took to open, `ChatUpgrade`
+//! notes when the negotiation ended, and the handler of the swarm puts the three together once
+//! the protocol is ready.
+
+use futures::{Future, IntoFuture, Stream};
+use libp2p::core::Transport;
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Error as IoError;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Sockets whose negotiation doesn't end within this delay are forgotten.
+const FORGET_AFTER: Duration = Duration::from_secs(60);
+
+/// How long each step of building a connection took.
+#[derive(Debug, Clone, Default)]
+pub struct Steps {
+    /// Opening the socket, including the websocket handshake. `None` if we didn't see it.
+    pub transport: Option<Duration>,
+    /// The multistream-select negotiation, if we know when the socket was opened.
+    pub negotiation: Option<Duration>,
+    /// The handshake of the negotiated protocol.
+    pub handshake: Duration,
+}
+
+/// The sockets opened by `Stopwatch` that are being negotiated, shared with the swarm.
+#[derive(Clone, Default)]
+pub struct Timings {
+    /// When we started opening the socket to each address, and when it was open.
+    opened: Rc<RefCell<HashMap<Multiaddr, (Instant, Instant)>>>,
+}
+
+impl Timings {
+    fn opened(&self, address: Multiaddr, started: Instant) {
+        let mut opened = self.opened.borrow_mut();
+        opened.retain(|_, &mut (_, open)| open.elapsed() < FORGET_AFTER);
+        opened.insert(address, (started, Instant::now()));
+    }
+
+    /// Called when the protocol negotiated at `chosen` on the socket to `address` is ready.
+    pub fn finish(&self, address: &Multiaddr, chosen: Instant) -> Steps {
+        let handshake = chosen.elapsed();
+        match self.opened.borrow_mut().remove(address) {
+            Some((started, open)) => Steps {
+                transport: Some(open.duration_since(started)),
+                negotiation: Some(chosen.duration_since(open)),
+                handshake,
+            },
+            None => Steps {
+                handshake,
+                ..Steps::default()
+            },
+        }
+    }
+
+    pub fn wrap<T>(&self, inner: T) -> Stopwatch<T> {
+        Stopwatch {
+            inner,
+            timings: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Stopwatch<T> {
+    inner: T,
+    timings: Timings,
+}
+
+impl<T> Transport for Stopwatch<T>
+where
+    T: Transport + 'static,
+    T::Output: 'static,
+    T::Listener: 'static,
+    T::ListenerUpgrade: 'static,
+    T::Dial: 'static,
+{
+    type Output = T::Output;
+    type Listener = Box<Stream<Item = Self::ListenerUpgrade, Error = IoError>>;
+    type ListenerUpgrade = Box<Future<Item = (Self::Output, Multiaddr), Error = IoError>>;
+    type Dial = Box<Future<Item = (Self::Output, Multiaddr), Error = IoError>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let Stopwatch { inner, timings } = self;
+        match inner.listen_on(addr) {
+            Ok((listener, addr)) => {
+                let listener = listener.map(move |upgrade| {
+                    let started = Instant::now();
+                    let timings = timings.clone();
+                    let upgrade = upgrade.into_future().map(move |(socket, addr)| {
+                        timings.opened(addr.clone(), started);
+                        (socket, addr)
+                    });
+                    Box::new(upgrade) as Box<Future<Item = _, Error = _>>
+                });
+                Ok((Box::new(listener), addr))
+            }
+            Err((inner, addr)) => Err((Stopwatch { inner, timings }, addr)),
+        }
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let Stopwatch { inner, timings } = self;
+        let started = Instant::now();
+        match inner.dial(addr) {
+            Ok(dial) => {
+                let dial = dial.into_future().map(move |(socket, addr)| {
+                    timings.opened(addr.clone(), started);
+                    (socket, addr)
+                });
+                Ok(Box::new(dial))
+            }
+            Err((inner, addr)) => Err((Stopwatch { inner, timings }, addr)),
+        }
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+/// The transport layers of a connection to `address`, from the top.
+pub fn transport(address: &Multiaddr, proxied: bool) -> String {
+    let address = address.to_string();
+    let mut layers = Vec::new();
+    if address.contains("/ws") {
+        layers.push("websocket");
+    }
+    if address.contains("/tcp/") {
+        layers.push("TCP");
+    }
+    if layers.is_empty() {
+        layers.push("serial link");
+    }
+    let mut transport = layers.join(" over ");
+    if proxied {
+        transport.push_str(" through SOCKS5");
+    }
+    transport
+}
+
+/// Formats `duration` in milliseconds.
+pub fn millis(duration: Duration) -> String {
+    let ms = duration.as_secs() * 1000 + u64::from(duration.subsec_nanos() / 1_000_000);
+    format!("{}ms", ms)
+}
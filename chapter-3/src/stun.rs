@@ -0,0 +1,299 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Discovery of our public address with a STUN server (RFC 5389).
+//!
+//! Behind a NAT, the address we listen on isn't the one the others can dial. We send a binding
+//! request to the server passed with `--stun`, which answers with the IP address and port it
+//! received the request from. Only the IP address is useful to us: the port is the one the NAT
+//! picked for our UDP socket, and we listen on TCP. The others are told to dial the public IP
+//! address on our listening port, which works when that port is forwarded or when the NAT keeps
+//! ports unchanged.
+//!
+//! libp2p's identify protocol would give us the addresses observed by our peers as well, but the
+//! swarm of this chapter doesn't negotiate it yet.
+
+use futures::future::{self, Either};
+use futures::Future;
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::io::{Error as IoError, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+use tokio_core::net::UdpSocket;
+use tokio_core::reactor::{Handle, Timeout};
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+/// Number of seconds we wait for the answer of the server.
+const PROBE_TIMEOUT_SECS: u64 = 5;
+
+/// Asks the STUN server at `server`, written as `host:port`, for our public IP address.
+pub fn probe(server: &str, handle: &Handle) -> Box<Future<Item = IpAddr, Error = IoError>> {
+    let server = match server.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(server)) => server,
+        Ok(None) => return Box::new(future::err(error("the STUN server has no address"))),
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let local: SocketAddr = if server.is_ipv4() {
+        ([0u8; 4], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = match UdpSocket::bind(&local, handle) {
+        Ok(socket) => socket,
+        Err(err) => return Box::new(future::err(err)),
+    };
+    let timer = match Timeout::new(Duration::from_secs(PROBE_TIMEOUT_SECS), handle) {
+        Ok(timer) => timer,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    let transaction: [u8; 12] = ::rand::random();
+    let mut request = Vec::with_capacity(HEADER_LEN);
+    request.extend_from_slice(&be16(BINDING_REQUEST));
+    request.extend_from_slice(&be16(0));
+    request.extend_from_slice(&be32(MAGIC_COOKIE));
+    request.extend_from_slice(&transaction);
+
+    let exchange = socket
+        .send_dgram(request, server)
+        .and_then(|(socket, _)| socket.recv_dgram(vec![0; 512]))
+        .and_then(move |(_, response, len, _)| {
+            parse_response(&response[..len], &transaction)
+                .ok_or_else(|| error("invalid answer from the STUN server"))
+        });
+    let future = exchange.select2(timer).then(|result| match result {
+        Ok(Either::A((ip, _))) => Ok(ip),
+        Ok(Either::B(((), _))) => Err(IoError::new(
+            ErrorKind::TimedOut,
+            "the STUN server didn't answer",
+        )),
+        Err(Either::A((err, _))) | Err(Either::B((err, _))) => Err(err),
+    });
+    Box::new(future)
+}
+
+/// Returns `listen` with its IP address replaced with `ip`.
+pub fn with_ip(listen: &Multiaddr, ip: IpAddr) -> Multiaddr {
+    listen
+        .iter()
+        .map(|component| match (component, ip) {
+            (AddrComponent::IP4(_), IpAddr::V4(ip)) | (AddrComponent::IP6(_), IpAddr::V4(ip)) => {
+                AddrComponent::IP4(ip)
+            }
+            (AddrComponent::IP4(_), IpAddr::V6(ip)) | (AddrComponent::IP6(_), IpAddr::V6(ip)) => {
+                AddrComponent::IP6(ip)
+            }
+            (component, _) => component,
+        })
+        .collect()
+}
+
+/// Extracts the mapped address from a binding response to the request with ID `transaction`.
+fn parse_response(response: &[u8], transaction: &[u8; 12]) -> Option<IpAddr> {
+    if response.len() < HEADER_LEN
+        || read16(&response[0..]) != BINDING_SUCCESS
+        || read32(&response[4..]) != MAGIC_COOKIE
+        || &response[8..HEADER_LEN] != transaction
+    {
+        return None;
+    }
+
+    let mut mapped = None;
+    let mut attributes = &response[HEADER_LEN..];
+    while attributes.len() >= 4 {
+        let kind = read16(attributes);
+        let len = read16(&attributes[2..]) as usize;
+        let value = attributes.get(4..4 + len)?;
+        match kind {
+            // Some NATs rewrite the addresses they find in packets, hence the XORed variant.
+            XOR_MAPPED_ADDRESS => return parse_address(value, Some(transaction)),
+            MAPPED_ADDRESS => mapped = parse_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of 4 bytes.
+        let padded = (4 + len + 3) & !3;
+        attributes = attributes.get(padded..).unwrap_or(&[]);
+    }
+    mapped
+}
+
+/// Parses the value of a `MAPPED-ADDRESS`, or of a `XOR-MAPPED-ADDRESS` if `xor` is the ID of
+/// the transaction.
+fn parse_address(value: &[u8], xor: Option<&[u8; 12]>) -> Option<IpAddr> {
+    // The XORed address is masked with the magic cookie followed by the transaction ID.
+    let key = match xor {
+        Some(transaction) => {
+            let mut key = be32(MAGIC_COOKIE).to_vec();
+            key.extend_from_slice(transaction);
+            key
+        }
+        None => vec![0; 16],
+    };
+    let unmask = |bytes: &[u8]| -> Vec<u8> { bytes.iter().zip(&key).map(|(b, k)| b ^ k).collect() };
+    match *value.get(1)? {
+        0x01 => {
+            let ip = unmask(value.get(4..8)?);
+            Some(IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])))
+        }
+        0x02 => {
+            let ip = unmask(value.get(4..20)?);
+            let mut segments = [0u16; 8];
+            for (n, segment) in segments.iter_mut().enumerate() {
+                *segment = read16(&ip[2 * n..]);
+            }
+            Some(IpAddr::V6(Ipv6Addr::from(segments)))
+        }
+        _ => None,
+    }
+}
+
+fn be16(n: u16) -> [u8; 2] {
+    [(n >> 8) as u8, n as u8]
+}
+
+fn be32(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn read16(bytes: &[u8]) -> u16 {
+    (u16::from(bytes[0]) << 8) | u16::from(bytes[1])
+}
+
+fn read32(bytes: &[u8]) -> u32 {
+    (u32::from(read16(bytes)) << 16) | u32::from(read16(&bytes[2..]))
+}
+
+fn error(message: &str) -> IoError {
+    IoError::new(ErrorKind::Other, message.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSACTION: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    /// A binding response to `TRANSACTION` with `attributes`, padded as the server would.
+    fn response(attributes: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for &(kind, ref value) in attributes {
+            body.extend_from_slice(&be16(kind));
+            body.extend_from_slice(&be16(value.len() as u16));
+            body.extend_from_slice(value);
+            while body.len() % 4 != 0 {
+                body.push(0);
+            }
+        }
+        let mut response = Vec::new();
+        response.extend_from_slice(&be16(BINDING_SUCCESS));
+        response.extend_from_slice(&be16(body.len() as u16));
+        response.extend_from_slice(&be32(MAGIC_COOKIE));
+        response.extend_from_slice(&TRANSACTION);
+        response.extend(body);
+        response
+    }
+
+    /// The value of a `MAPPED-ADDRESS` of 192.0.2.1, masked with the cookie if `xor`.
+    fn ipv4(xor: bool) -> Vec<u8> {
+        let mut ip = [192, 0, 2, 1];
+        if xor {
+            for (byte, key) in ip.iter_mut().zip(&be32(MAGIC_COOKIE)) {
+                *byte ^= key;
+            }
+        }
+        let mut value = vec![0, 0x01, 0x1f, 0x90];
+        value.extend_from_slice(&ip);
+        value
+    }
+
+    fn parse(response: &[u8]) -> Option<IpAddr> {
+        parse_response(response, &TRANSACTION)
+    }
+
+    #[test]
+    fn the_xored_address_is_unmasked() {
+        let ip = parse(&response(&[(XOR_MAPPED_ADDRESS, ipv4(true))]));
+        assert_eq!(ip, Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+    }
+
+    #[test]
+    fn the_xored_address_wins_over_the_plain_one() {
+        let other = vec![0, 0x01, 0, 0, 10, 0, 0, 1];
+        let ip = parse(&response(&[(MAPPED_ADDRESS, other), (XOR_MAPPED_ADDRESS, ipv4(true))]));
+        assert_eq!(ip, Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+    }
+
+    #[test]
+    fn unknown_attributes_are_skipped_with_their_padding() {
+        let software = (0x8022, b"stund".to_vec());
+        let ip = parse(&response(&[software, (MAPPED_ADDRESS, ipv4(false))]));
+        assert_eq!(ip, Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))));
+    }
+
+    #[test]
+    fn a_plain_ipv6_address_is_read() {
+        let mut value = vec![0, 0x02, 0, 80];
+        value.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        value.extend_from_slice(&[0; 11]);
+        value.push(1);
+        let ip = parse(&response(&[(MAPPED_ADDRESS, value)]));
+        assert_eq!(ip, Some("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn truncated_responses_are_rejected() {
+        let whole = response(&[(XOR_MAPPED_ADDRESS, ipv4(true))]);
+        // Cut in the header, or anywhere in the attribute.
+        for len in 0..whole.len() {
+            assert_eq!(parse(&whole[..len]), None, "{} bytes", len);
+        }
+        // An address shorter than its family says.
+        let short = vec![0, 0x02, 0, 80, 0x20, 0x01];
+        assert_eq!(parse(&response(&[(MAPPED_ADDRESS, short)])), None);
+    }
+
+    #[test]
+    fn malformed_responses_are_rejected() {
+        let mut other_transaction = response(&[(MAPPED_ADDRESS, ipv4(false))]);
+        other_transaction[HEADER_LEN - 1] ^= 1;
+        assert_eq!(parse(&other_transaction), None);
+        let mut error_response = response(&[(MAPPED_ADDRESS, ipv4(false))]);
+        error_response[1] = 0x11;
+        assert_eq!(parse(&error_response), None);
+        let unknown_family = vec![0, 0x03, 0, 80, 1, 2, 3, 4];
+        assert_eq!(parse(&response(&[(MAPPED_ADDRESS, unknown_family)])), None);
+        // A success without any address.
+        assert_eq!(parse(&response(&[])), None);
+    }
+
+    #[test]
+    fn only_the_ip_address_of_the_listening_address_changes() {
+        let listen: Multiaddr = "/ip4/0.0.0.0/tcp/63204/ws".parse().unwrap();
+        let public = with_ip(&listen, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(public.to_string(), "/ip4/192.0.2.1/tcp/63204/ws");
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Integration with systemd, for the nodes that run as services.
+//!
+//! With socket activation, systemd binds the listening socket itself and passes it to us as
+//! file descriptor 3, as described in `sd_listen_fds(3)`. `Activated` wraps the TCP transport so
+//! that the first `listen_on` uses that socket, whatever address it asks for, instead of binding
+//! a new one. Once we are listening, `notify_ready` tells systemd that the service has started,
+//! for units of `Type=notify`.
+//!
+//! Both do nothing when we weren't started by systemd, and on platforms other than Unix.
+
+use futures::future::{self, Future, FutureResult};
+use futures::Stream;
+use libp2p::core::Transport;
+use libp2p::multiaddr::AddrComponent;
+use libp2p::Multiaddr;
+use std::cell::RefCell;
+use std::env;
+use std::io::Error as IoError;
+use std::net::{IpAddr, SocketAddr, TcpListener as StdTcpListener};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+
+/// True once systemd has passed us a listening socket.
+static ACTIVATED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// File descriptor of the first socket passed by systemd.
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Returns the listening socket passed by systemd, if any.
+#[cfg(unix)]
+pub fn inherited_listener() -> Option<StdTcpListener> {
+    use std::os::unix::io::FromRawFd;
+    use std::process;
+
+    let pid = env::var("LISTEN_PID").ok()?;
+    let fds = env::var("LISTEN_FDS").ok()?;
+    // The variables must not be inherited by the processes we could start.
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if pid.parse::<u32>().ok() != Some(process::id()) || fds.parse::<u32>().ok()? < 1 {
+        return None;
+    }
+    ACTIVATED.store(true, Ordering::SeqCst);
+    Some(unsafe { StdTcpListener::from_raw_fd(LISTEN_FDS_START) })
+}
+#[cfg(not(unix))]
+pub fn inherited_listener() -> Option<StdTcpListener> {
+    None
+}
+
+/// Returns true if `inherited_listener` returned a socket, which the first listener uses.
+pub fn activated() -> bool {
+    ACTIVATED.load(Ordering::SeqCst)
+}
+
+/// Tells systemd that we are ready to serve.
+#[cfg(unix)]
+pub fn notify_ready() {
+    use std::os::unix::net::UnixDatagram;
+
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    // The standard library can't address the sockets of the abstract namespace.
+    if path.starts_with('@') {
+        say!("* Can't notify systemd through the abstract socket {}", path);
+        return;
+    }
+    let result = UnixDatagram::unbound().and_then(|socket| socket.send_to(b"READY=1", &path));
+    if let Err(err) = result {
+        say!("* Couldn't notify systemd: {}", err);
+    }
+}
+#[cfg(not(unix))]
+pub fn notify_ready() {}
+
+#[derive(Clone)]
+pub struct Activated<T> {
+    inner: T,
+    /// The socket passed by systemd, until something listens on it.
+    inherited: Rc<RefCell<Option<StdTcpListener>>>,
+    handle: Handle,
+}
+
+impl<T> Activated<T> {
+    pub fn new(inner: T, inherited: Option<StdTcpListener>, handle: Handle) -> Activated<T> {
+        Activated {
+            inner,
+            inherited: Rc::new(RefCell::new(inherited)),
+            handle,
+        }
+    }
+}
+
+impl<T> Transport for Activated<T>
+where
+    T: Transport<Output = TcpStream> + 'static,
+    T::Listener: 'static,
+    T::ListenerUpgrade: 'static,
+{
+    type Output = TcpStream;
+    type Listener = Box<Stream<Item = Self::ListenerUpgrade, Error = IoError>>;
+    type ListenerUpgrade = Box<Future<Item = (TcpStream, Multiaddr), Error = IoError>>;
+    type Dial = T::Dial;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<(Self::Listener, Multiaddr), (Self, Multiaddr)> {
+        let inherited = self.inherited.borrow_mut().take();
+        let listener = match inherited {
+            Some(listener) => listener,
+            None => {
+                let Activated {
+                    inner,
+                    inherited,
+                    handle,
+                } = self;
+                return match inner.listen_on(addr) {
+                    Ok((listener, addr)) => {
+                        let listener =
+                            listener.map(|upgrade| Box::new(upgrade) as Self::ListenerUpgrade);
+                        Ok((Box::new(listener), addr))
+                    }
+                    Err((inner, addr)) => Err((
+                        Activated {
+                            inner,
+                            inherited,
+                            handle,
+                        },
+                        addr,
+                    )),
+                };
+            }
+        };
+
+        let local = match listener.local_addr() {
+            Ok(local) => local,
+            Err(_) => return Err((self, addr)),
+        };
+        let listener = match TcpListener::from_listener(listener, &local, &self.handle) {
+            Ok(listener) => listener,
+            Err(_) => return Err((self, addr)),
+        };
+        let incoming = listener.incoming().map(|(socket, remote)| {
+            let upgrade: FutureResult<_, IoError> = future::ok((socket, tcp_address(remote)));
+            Box::new(upgrade) as Self::ListenerUpgrade
+        });
+        Ok((Box::new(incoming), tcp_address(local)))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, (Self, Multiaddr)> {
+        let Activated {
+            inner,
+            inherited,
+            handle,
+        } = self;
+        inner.dial(addr).map_err(|(inner, addr)| {
+            let transport = Activated {
+                inner,
+                inherited,
+                handle,
+            };
+            (transport, addr)
+        })
+    }
+
+    fn nat_traversal(&self, server: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.nat_traversal(server, observed)
+    }
+}
+
+fn tcp_address(addr: SocketAddr) -> Multiaddr {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => AddrComponent::IP4(ip),
+        IpAddr::V6(ip) => AddrComponent::IP6(ip),
+    };
+    vec![ip, AddrComponent::TCP(addr.port())]
+        .into_iter()
+        .collect()
+}
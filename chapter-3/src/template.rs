@@ -0,0 +1,62 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Placeholders in the canned texts of the bots, filled in when they are sent.
+//!
+//! The texts that the bots publish and the messages of `/schedule` are written in advance, so
+//! they can't know who is around when they are sent. They can contain `{{nick}}`, our nickname,
+//! `{{time}}`, the time of day in UTC, and `{{peer_count}}`, the number of peers around.
+//! Unknown placeholders are left as they are written.
+
+/// The values of the placeholders at send time.
+pub struct Vars {
+    pub nick: String,
+    pub time: String,
+    pub peer_count: usize,
+}
+
+/// Returns `text` with its placeholders replaced.
+pub fn render(text: &str, vars: &Vars) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(len) => start + len + 2,
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+        match value(rest[start + 2..end - 2].trim(), vars) {
+            Some(value) => rendered.push_str(&value),
+            None => rendered.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn value(name: &str, vars: &Vars) -> Option<String> {
+    match name {
+        "nick" => Some(vars.nick.clone()),
+        "time" => Some(vars.time.clone()),
+        "peer_count" => Some(vars.peer_count.to_string()),
+        _ => None,
+    }
+}
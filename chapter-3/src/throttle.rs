@@ -0,0 +1,279 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Bandwidth throttling.
+//!
+//! Every socket produced by the transport is wrapped in a `Throttled`, which only lets through as
+//! many bytes as allowed by a *token bucket*. The buckets are shared between all the connections,
+//! so the limits apply to the node as a whole. When a bucket is empty, the socket reports that it
+//! isn't ready and a timer wakes the task up once tokens are available again.
+//!
+//! The buckets are given the current time instead of reading the clock, as `admission` does, so
+//! that the tests control it.
+
+use futures::{Async, Future, Poll};
+use std::cell::RefCell;
+use std::cmp;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// The upload and download limits of the node. Cloning a `Limits` shares the buckets.
+#[derive(Clone)]
+pub struct Limits {
+    upload: Option<Rc<RefCell<Bucket>>>,
+    download: Option<Rc<RefCell<Bucket>>>,
+}
+
+impl Limits {
+    /// Builds limits of the given number of bytes per second. `None` means unlimited.
+    pub fn new(upload: Option<u64>, download: Option<u64>) -> Limits {
+        let now = Instant::now();
+        let bucket = |rate| Rc::new(RefCell::new(Bucket::new(rate, now)));
+        Limits {
+            upload: upload.map(&bucket),
+            download: download.map(&bucket),
+        }
+    }
+}
+
+struct Bucket {
+    /// Bytes per second, which is also the capacity of the bucket.
+    rate: u64,
+    tokens: u64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, now: Instant) -> Bucket {
+        // Beyond 18 GB/s, which no link reaches, the nanoseconds of a second of tokens would
+        // overflow in `refill`.
+        let rate = cmp::min(cmp::max(rate, 1), u64::max_value() / 1_000_000_000);
+        Bucket {
+            rate,
+            tokens: rate,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now <= self.last_refill {
+            return;
+        }
+        let elapsed = now.duration_since(self.last_refill);
+        // A second fills the bucket, whatever it held.
+        if elapsed.as_secs() > 0 {
+            self.tokens = self.rate;
+            self.last_refill = now;
+            return;
+        }
+        let new_tokens = u64::from(elapsed.subsec_nanos()) * self.rate / 1_000_000_000;
+        if self.tokens + new_tokens >= self.rate {
+            self.tokens = self.rate;
+            self.last_refill = now;
+            return;
+        }
+        // Only the time that produced whole tokens is used up. The remainder, shorter than a
+        // token, counts towards the next refill.
+        self.tokens += new_tokens;
+        let used = (new_tokens * 1_000_000_000 + self.rate - 1) / self.rate;
+        self.last_refill += Duration::new(0, used as u32);
+    }
+
+    /// Takes up to `max` tokens and returns how many were taken.
+    fn take(&mut self, max: usize, now: Instant) -> usize {
+        self.refill(now);
+        let taken = cmp::min(self.tokens, max as u64);
+        self.tokens -= taken;
+        taken as usize
+    }
+
+    /// Puts back tokens that were taken but not used.
+    fn give_back(&mut self, tokens: usize) {
+        self.tokens = cmp::min(self.tokens + tokens as u64, self.rate);
+    }
+
+    /// Time after which at least one token will be available.
+    fn delay(&self) -> Duration {
+        cmp::max(
+            Duration::new(0, (1_000_000_000 / self.rate) as u32),
+            Duration::from_millis(1),
+        )
+    }
+}
+
+/// A socket whose throughput is limited by `Limits`.
+pub struct Throttled<S> {
+    inner: S,
+    limits: Limits,
+    handle: Handle,
+    read_delay: Option<Timeout>,
+    write_delay: Option<Timeout>,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, limits: Limits, handle: Handle) -> Throttled<S> {
+        Throttled {
+            inner,
+            limits,
+            handle,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+}
+
+/// Takes up to `max` tokens from `bucket`. If none is available, arms `delay` so that the current
+/// task is woken up when there are, and returns a `WouldBlock` error.
+fn acquire(
+    bucket: &RefCell<Bucket>,
+    max: usize,
+    delay: &mut Option<Timeout>,
+    handle: &Handle,
+) -> Result<usize, IoError> {
+    loop {
+        if let Some(mut timeout) = delay.take() {
+            if let Async::NotReady = timeout.poll()? {
+                *delay = Some(timeout);
+                return Err(IoErrorKind::WouldBlock.into());
+            }
+        }
+        let taken = bucket.borrow_mut().take(max, Instant::now());
+        if taken > 0 || max == 0 {
+            return Ok(taken);
+        }
+        let wait = bucket.borrow().delay();
+        *delay = Some(Timeout::new(wait, handle)?);
+    }
+}
+
+impl<S: Read> Read for Throttled<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let bucket = match self.limits.download {
+            Some(ref bucket) => bucket,
+            None => return self.inner.read(buf),
+        };
+        let allowed = acquire(bucket, buf.len(), &mut self.read_delay, &self.handle)?;
+        let result = self.inner.read(&mut buf[..allowed]);
+        let used = *result.as_ref().unwrap_or(&0);
+        bucket.borrow_mut().give_back(allowed - used);
+        result
+    }
+}
+
+impl<S: Write> Write for Throttled<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        let bucket = match self.limits.upload {
+            Some(ref bucket) => bucket,
+            None => return self.inner.write(buf),
+        };
+        let allowed = acquire(bucket, buf.len(), &mut self.write_delay, &self.handle)?;
+        let result = self.inner.write(&buf[..allowed]);
+        let used = *result.as_ref().unwrap_or(&0);
+        bucket.borrow_mut().give_back(allowed - used);
+        result
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Throttled<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for Throttled<S> {
+    fn shutdown(&mut self) -> Poll<(), IoError> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bucket of `rate` bytes per second, emptied at the returned instant.
+    fn emptied(rate: u64) -> (Bucket, Instant) {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(rate, start);
+        bucket.tokens = 0;
+        (bucket, start)
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn a_new_bucket_is_full() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(1_000, now);
+        assert_eq!(bucket.take(4_096, now), 1_000);
+        assert_eq!(bucket.take(4_096, now), 0);
+    }
+
+    #[test]
+    fn half_a_second_refills_half_the_bucket() {
+        let (mut bucket, start) = emptied(1_000);
+        assert_eq!(bucket.take(4_096, start + ms(500)), 500);
+    }
+
+    #[test]
+    fn a_long_window_refills_up_to_the_capacity() {
+        let (mut bucket, start) = emptied(1_000);
+        assert_eq!(bucket.take(usize::max_value(), start + ms(10_000)), 1_000);
+    }
+
+    #[test]
+    fn a_window_shorter_than_a_token_isnt_lost() {
+        let (mut bucket, start) = emptied(1);
+        assert_eq!(bucket.take(1, start + ms(400)), 0);
+        assert_eq!(bucket.last_refill, start);
+        assert_eq!(bucket.take(1, start + ms(1_100)), 1);
+    }
+
+    #[test]
+    fn the_remainder_of_a_refill_counts_towards_the_next() {
+        // 1.5 tokens, then 1.5 more: 3 tokens in all, rather than 2.
+        let (mut bucket, start) = emptied(2);
+        assert_eq!(bucket.take(10, start + ms(750)), 1);
+        assert_eq!(bucket.take(10, start + ms(1_500)), 2);
+    }
+
+    #[test]
+    fn unused_tokens_go_back_up_to_the_capacity() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(100, now);
+        assert_eq!(bucket.take(60, now), 60);
+        bucket.give_back(1_000);
+        assert_eq!(bucket.take(1_000, now), 100);
+    }
+
+    #[test]
+    fn the_delay_waits_for_one_token_but_at_least_a_millisecond() {
+        let now = Instant::now();
+        assert_eq!(Bucket::new(1, now).delay(), Duration::from_secs(1));
+        assert_eq!(Bucket::new(100, now).delay(), Duration::from_millis(10));
+        assert_eq!(Bucket::new(10_000_000, now).delay(), Duration::from_millis(1));
+        // A rate of zero would never refill.
+        assert_eq!(Bucket::new(0, now).rate, 1);
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Trust on first use of the keys of the other nodes, and the rotation of keys.
+//!
+//! The first key that signs a message with a given nickname is trusted for that nickname. A
+//! message with the same nickname signed by another key is flagged, unless the trusted key was
+//! rotated to it.
+//!
+//! `/rotate` replaces our key with a new one. The announcement, `Kind::Rotate`, is signed with
+//! the old key and names the new one, along with a signature of the old key by the new one: that
+//! proves that whoever announces the rotation owns the new key, so that nobody can claim the key
+//! of someone else as their own. From then on the old key is revoked, and messages signed with it
+//! are flagged, since it may have leaked.
+//!
+//! With `--known-keys <file>`, what we learn is kept across runs, as JSON.
+
+use identity;
+use purge;
+use serde_json;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error as IoError;
+
+/// Prefix of what the new key signs in a rotation, followed by the old key.
+const ROTATION_CONTEXT: &[u8] = b"rustfest-chat key rotation:";
+
+/// A reason to distrust a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The nickname is trusted with another key, in base58.
+    Changed { nick: String, trusted: String },
+    /// The key was revoked in favour of another one, in base58.
+    Revoked { successor: String },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KnownKeys {
+    /// The trusted key of each nickname, in base58.
+    #[serde(default)]
+    nicks: HashMap<String, String>,
+    /// The revoked keys, with the key that replaced each of them, in base58.
+    #[serde(default)]
+    revoked: HashMap<String, String>,
+    #[serde(skip)]
+    path: Option<String>,
+    /// The keys we have warned about since we started, so that we only warn once per key.
+    #[serde(skip)]
+    warned: HashSet<String>,
+}
+
+impl KnownKeys {
+    /// Loads the keys stored at `path`, if there is a file there.
+    pub fn load(path: Option<String>) -> KnownKeys {
+        let mut keys = match path {
+            Some(ref path) => match fs::read(path) {
+                Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                    say!("* Ignoring the known keys in {}: {}", path, err);
+                    KnownKeys::default()
+                }),
+                Err(_) => KnownKeys::default(),
+            },
+            None => KnownKeys::default(),
+        };
+        keys.path = path;
+        keys
+    }
+
+    fn save(&self) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        let content = serde_json::to_vec_pretty(self).expect("known keys always serialize");
+        if let Err(err) = fs::write(path, content) {
+            say!("* Can't save the known keys to {}: {}", path, err);
+        }
+    }
+
+    /// Forgets every key, and deletes the file.
+    pub fn purge(&mut self) -> Result<(), IoError> {
+        self.nicks.clear();
+        self.revoked.clear();
+        self.warned.clear();
+        purge::remove_file(&self.path)
+    }
+
+    /// Checks the key that signed a message with `nick`, and trusts it if it is the first key
+    /// seen with that nickname. Only warns the first time about each key.
+    pub fn check(&mut self, nick: Option<&str>, key: &[u8]) -> Option<Warning> {
+        let key = identity::encode_key(key);
+        let successor = self.revoked.get(&key).cloned();
+        let warning = match successor {
+            Some(successor) => Warning::Revoked { successor },
+            None => {
+                let nick = match nick {
+                    Some(nick) => nick,
+                    None => return None,
+                };
+                let trusted = self.nicks.get(nick).cloned();
+                match trusted {
+                    Some(ref trusted) if *trusted == key => return None,
+                    Some(trusted) => Warning::Changed {
+                        nick: nick.to_owned(),
+                        trusted,
+                    },
+                    None => {
+                        self.nicks.insert(nick.to_owned(), key);
+                        self.save();
+                        return None;
+                    }
+                }
+            }
+        };
+        if self.warned.insert(key) {
+            Some(warning)
+        } else {
+            None
+        }
+    }
+
+    /// Records that `old` was replaced with `new`: the nicknames trusted with `old` are now
+    /// trusted with `new`, and `old` is revoked.
+    pub fn rotate(&mut self, old: &[u8], new: &[u8]) {
+        let (old, new) = (identity::encode_key(old), identity::encode_key(new));
+        for trusted in self.nicks.values_mut() {
+            if *trusted == old {
+                *trusted = new.clone();
+            }
+        }
+        self.warned.remove(&new);
+        self.revoked.insert(old, new);
+        self.save();
+    }
+
+    /// Returns true if `key` was revoked.
+    pub fn is_revoked(&self, key: &[u8]) -> bool {
+        self.revoked.contains_key(&identity::encode_key(key))
+    }
+}
+
+/// Returns what the new key signs when `old` is rotated.
+pub fn rotation_proof(old: &[u8]) -> Vec<u8> {
+    let mut proof = ROTATION_CONTEXT.to_vec();
+    proof.extend_from_slice(old);
+    proof
+}
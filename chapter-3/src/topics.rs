@@ -0,0 +1,102 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Construction of the floodsub topics.
+//!
+//! By default, the name of a topic is the name of the room with an optional namespace prefix
+//! (`--topic-namespace workshop/2018/`), so that several sessions can share a network without
+//! seeing each other's messages. With `--hash-topics`, the name is replaced with its SHA-256, so
+//! that the relays don't learn the names of the rooms. The messages that name a room, such as pins
+//! and bans, then write the name of its topic instead, and we don't advertise our rooms on the
+//! directory topic.
+//!
+//! Each major version of the protocol after the first has topics of its own, see the
+//! `migration` module.
+
+use libp2p::floodsub::{Topic, TopicBuilder};
+use sha2::{Digest, Sha256};
+use version;
+
+#[derive(Debug, Clone)]
+pub struct TopicNaming {
+    namespace: String,
+    hashed: bool,
+}
+
+impl TopicNaming {
+    pub fn new(namespace: String, hashed: bool) -> TopicNaming {
+        TopicNaming { namespace, hashed }
+    }
+
+    /// Builds the topic with the given name, such as `room` or `room/kv`.
+    pub fn topic(&self, name: &str) -> Topic {
+        self.versioned(name, version::PROTOCOL)
+    }
+
+    /// Builds the topic with the given name for the clients of the protocol `version`. The
+    /// first major version predates this, so its topics have no suffix.
+    pub fn versioned(&self, name: &str, version: &str) -> Topic {
+        let full_name = self.full_name(name, version);
+        if self.hashed {
+            TopicBuilder::new(hash(&full_name)).build()
+        } else {
+            TopicBuilder::new(full_name).build()
+        }
+    }
+
+    /// Builds the topic of the room that `label` names, as written by `label`. With
+    /// `--hash-topics`, the label is the name of the topic itself.
+    pub fn topic_of_label(&self, label: &str) -> Topic {
+        if self.hashed {
+            TopicBuilder::new(label).build()
+        } else {
+            self.topic(label)
+        }
+    }
+
+    /// Returns true if the names of the rooms must not appear in what we publish.
+    pub fn is_hashed(&self) -> bool {
+        self.hashed
+    }
+
+    /// The name of `room` as written in the messages about it: the room itself, or with
+    /// `--hash-topics` the name of its topic.
+    pub fn label(&self, room: &str) -> String {
+        if self.hashed {
+            hash(&self.full_name(room, version::PROTOCOL))
+        } else {
+            room.to_owned()
+        }
+    }
+
+    fn full_name(&self, name: &str, version: &str) -> String {
+        match version::major(version) {
+            "1" => format!("{}{}", self.namespace, name),
+            major => format!("{}{}@v{}", self.namespace, name, major),
+        }
+    }
+}
+
+fn hash(full_name: &str) -> String {
+    Sha256::digest(full_name.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
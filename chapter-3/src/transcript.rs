@@ -0,0 +1,461 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Checking that our history of a room matches the one of another peer, with a Merkle tree.
+//!
+//! `/verify [<delay>]` covers the messages of the current room sent during the last `delay`, or
+//! all of those we remember. The time range is cut in two halves, and each half again, until the
+//! ranges are no longer than `BUCKET_SECS`. The root of a range is the hash of the roots of its
+//! halves, and the root of a bucket is the Merkle root of its messages, sorted by the time their
+//! author sent them, then by ID. Since the shape of the tree only depends on the range and not on
+//! the messages, two peers agree on the root of a range exactly when they agree on its
+//! messages.
+//!
+//! We ask the room for the root of the whole range, and pick the first peer that answers. While
+//! roots differ, we ask that peer for the roots of the halves, and so only descend into the
+//! ranges that diverge. Once a range holds at most `MAX_LEAVES` messages, or is a bucket, the peer
+//! sends the hash of each message instead, which pinpoints the messages that were lost on either
+//! side or changed. A message that was edited or deleted on one side only diverges too.
+//!
+//! Timestamps are hashed as the author wrote them, since every node corrects them differently,
+//! see the `clocks` module.
+
+use history::{Entry, History};
+use libp2p::PeerId;
+use sha2::{Digest as Sha2Digest, Sha256};
+use std::collections::HashSet;
+
+/// Length of the ranges whose messages are hashed together, in seconds.
+pub const BUCKET_SECS: u64 = 60;
+
+/// Number of messages under which a peer sends the hash of each of them rather than a root.
+pub const MAX_LEAVES: usize = 8;
+
+/// Number of ranges in a query, at most.
+pub const MAX_RANGES: usize = 16;
+
+/// Number of seconds after which a query or its answer is no longer relevant.
+pub const REQUEST_TTL_SECS: u64 = 30;
+
+/// A range of time, in seconds since the UNIX epoch, `to` excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Range {
+    /// Returns false for a range that ends before it starts, which a peer could send us.
+    pub fn is_valid(&self) -> bool {
+        self.from <= self.to
+    }
+
+    fn contains(&self, timestamp: u64) -> bool {
+        self.from <= timestamp && timestamp < self.to
+    }
+
+    fn middle(&self) -> u64 {
+        self.from + (self.to - self.from) / 2
+    }
+
+    fn is_bucket(&self) -> bool {
+        self.to - self.from <= BUCKET_SECS
+    }
+
+    /// The two halves of the range.
+    pub fn halves(&self) -> (Range, Range) {
+        let middle = self.middle();
+        (
+            Range {
+                from: self.from,
+                to: middle,
+            },
+            Range {
+                from: middle,
+                to: self.to,
+            },
+        )
+    }
+}
+
+/// The hash of one message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Leaf {
+    pub id: u64,
+    /// When the author sent it, in seconds since the UNIX epoch.
+    pub sent: u64,
+    pub hash: String,
+}
+
+/// What a peer knows of the messages of a range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Digest {
+    pub range: Range,
+    /// The Merkle root of the range, in hexadecimal.
+    pub root: String,
+    /// The hash of each message, if the range is small enough.
+    pub leaves: Option<Vec<Leaf>>,
+}
+
+/// How our messages of a range differ from the ones of the peer.
+pub enum Difference {
+    /// The peer doesn't have our message with this ID.
+    Missing(u64),
+    /// We don't have this message of the peer.
+    Unknown(Leaf),
+    /// We both have the message with this ID, but not with the same contents.
+    Changed(u64),
+}
+
+/// What to do about a range after comparing its digests.
+pub enum Outcome {
+    Agree,
+    /// These messages diverge.
+    Differ(Vec<Difference>),
+    /// The roots differ: compare the halves.
+    Split(Range, Range),
+}
+
+/// A `/verify` in progress.
+pub struct Verification {
+    pub room: String,
+    /// The peer we compare with: the first one that answered.
+    pub peer: Option<PeerId>,
+    /// The queries that weren't answered yet.
+    pending: HashSet<u64>,
+    /// Number of diverging messages found so far.
+    pub differences: usize,
+}
+
+impl Verification {
+    pub fn new(room: String) -> Verification {
+        Verification {
+            room,
+            peer: None,
+            pending: HashSet::new(),
+            differences: 0,
+        }
+    }
+
+    pub fn add_pending(&mut self, request: u64) {
+        self.pending.insert(request);
+    }
+
+    /// Called when `peer` answers the query `request`. Returns false if the answer isn't for us:
+    /// the query was already answered, or `peer` isn't the one we compare with.
+    pub fn answered(&mut self, request: u64, peer: &PeerId) -> bool {
+        if self.peer.as_ref().map(|chosen| chosen != peer).unwrap_or(false) {
+            return false;
+        }
+        if !self.pending.remove(&request) {
+            return false;
+        }
+        self.peer = Some(peer.clone());
+        true
+    }
+
+    /// Returns true once all our queries were answered.
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// Returns our digest of the messages of `room` in `range`.
+pub fn digest(history: &History, room: &str, range: Range) -> Digest {
+    let leaves = leaves(history, room, range);
+    let root = hex(&root(&leaves, range));
+    let leaves = if range.is_bucket() || leaves.len() <= MAX_LEAVES {
+        Some(leaves)
+    } else {
+        None
+    };
+    Digest {
+        range,
+        root,
+        leaves,
+    }
+}
+
+/// Compares our digest of a range with the one of the peer.
+pub fn compare(ours: &Digest, theirs: &Digest) -> Outcome {
+    if ours.root == theirs.root {
+        return Outcome::Agree;
+    }
+    let (ours, theirs) = match (&ours.leaves, &theirs.leaves) {
+        (&Some(ref ours), &Some(ref theirs)) => (ours, theirs),
+        // Only a peer that doesn't follow the protocol omits the leaves of a bucket.
+        _ if theirs.range.is_bucket() => return Outcome::Differ(Vec::new()),
+        _ => {
+            let (first, second) = theirs.range.halves();
+            return Outcome::Split(first, second);
+        }
+    };
+    let mut differences = Vec::new();
+    let their_ids: HashSet<u64> = theirs.iter().map(|leaf| leaf.id).collect();
+    for leaf in ours.iter() {
+        if !their_ids.contains(&leaf.id) {
+            differences.push(Difference::Missing(leaf.id));
+        } else if !theirs.contains(leaf) {
+            differences.push(Difference::Changed(leaf.id));
+        }
+    }
+    let our_ids: HashSet<u64> = ours.iter().map(|leaf| leaf.id).collect();
+    for leaf in theirs.iter() {
+        if !our_ids.contains(&leaf.id) {
+            differences.push(Difference::Unknown(leaf.clone()));
+        }
+    }
+    Outcome::Differ(differences)
+}
+
+/// Returns the range of all the messages of `room` we remember, or `None` if there are none.
+pub fn everything(history: &History, room: &str) -> Option<Range> {
+    let mut sent = history
+        .iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| entry.room == room)
+        .map(|entry| entry.sent);
+    let first = sent.next()?;
+    let (from, to) = sent.fold((first, first), |(from, to), sent| (from.min(sent), to.max(sent)));
+    Some(Range { from, to: to + 1 })
+}
+
+/// The leaves of the messages of `room` in `range`, in the order of the tree.
+fn leaves(history: &History, room: &str, range: Range) -> Vec<Leaf> {
+    let mut leaves: Vec<Leaf> = history
+        .iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| entry.room == room && range.contains(entry.sent))
+        .map(leaf)
+        .collect();
+    leaves.sort_by_key(|leaf| (leaf.sent, leaf.id));
+    leaves
+}
+
+fn leaf(entry: &Entry) -> Leaf {
+    let mut hasher = Sha256::default();
+    hasher.input(&[0]);
+    hasher.input(entry.id.to_string().as_bytes());
+    hasher.input(&[0]);
+    hasher.input(entry.author.to_base58().as_bytes());
+    hasher.input(&[0]);
+    hasher.input(entry.sent.to_string().as_bytes());
+    hasher.input(&[0]);
+    hasher.input(entry.text.as_bytes());
+    Leaf {
+        id: entry.id,
+        sent: entry.sent,
+        hash: hex(&hasher.result()),
+    }
+}
+
+/// The root of `range`, given its leaves in order.
+fn root(leaves: &[Leaf], range: Range) -> Vec<u8> {
+    if leaves.is_empty() {
+        return Sha256::digest(b"").to_vec();
+    }
+    if range.is_bucket() {
+        return merkle(leaves);
+    }
+    let (first, second) = range.halves();
+    let split = leaves
+        .iter()
+        .take_while(|leaf| leaf.sent < second.from)
+        .count();
+    node(&root(&leaves[..split], first), &root(&leaves[split..], second))
+}
+
+/// The Merkle root of the messages of a bucket. The last hash of a level with an odd number of
+/// them goes up unchanged.
+fn merkle(leaves: &[Leaf]) -> Vec<u8> {
+    let mut level: Vec<Vec<u8>> = leaves.iter().map(|leaf| leaf.hash.as_bytes().to_vec()).collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                if pair.len() == 2 {
+                    node(&pair[0], &pair[1])
+                } else {
+                    pair[0].clone()
+                }
+            })
+            .collect();
+    }
+    level.remove(0)
+}
+
+/// The hash of an inner node of the tree. The prefix tells it apart from the hash of a message.
+fn node(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    hasher.input(&[1]);
+    hasher.input(left);
+    hasher.input(right);
+    hasher.result().to_vec()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn entry(id: u64, sent: u64, text: &str) -> Entry {
+        Entry {
+            id,
+            room: "general".to_owned(),
+            author: PeerId::from_public_key(&[1; 32]),
+            name: "alice".to_owned(),
+            // Only what the author wrote counts, not when we think it was sent.
+            timestamp: sent + 3,
+            sent,
+            text: text.to_owned(),
+            reply_to: None,
+            reactions: BTreeMap::new(),
+            edited: false,
+            deleted: false,
+        }
+    }
+
+    /// A history of `count` messages, one every 20 seconds.
+    fn history(count: u64) -> History {
+        let mut history = History::new();
+        for id in 0..count {
+            history.push(entry(id, 1_000 + id * 20, &format!("message {}", id)));
+        }
+        history
+    }
+
+    /// Compares the histories the way `/verify` does, descending into the ranges that differ.
+    /// Returns the differences, and the number of ranges that were compared.
+    fn verify(ours: &History, theirs: &History) -> (Vec<Difference>, usize) {
+        let mut ranges = vec![everything(ours, "general").unwrap()];
+        let (mut differences, mut compared) = (Vec::new(), 0);
+        while let Some(range) = ranges.pop() {
+            compared += 1;
+            let outcome = compare(
+                &digest(ours, "general", range),
+                &digest(theirs, "general", range),
+            );
+            match outcome {
+                Outcome::Agree => {}
+                Outcome::Differ(found) => differences.extend(found),
+                Outcome::Split(first, second) => ranges.extend(vec![first, second]),
+            }
+        }
+        (differences, compared)
+    }
+
+    #[test]
+    fn equal_histories_agree_at_the_root() {
+        let (differences, compared) = verify(&history(50), &history(50));
+        assert!(differences.is_empty());
+        assert_eq!(compared, 1);
+    }
+
+    #[test]
+    fn the_timestamps_we_corrected_dont_matter() {
+        let ours = history(3);
+        let mut theirs = History::new();
+        for (_, entry) in ours.iter() {
+            let mut entry = entry.clone();
+            entry.timestamp += 60;
+            theirs.push(entry);
+        }
+        let range = everything(&ours, "general").unwrap();
+        let outcome = compare(&digest(&ours, "general", range), &digest(&theirs, "general", range));
+        assert!(match outcome {
+            Outcome::Agree => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn only_the_ranges_that_diverge_are_compared() {
+        let ours = history(50);
+        let mut theirs = History::new();
+        for (_, entry) in ours.iter() {
+            let mut entry = entry.clone();
+            if entry.id == 7 {
+                entry.text = "rewritten".to_owned();
+            }
+            if entry.id != 30 {
+                theirs.push(entry);
+            }
+        }
+        let (differences, compared) = verify(&ours, &theirs);
+        let mut found: Vec<(char, u64)> = differences
+            .iter()
+            .map(|difference| match *difference {
+                Difference::Missing(id) => ('-', id),
+                Difference::Unknown(ref leaf) => ('+', leaf.id),
+                Difference::Changed(id) => ('~', id),
+            })
+            .collect();
+        found.sort();
+        assert_eq!(found, vec![('-', 30), ('~', 7)]);
+        // Far fewer than the 50 messages of the range.
+        assert!(compared < 12, "compared {} ranges", compared);
+    }
+
+    #[test]
+    fn the_messages_we_lack_are_unknown() {
+        let ours = history(3);
+        let mut theirs = history(3);
+        theirs.push(entry(3, 1_050, "late"));
+        let range = everything(&theirs, "general").unwrap();
+        match compare(&digest(&ours, "general", range), &digest(&theirs, "general", range)) {
+            Outcome::Differ(differences) => {
+                assert_eq!(differences.len(), 1);
+                match differences[0] {
+                    Difference::Unknown(ref leaf) => assert_eq!((leaf.id, leaf.sent), (3, 1_050)),
+                    _ => panic!("expected an unknown message"),
+                }
+            }
+            _ => panic!("the leaves should have been compared"),
+        }
+    }
+
+    #[test]
+    fn a_bucket_without_leaves_is_reported_without_splitting() {
+        let range = Range {
+            from: 1_000,
+            to: 1_000 + BUCKET_SECS,
+        };
+        let ours = digest(&history(3), "general", range);
+        let theirs = Digest {
+            range,
+            root: "not a root".to_owned(),
+            leaves: None,
+        };
+        match compare(&ours, &theirs) {
+            Outcome::Differ(differences) => assert!(differences.is_empty()),
+            _ => panic!("a bucket can't be split"),
+        }
+    }
+
+    #[test]
+    fn a_reversed_range_is_invalid() {
+        assert!(!Range { from: 10, to: 5 }.is_valid());
+        assert!(Range { from: 5, to: 5 }.is_valid());
+    }
+}
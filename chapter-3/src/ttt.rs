@@ -0,0 +1,300 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Tic-tac-toe between two peers.
+//!
+//! Contrary to chat messages, which are broadcast with floodsub, a game is played over a
+//! dedicated connection negotiated with its own protocol name. This shows how to write a custom
+//! connection upgrade: once the protocol is negotiated, the socket is turned into a stream and a
+//! sink of JSON-encoded `Message`s, one per line.
+//!
+//! The node that opens the connection plays `X` and moves first.
+
+use bytes::Bytes;
+use display;
+use futures::future::{self, FutureResult};
+use futures::sync::mpsc;
+use futures::{Future, Sink, Stream};
+use libp2p::core::{ConnectionUpgrade, Endpoint};
+use libp2p::Multiaddr;
+use serde_json;
+use std::cell::RefCell;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::iter;
+use std::rc::Rc;
+use tokio_io::codec::LinesCodec;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Name of the protocol, negotiated with multistream-select.
+pub const PROTOCOL_NAME: &[u8] = b"/rustfest-chat/ttt/1.0.0";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    /// The sender puts their mark on the given cell, numbered from 0 to 8.
+    Move(usize),
+    /// The sender gives up.
+    Resign,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mark {
+    X,
+    O,
+}
+
+impl Mark {
+    fn other(self) -> Mark {
+        match self {
+            Mark::X => Mark::O,
+            Mark::O => Mark::X,
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Mark::X => 'X',
+            Mark::O => 'O',
+        }
+    }
+}
+
+/// An open game connection, as produced by `TttUpgrade`.
+pub struct TttConnection {
+    pub endpoint: Endpoint,
+    pub incoming: Box<Stream<Item = Message, Error = IoError>>,
+    pub outgoing: Box<Sink<SinkItem = Message, SinkError = IoError>>,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TttUpgrade;
+
+impl<C> ConnectionUpgrade<C> for TttUpgrade
+where
+    C: AsyncRead + AsyncWrite + 'static,
+{
+    type NamesIter = iter::Once<(Bytes, ())>;
+    type UpgradeIdentifier = ();
+
+    fn protocol_names(&self) -> Self::NamesIter {
+        iter::once((Bytes::from(PROTOCOL_NAME), ()))
+    }
+
+    type Output = TttConnection;
+    type Future = FutureResult<TttConnection, IoError>;
+
+    fn upgrade(self, socket: C, _: (), endpoint: Endpoint, _: &Multiaddr) -> Self::Future {
+        let (sink, stream) = socket.framed(LinesCodec::new()).split();
+        let incoming = stream.and_then(|line| {
+            serde_json::from_str(&line).map_err(|err| IoError::new(IoErrorKind::InvalidData, err))
+        });
+        let outgoing = sink.with(|message: Message| {
+            serde_json::to_string(&message).map_err(|err| IoError::new(IoErrorKind::Other, err))
+        });
+        future::ok(TttConnection {
+            endpoint,
+            incoming: Box::new(incoming),
+            outgoing: Box::new(outgoing),
+        })
+    }
+}
+
+/// The game currently being played, if any.
+pub struct Games {
+    current: Option<Game>,
+}
+
+struct Game {
+    opponent: Multiaddr,
+    board: [Option<Mark>; 9],
+    ours: Mark,
+    turn: Mark,
+    sender: mpsc::UnboundedSender<Message>,
+}
+
+impl Games {
+    pub fn new() -> Rc<RefCell<Games>> {
+        Rc::new(RefCell::new(Games { current: None }))
+    }
+
+    /// Puts our mark on `cell`, numbered from 1 to 9 as on a phone keypad.
+    pub fn play(&mut self, cell: usize) {
+        let finished = match self.current {
+            Some(ref mut game) => {
+                if game.turn != game.ours {
+                    say!("* It's not your turn");
+                    return;
+                }
+                if cell < 1 || cell > 9 || game.board[cell - 1].is_some() {
+                    say!("* You can't play there");
+                    return;
+                }
+                let _ = game.sender.unbounded_send(Message::Move(cell - 1));
+                game.apply(cell - 1)
+            }
+            None => {
+                say!("* You aren't playing; start a game with `/ttt <multiaddr>`");
+                return;
+            }
+        };
+        if finished {
+            self.current = None;
+        }
+    }
+
+    /// Gives up the current game.
+    pub fn resign(&mut self) {
+        if let Some(game) = self.current.take() {
+            let _ = game.sender.unbounded_send(Message::Resign);
+            say!("* You resigned the game against {}", game.opponent);
+        }
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        let finished = match (self.current.as_mut(), message) {
+            (Some(game), Message::Move(cell)) => {
+                if game.turn == game.ours || cell >= 9 || game.board[cell].is_some() {
+                    say!("* {} made an invalid move", game.opponent);
+                    true
+                } else {
+                    game.apply(cell)
+                }
+            }
+            (Some(game), Message::Resign) => {
+                say!("* {} resigned, you win!", game.opponent);
+                true
+            }
+            (None, _) => false,
+        };
+        if finished {
+            self.current = None;
+        }
+    }
+
+    /// Called when the connection to `opponent` is closed.
+    fn connection_closed(&mut self, opponent: &Multiaddr) {
+        let abandoned = match self.current {
+            Some(ref game) => game.opponent == *opponent,
+            None => false,
+        };
+        if abandoned {
+            say!("* {} left the game", opponent);
+            self.current = None;
+        }
+    }
+}
+
+impl Game {
+    /// Puts the mark of the player whose turn it is on `cell`. Returns true if the game is over.
+    fn apply(&mut self, cell: usize) -> bool {
+        self.board[cell] = Some(self.turn);
+        self.turn = self.turn.other();
+        self.render();
+
+        match self.winner() {
+            Some(mark) if mark == self.ours => say!("* You win!"),
+            Some(_) => say!("* {} wins!", self.opponent),
+            None if self.board.iter().all(|c| c.is_some()) => say!("* It's a draw"),
+            None => {
+                if self.turn == self.ours {
+                    say!("* Your turn: `/move <1-9>`");
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn winner(&self) -> Option<Mark> {
+        const LINES: [[usize; 3]; 8] = [
+            [0, 1, 2], [3, 4, 5], [6, 7, 8],
+            [0, 3, 6], [1, 4, 7], [2, 5, 8],
+            [0, 4, 8], [2, 4, 6],
+        ];
+        LINES.iter().filter_map(|line| {
+            let mark = self.board[line[0]]?;
+            if self.board[line[1]] == Some(mark) && self.board[line[2]] == Some(mark) {
+                Some(mark)
+            } else {
+                None
+            }
+        }).next()
+    }
+
+    fn render(&self) {
+        for row in self.board.chunks(3) {
+            let row: Vec<String> = row.iter()
+                .map(|cell| cell.map(|m| m.symbol()).unwrap_or('.').to_string())
+                .collect();
+            display::reply(&format!("    {}", row.join(" ")));
+        }
+    }
+}
+
+/// Handles a game connection opened by us or by a remote, and returns the future that drives it.
+pub fn handle_connection(
+    games: Rc<RefCell<Games>>,
+    connection: TttConnection,
+    opponent: Multiaddr,
+) -> Box<Future<Item = (), Error = IoError>> {
+    if games.borrow().current.is_some() {
+        // We only play one game at a time. Dropping the connection tells the remote.
+        say!("* Declined a game from {}: you are already playing", opponent);
+        return Box::new(future::ok(()));
+    }
+
+    let ours = match connection.endpoint {
+        Endpoint::Dialer => Mark::X,
+        Endpoint::Listener => Mark::O,
+    };
+    let (sender, receiver) = mpsc::unbounded();
+    say!("* Started a game of tic-tac-toe against {}; you are {}", opponent, ours.symbol());
+    if ours == Mark::X {
+        say!("* Your turn: `/move <1-9>`");
+    }
+    games.borrow_mut().current = Some(Game {
+        opponent: opponent.clone(),
+        board: [None; 9],
+        ours,
+        turn: Mark::X,
+        sender,
+    });
+
+    let incoming = {
+        let games = games.clone();
+        connection.incoming.for_each(move |message| {
+            games.borrow_mut().handle_message(message);
+            Ok(())
+        })
+    };
+    let outgoing = connection
+        .outgoing
+        .send_all(receiver.map_err(|()| -> IoError { unreachable!() }))
+        .map(|_| ());
+    Box::new(
+        incoming
+            .select(outgoing)
+            .map(|_| ())
+            .map_err(|(err, _)| err)
+            .then(move |result| {
+                games.borrow_mut().connection_closed(&opponent);
+                result
+            }),
+    )
+}
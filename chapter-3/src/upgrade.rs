@@ -0,0 +1,275 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! The connection upgrade applied to every connection of the chat.
+//!
+//! Each connection negotiates one of several protocols: floodsub for the chat itself, or one of
+//! the direct protocols (such as tic-tac-toe, voice calls or screen sharing). Listeners accept all
+//! of them, while dialers can restrict what they propose in order to open a connection for a
+//! specific protocol.
+//!
+//! Tic-tac-toe and voice calls are only negotiated with the `games` and `calls` features.
+
+use bytes::Bytes;
+#[cfg(feature = "calls")]
+use audio::{self, AudioConnection, AudioUpgrade};
+use futures::Future;
+use libp2p::core::{ConnectionUpgrade, Endpoint};
+use libp2p::floodsub::FloodSubUpgrade;
+use libp2p::Multiaddr;
+use std::io::Error as IoError;
+use std::time::Instant;
+use tokio_io::{AsyncRead, AsyncWrite};
+use screen::{self, ScreenConnection, ScreenUpgrade};
+use screening::{Bans, Screened};
+#[cfg(feature = "games")]
+use ttt::{self, TttConnection, TttUpgrade};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Protocol {
+    FloodSub,
+    #[cfg(feature = "games")]
+    Ttt,
+    #[cfg(feature = "calls")]
+    Audio,
+    Screen,
+}
+
+impl Protocol {
+    /// The name negotiated by multistream-select.
+    pub fn name(&self) -> String {
+        let name: &[u8] = match *self {
+            // The name that `FloodSubUpgrade` proposes.
+            Protocol::FloodSub => b"/floodsub/1.0.0",
+            #[cfg(feature = "games")]
+            Protocol::Ttt => ttt::PROTOCOL_NAME,
+            #[cfg(feature = "calls")]
+            Protocol::Audio => audio::PROTOCOL_NAME,
+            Protocol::Screen => screen::PROTOCOL_NAME,
+        };
+        String::from_utf8_lossy(name).into_owned()
+    }
+
+    /// What the protocol is for, as told to the user.
+    pub fn describe(&self) -> &'static str {
+        match *self {
+            Protocol::FloodSub => "the chat",
+            #[cfg(feature = "games")]
+            Protocol::Ttt => "tic-tac-toe",
+            #[cfg(feature = "calls")]
+            Protocol::Audio => "voice calls",
+            Protocol::Screen => "screen sharing",
+        }
+    }
+}
+
+/// Asks for a new connection to `address`, negotiating `protocol`.
+#[derive(Debug, Clone)]
+pub struct DialRequest {
+    pub address: Multiaddr,
+    pub protocol: Protocol,
+}
+
+#[derive(Clone)]
+pub struct ChatUpgrade {
+    floodsub: FloodSubUpgrade,
+    /// The peers whose messages the floodsub connections drop.
+    bans: Bans,
+    /// Protocols that we propose or accept.
+    protocols: Vec<Protocol>,
+}
+
+impl ChatUpgrade {
+    /// Builds an upgrade that supports all the protocols of the chat.
+    pub fn new(floodsub: FloodSubUpgrade, bans: Bans) -> ChatUpgrade {
+        let mut protocols = vec![Protocol::FloodSub];
+        #[cfg(feature = "games")]
+        protocols.push(Protocol::Ttt);
+        #[cfg(feature = "calls")]
+        protocols.push(Protocol::Audio);
+        protocols.push(Protocol::Screen);
+        ChatUpgrade {
+            floodsub,
+            bans,
+            protocols,
+        }
+    }
+
+    /// Returns a copy of this upgrade that only supports `protocol`.
+    pub fn only(&self, protocol: Protocol) -> ChatUpgrade {
+        ChatUpgrade {
+            floodsub: self.floodsub.clone(),
+            bans: self.bans.clone(),
+            protocols: vec![protocol],
+        }
+    }
+}
+
+/// The transports used to dial, one per protocol. They are built once at startup, and each dial
+/// only needs a cheap clone of the right one.
+pub struct Dialers<T> {
+    floodsub: T,
+    #[cfg(feature = "games")]
+    ttt: T,
+    #[cfg(feature = "calls")]
+    audio: T,
+    screen: T,
+}
+
+impl<T: Clone> Dialers<T> {
+    /// Builds the transports by calling `build` for each protocol.
+    pub fn new<F>(build: F) -> Dialers<T>
+    where
+        F: Fn(Protocol) -> T,
+    {
+        Dialers {
+            floodsub: build(Protocol::FloodSub),
+            #[cfg(feature = "games")]
+            ttt: build(Protocol::Ttt),
+            #[cfg(feature = "calls")]
+            audio: build(Protocol::Audio),
+            screen: build(Protocol::Screen),
+        }
+    }
+
+    /// Returns the transport that only proposes `protocol`.
+    pub fn get(&self, protocol: Protocol) -> T {
+        match protocol {
+            Protocol::FloodSub => self.floodsub.clone(),
+            #[cfg(feature = "games")]
+            Protocol::Ttt => self.ttt.clone(),
+            #[cfg(feature = "calls")]
+            Protocol::Audio => self.audio.clone(),
+            Protocol::Screen => self.screen.clone(),
+        }
+    }
+}
+
+/// Output of `ChatUpgrade`.
+pub struct Negotiated<F> {
+    /// `Dialer` if we opened the connection, `Listener` if the remote did.
+    pub endpoint: Endpoint,
+    pub output: ChatOutput<F>,
+    /// When multistream-select chose the protocol, before its own handshake. See the `stack`
+    /// module.
+    pub chosen: Instant,
+}
+
+pub enum ChatOutput<F> {
+    /// The future that drives the floodsub protocol.
+    FloodSub(F),
+    #[cfg(feature = "games")]
+    Ttt(TttConnection),
+    #[cfg(feature = "calls")]
+    Audio(AudioConnection),
+    Screen(ScreenConnection),
+}
+
+impl<F> ChatOutput<F> {
+    pub fn protocol(&self) -> Protocol {
+        match *self {
+            ChatOutput::FloodSub(_) => Protocol::FloodSub,
+            #[cfg(feature = "games")]
+            ChatOutput::Ttt(_) => Protocol::Ttt,
+            #[cfg(feature = "calls")]
+            ChatOutput::Audio(_) => Protocol::Audio,
+            ChatOutput::Screen(_) => Protocol::Screen,
+        }
+    }
+}
+
+impl<C> ConnectionUpgrade<C> for ChatUpgrade
+where
+    C: AsyncRead + AsyncWrite + 'static,
+    FloodSubUpgrade: ConnectionUpgrade<Screened<C>, UpgradeIdentifier = ()>,
+    <FloodSubUpgrade as ConnectionUpgrade<Screened<C>>>::Future: 'static,
+{
+    type NamesIter = ::std::vec::IntoIter<(Bytes, Protocol)>;
+    type UpgradeIdentifier = Protocol;
+
+    fn protocol_names(&self) -> Self::NamesIter {
+        let mut names = Vec::new();
+        for protocol in &self.protocols {
+            match *protocol {
+                Protocol::FloodSub => names.extend(
+                    ConnectionUpgrade::<Screened<C>>::protocol_names(&self.floodsub)
+                        .map(|(name, ())| (name, Protocol::FloodSub)),
+                ),
+                #[cfg(feature = "games")]
+                Protocol::Ttt => names.extend(
+                    ConnectionUpgrade::<C>::protocol_names(&TttUpgrade)
+                        .map(|(name, ())| (name, Protocol::Ttt)),
+                ),
+                #[cfg(feature = "calls")]
+                Protocol::Audio => names.extend(
+                    ConnectionUpgrade::<C>::protocol_names(&AudioUpgrade)
+                        .map(|(name, ())| (name, Protocol::Audio)),
+                ),
+                Protocol::Screen => names.extend(
+                    ConnectionUpgrade::<C>::protocol_names(&ScreenUpgrade)
+                        .map(|(name, ())| (name, Protocol::Screen)),
+                ),
+            }
+        }
+        names.into_iter()
+    }
+
+    type Output = Negotiated<<FloodSubUpgrade as ConnectionUpgrade<Screened<C>>>::Output>;
+    type Future = Box<Future<Item = Self::Output, Error = IoError>>;
+
+    fn upgrade(
+        self,
+        socket: C,
+        protocol: Protocol,
+        endpoint: Endpoint,
+        remote_addr: &Multiaddr,
+    ) -> Self::Future {
+        let chosen = Instant::now();
+        let output: Box<Future<Item = _, Error = IoError>> = match protocol {
+            Protocol::FloodSub => Box::new(
+                self.floodsub
+                    .upgrade(Screened::new(socket, self.bans), (), endpoint, remote_addr)
+                    .map(ChatOutput::FloodSub),
+            ),
+            #[cfg(feature = "games")]
+            Protocol::Ttt => Box::new(
+                TttUpgrade
+                    .upgrade(socket, (), endpoint, remote_addr)
+                    .map(ChatOutput::Ttt),
+            ),
+            #[cfg(feature = "calls")]
+            Protocol::Audio => Box::new(
+                AudioUpgrade
+                    .upgrade(socket, (), endpoint, remote_addr)
+                    .map(ChatOutput::Audio),
+            ),
+            Protocol::Screen => Box::new(
+                ScreenUpgrade
+                    .upgrade(socket, (), endpoint, remote_addr)
+                    .map(ChatOutput::Screen),
+            ),
+        };
+        Box::new(output.map(move |output| Negotiated {
+            endpoint,
+            output,
+            chosen,
+        }))
+    }
+}
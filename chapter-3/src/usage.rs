@@ -0,0 +1,195 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Accounting of the bandwidth, for `/usage`.
+//!
+//! Floodsub sends every message to every connection, so its cost grows with the mesh. To help
+//! reasoning about it, we count the bytes that go through the sockets, and the envelopes that we
+//! receive and publish, by room, by peer and by hour of the session.
+
+use libp2p::PeerId;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+#[cfg(not(target_os = "emscripten"))]
+use std::io::{Error as IoError, Read, Write};
+use std::rc::Rc;
+use std::time::Instant;
+#[cfg(not(target_os = "emscripten"))]
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Bytes sent and received by all the sockets. Cloning a `Counters` shares them. They stay at
+/// zero in the browser, whose sockets we don't see.
+#[derive(Clone, Default)]
+pub struct Counters {
+    sent: Rc<Cell<u64>>,
+    received: Rc<Cell<u64>>,
+}
+
+impl Counters {
+    pub fn sent(&self) -> u64 {
+        self.sent.get()
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.get()
+    }
+}
+
+/// A socket whose traffic is added to `Counters`.
+#[cfg(not(target_os = "emscripten"))]
+pub struct Counted<S> {
+    inner: S,
+    counters: Counters,
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl<S> Counted<S> {
+    pub fn new(inner: S, counters: Counters) -> Counted<S> {
+        Counted { inner, counters }
+    }
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl<S: Read> Read for Counted<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let len = self.inner.read(buf)?;
+        let received = &self.counters.received;
+        received.set(received.get() + len as u64);
+        Ok(len)
+    }
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl<S: Write> Write for Counted<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IoError> {
+        let len = self.inner.write(buf)?;
+        let sent = &self.counters.sent;
+        sent.set(sent.get() + len as u64);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(not(target_os = "emscripten"))]
+impl<S: AsyncRead> AsyncRead for Counted<S> {}
+
+#[cfg(not(target_os = "emscripten"))]
+impl<S: AsyncWrite> AsyncWrite for Counted<S> {
+    fn shutdown(&mut self) -> ::futures::Poll<(), IoError> {
+        self.inner.shutdown()
+    }
+}
+
+/// A number of envelopes and their total size.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tally {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+impl Tally {
+    fn add(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// What each room, peer and hour cost us.
+pub struct Usage {
+    start: Instant,
+    /// Received and sent, by room.
+    rooms: BTreeMap<String, (Tally, Tally)>,
+    /// Received, by publisher.
+    peers: HashMap<PeerId, Tally>,
+    /// Received and sent together, by hour since the start.
+    hours: Vec<Tally>,
+}
+
+impl Usage {
+    pub fn new() -> Usage {
+        Usage {
+            start: Instant::now(),
+            rooms: BTreeMap::new(),
+            peers: HashMap::new(),
+            hours: Vec::new(),
+        }
+    }
+
+    /// Counts an envelope of `bytes` published by `peer` in `room`, if it is one of ours.
+    pub fn received(&mut self, room: Option<&str>, peer: &PeerId, bytes: usize) {
+        if let Some(room) = room {
+            self.rooms.entry(room.to_owned()).or_insert_with(Default::default).0.add(bytes);
+        }
+        self.peers.entry(peer.clone()).or_insert_with(Default::default).add(bytes);
+        self.this_hour().add(bytes);
+    }
+
+    /// Counts an envelope of `bytes` that we publish in `room`.
+    pub fn sent(&mut self, room: Option<&str>, bytes: usize) {
+        if let Some(room) = room {
+            self.rooms.entry(room.to_owned()).or_insert_with(Default::default).1.add(bytes);
+        }
+        self.this_hour().add(bytes);
+    }
+
+    /// Returns the received and sent tallies of each room.
+    pub fn rooms(&self) -> impl Iterator<Item = (&String, &(Tally, Tally))> {
+        self.rooms.iter()
+    }
+
+    /// Returns the tallies of the peers, the largest first.
+    pub fn peers(&self) -> Vec<(&PeerId, &Tally)> {
+        let mut peers: Vec<_> = self.peers.iter().collect();
+        peers.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes));
+        peers
+    }
+
+    /// Returns the tallies of each hour since the start.
+    pub fn hours(&self) -> &[Tally] {
+        &self.hours
+    }
+
+    fn this_hour(&mut self) -> &mut Tally {
+        let hour = (self.start.elapsed().as_secs() / 3600) as usize;
+        if self.hours.len() <= hour {
+            self.hours.resize(hour + 1, Tally::default());
+        }
+        &mut self.hours[hour]
+    }
+}
+
+/// Formats a number of bytes for humans, such as `12.3 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
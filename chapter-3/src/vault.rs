@@ -0,0 +1,148 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Passphrase-based encryption of the files that we write to disk.
+//!
+//! The key is derived from the passphrase with Argon2 and a random salt, so that guessing the
+//! passphrase of a stolen file is slow, and the content is encrypted and authenticated with
+//! ChaCha20-Poly1305. A sealed file is made of `MAGIC`, the salt, the nonce, and finally the
+//! ciphertext followed by its tag.
+//!
+//! Only the identity file is sealed, with `--encrypt-identity`. The other files that we write
+//! say in their module why they aren't; `/purge all` deletes them all.
+//!
+//! There are no files in the browser, so there everything fails. Without the
+//! `encrypted-identity` feature, it fails too, and a sealed identity file can't be opened.
+
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+use argon2;
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+use rand::{OsRng, Rng};
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+use ring::aead::{self, OpeningKey, SealingKey, CHACHA20_POLY1305};
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+use rpassword;
+use std::io::{Error as IoError, ErrorKind};
+
+/// Start of every sealed file, which lets us tell them apart from the plain ones.
+const MAGIC: &[u8] = b"rustfest-chat sealed v1\n";
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+const SALT_LEN: usize = 16;
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+const NONCE_LEN: usize = 12;
+
+/// Returns true if `data` was produced by `seal`.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Asks the user for the passphrase of an existing file.
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+pub fn ask_passphrase() -> Result<String, IoError> {
+    rpassword::prompt_password_stdout("Passphrase: ")
+}
+
+/// Asks the user to choose a passphrase, twice to avoid typos.
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+pub fn new_passphrase() -> Result<String, IoError> {
+    let passphrase = rpassword::prompt_password_stdout("New passphrase: ")?;
+    if rpassword::prompt_password_stdout("Repeat the passphrase: ")? != passphrase {
+        return Err(IoError::new(ErrorKind::InvalidInput, "the passphrases don't match"));
+    }
+    Ok(passphrase)
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase`.
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+pub fn seal(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>, IoError> {
+    let mut rng = OsRng::new()?;
+    let mut salt = [0; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill_bytes(&mut nonce);
+
+    let key = SealingKey::new(&CHACHA20_POLY1305, &derive_key(passphrase, &salt)?)
+        .expect("argon2 produces keys of the right length");
+    let tag_len = CHACHA20_POLY1305.tag_len();
+    let mut in_out = plaintext.to_vec();
+    in_out.extend(vec![0; tag_len]);
+    let len = aead::seal_in_place(&key, &nonce, &[], &mut in_out, tag_len)
+        .expect("encrypting in memory never fails");
+
+    let mut sealed = MAGIC.to_vec();
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&in_out[..len]);
+    Ok(sealed)
+}
+
+/// Decrypts data produced by `seal`. Fails if the passphrase is wrong or the data was modified.
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+pub fn open(passphrase: &str, sealed: &[u8]) -> Result<Vec<u8>, IoError> {
+    let invalid = || IoError::new(ErrorKind::InvalidData, "wrong passphrase or corrupted file");
+    if !is_sealed(sealed) || sealed.len() < MAGIC.len() + SALT_LEN + NONCE_LEN {
+        return Err(invalid());
+    }
+    let (salt, rest) = sealed[MAGIC.len()..].split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let key = OpeningKey::new(&CHACHA20_POLY1305, &derive_key(passphrase, salt)?)
+        .expect("argon2 produces keys of the right length");
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = aead::open_in_place(&key, nonce, &[], 0, &mut in_out).map_err(|_| invalid())?;
+    Ok(plaintext.to_vec())
+}
+
+#[cfg(all(feature = "encrypted-identity", not(target_os = "emscripten")))]
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Vec<u8>, IoError> {
+    // The default configuration produces 32 bytes, which is what ChaCha20 expects.
+    argon2::hash_raw(passphrase.as_bytes(), salt, &argon2::Config::default())
+        .map_err(|err| IoError::new(ErrorKind::Other, err.to_string()))
+}
+
+#[cfg(not(all(feature = "encrypted-identity", not(target_os = "emscripten"))))]
+pub fn ask_passphrase() -> Result<String, IoError> {
+    Err(unsupported())
+}
+
+#[cfg(not(all(feature = "encrypted-identity", not(target_os = "emscripten"))))]
+pub fn new_passphrase() -> Result<String, IoError> {
+    Err(unsupported())
+}
+
+#[cfg(not(all(feature = "encrypted-identity", not(target_os = "emscripten"))))]
+pub fn seal(_passphrase: &str, _plaintext: &[u8]) -> Result<Vec<u8>, IoError> {
+    Err(unsupported())
+}
+
+#[cfg(not(all(feature = "encrypted-identity", not(target_os = "emscripten"))))]
+pub fn open(_passphrase: &str, _sealed: &[u8]) -> Result<Vec<u8>, IoError> {
+    Err(unsupported())
+}
+
+#[cfg(not(all(feature = "encrypted-identity", not(target_os = "emscripten"))))]
+fn unsupported() -> IoError {
+    let message = if cfg!(target_os = "emscripten") {
+        "encryption isn't available in the browser"
+    } else {
+        "encrypting the identity file requires the encrypted-identity feature"
+    };
+    IoError::new(ErrorKind::Other, message)
+}
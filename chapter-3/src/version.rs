@@ -0,0 +1,51 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Versioning of the chat protocol.
+//!
+//! Every envelope carries the version of the protocol it was written with. Two versions with the
+//! same major number are compatible; messages from an incompatible version are reported once and
+//! then ignored, rather than being decoded into garbage.
+//!
+//! The agent string is what we would advertise through libp2p's identify protocol. The swarm of
+//! this chapter doesn't negotiate identify yet, so for now the envelopes are what peers see.
+
+/// Version of the format of the envelopes and of their bodies. Version 2 encodes the bytes of the
+/// envelopes in base64 and signs their version.
+pub const PROTOCOL: &str = "2.0";
+
+/// The version before the last incompatible change, and when `PROTOCOL` replaced it, in seconds
+/// since the UNIX epoch. To be updated along with the major number of `PROTOCOL`; see the
+/// `migration` module.
+pub const PREVIOUS: Option<(&str, u64)> = Some(("1.0", 1_528_848_000));
+
+/// Name and version of this client.
+pub fn agent() -> String {
+    format!("rustfest-chat/{} (protocol {})", env!("CARGO_PKG_VERSION"), PROTOCOL)
+}
+
+/// Returns true if we can understand messages written with the protocol `version`.
+pub fn is_compatible(version: &str) -> bool {
+    major(version) == major(PROTOCOL)
+}
+
+pub fn major(version: &str) -> &str {
+    version.split('.').next().unwrap_or("")
+}
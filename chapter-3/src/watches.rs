@@ -0,0 +1,69 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Keyword subscriptions, with `/watch <pattern>`.
+//!
+//! The messages of every room we are in are matched against the patterns. A message that matches
+//! is highlighted and notified like a mention, even in a room whose notification level is
+//! `silent`, and even while the room is flooded. The patterns are regular expressions, matched
+//! regardless of case, and are saved in the configuration file for the current profile.
+
+use regex::{Error as RegexError, Regex, RegexBuilder};
+
+pub struct Watches {
+    patterns: Vec<Regex>,
+}
+
+impl Watches {
+    pub fn new() -> Watches {
+        Watches {
+            patterns: Vec::new(),
+        }
+    }
+
+    /// Adds a pattern. Returns false if it was already watched.
+    pub fn add(&mut self, pattern: &str) -> Result<bool, RegexError> {
+        if self.patterns().any(|watched| watched == pattern) {
+            return Ok(false);
+        }
+        let regex = RegexBuilder::new(pattern).case_insensitive(true).build()?;
+        self.patterns.push(regex);
+        Ok(true)
+    }
+
+    /// Removes a pattern. Returns false if there was no such pattern.
+    pub fn remove(&mut self, pattern: &str) -> bool {
+        let before = self.patterns.len();
+        self.patterns.retain(|regex| regex.as_str() != pattern);
+        self.patterns.len() != before
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.patterns.iter().map(|regex| regex.as_str())
+    }
+
+    /// Returns the first pattern that `text` matches, if any.
+    pub fn matching(&self, text: &str) -> Option<&str> {
+        self.patterns
+            .iter()
+            .find(|regex| regex.is_match(text))
+            .map(|regex| regex.as_str())
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Interactive setup, on the first run with a configuration file that doesn't exist yet.
+//!
+//! A few questions are asked on the terminal, and the answers are saved as the `default` profile
+//! of the configuration file, which is used whenever `--profile` isn't passed. The key pair is
+//! generated right away, so that the user can share their public key before joining.
+
+use addresses;
+use config::{Config, Profile, DEFAULT_PROFILE};
+use identity::{self, Identity};
+use std::io::{self, BufRead, Error as IoError, Write};
+use std::path::Path;
+
+/// Asks the questions and writes the configuration file at `path`.
+pub fn run(path: &str) -> Result<(), IoError> {
+    say!("Welcome! Let's create {} with a few questions.", path);
+    say!("Press enter to keep the value in brackets.");
+
+    let nick = ask("Nickname", "")?;
+    let default_identity = Path::new(path).with_file_name("identity.key");
+    let identity_path = ask("File to store your key pair in", &default_identity.to_string_lossy())?;
+    let port = loop {
+        match ask("Port to listen on", "63204")?.parse::<u16>() {
+            Ok(port) => break port,
+            Err(_) => say!("That isn't a port number."),
+        }
+    };
+    let dial = loop {
+        let address = ask("Address of a node to join, if you were given one", "")?;
+        if address.is_empty() {
+            break address;
+        }
+        // The shorthands are saved expanded, as a reference for editing the file later.
+        match addresses::parse(&address) {
+            Ok(address) => break address.to_string(),
+            Err(err) => say!("That isn't a multiaddress: {}", err),
+        }
+    };
+
+    let identity = Identity::load_or_generate(&identity_path, false)?;
+    say!(
+        "Your public key is {}",
+        identity::encode_key(identity.public_key())
+    );
+
+    let profile = Profile {
+        identity: Some(identity_path),
+        nick: if nick.is_empty() { None } else { Some(nick) },
+        listen: vec![format!("/ip4/0.0.0.0/tcp/{}/ws", port)],
+        dial: if dial.is_empty() { Vec::new() } else { vec![dial] },
+        topics: Vec::new(),
+    };
+    let mut config = Config::default();
+    config.profile.insert(DEFAULT_PROFILE.to_owned(), profile);
+    config.save(path)?;
+    say!("Saved. You can edit {} to change these settings later.", path);
+    Ok(())
+}
+
+/// Asks `question` and returns the answer, or `default` if it is empty.
+fn ask(question: &'static str, default: &str) -> Result<String, IoError> {
+    let question = tr!(question);
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_owned())
+}
@@ -0,0 +1,85 @@
+// Copyright 2018 Pierre Krieger
+//
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Opening of the envelopes outside of the events loop.
+//!
+//! Verifying a signature is much more expensive than anything else we do with a message. On
+//! native platforms, envelopes are therefore opened on a pool of worker threads, while the
+//! events loop thread keeps handling the network and stdin. Browsers don't have threads, so there
+//! the envelopes are opened immediately.
+
+use bytes::Bytes;
+use envelope::{self, OpenError, Received};
+use futures::future::{self, Future};
+#[cfg(not(target_os = "emscripten"))]
+use futures_cpupool::{Builder, CpuPool};
+use std::io::Error as IoError;
+
+pub struct Workers {
+    #[cfg(not(target_os = "emscripten"))]
+    pool: Option<CpuPool>,
+    max_message_size: usize,
+}
+
+impl Workers {
+    /// Builds a pool of `threads` workers, one per CPU if `None`, or none at all if `Some(0)`.
+    pub fn new(threads: Option<usize>, max_message_size: usize) -> Workers {
+        #[cfg(not(target_os = "emscripten"))]
+        let pool = match threads {
+            Some(0) => None,
+            Some(threads) => Some(
+                Builder::new()
+                    .pool_size(threads)
+                    .name_prefix("crypto-")
+                    .create(),
+            ),
+            None => Some(CpuPool::new_num_cpus()),
+        };
+        #[cfg(target_os = "emscripten")]
+        let _ = threads;
+
+        Workers {
+            #[cfg(not(target_os = "emscripten"))]
+            pool,
+            max_message_size,
+        }
+    }
+
+    /// Parses and verifies an envelope received from floodsub.
+    pub fn open(
+        &self,
+        data: Bytes,
+    ) -> Box<Future<Item = Result<Received, OpenError>, Error = IoError>> {
+        if data.len() > self.max_message_size {
+            return Box::new(future::ok(Err(OpenError::TooLarge {
+                size: data.len(),
+                limit: self.max_message_size,
+            })));
+        }
+
+        #[cfg(not(target_os = "emscripten"))]
+        {
+            if let Some(ref pool) = self.pool {
+                return Box::new(pool.spawn_fn(move || Ok(envelope::open(&data))));
+            }
+        }
+        Box::new(future::ok(envelope::open(&data)))
+    }
+}